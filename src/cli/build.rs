@@ -0,0 +1,13 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // A protoc that is installed and given in PROTOC wins, otherwise the vendored one saves installing it.
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+    tonic_build::compile_protos("proto/cwl_mount.proto")?;
+    Ok(())
+}
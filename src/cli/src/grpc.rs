@@ -0,0 +1,334 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! The gRPC service in `proto/cwl_mount.proto`, so that sidecars and tools that are not written in Rust can read logs
+//! through the same rate limiting and caching as the mount without FUSE. The messages and the service trait are
+//! generated from the .proto file by build.rs.
+
+// Status is what every method of the generated trait returns, so it is returned as is rather than boxed.
+#![allow(clippy::result_large_err)]
+
+use std::future::Future;
+use std::net::TcpListener;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use cwl_lib::CloudWatchLogsActorHandle;
+use cwl_lib::CloudWatchLogsError;
+use cwl_lib::EventFilter;
+use format_cwl_log_event::LogFormatter;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_util::sync::CancellationToken;
+use tonic::{Code, Request, Response, Status};
+use tracing::{info, warn};
+
+use crate::time_windows;
+use crate::CAT_WINDOW_HOURS;
+use crate::DEFAULT_OUTPUT_FORMAT;
+
+use self::proto::cwl_mount_server::{CwlMount, CwlMountServer};
+use self::proto::{ListLogGroupsRequest, ListLogGroupsResponse, LogsRequest, LogsResponse};
+
+pub(crate) mod proto {
+    tonic::include_proto!("cwlmount.v1");
+}
+
+/// How many response messages of a stream wait for the client before reading more events waits too.
+const RESPONSE_BUFFER: usize = 4;
+
+fn status_from_error(err: &CloudWatchLogsError) -> Status {
+    let code = if err.is_credentials_error() {
+        Code::Unauthenticated
+    } else if err.is_access_denied() {
+        Code::PermissionDenied
+    } else if err.is_resource_not_found()
+        || matches!(err, CloudWatchLogsError::NoCloudWatchLogGroupsMatchFilter(_))
+    {
+        Code::NotFound
+    } else if err.is_unsupported_by_log_group_class() {
+        Code::FailedPrecondition
+    } else if err.is_throttling() {
+        Code::ResourceExhausted
+    } else if err.is_timeout() {
+        Code::DeadlineExceeded
+    } else {
+        Code::Internal
+    };
+    Status::new(code, err.to_string())
+}
+
+/// Unset proto3 strings are empty.
+fn non_empty(s: &str) -> Option<String> {
+    Some(s.to_string()).filter(|s| !s.is_empty())
+}
+
+impl LogsRequest {
+    /// The log group name and filter, exactly one of which is set.
+    fn log_groups(&self) -> Result<(Option<String>, Option<String>), Status> {
+        match (non_empty(&self.log_group_name), non_empty(&self.log_group_filter)) {
+            (Some(log_group_name), None) => Ok((Some(log_group_name), None)),
+            (None, Some(log_group_filter)) => Ok((None, Some(log_group_filter))),
+            _ => Err(Status::invalid_argument(
+                "set exactly one of log_group_name and log_group_filter",
+            )),
+        }
+    }
+
+    fn formatter(&self) -> Result<LogFormatter, Status> {
+        let output_format = non_empty(&self.output_format);
+        LogFormatter::new(output_format.as_deref().unwrap_or(DEFAULT_OUTPUT_FORMAT))
+            .map_err(|err| Status::invalid_argument(err.to_string()))
+    }
+
+    fn event_filter(&self) -> EventFilter {
+        EventFilter {
+            filter_pattern: non_empty(&self.filter_pattern),
+            ..EventFilter::default()
+        }
+    }
+}
+
+/// Unset proto3 times are 0.
+fn time_from_ms(ms: i64) -> Result<Option<DateTime<Utc>>, Status> {
+    if ms == 0 {
+        return Ok(None);
+    }
+    Utc.timestamp_millis_opt(ms)
+        .single()
+        .map(Some)
+        .ok_or_else(|| Status::invalid_argument(format!("{} is not a valid time", ms)))
+}
+
+struct CwlMountService {
+    cwl: CloudWatchLogsActorHandle,
+    stopping: CancellationToken,
+}
+
+#[tonic::async_trait]
+impl CwlMount for CwlMountService {
+    async fn list_log_groups(
+        &self,
+        _request: Request<ListLogGroupsRequest>,
+    ) -> Result<Response<ListLogGroupsResponse>, Status> {
+        let log_group_names = self
+            .cwl
+            .get_log_group_names()
+            .await
+            .map_err(|err| status_from_error(&err))?;
+        Ok(Response::new(ListLogGroupsResponse { log_group_names }))
+    }
+
+    type GetLogsStream = ReceiverStream<Result<LogsResponse, Status>>;
+
+    async fn get_logs(&self, request: Request<LogsRequest>) -> Result<Response<Self::GetLogsStream>, Status> {
+        let request = request.into_inner();
+        let (log_group_name, log_group_filter) = request.log_groups()?;
+        let formatter = request.formatter()?;
+        let start_time = time_from_ms(request.start_time_ms)?
+            .ok_or_else(|| Status::invalid_argument("GetLogs needs start_time_ms"))?;
+        let end_time = time_from_ms(request.end_time_ms)?.unwrap_or_else(Utc::now);
+        let event_filter = request.event_filter();
+        let cwl = self.cwl.clone();
+        let (responses, stream) = mpsc::channel(RESPONSE_BUFFER);
+        tokio::spawn(async move {
+            for (start_time, end_time) in
+                time_windows(start_time, end_time, Duration::hours(CAT_WINDOW_HOURS))
+            {
+                let result = cwl
+                    .get_logs_to_display(
+                        log_group_name.clone(),
+                        log_group_filter.clone(),
+                        start_time,
+                        end_time,
+                        event_filter.clone(),
+                        formatter.clone(),
+                    )
+                    .await;
+                let response = match result {
+                    Ok(data) if data.is_empty() => continue,
+                    Ok(data) => {
+                        let mut data = data.to_vec();
+                        data.push(b'\n');
+                        Ok(LogsResponse { data })
+                    }
+                    Err(err) => Err(status_from_error(&err)),
+                };
+                let failed = response.is_err();
+                // The client went away.
+                if responses.send(response).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
+
+    type TailStream = ReceiverStream<Result<LogsResponse, Status>>;
+
+    /// Follow new events until the client goes away or the server stops.
+    async fn tail(&self, request: Request<LogsRequest>) -> Result<Response<Self::TailStream>, Status> {
+        let request = request.into_inner();
+        let (log_group_name, log_group_filter) = request.log_groups()?;
+        let formatter = request.formatter()?;
+        let start_time = time_from_ms(request.start_time_ms)?;
+        let mut receiver = self
+            .cwl
+            .live_tail(
+                log_group_name,
+                log_group_filter,
+                request.event_filter(),
+                start_time,
+            )
+            .await
+            .map_err(|err| status_from_error(&err))?;
+        let stopping = self.stopping.clone();
+        let (responses, stream) = mpsc::channel(RESPONSE_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let result = tokio::select! {
+                    result = receiver.recv() => result,
+                    _ = responses.closed() => return,
+                    _ = stopping.cancelled() => {
+                        let _ = responses.send(Err(Status::unavailable("the server is stopping"))).await;
+                        return;
+                    }
+                };
+                match result {
+                    Some(Ok(event)) => {
+                        let data = format!("{}\n", formatter.format(event)).into_bytes();
+                        if responses.send(Ok(LogsResponse { data })).await.is_err() {
+                            return;
+                        }
+                    }
+                    // Polling carries on, so errors like throttling are only worth a warning.
+                    Some(Err(err)) => warn!("failed to poll for new events: {}", err),
+                    None => return,
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(stream)))
+    }
+}
+
+/// Serve the service in `proto/cwl_mount.proto` over plaintext HTTP/2 on `listener` until `shutdown` completes.
+/// Tails that are still going then end with UNAVAILABLE.
+pub async fn serve_grpc(
+    cwl: CloudWatchLogsActorHandle,
+    listener: TcpListener,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    let stopping = CancellationToken::new();
+    let service = CwlMountService {
+        cwl,
+        stopping: stopping.clone(),
+    };
+    // Failing here would have failed binding the listener already.
+    listener
+        .set_nonblocking(true)
+        .expect("listener can be non-blocking");
+    let listener = tokio::net::TcpListener::from_std(listener).expect("listener is a TCP listener");
+    info!(
+        "serving gRPC on {}",
+        listener.local_addr().expect("listener is bound")
+    );
+    tonic::transport::Server::builder()
+        .add_service(CwlMountServer::new(service))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+            shutdown.await;
+            stopping.cancel();
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use chrono::{TimeZone, Utc};
+    use cwl_lib::CloudWatchLogsActorHandle;
+    use cwl_lib::FakeCloudWatchLogs;
+    use tonic::Code;
+
+    use crate::grpc::proto::cwl_mount_client::CwlMountClient;
+    use crate::grpc::proto::{ListLogGroupsRequest, LogsRequest};
+    use crate::grpc::{serve_grpc, time_from_ms};
+
+    #[test]
+    fn test_logs_request() {
+        let request = LogsRequest {
+            log_group_name: "/app/web".to_string(),
+            filter_pattern: "ERROR".to_string(),
+            ..LogsRequest::default()
+        };
+        assert_eq!(Some("/app/web".to_string()), request.log_groups().unwrap().0);
+        assert_eq!(Some("ERROR".to_string()), request.event_filter().filter_pattern);
+        assert_eq!(
+            Code::InvalidArgument,
+            LogsRequest::default().log_groups().unwrap_err().code()
+        );
+        let both = LogsRequest {
+            log_group_filter: "/app/.*".to_string(),
+            ..request
+        };
+        assert_eq!(Code::InvalidArgument, both.log_groups().unwrap_err().code());
+        assert_eq!(None, time_from_ms(0).unwrap());
+        assert_eq!(
+            Some(Utc.ymd(2021, 12, 4).and_hms(0, 0, 0)),
+            time_from_ms(1638576000000).unwrap()
+        );
+        assert_eq!(Code::InvalidArgument, time_from_ms(i64::MAX).unwrap_err().code());
+    }
+
+    #[test]
+    fn test_serve_grpc() {
+        let start = Utc.ymd(2021, 12, 4).and_hms(0, 0, 0);
+        let cwl = FakeCloudWatchLogs::new(Some("us-west-2"));
+        cwl.create_log_group("/app/worker");
+        cwl.put_log_event("/app/web", "web-1", start, "GET /a".to_string());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio_test::block_on(async {
+            let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+            let server = tokio::spawn(serve_grpc(CloudWatchLogsActorHandle::new(cwl), listener, async {
+                let _ = stopped.await;
+            }));
+            let mut client = CwlMountClient::connect(format!("http://127.0.0.1:{}", port))
+                .await
+                .unwrap();
+
+            let mut log_group_names = client
+                .list_log_groups(ListLogGroupsRequest {})
+                .await
+                .unwrap()
+                .into_inner()
+                .log_group_names;
+            log_group_names.sort();
+            assert_eq!(vec!["/app/web", "/app/worker"], log_group_names);
+
+            let request = LogsRequest {
+                log_group_name: "/app/web".to_string(),
+                start_time_ms: start.timestamp_millis(),
+                end_time_ms: start.timestamp_millis() + 60_000,
+                output_format: "${message}".to_string(),
+                ..LogsRequest::default()
+            };
+            let mut responses = client.get_logs(request).await.unwrap().into_inner();
+            let mut data = vec![];
+            while let Some(response) = responses.message().await.unwrap() {
+                data.extend(response.data);
+            }
+            assert_eq!(b"GET /a\n".to_vec(), data);
+
+            let status = client.get_logs(LogsRequest::default()).await.unwrap_err();
+            assert_eq!(Code::InvalidArgument, status.code());
+
+            drop(client);
+            stop.send(()).unwrap();
+            server.await.unwrap().unwrap();
+        });
+    }
+}
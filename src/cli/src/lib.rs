@@ -0,0 +1,1397 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Mounts CloudWatch Logs log groups as a file system. The `cwl-mount` binary is a command line front end to
+//! [`run_mount`], which other tools can call to mount log groups without running the binary.
+
+use chrono::prelude::*;
+use chrono::Duration;
+
+// See:
+//
+// - https://github.com/cberner/fuser/blob/c05bea58/examples/simple.rs
+
+use cwl_lib::render_insights_query_results;
+use cwl_lib::CloudWatchLogsActorHandle;
+use cwl_lib::CloudWatchLogsError;
+use cwl_lib::EventFilter;
+use cwl_lib::LogEventsPageToken;
+use cwl_lib::MessageFilter;
+use cwl_lib::UsageStats;
+use format_cwl_log_event::LogFormatter;
+use fuse::create_file_tree_for_log_groups;
+use fuse::create_file_tree_for_time_range;
+use fuse::parse_filtered_filename;
+use fuser::consts::FOPEN_DIRECT_IO;
+use fuser::ReplyOpen;
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyWrite, Request, TimeOrNow, FUSE_ROOT_ID,
+};
+use libc::ENOENT;
+use regexes::LogGroupNameMatcher;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::future::Future;
+use std::io::Cursor;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+pub use crate::grpc::serve_grpc;
+
+mod grpc;
+
+const TTL: std::time::Duration = std::time::Duration::from_secs(1); // 1 second
+const FMODE_EXEC: i32 = 0x20;
+const EMPTY_BUFFER: [u8; 0] = [];
+
+/// Insights directory inodes start this far above the file tree's inodes, leaving the range in between for
+/// filtered virtual files.
+const INSIGHTS_FIRST_INODE_OFFSET: u64 = 1 << 40;
+
+/// What the health file reads when the mount is healthy.
+pub const HEALTH_OK: &[u8] = b"ok\n";
+
+/// Room that files limited by `--max-file-size` report past the limit for the truncation marker and warning lines
+/// that follow their events.
+const TRUNCATION_ALLOWANCE: u64 = 4096;
+
+/// How long reads that are in flight when cwl-mount is stopped get to finish.
+const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// `cat` reads its time range this many hours at a time, so that it prints as it goes rather than keep the whole
+/// range in memory. The gRPC service's GetLogs does the same.
+pub const CAT_WINDOW_HOURS: i64 = 1;
+
+/// What log files read like unless the mount is given another output format.
+pub const DEFAULT_OUTPUT_FORMAT: &str = "[${log_stream_name}] ${message}";
+
+/// How the mounted file tree is laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Time range directories at the root, with all matching log groups merged into each file.
+    Flat,
+
+    /// One directory per matching log group at the root, each containing time range directories.
+    LogGroup,
+}
+
+impl Layout {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "flat" => Some(Layout::Flat),
+            "log-group" => Some(Layout::LogGroup),
+            _ => None,
+        }
+    }
+}
+
+/// The log groups that a file tree is prepared for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogGroups<'a> {
+    /// One log group.
+    Name(&'a str),
+
+    /// Log groups whose names match the regular expression `filter` and not `exclude`.
+    Filter {
+        filter: &'a str,
+        exclude: Option<&'a str>,
+    },
+}
+
+impl<'a> LogGroups<'a> {
+    /// From exactly one of a log group name and a filter, or None if neither or both are set.
+    pub fn new(name: Option<&'a str>, filter: Option<&'a str>, exclude: Option<&'a str>) -> Option<Self> {
+        match (name, filter) {
+            (Some(name), None) => Some(LogGroups::Name(name)),
+            (None, Some(filter)) => Some(LogGroups::Filter { filter, exclude }),
+            _ => None,
+        }
+    }
+}
+
+/// File tree for `log_groups` between `start_time` and `end_time`. The `log-group` layout lists the log groups that
+/// a filter matches, and fails if they cannot be listed rather than mount an empty tree.
+pub async fn prepare_file_tree(
+    cwl: &CloudWatchLogsActorHandle,
+    layout: Layout,
+    log_groups: LogGroups<'_>,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+) -> Result<fuse::FileTree, CloudWatchLogsError> {
+    if layout == Layout::LogGroup {
+        let log_group_names = match log_groups {
+            LogGroups::Name(log_group_name) => vec![log_group_name.to_string()],
+            LogGroups::Filter { filter, exclude } => {
+                let mut matcher = LogGroupNameMatcher::new(filter);
+                if let Some(exclude) = exclude {
+                    matcher = matcher.excluding(exclude);
+                }
+                cwl.get_log_group_names()
+                    .await?
+                    .into_iter()
+                    .filter(|log_group_name| matcher.is_match(log_group_name))
+                    .collect()
+            }
+        };
+        return Ok(create_file_tree_for_log_groups(
+            &log_group_names,
+            start_time,
+            end_time,
+        ));
+    }
+
+    Ok(create_file_tree_for_time_range(start_time, end_time))
+}
+
+/// Everything about a mount besides how CloudWatch Logs is called, which is up to the
+/// [`CloudWatchLogsActorHandle`] that [`run_mount`] is given.
+#[derive(Clone, Debug)]
+pub struct MountConfig {
+    /// Empty directory to mount at.
+    pub mount_point: PathBuf,
+
+    /// FUSE mount options. Not read-only by default, because queries are written to `insights/new`.
+    pub mount_options: Vec<MountOption>,
+
+    pub layout: Layout,
+
+    /// Log group to mount. Exactly one of this and `log_group_filter` is set.
+    pub log_group_name: Option<String>,
+
+    /// Regular expression matching the names of the log groups to mount.
+    pub log_group_filter: Option<String>,
+
+    /// Regular expression matching the names of log groups that `log_group_filter` matches but that are left out
+    /// of the `log-group` layout.
+    pub log_group_exclude: Option<String>,
+
+    /// The time range that files are listed for. Outside of it directories are empty.
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+
+    /// Applied to every read unless a filtered virtual file overrides it.
+    pub event_filter: EventFilter,
+
+    pub formatter: LogFormatter,
+
+    /// How far back from the time a query is written to `insights/new` the query searches.
+    pub insights_window: Duration,
+
+    /// How many of the following time windows to fetch in the background when a file is read from the start.
+    pub readahead_windows: usize,
+
+    /// Largest size in bytes of a log file's events, or None for no limit.
+    pub max_file_size: Option<usize>,
+
+    /// User ID and group ID that own every file, or None for whoever looks at them, which outside a container that
+    /// mounted them is whoever has the same IDs as the user in the container.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+
+    /// Written with the process ID once mounted and removed when the mount stops, to tell whoever started the
+    /// mount that it succeeded.
+    pub pid_file: Option<PathBuf>,
+}
+
+impl MountConfig {
+    /// Mount the log groups that `log_group_filter` matches, or `log_group_name` if it is set, at `mount_point`
+    /// with the same defaults as the command line.
+    pub fn new(mount_point: impl Into<PathBuf>, start_time: DateTime<Utc>, end_time: DateTime<Utc>) -> Self {
+        MountConfig {
+            mount_point: mount_point.into(),
+            mount_options: vec![MountOption::FSName("cwl-mount".to_string())],
+            layout: Layout::Flat,
+            log_group_name: None,
+            log_group_filter: None,
+            log_group_exclude: None,
+            start_time,
+            end_time,
+            event_filter: EventFilter::default(),
+            formatter: LogFormatter::new(DEFAULT_OUTPUT_FORMAT).unwrap(),
+            insights_window: Duration::minutes(60),
+            readahead_windows: 0,
+            max_file_size: None,
+            uid: None,
+            gid: None,
+            pid_file: None,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MountError {
+    #[error("exactly one of a log group name and a log group filter must be set")]
+    NoLogGroups,
+
+    #[error("the start time must be before the end time")]
+    InvalidTimeRange,
+
+    #[error("failed to list log groups: {0}")]
+    LogGroups(#[source] CloudWatchLogsError),
+
+    #[error("failed to mount at {}: {1}", .0.display())]
+    Mount(PathBuf, #[source] std::io::Error),
+
+    #[error("failed to write pid file {}: {1}", .0.display())]
+    PidFile(PathBuf, #[source] std::io::Error),
+}
+
+/// Mount `config.mount_point` and serve reads until `stop` completes or the mount point is unmounted, e.g. with
+/// umount(8), then let reads that are in flight finish, close `cwl` and unmount.
+pub async fn run_mount(
+    cwl: CloudWatchLogsActorHandle,
+    config: MountConfig,
+    stop: impl Future<Output = ()>,
+) -> Result<(), MountError> {
+    let log_groups = LogGroups::new(
+        config.log_group_name.as_deref(),
+        config.log_group_filter.as_deref(),
+        config.log_group_exclude.as_deref(),
+    )
+    .ok_or(MountError::NoLogGroups)?;
+    if config.start_time >= config.end_time {
+        return Err(MountError::InvalidTimeRange);
+    }
+    let file_tree = Arc::new(
+        prepare_file_tree(
+            &cwl,
+            config.layout,
+            log_groups,
+            config.start_time,
+            config.end_time,
+        )
+        .await
+        .map_err(MountError::LogGroups)?,
+    );
+    let mount_point = config.mount_point.clone();
+    let mount_options = config.mount_options.clone();
+    let pid_file = config.pid_file.clone();
+    // See: https://github.com/cberner/fuser/issues/179
+    let (unmounted, mut unmounted_recv) = tokio::sync::mpsc::unbounded_channel();
+    let hello_fs = HelloFS::new(Handle::current(), cwl.clone(), file_tree, config, unmounted);
+    let bytes_served = Arc::clone(&hello_fs.bytes_served);
+
+    info!("starting...");
+    let _guard = fuser::Session::new(hello_fs, &mount_point, &mount_options)
+        .and_then(|session| session.spawn())
+        .map_err(|err| MountError::Mount(mount_point.clone(), err))?;
+    if let Some(pid_file) = &pid_file {
+        std::fs::write(pid_file, std::process::id().to_string())
+            .map_err(|err| MountError::PidFile(pid_file.clone(), err))?;
+    }
+
+    tokio::select! {
+        _ = stop => {}
+        _ = unmounted_recv.recv() => info!("unmounted"),
+    }
+    if let Ok(usage) = cwl.usage().await {
+        let stats = render_stats(&usage, bytes_served.load(Ordering::Relaxed));
+        info!(
+            "stats: {}",
+            stats.trim_end().replace('\t', "=").replace('\n', ", ")
+        );
+    }
+
+    // Let reads that are in flight finish before unmounting, rather than fail them.
+    if let Err(err) = cwl.close(SHUTDOWN_TIMEOUT).await {
+        warn!("{}", err);
+    }
+    if let Some(pid_file) = &pid_file {
+        let _ = std::fs::remove_file(pid_file);
+    }
+    Ok(())
+}
+
+struct HelloFS {
+    handle: Arc<Handle>,
+    cwl_actor_handle: Arc<CloudWatchLogsActorHandle>,
+
+    // Must use direct I/O for open files because we do not know how large files are before we do a network call,
+    // and we don't want to have to know the file size before opening a file. This bypasses the OS page cache.
+    // See: [1].
+    // [1] https://stackoverflow.com/questions/46267972/fuse-avoid-calculating-size-in-getattr
+    direct_io: bool,
+
+    /// Size reported for log files. Reads go to the end of the file regardless because of direct I/O, so it only
+    /// needs to be at least as large as any file, which is known when files have a size limit.
+    log_file_size: u64,
+
+    log_group_name: Option<String>,
+    log_group_filter: Option<String>,
+
+    /// Applied to every read unless a filtered virtual file overrides it.
+    event_filter: EventFilter,
+
+    file_tree: Arc<fuse::FileTree>,
+    virtual_files: fuse::VirtualFiles,
+    formatter: format_cwl_log_event::LogFormatter,
+
+    insights_directory: fuse::InsightsDirectory,
+
+    /// Inode of the health file, just below the insights directory's inodes, and of the stats file below that.
+    health_inode: u64,
+    stats_inode: u64,
+
+    /// Bytes of log files read from the mount.
+    bytes_served: Arc<AtomicU64>,
+
+    /// How far back from the time a query is written to `insights/new` the query searches.
+    insights_window: Duration,
+
+    /// How many of the following time windows to fetch in the background when a file is read from the start.
+    readahead_windows: usize,
+
+    /// Owner reported for every file instead of the user and group making the request.
+    uid: Option<u32>,
+    gid: Option<u32>,
+
+    /// Query strings being written to `insights/new`, keyed by file handle, that are submitted on release.
+    pending_insights_queries: HashMap<u64, Vec<u8>>,
+
+    /// Cancelled when the file handle is released, so that reads nobody waits for any more stop fetching.
+    open_files: HashMap<u64, CancellationToken>,
+
+    /// Files of a single log group, keyed by file handle, which are fetched a page at a time up to the end of the
+    /// latest read, so that reading the start of a large file does not fetch all of it.
+    paged_reads: HashMap<u64, Arc<tokio::sync::Mutex<PagedRead>>>,
+
+    next_fh: u64,
+
+    /// Told when the session ends, including when the mount point is unmounted with umount(8) rather than by
+    /// stopping cwl-mount, so that cwl-mount stops too.
+    unmounted: UnboundedSender<()>,
+}
+
+impl HelloFS {
+    fn new(
+        handle: Handle,
+        cwl_actor_handle: CloudWatchLogsActorHandle,
+        file_tree: Arc<fuse::FileTree>,
+        config: MountConfig,
+        unmounted: UnboundedSender<()>,
+    ) -> Self {
+        let direct_io = true;
+        let cwl_actor_handle = Arc::new(cwl_actor_handle);
+        let virtual_files = fuse::VirtualFiles::new(file_tree.max_inode() + 1);
+        let insights_directory =
+            fuse::InsightsDirectory::new(file_tree.max_inode() + 1 + INSIGHTS_FIRST_INODE_OFFSET);
+        let health_inode = file_tree.max_inode() + INSIGHTS_FIRST_INODE_OFFSET;
+
+        Self {
+            handle: Arc::new(handle),
+            cwl_actor_handle,
+            direct_io,
+            log_file_size: config.max_file_size.map_or(i32::MAX as u64, |max_file_size| {
+                std::cmp::min(i32::MAX as u64, max_file_size as u64 + TRUNCATION_ALLOWANCE)
+            }),
+            log_group_name: config.log_group_name,
+            log_group_filter: config.log_group_filter,
+            event_filter: config.event_filter,
+            file_tree,
+            virtual_files,
+            formatter: config.formatter,
+            insights_directory,
+            health_inode,
+            stats_inode: health_inode - 1,
+            bytes_served: Arc::new(AtomicU64::new(0)),
+            insights_window: config.insights_window,
+            readahead_windows: config.readahead_windows,
+            uid: config.uid,
+            gid: config.gid,
+            pending_insights_queries: HashMap::new(),
+            open_files: HashMap::new(),
+            paged_reads: HashMap::new(),
+            next_fh: 1,
+            unmounted,
+        }
+    }
+
+    /// Get the file tree file for an inode along with the event filter to apply when reading it. Virtual
+    /// files resolve to the file tree file that they are derived from. Their filter pattern replaces the
+    /// mount's filter pattern because CloudWatch Logs cannot combine arbitrary filter patterns, and likewise their
+    /// message filter replaces the mount's message filter.
+    fn resolve_inode(&self, ino: u64) -> Option<(fuse::FileWithFileKey<'_>, EventFilter)> {
+        if let Some(file) = self.file_tree.get_file_by_inode(ino) {
+            return Some((file, self.event_filter.clone()));
+        }
+        let virtual_file = self.virtual_files.get(ino)?;
+        let file = self.file_tree.get_file_by_inode(virtual_file.base_inode)?;
+        let event_filter = match &virtual_file.filter {
+            fuse::ViewFilter::FilterPattern(filter_pattern) => EventFilter {
+                filter_pattern: Some(filter_pattern.clone()),
+                ..self.event_filter.clone()
+            },
+            // Validated when the virtual file was looked up.
+            fuse::ViewFilter::MessageFilter(regex) => EventFilter {
+                message_filter: MessageFilter::new(regex).ok(),
+                ..self.event_filter.clone()
+            },
+        };
+        Some((file, event_filter))
+    }
+
+    /// Fetch the time windows after `time_bounds` in the background, so that reading through files in order, e.g.
+    /// with `cat`, finds them cached.
+    fn read_ahead(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        time_bounds: fuse::TimeBounds,
+        event_filter: EventFilter,
+    ) {
+        let window = time_bounds.end_time - time_bounds.start_time;
+        let windows = (0..self.readahead_windows as i32)
+            .map(|index| {
+                let start_time = time_bounds.end_time + window * index;
+                (start_time, start_time + window)
+            })
+            .collect();
+        let cwl_actor_handle = Arc::clone(&self.cwl_actor_handle);
+        let formatter = self.formatter.clone();
+        self.handle.spawn(async move {
+            if let Err(err) = cwl_actor_handle
+                .prefetch_logs_to_display(log_group_name, log_group_filter, windows, event_filter, formatter)
+                .await
+            {
+                debug!("read ahead failed: {}", err);
+            }
+        });
+    }
+
+    /// The insights directory is hidden if a log group directory with the same name is at the root.
+    fn has_insights_directory(&self) -> bool {
+        self.file_tree
+            .get_child_for_inode(FUSE_ROOT_ID, fuse::INSIGHTS_DIRECTORY_NAME)
+            .is_none()
+    }
+
+    fn attr_for_inode(&self, req: &Request, ino: u64) -> Option<FileAttr> {
+        if let Some((file, _event_filter)) = self.resolve_inode(ino) {
+            return Some(tree_file_attr(
+                self.owner(req),
+                ino,
+                &file.file.file_type,
+                self.log_file_size,
+            ));
+        }
+        if ino == self.insights_directory.directory_inode {
+            return Some(file_attr(self.owner(req), ino, FileType::Directory, 0));
+        }
+        if ino == self.insights_directory.new_query_inode {
+            return Some(file_attr(self.owner(req), ino, FileType::RegularFile, 0));
+        }
+        if ino == self.health_inode {
+            return Some(file_attr(
+                self.owner(req),
+                ino,
+                FileType::RegularFile,
+                HEALTH_OK.len() as u64,
+            ));
+        }
+        if ino == self.stats_inode {
+            return Some(file_attr(
+                self.owner(req),
+                ino,
+                FileType::RegularFile,
+                i32::MAX as u64,
+            ));
+        }
+        self.insights_directory
+            .get_query(ino)
+            .map(|_query| file_attr(self.owner(req), ino, FileType::RegularFile, i32::MAX as u64))
+    }
+
+    /// The user ID and group ID that own files, as seen by `req`.
+    fn owner(&self, req: &Request) -> (u32, u32) {
+        (
+            self.uid.unwrap_or_else(|| req.uid()),
+            self.gid.unwrap_or_else(|| req.gid()),
+        )
+    }
+
+    fn allocate_fh(&mut self) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        fh
+    }
+
+    /// Reply to a read of the results file of an Insights query. The first read runs the query, see
+    /// `fuse::InsightsResults`.
+    fn read_insights_query(&self, query: &fuse::InsightsQuery, offset: i64, size: u32, reply: ReplyData) {
+        let results = Arc::clone(&query.results);
+        let cwl_actor_handle = Arc::clone(&self.cwl_actor_handle);
+        let log_group_name = self.log_group_name.clone();
+        let log_group_filter = self.log_group_filter.clone();
+        let query_string = query.query_string.clone();
+        let time_bounds = query.time_bounds;
+
+        // Reply from the task like file reads do, since a query can take minutes.
+        self.handle.spawn(async move {
+            let results = results
+                .get_or_init(|| async {
+                    let query_string = resolve_saved_query(&cwl_actor_handle, query_string).await;
+                    let res = cwl_actor_handle
+                        .run_insights_query(
+                            log_group_name,
+                            log_group_filter,
+                            query_string,
+                            time_bounds.start_time,
+                            time_bounds.end_time,
+                        )
+                        .await;
+                    match res {
+                        Ok(rows) => Ok(render_insights_query_results(&rows)),
+                        Err(err) => {
+                            error!("Insights query failed: {:?}{}", err, credentials_hint(&err));
+                            Err(errno(&err))
+                        }
+                    }
+                })
+                .await;
+            match results {
+                Ok(results) => reply_with_slice(results, offset, size, reply),
+                Err(errno) => reply.error(*errno),
+            }
+        });
+    }
+}
+
+/// The query string of the saved query called `query_string`, if there is one, so that writing a saved query's name
+/// to `insights/new` runs it. Anything else is taken to be a query string already.
+async fn resolve_saved_query(cwl_actor_handle: &CloudWatchLogsActorHandle, query_string: String) -> String {
+    if query_string.contains('\n') || query_string.contains('|') {
+        return query_string;
+    }
+    match cwl_actor_handle.find_query_definition(&query_string).await {
+        Ok(Some(query_definition)) => query_definition.query_string,
+        Ok(None) => query_string,
+        Err(err) => {
+            warn!("failed to look up saved query {}: {}", query_string, err);
+            query_string
+        }
+    }
+}
+
+/// What has been fetched of a file that is read a page at a time.
+#[derive(Debug, Default)]
+struct PagedRead {
+    data: Vec<u8>,
+    next_page_token: Option<LogEventsPageToken>,
+    done: bool,
+}
+
+impl PagedRead {
+    /// Fetch pages until the file is at least `len` bytes long or there are no more pages.
+    async fn fetch_until(
+        &mut self,
+        cwl_actor_handle: &CloudWatchLogsActorHandle,
+        log_group_name: &str,
+        time_bounds: fuse::TimeBounds,
+        event_filter: &EventFilter,
+        formatter: &LogFormatter,
+        len: usize,
+    ) -> Result<(), CloudWatchLogsError> {
+        while !self.done && self.data.len() < len {
+            let page = cwl_actor_handle
+                .get_rendered_page(
+                    log_group_name.to_string(),
+                    time_bounds.start_time,
+                    time_bounds.end_time,
+                    event_filter.clone(),
+                    formatter.clone(),
+                    self.next_page_token.clone(),
+                )
+                .await?;
+            debug!(
+                "read a page of {} events from {}, {} bytes so far",
+                page.events.len(),
+                log_group_name,
+                page.bytes_rendered
+            );
+            self.data.extend_from_slice(&page.data);
+            self.done = page.next_page_token.is_none();
+            self.next_page_token = page.next_page_token;
+        }
+        Ok(())
+    }
+}
+
+fn tree_file_attr(owner: (u32, u32), inode: u64, file_type: &fuse::FileType, file_size: u64) -> FileAttr {
+    match file_type {
+        fuse::FileType::Directory => file_attr(owner, inode, FileType::Directory, 0),
+        fuse::FileType::File(_) => file_attr(owner, inode, FileType::RegularFile, file_size),
+    }
+}
+
+/// Attributes of a file owned by `owner`, a user ID and group ID.
+fn file_attr((uid, gid): (u32, u32), inode: u64, kind: FileType, size: u64) -> FileAttr {
+    FileAttr {
+        ino: inode,
+        size,
+        blocks: match kind {
+            FileType::Directory => 0,
+            _ => 1,
+        },
+        atime: UNIX_EPOCH, // 1970-01-01 00:00:00
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        crtime: UNIX_EPOCH,
+        kind,
+        perm: 0o777,
+        nlink: match kind {
+            FileType::Directory => 2,
+            _ => 1,
+        },
+        uid,
+        gid,
+        rdev: 0,
+        flags: 0,
+        blksize: 512,
+    }
+}
+
+/// Split `[start_time, end_time)` into consecutive windows of length `window`, the last of which may be shorter.
+pub fn time_windows(
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    window: Duration,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut windows = vec![];
+    let mut window_start = start_time;
+    while window_start < end_time {
+        let window_end = std::cmp::min(window_start + window, end_time);
+        windows.push((window_start, window_end));
+        window_start = window_end;
+    }
+    windows
+}
+
+/// What to do about an error, to append to its message, if it is caused by credentials that have expired.
+pub fn credentials_hint(err: &CloudWatchLogsError) -> &'static str {
+    if err.is_credentials_error() {
+        ". AWS rejected the credentials, they may have expired. Log in again, e.g. with `aws sso login`, and the \
+         next read uses the new credentials without remounting."
+    } else {
+        ""
+    }
+}
+
+/// The errno to fail a FUSE request with, so that tools reading the file can tell why it failed, e.g. to retry
+/// on EAGAIN but not on EACCES.
+fn errno(err: &CloudWatchLogsError) -> libc::c_int {
+    if err.is_access_denied() || err.is_credentials_error() {
+        libc::EACCES
+    } else if err.is_resource_not_found()
+        || matches!(err, CloudWatchLogsError::NoCloudWatchLogGroupsMatchFilter(_))
+    {
+        ENOENT
+    } else if err.is_unsupported_by_log_group_class() {
+        libc::EOPNOTSUPP
+    } else if err.is_throttling() {
+        libc::EAGAIN
+    } else if err.is_timeout() {
+        libc::ETIMEDOUT
+    } else {
+        libc::EIO
+    }
+}
+
+/// Reply with the part of `data` that a read at `offset` of `size` bytes covers.
+fn reply_with_slice(data: &[u8], offset: i64, size: u32, reply: ReplyData) {
+    let read_size = min(size, data.len().saturating_sub(offset as usize) as u32);
+    if read_size == 0 {
+        reply.data(&EMPTY_BUFFER);
+        return;
+    }
+    let mut buffer = vec![0; read_size as usize];
+    let mut reader = Cursor::new(&data[offset as usize..]);
+    reader.read_exact(&mut buffer).unwrap();
+    reply.data(&buffer);
+}
+
+/// What the stats file reads, one tab-separated name and value per line.
+fn render_stats(usage: &UsageStats, bytes_served: u64) -> String {
+    let mut stats = vec![("api_calls".to_string(), usage.total_api_calls().to_string())];
+    for (action, calls) in &usage.api_calls {
+        stats.push((format!("api_calls.{}", action.as_str()), calls.to_string()));
+    }
+    stats.extend([
+        ("events_fetched".to_string(), usage.events_returned.to_string()),
+        ("bytes_served".to_string(), bytes_served.to_string()),
+        (
+            "memory_cache_hits".to_string(),
+            usage.memory_cache_hits.to_string(),
+        ),
+        ("disk_cache_hits".to_string(), usage.disk_cache_hits.to_string()),
+        ("cache_misses".to_string(), usage.cache_misses.to_string()),
+        (
+            "cache_hit_rate".to_string(),
+            usage
+                .cache_hit_rate()
+                .map_or_else(|| "-".to_string(), |rate| format!("{:.2}", rate)),
+        ),
+        (
+            "insights_records_scanned".to_string(),
+            usage.insights_records_scanned.to_string(),
+        ),
+        (
+            "insights_bytes_scanned".to_string(),
+            usage.insights_bytes_scanned.to_string(),
+        ),
+    ]);
+    stats
+        .into_iter()
+        .map(|(name, value)| format!("{}\t{}\n", name, value))
+        .collect()
+}
+
+impl Filesystem for HelloFS {
+    fn destroy(&mut self) {
+        // Nobody listens any more if cwl-mount is the one unmounting.
+        let _ = self.unmounted.send(());
+    }
+
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let filename = name.to_string_lossy().to_string();
+        debug!("lookup call. parent: {}, name: {}", parent, filename);
+        if let Some(child) = self.file_tree.get_child_for_inode(parent, &filename) {
+            reply.entry(
+                &TTL,
+                &tree_file_attr(
+                    self.owner(req),
+                    child.file.inode,
+                    &child.file.file_type,
+                    self.log_file_size,
+                ),
+                0,
+            );
+            return;
+        }
+        if parent == FUSE_ROOT_ID
+            && filename == fuse::INSIGHTS_DIRECTORY_NAME
+            && self.has_insights_directory()
+        {
+            let inode = self.insights_directory.directory_inode;
+            reply.entry(
+                &TTL,
+                &file_attr(self.owner(req), inode, FileType::Directory, 0),
+                0,
+            );
+            return;
+        }
+        if parent == FUSE_ROOT_ID && filename == fuse::HEALTH_FILENAME {
+            let inode = self.health_inode;
+            reply.entry(
+                &TTL,
+                &file_attr(
+                    self.owner(req),
+                    inode,
+                    FileType::RegularFile,
+                    HEALTH_OK.len() as u64,
+                ),
+                0,
+            );
+            return;
+        }
+        if parent == FUSE_ROOT_ID && filename == fuse::STATS_FILENAME {
+            let inode = self.stats_inode;
+            reply.entry(
+                &TTL,
+                &file_attr(self.owner(req), inode, FileType::RegularFile, i32::MAX as u64),
+                0,
+            );
+            return;
+        }
+        if parent == self.insights_directory.directory_inode {
+            let inode = if filename == fuse::INSIGHTS_NEW_QUERY_FILENAME {
+                Some(self.insights_directory.new_query_inode)
+            } else {
+                self.insights_directory
+                    .get_query_by_name(&filename)
+                    .map(|query| query.inode)
+            };
+            match inode.and_then(|inode| self.attr_for_inode(req, inode)) {
+                Some(attr) => reply.entry(&TTL, &attr, 0),
+                None => reply.error(ENOENT),
+            }
+            return;
+        }
+
+        // Files like "12-30.filter=ERROR" are not in the file tree, they are created on demand.
+        let (base_filename, filter) = match parse_filtered_filename(&filename) {
+            Some(parsed) => parsed,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        if let fuse::ViewFilter::MessageFilter(regex) = &filter {
+            if MessageFilter::new(regex).is_err() {
+                reply.error(libc::EINVAL);
+                return;
+            }
+        }
+        let owner = self.owner(req);
+        match self.file_tree.get_child_for_inode(parent, base_filename) {
+            Some(base) if base.file.file_type != fuse::FileType::Directory => {
+                let virtual_file = self.virtual_files.get_or_create(base.file.inode, filter);
+                reply.entry(
+                    &TTL,
+                    &tree_file_attr(
+                        owner,
+                        virtual_file.inode,
+                        &base.file.file_type,
+                        self.log_file_size,
+                    ),
+                    0,
+                );
+            }
+            _ => reply.error(ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        debug!("getattr call. ino: {}", ino);
+        match self.attr_for_inode(req, ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(ENOENT),
+        }
+
+        // match ino {
+        //     1 => reply.attr(&TTL, &HELLO_DIR_ATTR),
+        //     2 => reply.attr(&TTL, &HELLO_TXT_ATTR),
+        //     _ => reply.error(ENOENT),
+        // }
+    }
+
+    /// Only needed so that `echo ... > insights/new` can truncate the file, sizes and modes never change.
+    fn setattr(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        _size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        debug!("setattr call. ino: {}", ino);
+        match self.attr_for_inode(req, ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock: Option<u64>,
+        reply: ReplyData,
+    ) {
+        debug!("ino: {}, offset: {}, size: {}", ino, offset, size);
+        if ino == self.health_inode {
+            // Round trip through the actor, so that a wedged actor fails the health check too.
+            let cwl_actor_handle = Arc::clone(&self.cwl_actor_handle);
+            self.handle.spawn(async move {
+                match cwl_actor_handle.usage().await {
+                    Ok(_) => reply_with_slice(HEALTH_OK, offset, size, reply),
+                    Err(err) => {
+                        warn!("health check failed: {}", err);
+                        reply.error(errno(&err));
+                    }
+                }
+            });
+            return;
+        }
+        if ino == self.stats_inode {
+            let cwl_actor_handle = Arc::clone(&self.cwl_actor_handle);
+            let bytes_served = self.bytes_served.load(Ordering::Relaxed);
+            self.handle.spawn(async move {
+                match cwl_actor_handle.usage().await {
+                    Ok(usage) => {
+                        reply_with_slice(render_stats(&usage, bytes_served).as_bytes(), offset, size, reply)
+                    }
+                    Err(err) => reply.error(errno(&err)),
+                }
+            });
+            return;
+        }
+        if let Some(query) = self.insights_directory.get_query(ino) {
+            self.read_insights_query(query, offset, size, reply);
+            return;
+        }
+        let file_tree = Arc::clone(&self.file_tree);
+        let file = self.resolve_inode(ino);
+        if file.is_none() {
+            reply.error(ENOENT);
+            return;
+        }
+        let (file, event_filter) = file.unwrap();
+        match file.file.file_type {
+            fuse::FileType::Directory => {
+                reply.error(ENOENT);
+            }
+            fuse::FileType::File(time_bounds) => {
+                let (log_group_name, log_group_filter) = match file_tree.get_log_group_name(file.file_key) {
+                    Some(log_group_name) => (Some(log_group_name), None),
+                    None => (self.log_group_name.clone(), self.log_group_filter.clone()),
+                };
+                let cwl_actor_handle = Arc::clone(&self.cwl_actor_handle);
+                let handle = Arc::clone(&self.handle);
+                let formatter = self.formatter.clone();
+                let cancellation = self.open_files.get(&fh).cloned().unwrap_or_default();
+                let bytes_served = Arc::clone(&self.bytes_served);
+                if offset == 0 && self.readahead_windows > 0 {
+                    self.read_ahead(
+                        log_group_name.clone(),
+                        log_group_filter.clone(),
+                        time_bounds,
+                        event_filter.clone(),
+                    );
+                }
+                if let (Some(log_group_name), None) = (&log_group_name, &log_group_filter) {
+                    let log_group_name = log_group_name.clone();
+                    let paged_read = Arc::clone(self.paged_reads.entry(fh).or_default());
+                    handle.spawn(async move {
+                        // Reads of the same file handle wait for each other, so that each page is fetched once.
+                        let mut paged_read = paged_read.lock().await;
+                        let len = offset as usize + size as usize;
+                        let res = tokio::select! {
+                            res = paged_read.fetch_until(
+                                &cwl_actor_handle,
+                                &log_group_name,
+                                time_bounds,
+                                &event_filter,
+                                &formatter,
+                                len,
+                            ) => res,
+                            _ = cancellation.cancelled() => {
+                                debug!("file handle {} released, cancelling read", fh);
+                                reply.error(libc::EINTR);
+                                return;
+                            }
+                        };
+                        match res {
+                            Ok(()) => {
+                                let served = min(
+                                    size as usize,
+                                    paged_read.data.len().saturating_sub(offset as usize),
+                                );
+                                bytes_served.fetch_add(served as u64, Ordering::Relaxed);
+                                reply_with_slice(&paged_read.data, offset, size, reply);
+                            }
+                            Err(err) => {
+                                error!("failed to get logs to display: {}{}", err, credentials_hint(&err));
+                                reply.error(errno(&err));
+                            }
+                        }
+                    });
+                    return;
+                }
+
+                // Reply from the task rather than wait here, so that other requests, including the release that
+                // cancels this read, are handled meanwhile.
+                handle.spawn(async move {
+                    let res = tokio::select! {
+                        res = cwl_actor_handle.get_log_page(
+                            log_group_name,
+                            log_group_filter,
+                            time_bounds.start_time,
+                            time_bounds.end_time,
+                            event_filter,
+                            formatter,
+                        ) => res,
+                        _ = cancellation.cancelled() => {
+                            debug!("file handle {} released, cancelling read", fh);
+                            reply.error(libc::EINTR);
+                            return;
+                        }
+                    };
+                    match res {
+                        Ok(page) => {
+                            debug!(
+                                "read {} events from {} log groups in {} pages, {:?}, cached: {}, truncated: {}",
+                                page.events,
+                                page.log_groups_queried,
+                                page.pages_fetched,
+                                page.fetch_duration,
+                                page.cached,
+                                page.truncated
+                            );
+                            let served = min(size as usize, page.data.len().saturating_sub(offset as usize));
+                            bytes_served.fetch_add(served as u64, Ordering::Relaxed);
+                            reply_with_slice(&page.data, offset, size, reply);
+                        }
+                        Err(err) => {
+                            error!("failed to get logs to display: {}{}", err, credentials_hint(&err));
+                            reply.error(errno(&err));
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        _offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        debug!("write call. ino: {}, fh: {}, size: {}", ino, fh, data.len());
+        if ino != self.insights_directory.new_query_inode {
+            reply.error(libc::EACCES);
+            return;
+        }
+        self.pending_insights_queries
+            .entry(fh)
+            .or_default()
+            .extend_from_slice(data);
+        reply.written(data.len() as u32);
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        if let Some(cancellation) = self.open_files.remove(&fh) {
+            cancellation.cancel();
+        }
+        self.paged_reads.remove(&fh);
+        if let Some(query_string) = self.pending_insights_queries.remove(&fh) {
+            let query_string = String::from_utf8_lossy(&query_string).trim().to_string();
+            if !query_string.is_empty() {
+                let end_time = Utc::now();
+                let time_bounds = fuse::TimeBounds {
+                    start_time: end_time - self.insights_window,
+                    end_time,
+                };
+                let query = self.insights_directory.add_query(query_string, time_bounds);
+                info!("created insights query {}: {}", query.name, query.query_string);
+            }
+        }
+        reply.ok();
+    }
+
+    fn open(&mut self, _req: &Request, inode: u64, flags: i32, reply: ReplyOpen) {
+        debug!("open() called for {:?}", inode);
+        let (_access_mask, _read, _write) = match flags & libc::O_ACCMODE {
+            libc::O_RDONLY => {
+                // Behavior is undefined, but most filesystems return EACCES
+                if flags & libc::O_TRUNC != 0 {
+                    reply.error(libc::EACCES);
+                    return;
+                }
+                if flags & FMODE_EXEC != 0 {
+                    // Open is from internal exec syscall
+                    (libc::X_OK, true, false)
+                } else {
+                    (libc::R_OK, true, false)
+                }
+            }
+            libc::O_WRONLY => (libc::W_OK, false, true),
+            libc::O_RDWR => (libc::R_OK | libc::W_OK, true, true),
+            // Exactly one access mode flag must be specified
+            _ => {
+                reply.error(libc::EINVAL);
+                return;
+            }
+        };
+
+        let open_flags = if self.direct_io { FOPEN_DIRECT_IO } else { 0 };
+        if inode == self.insights_directory.new_query_inode
+            || inode == self.health_inode
+            || inode == self.stats_inode
+            || self.insights_directory.get_query(inode).is_some()
+        {
+            let fh = self.allocate_fh();
+            reply.opened(fh, open_flags);
+            return;
+        }
+        let is_file = match self.resolve_inode(inode) {
+            Some((file, _event_filter)) => file.file.file_type != fuse::FileType::Directory,
+            None => false,
+        };
+        if is_file {
+            let fh = self.allocate_fh();
+            self.open_files.insert(fh, CancellationToken::new());
+            reply.opened(fh, open_flags);
+            return;
+        }
+        reply.error(libc::EACCES);
+    }
+
+    fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        debug!("readdir, ino: {}, offset: {}", ino, offset);
+        let mut entries: VecDeque<(u64, FileType, String)>;
+        if ino == self.insights_directory.directory_inode {
+            entries = self
+                .insights_directory
+                .list_queries()
+                .into_iter()
+                .map(|query| (query.inode, FileType::RegularFile, query.name.clone()))
+                .collect();
+            entries.push_front((
+                self.insights_directory.new_query_inode,
+                FileType::RegularFile,
+                fuse::INSIGHTS_NEW_QUERY_FILENAME.to_string(),
+            ));
+            entries.push_front((FUSE_ROOT_ID, FileType::Directory, "..".to_string()));
+            entries.push_front((ino, FileType::Directory, ".".to_string()));
+        } else {
+            let directory = self.file_tree.get_file_by_inode(ino);
+            if directory.is_none() {
+                reply.error(ENOENT);
+                return;
+            }
+            let directory = directory.unwrap();
+            let children = self.file_tree.list_directory(directory.file_key);
+            entries = children
+                .into_iter()
+                .map(|file| {
+                    (
+                        file.file.inode,
+                        match file.file.file_type {
+                            fuse::FileType::Directory => FileType::Directory,
+                            fuse::FileType::File(_) => FileType::RegularFile,
+                        },
+                        file.file.name.clone(),
+                    )
+                })
+                .collect();
+            let parent_inode = self.file_tree.get_parent_for_ls(directory.file_key).file.inode;
+            entries.push_front((parent_inode, FileType::Directory, "..".to_string()));
+            entries.push_front((parent_inode, FileType::Directory, ".".to_string()));
+            if ino == FUSE_ROOT_ID && self.has_insights_directory() {
+                entries.push_back((
+                    self.insights_directory.directory_inode,
+                    FileType::Directory,
+                    fuse::INSIGHTS_DIRECTORY_NAME.to_string(),
+                ));
+            }
+        }
+
+        // if ino != 1 {
+        //     reply.error(ENOENT);
+        //     return;
+        // }
+        // let entries = vec![
+        //     (1, FileType::Directory, "."),
+        //     (1, FileType::Directory, ".."),
+        //     (2, FileType::RegularFile, "hello.txt"),
+        // ];
+
+        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
+            // i + 1 means the index of the next entry
+            debug!("readdir add entry: {:?}", entry);
+            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::TimeZone;
+    use chrono::Utc;
+
+    use cwl_lib::CloudWatchLogsActorHandle;
+    use cwl_lib::EventFilter;
+    use cwl_lib::FakeCloudWatchLogs;
+    use cwl_lib::QueryDefinition;
+    use format_cwl_log_event::LogFormatter;
+
+    use crate::prepare_file_tree;
+    use crate::resolve_saved_query;
+    use crate::run_mount;
+    use crate::Layout;
+    use crate::LogGroups;
+    use crate::MountConfig;
+    use crate::MountError;
+    use crate::PagedRead;
+
+    #[test]
+    fn test_run_mount_rejects_invalid_config() {
+        let start_time = Utc.ymd(2021, 12, 4).and_hms(0, 0, 0);
+        let end_time = start_time + Duration::hours(1);
+        let run = |config: MountConfig| {
+            tokio_test::block_on(async {
+                let cwl = CloudWatchLogsActorHandle::new(FakeCloudWatchLogs::new(Some("us-west-2")));
+                run_mount(cwl, config, async {}).await
+            })
+        };
+
+        let config = MountConfig::new("/mnt/logs", start_time, end_time);
+        assert!(matches!(run(config.clone()), Err(MountError::NoLogGroups)));
+        let both = MountConfig {
+            log_group_name: Some("/app/web".to_string()),
+            log_group_filter: Some("/app/.*".to_string()),
+            ..config.clone()
+        };
+        assert!(matches!(run(both), Err(MountError::NoLogGroups)));
+        let backwards = MountConfig {
+            log_group_name: Some("/app/web".to_string()),
+            start_time: end_time,
+            end_time: start_time,
+            ..config
+        };
+        assert!(matches!(run(backwards), Err(MountError::InvalidTimeRange)));
+    }
+
+    #[test]
+    fn test_log_groups_needs_exactly_one_of_a_name_and_a_filter() {
+        assert_eq!(
+            Some(LogGroups::Name("/app/web")),
+            LogGroups::new(Some("/app/web"), None, None)
+        );
+        assert_eq!(
+            Some(LogGroups::Filter {
+                filter: "/app/.*",
+                exclude: None
+            }),
+            LogGroups::new(None, Some("/app/.*"), None)
+        );
+        assert_eq!(None, LogGroups::new(None, None, Some(".*canary")));
+        assert_eq!(None, LogGroups::new(Some("/app/web"), Some("/app/.*"), None));
+    }
+
+    #[test]
+    fn test_prepare_file_tree_adds_a_directory_per_log_group() {
+        let start_time = Utc.ymd(2021, 12, 4).and_hms(0, 0, 0);
+        let end_time = start_time + Duration::hours(1);
+        let cwl = FakeCloudWatchLogs::new(Some("us-west-2"));
+        for log_group_name in ["/app/web", "/app/worker", "/app/web-canary"] {
+            cwl.create_log_group(log_group_name);
+        }
+        let file_tree = tokio_test::block_on(async {
+            let cwl = CloudWatchLogsActorHandle::new(cwl);
+            prepare_file_tree(
+                &cwl,
+                Layout::LogGroup,
+                LogGroups::Filter {
+                    filter: "/app/.*",
+                    exclude: Some(".*canary"),
+                },
+                start_time,
+                end_time,
+            )
+            .await
+            .expect("listing log groups should succeed")
+        });
+        let root = file_tree.get_root().unwrap();
+        let mut names: Vec<String> = file_tree
+            .list_directory(root)
+            .into_iter()
+            .map(|child| child.file.name.clone())
+            .collect();
+        names.sort();
+        assert_eq!(vec!["%2Fapp%2Fweb", "%2Fapp%2Fworker"], names);
+    }
+    #[test]
+    fn test_resolve_saved_query() {
+        let cwl = FakeCloudWatchLogs::new(Some("us-west-2"));
+        cwl.put_query_definition(QueryDefinition {
+            query_definition_id: "errors-id".to_string(),
+            region: None,
+            name: "errors".to_string(),
+            query_string: "filter @message like /ERROR/".to_string(),
+            log_group_names: vec![],
+            last_modified: None,
+        });
+        tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::new(cwl);
+            let resolve = |query_string: &str| resolve_saved_query(&handle, query_string.to_string());
+            assert_eq!("filter @message like /ERROR/", resolve("errors").await);
+            assert_eq!("unknown", resolve("unknown").await);
+            assert_eq!(
+                "fields @message | limit 5",
+                resolve("fields @message | limit 5").await
+            );
+        });
+    }
+
+    #[test]
+    fn test_paged_read_fetches_pages_up_to_the_end_of_the_read() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let cwl = FakeCloudWatchLogs::new(Some("us-east-1")).with_page_size(2);
+        for second in 0..6 {
+            cwl.put_log_event(
+                "/app/web",
+                "web-1",
+                start + Duration::seconds(second),
+                format!("event-{}", second),
+            );
+        }
+        let time_bounds = fuse::TimeBounds {
+            start_time: start,
+            end_time: start + Duration::minutes(1),
+        };
+        let formatter = LogFormatter::new("$message").unwrap();
+        let (first, all) = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::new(cwl);
+            let event_filter = EventFilter::default();
+            let mut paged_read = PagedRead::default();
+            paged_read
+                .fetch_until(&handle, "/app/web", time_bounds, &event_filter, &formatter, 10)
+                .await
+                .unwrap();
+            let first = String::from_utf8_lossy(&paged_read.data).to_string();
+            paged_read
+                .fetch_until(
+                    &handle,
+                    "/app/web",
+                    time_bounds,
+                    &event_filter,
+                    &formatter,
+                    usize::MAX,
+                )
+                .await
+                .unwrap();
+            (first, String::from_utf8_lossy(&paged_read.data).to_string())
+        });
+        assert_eq!("event-0\nevent-1", first);
+        let expected: Vec<String> = (0..6).map(|second| format!("event-{}", second)).collect();
+        assert_eq!(expected.join("\n"), all);
+    }
+}
@@ -10,487 +10,2815 @@ use chrono::Duration;
 //
 // - https://github.com/cberner/fuser/blob/c05bea58/examples/simple.rs
 
-use clap::ArgGroup;
-use clap::SubCommand;
-use clap::{crate_version, App, Arg};
+use clap::crate_version;
+use clap::ArgMatches;
+use config::Command;
+use config::Config;
+use config::MountArgs;
+use cwl_lib::default_region;
+use cwl_lib::CacheEncryptionKey;
 use cwl_lib::CloudWatchLogsActorHandle;
+use cwl_lib::CloudWatchLogsError;
 use cwl_lib::CloudWatchLogsImpl;
-use fuse::create_file_tree_for_time_range;
-use fuser::consts::FOPEN_DIRECT_IO;
-use fuser::ReplyOpen;
-use fuser::{
-    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request,
-};
-use libc::ENOENT;
-use std::cmp::min;
+use cwl_lib::EventFilter;
+use cwl_lib::FakeCloudWatchLogs;
+use cwl_lib::LogGroupMetadata;
+use cwl_lib::Partition;
+use cwl_lib::Progress;
+use cwl_lib::ProxyOptions;
+use cwl_mount::credentials_hint;
+use cwl_mount::prepare_file_tree;
+use cwl_mount::run_mount;
+use cwl_mount::serve_grpc;
+use cwl_mount::time_windows;
+use cwl_mount::Layout;
+use cwl_mount::LogGroups;
+use cwl_mount::MountConfig;
+use cwl_mount::MountError;
+use cwl_mount::CAT_WINDOW_HOURS;
+use cwl_mount::HEALTH_OK;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use fuser::MountOption;
+use indicatif::HumanBytes;
+use indicatif::ProgressBar;
+use indicatif::ProgressStyle;
+use regexes::LogGroupNameMatcher;
+use std::collections::HashSet;
 use std::collections::VecDeque;
-use std::ffi::OsStr;
-use std::io::Cursor;
+use std::ffi::OsString;
+use std::io::IsTerminal;
 use std::io::Read;
-use std::sync::Arc;
-use std::time::UNIX_EPOCH;
-use tokio::runtime::Handle;
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::path::PathBuf;
+use structopt::StructOpt;
+use tokio::signal::unix::signal;
+use tokio::signal::unix::SignalKind;
+use tokio::sync::watch;
 use tracing::Level;
-use tracing::{debug, error, info};
+use tracing::{error, info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::FmtSubscriber;
 
-const TTL: std::time::Duration = std::time::Duration::from_secs(1); // 1 second
-const FMODE_EXEC: i32 = 0x20;
-const EMPTY_BUFFER: [u8; 0] = [];
+mod config;
 
-pub async fn prepare_file_tree(_cwl: &CloudWatchLogsImpl) -> fuse::FileTree {
-    let end_time = Utc::now();
-    let default_start_time = end_time - Duration::days(365);
-    let start_time = default_start_time;
+/// What cwl-mount exits with when it fails, so that scripts and systemd units can tell failures apart without
+/// parsing logs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExitCode {
+    /// Any failure not covered below, e.g. CloudWatch Logs being unreachable.
+    Failure = 1,
+
+    /// Invalid arguments or configuration.
+    Config = 2,
+
+    /// Credentials that do not load, have expired, or are not allowed to call CloudWatch Logs.
+    Credentials = 3,
+
+    /// No log groups to read.
+    NoMatchingLogGroups = 4,
 
-    // TODO use CloudWatch actor to get this start time
-    // let start_time = cwl
-    //     .get_first_event_time_for_log_group(log_group_name.into())
-    //     .await
-    //     .unwrap_or(Some(default_start_time))
-    //     .unwrap_or(default_start_time);
+    /// The file system did not mount.
+    MountFailed = 5,
 
-    create_file_tree_for_time_range(start_time, end_time)
+    /// Cancelled by the user, the same code as a shell gives a process stopped by CTRL-C.
+    Interrupted = 130,
 }
 
-struct HelloFS {
-    handle: Arc<Handle>,
-    cwl_actor_handle: Arc<CloudWatchLogsActorHandle>,
+impl ExitCode {
+    fn for_error(err: &CloudWatchLogsError) -> Self {
+        if err.is_access_denied() || err.is_credentials_error() {
+            ExitCode::Credentials
+        } else if matches!(err, CloudWatchLogsError::NoCloudWatchLogGroupsMatchFilter(_)) {
+            ExitCode::NoMatchingLogGroups
+        } else {
+            ExitCode::Failure
+        }
+    }
 
-    // Must use direct I/O for open files because we do not know how large files are before we do a network call,
-    // and we don't want to have to know the file size before opening a file. This bypasses the OS page cache.
-    // See: [1].
-    // [1] https://stackoverflow.com/questions/46267972/fuse-avoid-calculating-size-in-getattr
-    direct_io: bool,
+    fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
 
-    log_group_name: Option<String>,
-    log_group_filter: Option<String>,
-    file_tree: Arc<fuse::FileTree>,
-    formatter: format_cwl_log_event::LogFormatter,
+trait ExitWith {
+    /// Print the error the way [`clap::Error::exit`] does, but exit with `code` rather than always 1.
+    fn exit_with(&self, code: ExitCode) -> !;
 }
 
-impl HelloFS {
-    pub fn new(
-        handle: Handle,
-        cwl: CloudWatchLogsImpl,
-        log_group_name: Option<&str>,
-        log_group_filter: Option<&str>,
-        file_tree: Arc<fuse::FileTree>,
-        formatter: format_cwl_log_event::LogFormatter,
-    ) -> Self {
-        let direct_io = true;
-        let cwl_actor_handle = Arc::new(CloudWatchLogsActorHandle::new(cwl));
-
-        Self {
-            handle: Arc::new(handle),
-            cwl_actor_handle,
-            direct_io,
-            log_group_name: log_group_name.map(|s| s.to_string()),
-            log_group_filter: log_group_filter.map(|s| s.to_string()),
-            file_tree,
-            formatter,
-        }
-    }
-}
-
-impl Filesystem for HelloFS {
-    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        let filename = name.to_string_lossy().to_string();
-        debug!("lookup call. parent: {}, name: {}", parent, filename);
-        let child = self.file_tree.get_child_for_inode(parent, filename);
-        if child.is_none() {
-            reply.error(ENOENT);
-            return;
+impl ExitWith for clap::Error {
+    fn exit_with(&self, code: ExitCode) -> ! {
+        // Help and version are not errors.
+        if !self.use_stderr() {
+            self.exit();
         }
-        let child = child.unwrap();
-        reply.entry(
-            &TTL,
-            &FileAttr {
-                ino: child.file.inode,
-                size: match child.file.file_type {
-                    fuse::FileType::Directory => 0,
-                    fuse::FileType::File(_) => i32::MAX as u64,
-                },
-                blocks: match child.file.file_type {
-                    fuse::FileType::Directory => 0,
-                    fuse::FileType::File(_) => 1,
-                },
-                atime: UNIX_EPOCH, // 1970-01-01 00:00:00
-                mtime: UNIX_EPOCH,
-                ctime: UNIX_EPOCH,
-                crtime: UNIX_EPOCH,
-                kind: match child.file.file_type {
-                    fuse::FileType::Directory => FileType::Directory,
-                    fuse::FileType::File(_) => FileType::RegularFile,
-                },
-                perm: match child.file.file_type {
-                    fuse::FileType::Directory => 0o777,
-                    fuse::FileType::File(_) => 0o777,
-                },
-                nlink: match child.file.file_type {
-                    fuse::FileType::Directory => 2,
-                    fuse::FileType::File(_) => 1,
-                },
-                uid: req.uid(),
-                gid: req.gid(),
-                rdev: 0,
-                flags: 0,
-                blksize: 512,
-            },
-            0,
-        );
+        eprintln!("{}", self.message);
+        code.exit()
     }
+}
 
-    fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
-        debug!("getattr call. ino: {}", ino);
-        let file = self.file_tree.get_file_by_inode(ino);
-        if file.is_none() {
-            reply.error(ENOENT);
-            return;
+/// Ask which log groups to mount, fuzzy searching the log groups in the regions being read. One log group is mounted
+/// by name and several by a filter that matches exactly them, printed so that the same mount can be repeated without
+/// picking again.
+async fn pick_log_groups(cwl: &CloudWatchLogsActorHandle) -> (Option<String>, Option<String>) {
+    let log_group_names = match cwl.get_log_group_names().await {
+        Ok(log_group_names) if log_group_names.is_empty() => {
+            error!("There are no log groups to pick from");
+            ExitCode::NoMatchingLogGroups.exit();
+        }
+        Ok(log_group_names) => log_group_names,
+        Err(err) => {
+            error!("Failed to list log groups: {}{}", err, credentials_hint(&err));
+            ExitCode::for_error(&err).exit();
+        }
+    };
+    let picked = tokio::task::spawn_blocking(move || {
+        inquire::MultiSelect::new("Log groups to mount:", log_group_names)
+            .with_help_message("type to search, space to select, enter to mount, esc to cancel")
+            .with_page_size(15)
+            .with_validator(|picked: &[inquire::list_option::ListOption<&String>]| {
+                Ok(match picked {
+                    [] => inquire::validator::Validation::Invalid("select at least one log group".into()),
+                    _ => inquire::validator::Validation::Valid,
+                })
+            })
+            .prompt()
+    })
+    .await
+    .expect("picking log groups does not panic");
+    match picked {
+        Ok(picked) if picked.len() == 1 => {
+            eprintln!("mounting with --log-group-name {}", picked[0]);
+            (picked.into_iter().next(), None)
+        }
+        Ok(picked) => {
+            let filter = regexes::exact_log_group_names_filter(&picked);
+            eprintln!("mounting with --log-group-filter '{}'", filter);
+            (None, Some(filter))
+        }
+        Err(inquire::InquireError::OperationCanceled) | Err(inquire::InquireError::OperationInterrupted) => {
+            ExitCode::Interrupted.exit()
+        }
+        Err(err) => {
+            error!("Failed to pick log groups: {}", err);
+            ExitCode::Failure.exit();
+        }
+    }
+}
+
+/// The log group in `--log-group-name`, which may be given by name or by ARN. The ARN's region must be one of the
+/// regions being read. A log group in one of `account_ids`, the accounts of the credentials, is read by its name, and
+/// one in another account by its ARN, which works from a monitoring account that the other account is linked to.
+fn log_group_name_arg(
+    log_group_name: Option<&str>,
+    regions: &[String],
+    account_ids: &[String],
+) -> Option<String> {
+    log_group_name.map(|value| parse_log_group_name(value, regions, account_ids))
+}
+
+/// Like `log_group_name_arg`, for subcommands that take `--log-group-name` more than once.
+fn log_group_names_arg(
+    log_group_names: &[String],
+    regions: &[String],
+    account_ids: &[String],
+) -> Vec<String> {
+    log_group_names
+        .iter()
+        .map(|value| parse_log_group_name(value, regions, account_ids))
+        .collect()
+}
+
+fn parse_log_group_name(value: &str, regions: &[String], account_ids: &[String]) -> String {
+    match regexes::LogGroupArn::parse(value) {
+        Some(arn) if !regions.contains(&arn.region) => clap::Error::with_description(
+            &format!(
+                "log group {} is in {}, pass --region {}",
+                value, arn.region, arn.region
+            ),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit_with(ExitCode::Config),
+        Some(arn) if account_ids.contains(&arn.account_id) => arn.log_group_name,
+        Some(arn) => arn.identifier(),
+        None => value.to_string(),
+    }
+}
+
+/// The accounts of the credentials if a log group ARN in `--log-group-name` needs them to tell whether it is in one of
+/// them or in a linked source account, see `log_group_name_arg`. Exits if they cannot be looked up.
+async fn log_group_arn_accounts(log_group_names: &[&str], cwl: &CloudWatchLogsActorHandle) -> Vec<String> {
+    if !log_group_names
+        .iter()
+        .any(|log_group_name| regexes::LogGroupArn::parse(log_group_name).is_some())
+    {
+        return vec![];
+    }
+    let account_ids = match cwl.account_ids().await {
+        Ok(account_ids) => account_ids,
+        Err(err) => {
+            error!(
+                "Failed to look up the AWS account: {}{}",
+                err,
+                credentials_hint(&err)
+            );
+            ExitCode::for_error(&err).exit();
+        }
+    };
+    if account_ids.is_empty() {
+        clap::Error::with_description(
+            "the account of the credentials could not be looked up to tell whether the log group ARN is in it, pass the log group name instead",
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit_with(ExitCode::Config);
+    }
+    account_ids
+}
+
+/// Regions in different partitions need different credentials, so they cannot be read together.
+fn check_partition(regions: &[String], use_fips: bool) -> Result<(), String> {
+    let partitions: HashSet<Partition> = regions
+        .iter()
+        .map(|region| Partition::for_region(region))
+        .collect();
+    if partitions.len() > 1 {
+        return Err(format!(
+            "regions {} are in different partitions, mount each partition separately",
+            regions.join(", ")
+        ));
+    }
+    match partitions.into_iter().next() {
+        Some(partition) if use_fips && !partition.supports_fips() => Err(format!(
+            "--use-fips isn't supported in the {} partition",
+            partition.as_str()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// The first pair of options that cannot be used together. fuser only checks this when mounting in the foreground.
+fn conflicting_mount_options(options: &[MountOption]) -> Option<(&MountOption, &MountOption)> {
+    use MountOption::*;
+    let conflicts = [
+        (AllowOther, AllowRoot),
+        (Dev, NoDev),
+        (Suid, NoSuid),
+        (RO, RW),
+        (Exec, NoExec),
+        (Atime, NoAtime),
+        (Sync, Async),
+    ];
+    conflicts.iter().find_map(|(a, b)| {
+        let a = options.iter().find(|option| *option == a)?;
+        let b = options.iter().find(|option| *option == b)?;
+        Some((a, b))
+    })
+}
+
+/// On Linux only root may mount with the allow_other FUSE option, unless /etc/fuse.conf has `user_allow_other`.
+#[cfg(target_os = "linux")]
+fn check_user_allow_other() -> Result<(), String> {
+    if unsafe { libc::geteuid() } == 0 {
+        return Ok(());
+    }
+    let fuse_conf = std::fs::read_to_string("/etc/fuse.conf").unwrap_or_default();
+    if fuse_conf.lines().any(|line| line.trim() == "user_allow_other") {
+        return Ok(());
+    }
+    Err("--allow-other and --allow-root need 'user_allow_other' in /etc/fuse.conf, or running as root. Add the line \
+        'user_allow_other' to /etc/fuse.conf, it may already be there but commented out."
+        .to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn check_user_allow_other() -> Result<(), String> {
+    Ok(())
+}
+
+/// Whether cwl-mount runs in a Docker, Podman or Kubernetes container.
+#[cfg(target_os = "linux")]
+fn running_in_container() -> bool {
+    if Path::new("/.dockerenv").exists() || Path::new("/run/.containerenv").exists() {
+        return true;
+    }
+    let cgroup = std::fs::read_to_string("/proc/1/cgroup").unwrap_or_default();
+    ["docker", "kubepods", "containerd", "libpod"]
+        .iter()
+        .any(|runtime| cgroup.contains(runtime))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn running_in_container() -> bool {
+    false
+}
+
+/// Whether mounting at `mount_point` would not be seen outside the container, because the mount it is under is a
+/// volume, i.e. not the container's root file system, that is not mounted with shared propagation. See
+/// proc_pid_mountinfo(5).
+fn mount_hidden_from_host(mount_point: &Path) -> bool {
+    let mount_point = std::fs::canonicalize(mount_point).unwrap_or_else(|_| mount_point.to_path_buf());
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap_or_default();
+    let under = mountinfo
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let shared = fields
+                .get(6..)?
+                .iter()
+                .take_while(|field| **field != "-")
+                .any(|field| field.starts_with("shared:"));
+            Some((Path::new(*fields.get(4)?), shared))
+        })
+        .filter(|(path, _)| mount_point.starts_with(path))
+        .max_by_key(|(path, _)| path.components().count());
+    under.is_some_and(|(path, shared)| path != Path::new("/") && !shared)
+}
+
+/// Keeps the thread that writes logs to a file running. Dropped when the process exits, see [`flush_logs`].
+static LOG_GUARD: std::sync::Mutex<Option<WorkerGuard>> = std::sync::Mutex::new(None);
+
+/// Write out buffered logs. Registered with atexit, so that it also runs on `std::process::exit`, which does not drop
+/// anything.
+extern "C" fn flush_logs() {
+    if let Ok(mut guard) = LOG_GUARD.lock() {
+        guard.take();
+    }
+}
+
+/// An error followed by its sources, e.g. `... describe logs error: dispatch failure: dns error`, since the SDK's
+/// own messages say little about what went wrong. Sources that repeat the message before them are left out.
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut chain = err.to_string();
+    let mut previous = chain.clone();
+    let mut source = err.source();
+    while let Some(err) = source {
+        let message = err.to_string();
+        if !previous.contains(&message) {
+            chain.push_str(": ");
+            chain.push_str(&message);
         }
-        let file = file.unwrap();
-        match &file.file.file_type {
-            fuse::FileType::Directory => {}
-            fuse::FileType::File(_info) => {
-                debug!("file: {:?}", file.file);
+        previous = message;
+        source = err.source();
+    }
+    chain
+}
+
+/// Checks run by `doctor`, printed as they finish as tab-separated status, check and details.
+#[derive(Default)]
+struct Doctor {
+    failures: usize,
+}
+
+/// Log groups named by `log_group_name`, or else matched by `log_group_filter` and not by `log_group_exclude`, that
+/// exist in the regions being read.
+async fn matching_log_groups(
+    cwl: &CloudWatchLogsActorHandle,
+    log_group_name: Option<&str>,
+    log_group_filter: Option<&str>,
+    log_group_exclude: Option<&str>,
+) -> Result<Vec<String>, CloudWatchLogsError> {
+    let log_group_names = cwl.get_log_group_names().await?;
+    let matching = match (log_group_name, log_group_filter) {
+        (Some(log_group_name), _) => log_group_names
+            .into_iter()
+            .filter(|name| name == log_group_name)
+            .collect(),
+        (None, Some(log_group_filter)) => {
+            let mut matcher = LogGroupNameMatcher::new(log_group_filter);
+            if let Some(log_group_exclude) = log_group_exclude {
+                matcher = matcher.excluding(log_group_exclude);
             }
+            log_group_names
+                .into_iter()
+                .filter(|name| matcher.is_match(name))
+                .collect()
         }
-        reply.attr(
-            &TTL,
-            &FileAttr {
-                ino: file.file.inode,
-                size: match file.file.file_type {
-                    fuse::FileType::Directory => 0,
-                    fuse::FileType::File(_) => i32::MAX as u64,
-                },
-                blocks: match file.file.file_type {
-                    fuse::FileType::Directory => 0,
-                    fuse::FileType::File(_) => 1,
-                },
-                atime: UNIX_EPOCH, // 1970-01-01 00:00:00
-                mtime: UNIX_EPOCH,
-                ctime: UNIX_EPOCH,
-                crtime: UNIX_EPOCH,
-                kind: match file.file.file_type {
-                    fuse::FileType::Directory => FileType::Directory,
-                    fuse::FileType::File(_) => FileType::RegularFile,
-                },
-                perm: match file.file.file_type {
-                    fuse::FileType::Directory => 0o777,
-                    fuse::FileType::File(_) => 0o777,
-                },
-                nlink: match file.file.file_type {
-                    fuse::FileType::Directory => 2,
-                    fuse::FileType::File(_) => 1,
-                },
-                uid: req.uid(),
-                gid: req.gid(),
-                rdev: 0,
-                flags: 0,
-                blksize: 512,
-            },
+        (None, None) => vec![],
+    };
+    Ok(matching)
+}
+
+/// Check before mounting that the mounted log groups can be read, by reading at most one event of the last minute of
+/// one of them, so that an IAM policy that allows listing log groups but not reading them fails the mount with a
+/// hint rather than every read. Returns the log group that was read, or why it could not be along with what to
+/// exit with.
+async fn preflight_read(
+    cwl: &CloudWatchLogsActorHandle,
+    log_group_name: Option<&str>,
+    log_group_filter: Option<&str>,
+    log_group_exclude: Option<&str>,
+) -> Result<String, (ExitCode, String)> {
+    let matching = matching_log_groups(cwl, log_group_name, log_group_filter, log_group_exclude)
+        .await
+        .map_err(|err| {
+            let message = match err.is_access_denied() {
+                true => format!(
+                    "{}. Allow logs:DescribeLogGroups in the IAM policy.",
+                    error_chain(&err)
+                ),
+                false => format!("{}{}", error_chain(&err), credentials_hint(&err)),
+            };
+            (ExitCode::for_error(&err), message)
+        })?;
+    let first = matching.into_iter().next().ok_or_else(|| {
+        let message = format!(
+            "no log group matches {}, see `cwl-mount list-log-groups`, or pass --skip-preflight to mount log groups \
+             that do not exist yet",
+            log_group_name.or(log_group_filter).unwrap_or_default()
+        );
+        (ExitCode::NoMatchingLogGroups, message)
+    })?;
+    let end_time = Utc::now();
+    let result = cwl
+        .get_log_events(
+            first.clone(),
+            Some(end_time - Duration::minutes(1)),
+            Some(end_time),
+            Some(1),
+            EventFilter::default(),
         )
+        .await;
+    match result {
+        Ok(_) => Ok(first),
+        Err(err) if err.is_access_denied() => Err((
+            ExitCode::Credentials,
+            format!(
+                "{}. Allow logs:FilterLogEvents on {} in the IAM policy.",
+                error_chain(&err),
+                first
+            ),
+        )),
+        Err(err) => Err((
+            ExitCode::for_error(&err),
+            format!("{}{}", error_chain(&err), credentials_hint(&err)),
+        )),
+    }
+}
+
+impl Doctor {
+    fn report(&mut self, check: &str, result: Result<String, String>) {
+        match result {
+            Ok(details) => println!("ok\t{}\t{}", check, details),
+            Err(details) => {
+                self.failures += 1;
+                println!("fail\t{}\t{}", check, details);
+            }
+        }
+    }
 
-        // match ino {
-        //     1 => reply.attr(&TTL, &HELLO_DIR_ATTR),
-        //     2 => reply.attr(&TTL, &HELLO_TXT_ATTR),
-        //     _ => reply.error(ENOENT),
-        // }
+    fn skip(&self, check: &str, reason: &str) {
+        println!("skip\t{}\t{}", check, reason);
     }
 
-    fn read(
+    async fn check_aws(
         &mut self,
-        _req: &Request,
-        ino: u64,
-        _fh: u64,
-        offset: i64,
-        size: u32,
-        _flags: i32,
-        _lock: Option<u64>,
-        reply: ReplyData,
+        cwl: &CloudWatchLogsActorHandle,
+        log_group_name: Option<&str>,
+        log_group_filter: Option<&str>,
+        log_group_exclude: Option<&str>,
+        regions: &[String],
+        account_ids: &[String],
     ) {
-        debug!("ino: {}, offset: {}, size: {}", ino, offset, size);
-        let file_tree = Arc::clone(&self.file_tree);
-        let file = file_tree.get_file_by_inode(ino);
-        if file.is_none() {
-            reply.error(ENOENT);
+        // Preflight loads credentials and calls DescribeLogGroups in every region.
+        match cwl.preflight().await {
+            Ok(reports) => {
+                for report in reports {
+                    self.report(
+                        "credentials",
+                        Ok(format!(
+                            "from {} in {}{}",
+                            report.credentials_source,
+                            report.region.as_deref().unwrap_or_default(),
+                            report
+                                .credentials_expiry
+                                .map(|expiry| format!(", expiring at {}", expiry.to_rfc3339()))
+                                .unwrap_or_default()
+                        )),
+                    );
+                }
+                self.report("logs:DescribeLogGroups", Ok("allowed".to_string()));
+            }
+            Err(err) if err.is_access_denied() => {
+                self.report("credentials", Ok("loaded".to_string()));
+                self.report(
+                    "logs:DescribeLogGroups",
+                    Err(format!(
+                        "{}. Allow logs:DescribeLogGroups in the IAM policy.",
+                        error_chain(&err)
+                    )),
+                );
+                return;
+            }
+            Err(err) => {
+                self.report(
+                    "credentials",
+                    Err(format!(
+                        "{}{}. Check the profile, or that the environment has credentials, e.g. with `aws sts get-caller-identity`.",
+                        error_chain(&err),
+                        credentials_hint(&err)
+                    )),
+                );
+                return;
+            }
+        }
+
+        let log_group_name = log_group_name_arg(log_group_name, regions, account_ids);
+        if log_group_name.is_none() && log_group_filter.is_none() {
+            self.skip(
+                "log groups",
+                "pass --log-group-name or --log-group-filter to check them",
+            );
+            self.skip("logs:FilterLogEvents", "no log group to read");
             return;
         }
-        let file = file.unwrap().clone();
-        match file.file.file_type {
-            fuse::FileType::Directory => {
-                reply.error(ENOENT);
+        let matching = match matching_log_groups(
+            cwl,
+            log_group_name.as_deref(),
+            log_group_filter,
+            log_group_exclude,
+        )
+        .await
+        {
+            Ok(matching) => matching,
+            Err(err) => {
+                self.report("log groups", Err(error_chain(&err)));
                 return;
             }
-            fuse::FileType::File(time_bounds) => {
-                let log_group_name = self.log_group_name.clone();
-                let log_group_filter = self.log_group_filter.clone();
-                let cwl_actor_handle = Arc::clone(&self.cwl_actor_handle);
-                let (tx, rx) = crossbeam::channel::bounded(1);
-                let handle = Arc::clone(&self.handle);
-                let formatter = self.formatter.clone();
-                handle.spawn(async move {
-                    let res = cwl_actor_handle
-                        .get_logs_to_display(
-                            log_group_name,
-                            log_group_filter,
-                            time_bounds.start_time,
-                            time_bounds.end_time,
-                            formatter,
-                        )
-                        .await;
-                    let _ = tx.send(res);
-                });
-                let res = rx.recv().unwrap().unwrap();
-                let file_size = res.len();
-                debug!("logs to display: {:?}", res);
-                let read_size = min(size, file_size.saturating_sub(offset as usize) as u32);
-                if read_size == 0 {
-                    reply.data(&EMPTY_BUFFER);
-                    return;
-                }
-                let mut buffer = vec![0; read_size as usize];
-                let res_as_slice = res.as_ref();
-                let mut reader = Cursor::new(&res_as_slice[offset as usize..]);
-                reader.read_exact(&mut buffer).unwrap();
-                reply.data(&buffer);
+        };
+        let first = match matching.first() {
+            Some(first) => first.clone(),
+            None => {
+                self.report(
+                    "log groups",
+                    Err(format!(
+                        "none match {}, see `cwl-mount list-log-groups` for the log groups in {}",
+                        log_group_name.as_deref().or(log_group_filter).unwrap(),
+                        regions.join(", ")
+                    )),
+                );
+                self.skip("logs:FilterLogEvents", "no log group to read");
+                return;
             }
+        };
+        self.report(
+            "log groups",
+            Ok(format!("{} match, e.g. {}", matching.len(), first)),
+        );
+
+        // Reading the last minute of one log group is enough to tell whether reads are allowed.
+        let end_time = Utc::now();
+        let result = cwl
+            .get_logs_to_display(
+                Some(first.clone()),
+                None,
+                end_time - Duration::minutes(1),
+                end_time,
+                EventFilter::default(),
+                format_cwl_log_event::LogFormatter::new("${message}").unwrap(),
+            )
+            .await;
+        match result {
+            Ok(_) => self.report("logs:FilterLogEvents", Ok(format!("allowed on {}", first))),
+            Err(err) if err.is_access_denied() => self.report(
+                "logs:FilterLogEvents",
+                Err(format!(
+                    "{}. Allow logs:FilterLogEvents on {} in the IAM policy.",
+                    error_chain(&err),
+                    first
+                )),
+            ),
+            Err(err) => self.report(
+                "logs:FilterLogEvents",
+                Err(format!("{}{}", error_chain(&err), credentials_hint(&err))),
+            ),
         }
     }
 
-    fn open(&mut self, _req: &Request, inode: u64, flags: i32, reply: ReplyOpen) {
-        debug!("open() called for {:?}", inode);
-        let (_access_mask, _read, _write) = match flags & libc::O_ACCMODE {
-            libc::O_RDONLY => {
-                // Behavior is undefined, but most filesystems return EACCES
-                if flags & libc::O_TRUNC != 0 {
-                    reply.error(libc::EACCES);
-                    return;
-                }
-                if flags & FMODE_EXEC != 0 {
-                    // Open is from internal exec syscall
-                    (libc::X_OK, true, false)
-                } else {
-                    (libc::R_OK, true, false)
-                }
+    fn check_fuse(&mut self) {
+        #[cfg(target_os = "linux")]
+        {
+            self.report(
+                "/dev/fuse",
+                match Path::new("/dev/fuse").exists() {
+                    true => Ok("exists".to_string()),
+                    false => Err(
+                        "missing. Load the fuse kernel module with `modprobe fuse`, or in a container pass \
+                        `--device /dev/fuse --cap-add SYS_ADMIN`."
+                            .to_string(),
+                    ),
+                },
+            );
+            self.report(
+                "fusermount",
+                match find_fusermount() {
+                    Some(fusermount) => Ok(fusermount.display().to_string()),
+                    None => Err(
+                        "not found in PATH. Install fuse, e.g. `apt install fuse3` or `yum install fuse`."
+                            .to_string(),
+                    ),
+                },
+            );
+        }
+        #[cfg(target_os = "macos")]
+        {
+            self.report(
+                "macFUSE",
+                match Path::new("/Library/Filesystems/macfuse.fs").exists() {
+                    true => Ok("installed".to_string()),
+                    false => {
+                        Err("not installed. Install it with `brew install macfuse` and restart.".to_string())
+                    }
+                },
+            );
+        }
+    }
+}
+
+/// Stat and read a file at the root of a mount. Both happen on a thread of their own, because calls into a wedged
+/// mount never return.
+fn read_mount_file(
+    mount_point: &Path,
+    filename: &str,
+    timeout: std::time::Duration,
+) -> Result<std::io::Result<Vec<u8>>, std::sync::mpsc::RecvTimeoutError> {
+    let (send, recv) = std::sync::mpsc::channel();
+    let path = mount_point.join(filename);
+    std::thread::spawn(move || {
+        let read = || -> std::io::Result<Vec<u8>> {
+            std::fs::metadata(&path)?;
+            // Not std::fs::read, which would allocate for the size the stats file claims to have.
+            let mut data = vec![];
+            std::fs::File::open(&path)?.read_to_end(&mut data)?;
+            Ok(data)
+        };
+        let _ = send.send(read());
+    });
+    recv.recv_timeout(timeout)
+}
+
+/// Read the mount's health file.
+fn check_health(mount_point: &Path, timeout: std::time::Duration) -> Result<(), String> {
+    match read_mount_file(mount_point, fuse::HEALTH_FILENAME, timeout) {
+        Ok(Ok(data)) if data == HEALTH_OK => Ok(()),
+        Ok(Ok(data)) => Err(format!(
+            "{} is not a cwl-mount mount, {} reads {:?}",
+            mount_point.display(),
+            fuse::HEALTH_FILENAME,
+            String::from_utf8_lossy(&data)
+        )),
+        Ok(Err(err)) => Err(format!("{} is not healthy: {}", mount_point.display(), err)),
+        Err(_) => Err(format!(
+            "{} did not respond within {:?}",
+            mount_point.display(),
+            timeout
+        )),
+    }
+}
+
+/// Set in the environment of the background process that `--daemon` starts.
+const DAEMON_ENV_VAR: &str = "CWL_MOUNT_DAEMONIZED";
+
+/// How long `umount` waits for a daemon to finish reads in flight and unmount.
+const UMOUNT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Names that mount(8) runs cwl-mount as for fstab entries and systemd mount units of type `cwlmount`, when it is
+/// linked to e.g. `/sbin/mount.cwlmount`.
+const MOUNT_HELPER_NAMES: [&str; 2] = ["mount.cwlmount", "mount.fuse.cwlmount"];
+
+/// Translate the arguments that mount(8) passes to a mount helper, `<spec> <dir> [-sfnv] [-o <options>]`, into
+/// cwl-mount's. The spec is a log group name or filter, optionally after `cwlmount#`. Options that are cwl-mount
+/// flags, e.g. `region=us-west-2` or `tps=5`, are passed as those flags, options meant for mount(8) or systemd are
+/// dropped, and the rest are passed to FUSE. Always mounts as a daemon because mount(8) waits for the helper to
+/// exit. None for a fake mount.
+fn mount_helper_args(app: &clap::App, args: &[String]) -> Result<Option<Vec<String>>, String> {
+    let mut positional = vec![];
+    let mut options = vec![];
+    let mut verbose = false;
+    let mut fake = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => options.extend(
+                args.next()
+                    .ok_or("-o needs options")?
+                    .split(',')
+                    .filter(|option| !option.is_empty())
+                    .map(|option| option.to_string()),
+            ),
+            // The file system type and mount namespace.
+            "-t" | "-N" => {
+                args.next();
             }
-            libc::O_WRONLY => (libc::W_OK, false, true),
-            libc::O_RDWR => (libc::R_OK | libc::W_OK, true, true),
-            // Exactly one access mode flag must be specified
-            _ => {
-                reply.error(libc::EINVAL);
-                return;
+            "-f" => fake = true,
+            "-v" => verbose = true,
+            // Sloppy and no mtab, which mean nothing to FUSE.
+            "-s" | "-n" => {}
+            arg if arg.starts_with('-') => return Err(format!("unknown flag {}", arg)),
+            arg => positional.push(arg.to_string()),
+        }
+    }
+    let (spec, mount_point) = match positional.as_slice() {
+        [spec, mount_point] => (spec.strip_prefix("cwlmount#").unwrap_or(spec), mount_point),
+        _ => {
+            return Err(
+                "usage: mount.cwlmount <log group name or filter> <mount point> [-o <options>]".to_string(),
+            )
+        }
+    };
+
+    let mut global = vec!["cwl-mount".to_string()];
+    if verbose {
+        global.push("-v".to_string());
+    }
+    let log_group = if regexes::clap_validate_cwl_log_group_name_or_arn(spec).is_ok() {
+        "--log-group-name"
+    } else {
+        "--log-group-filter"
+    };
+    let mut mount = vec![
+        "mount".to_string(),
+        mount_point.to_string(),
+        "--daemon".to_string(),
+        log_group.to_string(),
+        spec.to_string(),
+    ];
+    let mut fuse_options = vec![];
+    for option in options {
+        if is_fstab_only_option(&option) {
+            continue;
+        }
+        let flag = format!("--{}", option);
+        // Parsing fails for other reasons, e.g. missing arguments, when the flag itself is known.
+        let knows = |args: &[&str]| !matches!(app.clone().get_matches_from_safe(args), Err(err) if err.kind == clap::ErrorKind::UnknownArgument);
+        if knows(&["cwl-mount", &flag, "completions", "bash"]) {
+            global.push(flag);
+        } else if knows(&["cwl-mount", "mount", "/", &flag]) {
+            mount.push(flag);
+        } else {
+            fuse_options.push(option);
+        }
+    }
+    if !fuse_options.is_empty() {
+        mount.push("-o".to_string());
+        mount.push(fuse_options.join(","));
+    }
+    if fake {
+        return Ok(None);
+    }
+    global.extend(mount);
+    Ok(Some(global))
+}
+
+/// fstab options that are meant for mount(8) or systemd rather than the file system.
+fn is_fstab_only_option(option: &str) -> bool {
+    matches!(
+        option,
+        "defaults"
+            | "auto"
+            | "noauto"
+            | "nofail"
+            | "user"
+            | "nouser"
+            | "users"
+            | "owner"
+            | "group"
+            | "_netdev"
+    ) || option.starts_with("x-")
+        || option.starts_with("comment=")
+}
+
+/// Name that systemd gives units for `path`, like `systemd-escape --path`, e.g. `mnt-my\x20logs` for `/mnt/my logs`.
+fn systemd_escape_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let components: Vec<&str> = path
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .collect();
+    if components.is_empty() {
+        return "-".to_string();
+    }
+    let mut escaped = String::new();
+    for (i, byte) in components.join("/").bytes().enumerate() {
+        match byte {
+            b'/' => escaped.push('-'),
+            b'.' if i == 0 => escaped.push_str("\\x2e"),
+            b':' | b'_' | b'.' => escaped.push(byte as char),
+            byte if byte.is_ascii_alphanumeric() => escaped.push(byte as char),
+            byte => escaped.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+/// Quote `arg` for a command line in a unit file, see systemd.service(5). Specifiers and variables are escaped so
+/// that e.g. `--output-format '${message}'` reaches cwl-mount as given.
+fn systemd_quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+    let plain = |c: char| !c.is_whitespace() && !matches!(c, '"' | '\'' | '\\' | ';');
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return arg;
+    }
+    let quoted = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", quoted)
+}
+
+/// Translate the flags of a mount into options for a systemd mount unit of type `cwlmount`, the inverse of
+/// `mount_helper_args`. `matches` are those of the mount, used to tell flags that take a value from switches.
+fn mount_unit_options(args: &[String], matches: &ArgMatches) -> Result<Vec<String>, String> {
+    let mount_matches = matches.subcommand_matches("mount").unwrap();
+    // The mount runs in the background, after the network is up.
+    let mut options = vec!["_netdev".to_string()];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            options.extend(
+                args.next()
+                    .into_iter()
+                    .flat_map(|fuse_options| fuse_options.split(','))
+                    .filter(|option| !option.is_empty())
+                    .map(|option| option.to_string()),
+            );
+            continue;
+        }
+        let flag = arg.strip_prefix("--").ok_or_else(|| {
+            format!(
+                "{} can't be a mount option, give flags by their long names, e.g. --verbose for -v",
+                arg
+            )
+        })?;
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None if matches.value_of(flag).is_some() || mount_matches.value_of(flag).is_some() => {
+                (flag, args.next().cloned())
             }
+            None => (flag, None),
         };
-
-        let file_tree = Arc::clone(&self.file_tree);
-        match file_tree.get_file_by_inode(inode) {
-            Some(file) => match file.file.file_type {
-                fuse::FileType::Directory => {}
-                fuse::FileType::File(_) => {
-                    let open_flags = if self.direct_io { FOPEN_DIRECT_IO } else { 0 };
-                    let fh = 10;
-                    reply.opened(fh, open_flags);
-                    return;
-                }
-            },
-            None => {}
+        match value {
+            // The log group is the unit's What=.
+            _ if name == "log-group-name" || name == "log-group-filter" => {}
+            Some(value) if value.contains(',') => {
+                return Err(format!(
+                    "--{} {} can't be a mount option because it has a comma",
+                    name, value
+                ))
+            }
+            Some(value) => options.push(format!("{}={}", name, value)),
+            None => options.push(name.to_string()),
         }
-        reply.error(libc::EACCES);
     }
+    Ok(options)
+}
 
-    fn readdir(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
-        debug!("readdir, ino: {}, offset: {}", ino, offset);
-        let directory = self.file_tree.get_file_by_inode(ino);
-        if directory.is_none() {
-            reply.error(ENOENT);
-            return;
+/// File names and contents of the units that `generate-systemd-unit` writes for a mount at `mount_point`, which is
+/// absolute. `args` are the global flags and `mount_args` the flags after the mount point, `mount` and `matches` the
+/// mount they make up. A service runs `program` in the foreground; with `automount_idle_secs` a mount unit and an
+/// automount unit mount through `mount.cwlmount` on first access instead.
+fn systemd_units(
+    program: &Path,
+    args: &[String],
+    mount_point: &Path,
+    mount_args: &[String],
+    mount: &MountArgs,
+    matches: &ArgMatches,
+    automount_idle_secs: Option<u64>,
+) -> Result<Vec<(String, String)>, String> {
+    if mount.daemon {
+        return Err("--daemon can't be used because systemd keeps cwl-mount running itself".to_string());
+    }
+    let log_group_names = mount.log_group_name.as_slice();
+    let log_group_filter = mount.log_group_filter.as_deref();
+    if log_group_names.is_empty() && log_group_filter.is_none() {
+        return Err(
+            "give --log-group-name or --log-group-filter, because there is no terminal to pick log groups from"
+                .to_string(),
+        );
+    }
+    let name = systemd_escape_path(mount_point);
+    let description = format!(
+        "Description=AWS CloudWatch Logs mounted at {} by cwl-mount",
+        mount_point.display()
+    );
+
+    if automount_idle_secs.is_none() {
+        let command = |args: Vec<String>| {
+            std::iter::once(program.to_string_lossy().to_string())
+                .chain(args)
+                .map(|arg| systemd_quote(&arg))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let mut exec_start = args.to_vec();
+        exec_start.push("mount".to_string());
+        exec_start.push(mount_point.to_string_lossy().to_string());
+        exec_start.extend_from_slice(mount_args);
+        let exec_stop = vec!["umount".to_string(), mount_point.to_string_lossy().to_string()];
+        let service = [
+            "[Unit]",
+            &description,
+            "Wants=network-online.target",
+            "After=network-online.target",
+            "",
+            "[Service]",
+            "Type=simple",
+            &format!("ExecStart={}", command(exec_start)),
+            // Unmounts, falling back to fusermount if cwl-mount is stuck, and cwl-mount then exits.
+            &format!("ExecStop={}", command(exec_stop)),
+            "Restart=on-failure",
+            // Invalid flags, credentials that don't work and missing log groups aren't fixed by restarting.
+            &format!(
+                "RestartPreventExitStatus={} {} {}",
+                ExitCode::Config as i32,
+                ExitCode::Credentials as i32,
+                ExitCode::NoMatchingLogGroups as i32
+            ),
+            "",
+            "[Install]",
+            "WantedBy=multi-user.target",
+        ];
+        return Ok(vec![(
+            format!("cwl-mount-{}.service", name),
+            service.join("\n") + "\n",
+        )]);
+    }
+
+    let what = match (log_group_names, log_group_filter) {
+        ([log_group_name], None) => log_group_name.to_string(),
+        ([], Some(filter)) if regexes::clap_validate_cwl_log_group_name_or_arn(filter).is_err() => filter.to_string(),
+        ([], Some(filter)) => {
+            return Err(format!(
+                "mount.cwlmount would take --log-group-filter {} for a log group name, anchor it like ^{}",
+                filter, filter
+            ))
         }
-        let directory = directory.unwrap();
-        let children = self.file_tree.list_directory(directory.file_key);
-        let mut entries: VecDeque<(u64, FileType, String)> = children
-            .into_iter()
-            .map(|file| {
-                (
-                    file.file.inode,
-                    match file.file.file_type {
-                        fuse::FileType::Directory => FileType::Directory,
-                        fuse::FileType::File(_) => FileType::RegularFile,
-                    },
-                    file.file.name.clone(),
+        _ => {
+            return Err(
+                "--automount mounts a single --log-group-name or a --log-group-filter, because mount units have one source"
+                    .to_string(),
+            )
+        }
+    };
+    let options = mount_unit_options(&[args, mount_args].concat(), matches)?;
+    let mount = [
+        "[Unit]",
+        &description,
+        "Wants=network-online.target",
+        "After=network-online.target",
+        "",
+        "[Mount]",
+        &format!("What={}", what.replace('%', "%%")),
+        &format!("Where={}", mount_point.display()),
+        "Type=cwlmount",
+        &format!("Options={}", options.join(",").replace('%', "%%")),
+    ];
+    let automount = [
+        "[Unit]",
+        &description,
+        "",
+        "[Automount]",
+        &format!("Where={}", mount_point.display()),
+        &format!("TimeoutIdleSec={}", automount_idle_secs.unwrap()),
+        "",
+        "[Install]",
+        "WantedBy=multi-user.target",
+    ];
+    Ok(vec![
+        (format!("{}.mount", name), mount.join("\n") + "\n"),
+        (format!("{}.automount", name), automount.join("\n") + "\n"),
+    ])
+}
+
+/// Where a daemon mounted at `mount_point` writes its process ID if `--pid-file` is not given, so that `umount` can
+/// find it from the mount point alone.
+fn default_pid_file(mount_point: &Path) -> PathBuf {
+    let mount_point = std::path::absolute(mount_point).unwrap_or_else(|_| mount_point.to_path_buf());
+    let name: String = mount_point
+        .to_string_lossy()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    std::env::temp_dir().join(format!("cwl-mount{}.pid", name))
+}
+
+fn pid_file_arg(pid_file: Option<&Path>, mount_point: &Path) -> PathBuf {
+    pid_file.map_or_else(|| default_pid_file(mount_point), Path::to_path_buf)
+}
+
+fn log_dir_arg(log_dir: Option<&Path>) -> PathBuf {
+    log_dir.map_or_else(std::env::temp_dir, Path::to_path_buf)
+}
+
+/// The process ID in `pid_file`, if it names a process that is still running.
+fn running_pid(pid_file: &Path) -> Option<libc::pid_t> {
+    let pid = std::fs::read_to_string(pid_file).ok()?.trim().parse().ok()?;
+    // Signal 0 only checks that the process exists.
+    Some(pid).filter(|pid| unsafe { libc::kill(*pid, 0) } == 0)
+}
+
+/// Start this command again in the background, in a session of its own, and exit once it has mounted.
+///
+/// Forking after the mount would lose the threads of the runtime and of the mount, so the daemon is a new process
+/// that is told it is the daemon by [`DAEMON_ENV_VAR`]. It writes `pid_file` once mounted.
+fn start_daemon(args: &[OsString], pid_file: &Path, logs: &Path) -> ! {
+    if let Some(pid) = running_pid(pid_file) {
+        clap::Error::with_description(
+            &format!("already mounted by process {}, see {}", pid, pid_file.display()),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit_with(ExitCode::MountFailed);
+    }
+    let _ = std::fs::remove_file(pid_file);
+    let mut command =
+        std::process::Command::new(std::env::current_exe().expect("current executable has a path"));
+    command
+        .args(&args[1..])
+        .env(DAEMON_ENV_VAR, "1")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    // Leave the terminal's session so that closing the terminal does not stop the daemon.
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let mut child = command.spawn().unwrap_or_else(|err| {
+        clap::Error::with_description(&format!("failed to start daemon: {}", err), clap::ErrorKind::Io)
+            .exit_with(ExitCode::MountFailed)
+    });
+    loop {
+        if running_pid(pid_file) == Some(child.id() as libc::pid_t) {
+            println!("{}", child.id());
+            std::process::exit(0);
+        }
+        if let Ok(Some(status)) = child.try_wait() {
+            eprintln!(
+                "{}",
+                clap::Error::with_description(
+                    &format!(
+                        "daemon exited with {} before mounting, see the logs in {}",
+                        status,
+                        logs.display()
+                    ),
+                    clap::ErrorKind::Io,
                 )
-            })
-            .collect();
-        let parent_inode = self.file_tree.get_parent_for_ls(directory.file_key).file.inode;
-        entries.push_front((parent_inode, FileType::Directory, "..".to_string()));
-        entries.push_front((parent_inode, FileType::Directory, ".".to_string()));
+                .message
+            );
+            // The daemon's exit code says why it failed, e.g. that credentials did not load.
+            std::process::exit(status.code().unwrap_or(ExitCode::MountFailed as i32));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
 
-        // if ino != 1 {
-        //     reply.error(ENOENT);
-        //     return;
-        // }
-        // let entries = vec![
-        //     (1, FileType::Directory, "."),
-        //     (1, FileType::Directory, ".."),
-        //     (2, FileType::RegularFile, "hello.txt"),
-        // ];
+/// Read one time range the way a mount reads a file, and report how fast it was read, one tab-separated name and
+/// value per line. Comparing the API calls per second with `tps`, the configured rate limit, tells whether raising
+/// `--tps` or `--parallel-sub-ranges` would read faster.
+async fn run_bench(
+    cwl: &CloudWatchLogsActorHandle,
+    log_group_name: Option<String>,
+    log_group_filter: Option<String>,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    tps: Option<usize>,
+) -> Result<String, CloudWatchLogsError> {
+    let before = cwl.usage().await?;
+    let page = cwl
+        .get_log_page(
+            log_group_name,
+            log_group_filter,
+            start_time,
+            end_time,
+            EventFilter::default(),
+            format_cwl_log_event::LogFormatter::new("${message}").unwrap(),
+        )
+        .await?;
+    let after = cwl.usage().await?;
+    if page.cached {
+        warn!("the window was read from the cache, so nothing was fetched; pick another --end-time or leave out --cache-dir");
+    }
+    let seconds = page.fetch_duration.as_secs_f64();
+    let per_second = |count: u64| match seconds {
+        seconds if seconds > 0.0 => format!("{:.1}", count as f64 / seconds),
+        _ => "-".to_string(),
+    };
+    let api_calls = after.total_api_calls() - before.total_api_calls();
+    let mut report = vec![
+        (
+            "window".to_string(),
+            format!("{}/{}", start_time.to_rfc3339(), end_time.to_rfc3339()),
+        ),
+        ("log_groups".to_string(), page.log_groups_queried.to_string()),
+        ("events".to_string(), page.events.to_string()),
+        ("bytes".to_string(), page.data.len().to_string()),
+        ("truncated".to_string(), page.truncated.to_string()),
+        ("pages".to_string(), page.pages_fetched.to_string()),
+        ("api_calls".to_string(), api_calls.to_string()),
+    ];
+    for (action, calls) in &after.api_calls {
+        let calls = calls - before.api_calls.get(action).copied().unwrap_or_default();
+        if calls > 0 {
+            report.push((format!("api_calls.{}", action.as_str()), calls.to_string()));
+        }
+    }
+    report.extend([
+        ("seconds".to_string(), format!("{:.3}", seconds)),
+        ("events_per_second".to_string(), per_second(page.events as u64)),
+        (
+            "pages_per_second".to_string(),
+            per_second(page.pages_fetched as u64),
+        ),
+        ("api_calls_per_second".to_string(), per_second(api_calls)),
+        (
+            "tps_limit".to_string(),
+            tps.map_or_else(|| "-".to_string(), |tps| tps.to_string()),
+        ),
+    ]);
+    Ok(report
+        .into_iter()
+        .map(|(name, value)| format!("{}\t{}\n", name, value))
+        .collect())
+}
 
-        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
-            // i + 1 means the index of the next entry
-            debug!("readdir add entry: {:?}", entry);
-            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
-                break;
+/// Ask the daemon in `pid_file` to drop its cached files, the same as sending it SIGUSR2.
+fn clear_daemon_cache(pid_file: &Path) {
+    let pid = running_pid(pid_file).unwrap_or_else(|| {
+        clap::Error::with_description(
+            &format!("no running daemon found in {}", pid_file.display()),
+            clap::ErrorKind::InvalidValue,
+        )
+        .exit_with(ExitCode::Config)
+    });
+    if unsafe { libc::kill(pid, libc::SIGUSR2) } != 0 {
+        clap::Error::with_description(
+            &format!(
+                "failed to signal daemon {}: {}",
+                pid,
+                std::io::Error::last_os_error()
+            ),
+            clap::ErrorKind::Io,
+        )
+        .exit_with(ExitCode::Failure);
+    }
+}
+
+/// Ask the daemon in `pid_file` to unmount, the same as CTRL-C, and wait for it to exit. Without a daemon, e.g. for
+/// a mount that was not started with --daemon or whose process crashed, or if the daemon does not exit in time,
+/// unmount `mount_point` with fusermount instead.
+fn stop_daemon(pid_file: &Path, mount_point: &Path) {
+    let pid = match running_pid(pid_file) {
+        Some(pid) => pid,
+        None => {
+            if let Err(err) = force_unmount(mount_point) {
+                clap::Error::with_description(
+                    &format!("no running daemon found in {} and {}", pid_file.display(), err),
+                    clap::ErrorKind::InvalidValue,
+                )
+                .exit_with(ExitCode::Failure);
             }
+            return;
         }
-        reply.ok();
+    };
+    unsafe {
+        libc::kill(pid, libc::SIGTERM);
+    }
+    let deadline = std::time::Instant::now() + UMOUNT_TIMEOUT;
+    while unsafe { libc::kill(pid, 0) } == 0 {
+        if std::time::Instant::now() > deadline {
+            // The daemon stops once its mount is gone.
+            if let Err(err) = force_unmount(mount_point) {
+                clap::Error::with_description(
+                    &format!(
+                        "daemon {} did not exit within {:?} and {}",
+                        pid, UMOUNT_TIMEOUT, err
+                    ),
+                    clap::ErrorKind::Io,
+                )
+                .exit_with(ExitCode::Failure);
+            }
+            return;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
     }
 }
 
-/// Valid transactions per second (TPS) value fits in usize and is not zero.
-pub fn is_valid_tps(v: String) -> Result<(), String> {
-    match v.parse::<usize>() {
-        Ok(value) => match value {
-            0 => Err("Zero is not a valid transactions per second value".to_string()),
-            _ => Ok(()),
-        },
-        Err(_) => Err(format!(
-            "{} isn't a valid transactions per second value because not a positive integer",
-            &*v
+/// fusermount, which unmounts FUSE file systems without root, from PATH.
+fn find_fusermount() -> Option<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    ["fusermount3", "fusermount"].iter().find_map(|name| {
+        std::env::split_paths(&path)
+            .map(|dir| dir.join(name))
+            .find(|candidate| candidate.exists())
+    })
+}
+
+/// Unmount `mount_point` without asking the process that mounted it, with fusermount on Linux and umount(8)
+/// elsewhere.
+fn force_unmount(mount_point: &Path) -> Result<(), String> {
+    let mut command = match cfg!(target_os = "linux") {
+        true => {
+            let fusermount = find_fusermount().ok_or("fusermount is not in PATH to unmount with")?;
+            let mut command = std::process::Command::new(fusermount);
+            command.arg("-u");
+            command
+        }
+        false => std::process::Command::new("umount"),
+    };
+    let output = command
+        .arg(mount_point)
+        .output()
+        .map_err(|err| format!("failed to unmount {}: {}", mount_point.display(), err))?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(format!(
+            "failed to unmount {}: {}",
+            mount_point.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
         )),
     }
 }
 
+/// Whether `mount_point` is left over from a cwl-mount that crashed, which fails every access with "Transport
+/// endpoint is not connected".
+fn is_stale_mount(mount_point: &Path) -> bool {
+    matches!(std::fs::metadata(mount_point), Err(err) if err.raw_os_error() == Some(libc::ENOTCONN))
+}
+
+/// `export` fetches and writes this many files at a time, and `cache-warm` fetches as many.
+const EXPORT_CONCURRENCY: usize = 8;
+
+/// Write one exported file, creating its directories, with `.gz` appended to its name if `gzip` is set.
+fn write_export_file(path: &Path, data: &[u8], gzip: bool) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if !gzip {
+        return std::fs::write(path, data);
+    }
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".gz");
+    let mut encoder = GzEncoder::new(
+        std::fs::File::create(path.with_file_name(file_name))?,
+        Compression::default(),
+    );
+    encoder.write_all(data)?;
+    encoder.finish().map(|_| ())
+}
+
+/// Log groups as `list-log-groups --output` prints them.
+fn render_log_groups(log_groups: &[LogGroupMetadata], output: &str) -> String {
+    let creation_time = |log_group: &LogGroupMetadata| {
+        log_group
+            .creation_time
+            .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+    };
+    match output {
+        "tsv" => log_groups
+            .iter()
+            .map(|log_group| {
+                format!(
+                    "{}\t{}\t{}\t{}\t{}\n",
+                    log_group.log_group_name,
+                    log_group.region.as_deref().unwrap_or_default(),
+                    log_group
+                        .retention_in_days
+                        .map(|days| days.to_string())
+                        .unwrap_or_default(),
+                    log_group.stored_bytes.unwrap_or_default(),
+                    creation_time(log_group).unwrap_or_default()
+                )
+            })
+            .collect(),
+        "json" => {
+            let log_groups: Vec<serde_json::Value> = log_groups
+                .iter()
+                .map(|log_group| {
+                    serde_json::json!({
+                        "log_group_name": log_group.log_group_name,
+                        "region": log_group.region,
+                        "arn": log_group.arn,
+                        "retention_in_days": log_group.retention_in_days,
+                        "stored_bytes": log_group.stored_bytes,
+                        "creation_time": creation_time(log_group),
+                    })
+                })
+                .collect();
+            serde_json::to_string_pretty(&log_groups).unwrap() + "\n"
+        }
+        "table" => {
+            let mut rows = vec![[
+                "LOG GROUP".to_string(),
+                "REGION".to_string(),
+                "RETENTION".to_string(),
+                "STORED".to_string(),
+                "CREATED".to_string(),
+            ]];
+            rows.extend(log_groups.iter().map(|log_group| {
+                [
+                    log_group.log_group_name.clone(),
+                    log_group.region.clone().unwrap_or_default(),
+                    match log_group.retention_in_days {
+                        Some(days) => format!("{} days", days),
+                        None => "forever".to_string(),
+                    },
+                    HumanBytes(log_group.stored_bytes.unwrap_or_default().max(0) as u64).to_string(),
+                    creation_time(log_group).unwrap_or_default(),
+                ]
+            }));
+            let widths: Vec<usize> = (0..5)
+                .map(|column| rows.iter().map(|row| row[column].chars().count()).max().unwrap())
+                .collect();
+            rows.iter()
+                .map(|row| {
+                    let cells: Vec<String> = row
+                        .iter()
+                        .zip(&widths)
+                        .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                        .collect();
+                    cells.join("  ").trim_end().to_string() + "\n"
+                })
+                .collect()
+        }
+        // Log groups in several regions are listed once.
+        _ => {
+            let mut seen = HashSet::new();
+            log_groups
+                .iter()
+                .filter(|log_group| seen.insert(&log_group.log_group_name))
+                .map(|log_group| format!("{}\n", log_group.log_group_name))
+                .collect()
+        }
+    }
+}
+
+/// The proxy from `--proxy-url`, or else from the environment.
+fn proxy_arg(config: &Config) -> Option<ProxyOptions> {
+    match &config.proxy_url {
+        Some(proxy) => Some(Ok(proxy.clone())),
+        None => ProxyOptions::from_env(),
+    }
+    .transpose()
+    .unwrap_or_else(|err| {
+        clap::Error::with_description(&err, clap::ErrorKind::InvalidValue).exit_with(ExitCode::Config)
+    })
+}
+
+/// Where `check-update` and `--notify-updates` look up the latest release.
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/asimihsan/cwl-mount/releases/latest";
+
+/// How long to wait for GitHub when checking for updates.
+const CHECK_UPDATE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// A release of cwl-mount on GitHub.
+struct Release {
+    version: semver::Version,
+
+    /// Page of the release, with its changes.
+    url: String,
+}
+
+fn current_version() -> semver::Version {
+    semver::Version::parse(crate_version!()).expect("the crate version is a valid version")
+}
+
+/// The latest release of cwl-mount, which leaves out drafts and pre-releases.
+async fn latest_release(proxy: Option<ProxyOptions>) -> Result<Release, String> {
+    let request = hyper::Request::get(LATEST_RELEASE_URL)
+        // GitHub rejects requests without a user agent.
+        .header(
+            hyper::header::USER_AGENT,
+            format!("cwl-mount/{}", crate_version!()),
+        )
+        .header(hyper::header::ACCEPT, "application/vnd.github+json")
+        .body(hyper::Body::empty())
+        .unwrap();
+    let (status, body) = tokio::time::timeout(CHECK_UPDATE_TIMEOUT, async {
+        let response = cwl_lib::https_request(request, proxy).await?;
+        let status = response.status();
+        hyper::body::to_bytes(response.into_body())
+            .await
+            .map(|body| (status, body))
+    })
+    .await
+    .map_err(|_| format!("GitHub did not respond within {:?}", CHECK_UPDATE_TIMEOUT))?
+    .map_err(|err| format!("failed to reach GitHub: {}", err))?;
+    if !status.is_success() {
+        return Err(format!("GitHub returned {} for {}", status, LATEST_RELEASE_URL));
+    }
+    let release: serde_json::Value = serde_json::from_slice(&body)
+        .map_err(|err| format!("GitHub returned a release that isn't JSON: {}", err))?;
+    let tag = release["tag_name"]
+        .as_str()
+        .ok_or("GitHub returned a release without a tag")?;
+    let version = semver::Version::parse(tag.trim_start_matches('v'))
+        .map_err(|err| format!("latest release {} isn't a version: {}", tag, err))?;
+    let url = release["html_url"]
+        .as_str()
+        .map(|url| url.to_string())
+        .unwrap_or_else(|| format!("https://github.com/asimihsan/cwl-mount/releases/tag/{}", tag));
+    Ok(Release { version, url })
+}
+
+/// A progress bar on stderr for `len` items in the style of `template`, hidden with `--quiet`. indicatif also hides
+/// it when stderr is not a terminal, so that logs and pipes don't fill up with redraws.
+fn progress_bar(quiet: bool, len: u64, template: &str) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
+    let progress = ProgressBar::new(len);
+    progress.set_style(ProgressStyle::with_template(template).unwrap());
+    progress
+}
+
+/// Like `progress_bar`, for calls that can't tell how far along they are.
+fn progress_spinner(quiet: bool, message: &'static str) -> ProgressBar {
+    if quiet {
+        return ProgressBar::hidden();
+    }
+    let progress = ProgressBar::new_spinner();
+    progress.set_style(ProgressStyle::with_template("{spinner} {msg}, {elapsed} elapsed").unwrap());
+    progress.set_message(message);
+    progress.enable_steady_tick(std::time::Duration::from_millis(100));
+    progress
+}
+
+/// Move `progress` along as the actor reports it.
+fn watch_progress(progress: &ProgressBar) -> watch::Sender<Progress> {
+    let (send, mut recv) = watch::channel(Progress::default());
+    let progress = progress.clone();
+    tokio::spawn(async move {
+        while recv.changed().await.is_ok() {
+            let Progress { done, total } = *recv.borrow();
+            progress.set_length(total as u64);
+            progress.set_position(done as u64);
+        }
+    });
+    send
+}
+
+/// Environment variable to take the disk cache encryption key from when --cache-key-file is not given.
+const CACHE_KEY_ENV_VAR: &str = "CWL_MOUNT_CACHE_KEY";
+
+/// Key to encrypt the disk cache with, from --cache-key-file or the environment.
+fn cache_encryption_key(cache_key_file: Option<&Path>) -> Result<Option<CacheEncryptionKey>, String> {
+    let key = match cache_key_file {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?,
+        None => match std::env::var(CACHE_KEY_ENV_VAR) {
+            Ok(key) => key,
+            Err(_) => return Ok(None),
+        },
+    };
+    CacheEncryptionKey::from_hex(&key).map(Some)
+}
+
 #[tokio::main]
 async fn main() {
-    let matches = App::new("cwl-mount")
-        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
-        .version(crate_version!())
-        .subcommands(vec![
-            SubCommand::with_name("list-log-groups").about("List AWS CloudWatch Logs log groups then quit."),
-            SubCommand::with_name("mount")
-                .about("Mount AWS CloudWatch Logs to a directory.")
-                .arg(
-                    Arg::with_name("mount-point")
-                        .index(1)
-                        .required(true)
-                        .takes_value(true)
-                        .help("Mount the AWS CloudWatch logs at the given directory"),
-                )
-                .arg(
-                    Arg::with_name("log-group-name")
-                        .long("log-group-name")
-                        .takes_value(true)
-                        .validator(regexes::clap_validate_cwl_log_group_name)
-                        .help("CloudWatch Logs log group name"),
-                )
-                .arg(
-                    Arg::with_name("log-group-filter")
-                        .long("log-group-filter")
-                        .takes_value(true)
-                        .validator(regexes::validate_regex)
-                        .help("CloudWatch Logs log group filter, a regular expression"),
-                )
-                .arg(
-                    Arg::with_name("allow-root")
-                        .long("allow-root")
-                        .help("Allow root user to access filesystem"),
-                )
-                .arg(
-                    Arg::with_name("output-format")
-                        .long("output-format")
-                        .takes_value(true)
-                        .default_value("[${log_stream_name}] ${message}")
-                        .validator(format_cwl_log_event::clap_validate_output_format)
-                        .help("Output format string. Valid parameters to use are [log_group_name, event_id, ingestion_time, log_stream_name, message, timestamp]."),
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let program = args
+        .first()
+        .and_then(|program| Path::new(program).file_name())
+        .map(|program| program.to_string_lossy().to_string());
+    if let Some(program) = program.filter(|program| MOUNT_HELPER_NAMES.contains(&program.as_str())) {
+        let helper_args: Vec<String> = args
+            .iter()
+            .skip(1)
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect();
+        args = match mount_helper_args(&Config::clap(), &helper_args) {
+            Ok(Some(args)) => args.into_iter().map(OsString::from).collect(),
+            // A fake mount, which only checks the arguments.
+            Ok(None) => return,
+            Err(err) => {
+                eprintln!("{}: {}", program, err);
+                ExitCode::Config.exit();
+            }
+        };
+    }
+    let config = Config::from_iter_safe(&args).unwrap_or_else(|err| err.exit_with(ExitCode::Config));
+
+    // Need no AWS region or credentials, so they are handled before any are looked up.
+    match &config.command {
+        Command::Completions { shell } => {
+            Config::clap().gen_completions_to("cwl-mount", *shell, &mut std::io::stdout());
+            return;
+        }
+        Command::Healthcheck {
+            mount_point,
+            timeout_secs,
+        } => {
+            let timeout = std::time::Duration::from_secs(*timeout_secs);
+            if let Err(err) = check_health(mount_point, timeout) {
+                clap::Error::with_description(&err, clap::ErrorKind::Io).exit_with(ExitCode::Failure);
+            }
+            println!("ok");
+            return;
+        }
+        Command::Stats {
+            mount_point,
+            timeout_secs,
+        } => {
+            let timeout = std::time::Duration::from_secs(*timeout_secs);
+            let err = match read_mount_file(mount_point, fuse::STATS_FILENAME, timeout) {
+                Ok(Ok(stats)) => {
+                    print!("{}", String::from_utf8_lossy(&stats));
+                    return;
+                }
+                Ok(Err(err)) => format!("failed to read stats of {}: {}", mount_point.display(), err),
+                Err(_) => format!("{} did not respond within {:?}", mount_point.display(), timeout),
+            };
+            clap::Error::with_description(&err, clap::ErrorKind::Io).exit_with(ExitCode::Failure);
+        }
+        Command::Umount {
+            mount_point,
+            pid_file,
+        } => {
+            stop_daemon(&pid_file_arg(pid_file.as_deref(), mount_point), mount_point);
+            return;
+        }
+        Command::GenerateSystemdUnit {
+            mount_point,
+            mount_args,
+            automount,
+            idle_timeout_secs,
+            output_dir,
+        } => {
+            // Global flags are only valid before the subcommand.
+            let subcommand = args
+                .iter()
+                .position(|arg| arg == "generate-systemd-unit")
+                .unwrap();
+            let global_args: Vec<String> = args[1..subcommand]
+                .iter()
+                .map(|arg| arg.to_string_lossy().to_string())
+                .collect();
+            let mount_point = std::path::absolute(mount_point).unwrap_or_else(|_| mount_point.clone());
+            let mount_matches = Config::clap()
+                .get_matches_from_safe(
+                    std::iter::once("cwl-mount".to_string())
+                        .chain(global_args.iter().cloned())
+                        .chain(["mount".to_string(), mount_point.to_string_lossy().to_string()])
+                        .chain(mount_args.iter().cloned()),
                 )
-                .group(
-                    ArgGroup::with_name("log-group-specifiers")
-                        .args(&["log-group-name", "log-group-filter"])
-                        .required(true)
-                        .multiple(false),
+                .unwrap_or_else(|err| err.exit_with(ExitCode::Config));
+            let mount = match Config::from_clap(&mount_matches).command {
+                Command::Mount(mount) => mount,
+                _ => unreachable!("the arguments are those of a mount"),
+            };
+            let program = std::env::current_exe().unwrap_or_else(|err| {
+                eprintln!("Failed to find the cwl-mount executable: {}", err);
+                ExitCode::Failure.exit();
+            });
+            let units = systemd_units(
+                &program,
+                &global_args,
+                &mount_point,
+                mount_args,
+                &mount,
+                &mount_matches,
+                automount.then_some(*idle_timeout_secs),
+            )
+            .unwrap_or_else(|err| {
+                clap::Error::with_description(&err, clap::ErrorKind::InvalidValue).exit_with(ExitCode::Config)
+            });
+            match output_dir {
+                Some(output_dir) => {
+                    for (name, contents) in &units {
+                        let path = output_dir.join(name);
+                        if let Err(err) = std::fs::write(&path, contents) {
+                            eprintln!("Failed to write {}: {}", path.display(), err);
+                            ExitCode::Failure.exit();
+                        }
+                        println!("{}", path.display());
+                    }
+                    // The automount unit is the one enabled, which pulls in the mount unit on first access.
+                    eprintln!(
+                        "Run systemctl daemon-reload && systemctl enable --now {} to start it now and after reboots",
+                        units.last().unwrap().0
+                    );
+                }
+                None => {
+                    for (i, (name, contents)) in units.iter().enumerate() {
+                        if i > 0 {
+                            println!();
+                        }
+                        println!("# /etc/systemd/system/{}", name);
+                        print!("{}", contents);
+                    }
+                }
+            }
+            return;
+        }
+        Command::CheckUpdate => {
+            let current = current_version();
+            match latest_release(proxy_arg(&config)).await {
+                Ok(release) if release.version > current => println!(
+                    "cwl-mount {} is available, this is {}. Changes: {}",
+                    release.version, current, release.url
                 ),
-        ])
-        .arg(
-            Arg::with_name("verbose")
-                .long("verbose")
-                .short("v")
-                .multiple(true)
-                .help("Verbose output. Set three times for maximum verbosity."),
-        )
-        .arg(
-            Arg::with_name("region")
-                .long("region")
-                .required(true)
-                .takes_value(true)
-                .help("AWS region, e.g. 'us-west-2'"),
-        )
-        .arg(
-            Arg::with_name("tps")
-                .long("tps")
-                .takes_value(true)
-                .validator(is_valid_tps)
-                .default_value("5")
-                .help("Transactions per second (TPS) at which to call AWS CloudWatch Logs."),
-        )
-        .get_matches();
+                Ok(release) if release.version < current => println!(
+                    "cwl-mount {} is newer than the latest release, {}. Changes: {}",
+                    current, release.version, release.url
+                ),
+                Ok(release) => println!(
+                    "cwl-mount {} is the latest release. Changes: {}",
+                    current, release.url
+                ),
+                Err(err) => {
+                    eprintln!("Failed to check for updates: {}", err);
+                    ExitCode::Failure.exit();
+                }
+            }
+            return;
+        }
+        Command::CacheClear {
+            mount_point,
+            pid_file,
+        } => {
+            clear_daemon_cache(&pid_file_arg(pid_file.as_deref(), mount_point));
+            return;
+        }
+        Command::Bench {
+            log_groups,
+            window,
+            end_time,
+            mock_events: Some(events),
+        } => {
+            let end_time = end_time.unwrap_or_else(Utc::now);
+            let start_time = end_time - *window;
+            let log_group_name = log_groups
+                .log_group_name
+                .as_deref()
+                .expect("clap requires --log-group-name with --mock-events");
+            // As many events per page as FilterLogEvents returns at most.
+            let fake = FakeCloudWatchLogs::new(Some("mock")).with_page_size(10_000);
+            fake.create_log_group(log_group_name);
+            let step = (end_time - start_time).num_milliseconds() / (*events).max(1) as i64;
+            for index in 0..*events {
+                fake.put_log_event(
+                    log_group_name,
+                    "bench",
+                    start_time + Duration::milliseconds(step * index as i64),
+                    format!("bench event {}", index),
+                );
+            }
+            let cwl = CloudWatchLogsActorHandle::new(fake);
+            let report = run_bench(
+                &cwl,
+                Some(log_group_name.to_string()),
+                None,
+                start_time,
+                end_time,
+                None,
+            )
+            .await;
+            match report {
+                Ok(report) => print!("{}", report),
+                Err(err) => clap::Error::with_description(&err.to_string(), clap::ErrorKind::Io)
+                    .exit_with(ExitCode::Failure),
+            }
+            return;
+        }
+        _ => {}
+    }
+    let daemonized = std::env::var_os(DAEMON_ENV_VAR).is_some();
 
-    let region = matches.value_of("region");
-    let tps = matches.value_of("tps").unwrap().parse::<usize>().unwrap();
-    let tracing_level = match matches.occurrences_of("verbose") {
+    let (regions, region_source): (Vec<String>, &str) = if config.all_regions {
+        let regions = config
+            .partition
+            .regions()
+            .iter()
+            .map(|region| region.to_string())
+            .collect();
+        (regions, "--all-regions")
+    } else if !config.region.is_empty() {
+        (config.region.clone(), "--region")
+    } else {
+        let no_region = "no AWS region given, pass --region or --all-regions, set AWS_REGION or AWS_DEFAULT_REGION, or set a region in the profile";
+        match default_region(config.profile.as_deref()).await {
+            Some(region) => (vec![region], "the environment or profile"),
+            None if matches!(config.command, Command::Doctor { .. }) => {
+                let mut doctor = Doctor::default();
+                doctor.report("region", Err(no_region.to_string()));
+                doctor.check_fuse();
+                ExitCode::Config.exit();
+            }
+            None => clap::Error::with_description(no_region, clap::ErrorKind::MissingRequiredArgument)
+                .exit_with(ExitCode::Config),
+        }
+    };
+    if let Err(err) = check_partition(&regions, config.use_fips) {
+        clap::Error::with_description(&err, clap::ErrorKind::InvalidValue).exit_with(ExitCode::Config);
+    }
+    let daemon = match &config.command {
+        Command::Mount(mount) if mount.daemon => Some(mount),
+        _ => None,
+    };
+    if let Command::Mount(mount) = &config.command {
+        if mount.log_group_name.is_empty()
+            && mount.log_group_filter.is_none()
+            && (daemon.is_some() || !std::io::stdin().is_terminal())
+        {
+            clap::Error::with_description(
+                "pass --log-group-name or --log-group-filter, or mount from a terminal without --daemon to pick log groups",
+                clap::ErrorKind::MissingRequiredArgument,
+            )
+            .exit_with(ExitCode::Config);
+        }
+    }
+    if let Command::CacheWarm {
+        layout, granularity, ..
+    } = &config.command
+    {
+        if config.cache_dir.is_none() {
+            clap::Error::with_description(
+                "cache-warm fills the disk cache, pass --cache-dir with the directory that mounts will use",
+                clap::ErrorKind::MissingRequiredArgument,
+            )
+            .exit_with(ExitCode::Config);
+        }
+        if granularity == "hour" && *layout == Layout::LogGroup {
+            clap::Error::with_description(
+                "--granularity hour fetches what cat reads, which merges log groups, so it cannot be combined with --layout log-group",
+                clap::ErrorKind::ArgumentConflict,
+            )
+            .exit_with(ExitCode::Config);
+        }
+    }
+    if let Some(daemon) = daemon.filter(|_| !daemonized) {
+        let logs = config
+            .log_file
+            .clone()
+            .unwrap_or_else(|| log_dir_arg(daemon.log_dir.as_deref()));
+        start_daemon(
+            &args,
+            &pid_file_arg(daemon.pid_file.as_deref(), &daemon.mount_point),
+            &logs,
+        );
+    }
+    let proxy = proxy_arg(&config);
+    let tracing_level = match config.verbose {
         0 => Level::WARN,
         1 => Level::INFO,
         2 => Level::DEBUG,
         _ => Level::TRACE,
     };
-    let subscriber = FmtSubscriber::builder().with_max_level(tracing_level).finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
-    let cwl = CloudWatchLogsImpl::new(tps, region).await;
+    let (writer, guard) = match (&config.log_file, daemon.filter(|_| daemonized)) {
+        (Some(log_file), _) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file)
+                .unwrap_or_else(|err| {
+                    clap::Error::with_description(
+                        &format!("failed to open log file {}: {}", log_file.display(), err),
+                        clap::ErrorKind::Io,
+                    )
+                    .exit_with(ExitCode::Failure)
+                });
+            let (writer, guard) = tracing_appender::non_blocking(file);
+            (BoxMakeWriter::new(writer), Some(guard))
+        }
+        (None, Some(daemon)) => {
+            let (writer, guard) = tracing_appender::non_blocking(tracing_appender::rolling::daily(
+                log_dir_arg(daemon.log_dir.as_deref()),
+                "cwl-mount.log",
+            ));
+            (BoxMakeWriter::new(writer), Some(guard))
+        }
+        (None, None) => (BoxMakeWriter::new(std::io::stdout), None),
+    };
+    let ansi = guard.is_none();
+    if let Some(guard) = guard {
+        *LOG_GUARD.lock().unwrap() = Some(guard);
+        unsafe {
+            libc::atexit(flush_logs);
+        }
+    }
+    let builder = FmtSubscriber::builder()
+        .with_max_level(tracing_level)
+        .with_ansi(ansi)
+        .with_writer(writer);
+    match config.log_format.as_str() {
+        "json" => tracing::subscriber::set_global_default(builder.json().finish()),
+        _ => tracing::subscriber::set_global_default(builder.finish()),
+    }
+    .expect("setting default subscriber failed");
+    if config.notify_updates {
+        let proxy = proxy.clone();
+        tokio::spawn(async move {
+            let current = current_version();
+            match latest_release(proxy).await {
+                Ok(release) if release.version > current => warn!(
+                    "cwl-mount {} is available, this is {}. Changes: {}",
+                    release.version, current, release.url
+                ),
+                Ok(release) if release.version < current => info!(
+                    "cwl-mount {} is newer than the latest release, {}",
+                    current, release.version
+                ),
+                Ok(_) => info!("cwl-mount {} is the latest release", current),
+                Err(err) => info!("Failed to check for updates: {}", err),
+            }
+        });
+    }
+    let encryption_key = cache_encryption_key(config.cache_key_file.as_deref()).unwrap_or_else(|err| {
+        clap::Error::with_description(&err, clap::ErrorKind::InvalidValue).exit_with(ExitCode::Config)
+    });
+    let cwl = CloudWatchLogsActorHandle::with_options(
+        CloudWatchLogsImpl::new_for_regions(config.client_options(proxy), regions.clone()).await,
+        config.actor_options(encryption_key),
+    );
+    let account_ids = log_group_arn_accounts(&config.command.log_group_names(), &cwl).await;
 
-    match matches.subcommand() {
-        ("list-log-groups", _matches) => {
+    let quiet = config.quiet;
+    match config.command {
+        Command::ListLogGroups {
+            prefix,
+            filter,
+            output,
+        } => {
             info!("listing log groups...");
-            match cwl.get_log_group_names().await {
-                Ok(log_group_names) => print!("{}", log_group_names.join("\n")),
+            let matcher = filter.as_deref().map(LogGroupNameMatcher::new);
+            // Only log groups that start with the filter's literal prefix can match it.
+            let prefix = prefix.or_else(|| matcher.as_ref().and_then(|matcher| matcher.literal_prefix()));
+            let progress = progress_spinner(quiet, "listing log groups");
+            let result = cwl.describe_log_groups(prefix).await;
+            progress.finish_and_clear();
+            match result {
+                Ok(log_groups) => {
+                    let log_groups: Vec<_> = log_groups
+                        .into_iter()
+                        .filter(|log_group| {
+                            matcher
+                                .as_ref()
+                                .is_none_or(|matcher| matcher.is_match(&log_group.log_group_name))
+                        })
+                        .collect();
+                    print!("{}", render_log_groups(&log_groups, &output));
+                }
+                Err(err) => {
+                    error!("Failed to list log groups: {:?}{}", err, credentials_hint(&err));
+                    ExitCode::for_error(&err).exit();
+                }
+            }
+        }
+        Command::ListMetricFilters(log_groups) => {
+            let progress = progress_bar(
+                quiet,
+                0,
+                "{wide_bar} {pos}/{len} log groups, {elapsed} elapsed, eta {eta}",
+            );
+            let result = cwl
+                .describe_metric_filters(
+                    log_group_name_arg(log_groups.log_group_name.as_deref(), &regions, &account_ids),
+                    log_groups.log_group_filter,
+                    Some(watch_progress(&progress)),
+                )
+                .await;
+            progress.finish_and_clear();
+            match result {
+                Ok(metric_filters) => {
+                    for metric_filter in metric_filters {
+                        let metrics = metric_filter
+                            .metric_transformations
+                            .iter()
+                            .map(|transformation| {
+                                format!(
+                                    "{}/{}",
+                                    transformation.metric_namespace, transformation.metric_name
+                                )
+                            })
+                            .collect::<Vec<_>>()
+                            .join(",");
+                        println!(
+                            "{}\t{}\t{}\t{}\t{}",
+                            metric_filter.log_group_name,
+                            metric_filter.region.unwrap_or_default(),
+                            metric_filter.filter_name,
+                            metrics,
+                            metric_filter.filter_pattern
+                        );
+                    }
+                }
+                Err(err) => {
+                    error!("Failed to list metric filters: {}", err);
+                    ExitCode::for_error(&err).exit();
+                }
+            }
+        }
+        Command::ListSubscriptionFilters(log_groups) => {
+            let progress = progress_bar(
+                quiet,
+                0,
+                "{wide_bar} {pos}/{len} log groups, {elapsed} elapsed, eta {eta}",
+            );
+            let result = cwl
+                .describe_subscription_filters(
+                    log_group_name_arg(log_groups.log_group_name.as_deref(), &regions, &account_ids),
+                    log_groups.log_group_filter,
+                    Some(watch_progress(&progress)),
+                )
+                .await;
+            progress.finish_and_clear();
+            match result {
+                Ok(subscription_filters) => {
+                    for subscription_filter in subscription_filters {
+                        println!(
+                            "{}\t{}\t{}\t{}\t{}",
+                            subscription_filter.log_group_name,
+                            subscription_filter.region.unwrap_or_default(),
+                            subscription_filter.filter_name,
+                            subscription_filter.destination_arn.unwrap_or_default(),
+                            subscription_filter.filter_pattern
+                        );
+                    }
+                }
+                Err(err) => {
+                    error!("Failed to list subscription filters: {}", err);
+                    ExitCode::for_error(&err).exit();
+                }
+            }
+        }
+        Command::ListQueries { name_prefix } => {
+            let result = cwl.describe_query_definitions(name_prefix).await;
+            match result {
+                Ok(query_definitions) => {
+                    for query_definition in query_definitions {
+                        println!(
+                            "{}\t{}\t{}\t{}",
+                            query_definition.name,
+                            query_definition.region.unwrap_or_default(),
+                            query_definition.log_group_names.join(","),
+                            query_definition.query_string.replace('\n', " ")
+                        );
+                    }
+                }
+                Err(err) => {
+                    error!("Failed to list saved queries: {}{}", err, credentials_hint(&err));
+                    ExitCode::for_error(&err).exit();
+                }
+            }
+        }
+        Command::ExportS3 {
+            log_groups,
+            bucket,
+            prefix,
+            start_time,
+            end_time,
+        } => {
+            let end_time = end_time.unwrap_or_else(Utc::now);
+            info!("exporting to S3, this can take a while...");
+            let result = cwl
+                .export_to_s3(
+                    log_group_name_arg(log_groups.log_group_name.as_deref(), &regions, &account_ids),
+                    log_groups.log_group_filter,
+                    start_time,
+                    end_time,
+                    bucket,
+                    prefix,
+                )
+                .await;
+            match result {
+                Ok(export_tasks) => {
+                    for export_task in export_tasks {
+                        println!(
+                            "{}\t{}\t{}\t{:?}",
+                            export_task.log_group_name,
+                            export_task.region.unwrap_or_default(),
+                            export_task.task_id,
+                            export_task.status
+                        );
+                    }
+                }
+                Err(err) => {
+                    error!("Failed to export to S3: {}", err);
+                    ExitCode::for_error(&err).exit();
+                }
+            }
+        }
+        Command::Doctor {
+            log_group_name,
+            log_group_filter,
+            log_group_exclude,
+        } => {
+            let mut doctor = Doctor::default();
+            doctor.report(
+                "region",
+                Ok(format!("{} from {}", regions.join(", "), region_source)),
+            );
+            doctor
+                .check_aws(
+                    &cwl,
+                    log_group_name.as_deref(),
+                    log_group_filter.as_deref(),
+                    log_group_exclude.as_deref(),
+                    &regions,
+                    &account_ids,
+                )
+                .await;
+            doctor.check_fuse();
+            if doctor.failures > 0 {
+                ExitCode::Failure.exit();
+            }
+        }
+        Command::Tail {
+            log_groups,
+            event_filter,
+            since,
+            output_format,
+        } => {
+            let formatter = output_format.output_format;
+            let result = cwl
+                .live_tail(
+                    log_group_name_arg(log_groups.log_group_name.as_deref(), &regions, &account_ids),
+                    log_groups.log_group_filter,
+                    event_filter.event_filter(),
+                    since,
+                )
+                .await;
+            match result {
+                Ok(mut receiver) => {
+                    let stdout = std::io::stdout();
+                    while let Some(result) = receiver.recv().await {
+                        match result {
+                            Ok(event) => {
+                                // Stop once nobody reads the output any more, e.g. `tail | head`.
+                                if writeln!(stdout.lock(), "{}", formatter.format(event)).is_err() {
+                                    break;
+                                }
+                            }
+                            // Polling carries on, so errors like throttling are only worth a warning.
+                            Err(err) => {
+                                warn!("failed to poll for new events: {}{}", err, credentials_hint(&err))
+                            }
+                        }
+                    }
+                }
+                Err(err) => {
+                    error!("Failed to tail log groups: {}{}", err, credentials_hint(&err));
+                    ExitCode::for_error(&err).exit();
+                }
+            }
+        }
+        Command::Cat {
+            log_groups,
+            start_time,
+            end_time,
+            event_filter,
+            output_format,
+        } => {
+            let formatter = output_format.output_format;
+            let end_time = end_time.unwrap_or_else(Utc::now);
+            let log_group_name =
+                log_group_name_arg(log_groups.log_group_name.as_deref(), &regions, &account_ids);
+            let log_group_filter = log_groups.log_group_filter;
+            let event_filter = event_filter.event_filter();
+            let fetch = |(start_time, end_time): (DateTime<Utc>, DateTime<Utc>)| {
+                let cwl = cwl.clone();
+                let log_group_name = log_group_name.clone();
+                let log_group_filter = log_group_filter.clone();
+                let event_filter = event_filter.clone();
+                let formatter = formatter.clone();
+                tokio::spawn(async move {
+                    cwl.get_logs_to_display(
+                        log_group_name,
+                        log_group_filter,
+                        start_time,
+                        end_time,
+                        event_filter,
+                        formatter,
+                    )
+                    .await
+                })
+            };
+
+            // Fetch the next window while printing the current one.
+            let windows = time_windows(start_time, end_time, Duration::hours(CAT_WINDOW_HOURS));
+            let mut next = windows.first().copied().map(fetch);
+            let stdout = std::io::stdout();
+            for index in 0..windows.len() {
+                let current = next.take().expect("there is a fetch for every window");
+                next = windows.get(index + 1).copied().map(fetch);
+                match current.await.expect("fetch task does not panic") {
+                    Ok(data) if data.is_empty() => {}
+                    Ok(data) => {
+                        let mut stdout = stdout.lock();
+                        if stdout
+                            .write_all(&data)
+                            .and_then(|_| stdout.write_all(b"\n"))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        error!("Failed to read log groups: {}{}", err, credentials_hint(&err));
+                        ExitCode::for_error(&err).exit();
+                    }
+                }
+            }
+        }
+        Command::Export {
+            dest,
+            log_groups,
+            start_time,
+            end_time,
+            event_filter,
+            output_format,
+            layout,
+            gzip,
+        } => {
+            let formatter = output_format.output_format;
+            let end_time = end_time.unwrap_or_else(Utc::now);
+            let log_group_name =
+                log_group_name_arg(log_groups.log_group_name.as_deref(), &regions, &account_ids);
+            let log_group_filter = log_groups.log_group_filter;
+            let log_groups = LogGroups::new(
+                log_group_name.as_deref(),
+                log_group_filter.as_deref(),
+                log_groups.log_group_exclude.as_deref(),
+            )
+            .expect("clap requires exactly one of a log group name and a filter");
+            let event_filter = event_filter.event_filter();
+            let file_tree = match prepare_file_tree(&cwl, layout, log_groups, start_time, end_time).await {
+                Ok(file_tree) => file_tree,
                 Err(err) => {
-                    error!("Failed to list log groups: {:?}", err);
+                    error!("Failed to list log groups: {}{}", err, credentials_hint(&err));
+                    ExitCode::for_error(&err).exit();
+                }
+            };
+            let files: Vec<_> = file_tree
+                .files_in_time_range(start_time, end_time)
+                .into_iter()
+                .filter_map(|(path, file)| match file.file.file_type {
+                    fuse::FileType::File(time_bounds) => match file_tree.get_log_group_name(file.file_key) {
+                        Some(log_group_name) => Some((path, Some(log_group_name), None, time_bounds)),
+                        None => Some((
+                            path,
+                            log_group_name.clone(),
+                            log_group_filter.clone(),
+                            time_bounds,
+                        )),
+                    },
+                    fuse::FileType::Directory => None,
+                })
+                .collect();
+
+            let progress = progress_bar(
+                quiet,
+                files.len() as u64,
+                "{wide_bar} {pos}/{len} files, {elapsed} elapsed, eta {eta}",
+            );
+            let mut in_flight = VecDeque::new();
+            let mut written = 0;
+            let mut failed = 0;
+            let mut files = files.into_iter();
+            loop {
+                while in_flight.len() < EXPORT_CONCURRENCY {
+                    let (path, log_group_name, log_group_filter, time_bounds) = match files.next() {
+                        Some(file) => file,
+                        None => break,
+                    };
+                    let cwl = cwl.clone();
+                    let event_filter = event_filter.clone();
+                    let formatter = formatter.clone();
+                    let dest = dest.join(&path);
+                    in_flight.push_back((
+                        path,
+                        tokio::spawn(async move {
+                            let data = cwl
+                                .get_logs_to_display(
+                                    log_group_name,
+                                    log_group_filter,
+                                    time_bounds.start_time,
+                                    time_bounds.end_time,
+                                    event_filter,
+                                    formatter,
+                                )
+                                .await
+                                .map_err(|err| format!("{}{}", err, credentials_hint(&err)))?;
+                            if data.is_empty() {
+                                return Ok(false);
+                            }
+                            tokio::task::spawn_blocking(move || write_export_file(&dest, &data, gzip))
+                                .await
+                                .expect("writing a file does not panic")
+                                .map(|_| true)
+                                .map_err(|err| err.to_string())
+                        }),
+                    ));
+                }
+                let (path, task) = match in_flight.pop_front() {
+                    Some(file) => file,
+                    None => break,
+                };
+                match task.await.expect("export task does not panic") {
+                    Ok(true) => written += 1,
+                    Ok(false) => {}
+                    Err(err) => {
+                        // Carry on, so that one bad file does not lose the rest of the export.
+                        progress.suspend(|| error!("Failed to export {}: {}", path, err));
+                        failed += 1;
+                    }
+                }
+                progress.inc(1);
+            }
+            progress.finish_and_clear();
+            info!("exported {} files with events to {}", written, dest.display());
+            if failed > 0 {
+                error!("Failed to export {} files, see the errors above", failed);
+                ExitCode::Failure.exit();
+            }
+        }
+        Command::Bench {
+            log_groups,
+            window,
+            end_time,
+            ..
+        } => {
+            let end_time = end_time.unwrap_or_else(Utc::now);
+            match run_bench(
+                &cwl,
+                log_group_name_arg(log_groups.log_group_name.as_deref(), &regions, &account_ids),
+                log_groups.log_group_filter,
+                end_time - window,
+                end_time,
+                Some(config.tps),
+            )
+            .await
+            {
+                Ok(report) => print!("{}", report),
+                Err(err) => {
+                    error!("Failed to read log groups: {}{}", err, credentials_hint(&err));
+                    ExitCode::for_error(&err).exit();
+                }
+            }
+        }
+        Command::ServeGrpc { listen } => {
+            let listener = std::net::TcpListener::bind(listen).unwrap_or_else(|err| {
+                clap::Error::with_description(
+                    &format!("failed to listen on {}: {}", listen, err),
+                    clap::ErrorKind::Io,
+                )
+                .exit_with(ExitCode::Failure)
+            });
+            let shutdown = async {
+                let mut terminate = signal(SignalKind::terminate()).unwrap();
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
                 }
+                info!("stopping");
+            };
+            if let Err(err) = serve_grpc(cwl.clone(), listener, shutdown).await {
+                error!("Failed to serve gRPC: {}", err);
+                ExitCode::Failure.exit();
             }
         }
-        (_, matches) => {
+        Command::CacheWarm {
+            log_groups,
+            start_time,
+            end_time,
+            event_filter,
+            output_format,
+            layout,
+            granularity,
+        } => {
+            let formatter = output_format.output_format;
+            let end_time = end_time.unwrap_or_else(Utc::now);
+            let log_group_name =
+                log_group_name_arg(log_groups.log_group_name.as_deref(), &regions, &account_ids);
+            let log_group_filter = log_groups.log_group_filter;
+            let log_groups = LogGroups::new(
+                log_group_name.as_deref(),
+                log_group_filter.as_deref(),
+                log_groups.log_group_exclude.as_deref(),
+            )
+            .expect("clap requires exactly one of a log group name and a filter");
+            let event_filter = event_filter.event_filter();
+
+            // The cache is keyed by each file's exact time range and log groups, so fetch the same files that a
+            // mount or cat would.
+            let files: Vec<_> = if granularity == "hour" {
+                time_windows(start_time, end_time, Duration::hours(CAT_WINDOW_HOURS))
+                    .into_iter()
+                    .map(|(start_time, end_time)| {
+                        (
+                            log_group_name.clone(),
+                            log_group_filter.clone(),
+                            start_time,
+                            end_time,
+                        )
+                    })
+                    .collect()
+            } else {
+                let file_tree = match prepare_file_tree(&cwl, layout, log_groups, start_time, end_time).await
+                {
+                    Ok(file_tree) => file_tree,
+                    Err(err) => {
+                        error!("Failed to list log groups: {}{}", err, credentials_hint(&err));
+                        ExitCode::for_error(&err).exit();
+                    }
+                };
+                file_tree
+                    .files_in_time_range(start_time, end_time)
+                    .into_iter()
+                    .filter_map(|(_path, file)| match file.file.file_type {
+                        fuse::FileType::File(time_bounds) => {
+                            let (log_group_name, log_group_filter) =
+                                match file_tree.get_log_group_name(file.file_key) {
+                                    Some(log_group_name) => (Some(log_group_name), None),
+                                    None => (log_group_name.clone(), log_group_filter.clone()),
+                                };
+                            Some((
+                                log_group_name,
+                                log_group_filter,
+                                time_bounds.start_time,
+                                time_bounds.end_time,
+                            ))
+                        }
+                        fuse::FileType::Directory => None,
+                    })
+                    .collect()
+            };
+
+            let progress = progress_bar(
+                quiet,
+                files.len() as u64,
+                "{wide_bar} {pos}/{len} files, {elapsed} elapsed, eta {eta}",
+            );
+            let mut in_flight = VecDeque::new();
+            let mut fetched = 0;
+            let mut already_cached = 0;
+            let mut failed = 0;
+            let mut files = files.into_iter();
+            loop {
+                while in_flight.len() < EXPORT_CONCURRENCY {
+                    let (log_group_name, log_group_filter, start_time, end_time) = match files.next() {
+                        Some(file) => file,
+                        None => break,
+                    };
+                    let cwl = cwl.clone();
+                    let event_filter = event_filter.clone();
+                    let formatter = formatter.clone();
+                    let log_groups = log_group_name
+                        .clone()
+                        .or_else(|| log_group_filter.clone())
+                        .unwrap_or_default();
+                    in_flight.push_back((
+                        (log_groups, start_time),
+                        tokio::spawn(async move {
+                            cwl.get_log_page(
+                                log_group_name,
+                                log_group_filter,
+                                start_time,
+                                end_time,
+                                event_filter,
+                                formatter,
+                            )
+                            .await
+                        }),
+                    ));
+                }
+                let ((log_groups, start_time), task) = match in_flight.pop_front() {
+                    Some(file) => file,
+                    None => break,
+                };
+                match task.await.expect("cache-warm task does not panic") {
+                    Ok(page) if page.cached => already_cached += 1,
+                    Ok(_) => fetched += 1,
+                    Err(err) => {
+                        // Carry on, so that one bad file does not stop the rest from being cached.
+                        progress.suspend(|| {
+                            error!(
+                                "Failed to fetch {} from {}: {}{}",
+                                log_groups,
+                                start_time.to_rfc3339(),
+                                err,
+                                credentials_hint(&err)
+                            )
+                        });
+                        failed += 1;
+                    }
+                }
+                progress.inc(1);
+            }
+            progress.finish_and_clear();
+            info!(
+                "fetched {} files, {} were already cached",
+                fetched, already_cached
+            );
+            if failed > 0 {
+                error!("Failed to fetch {} files, see the errors above", failed);
+                ExitCode::Failure.exit();
+            }
+        }
+        Command::Mount(ref mount) => {
             info!("mounting...");
-            let matches = matches.unwrap();
-            let log_group_name = matches.value_of("log-group-name");
-            let log_group_filter = matches.value_of("log-group-filter");
-            let output_format = matches.value_of("output-format").unwrap();
-            let formatter = format_cwl_log_event::LogFormatter::new(output_format).unwrap();
-            let mountpoint = matches.value_of("mount-point").unwrap();
-            let mut options = vec![MountOption::RO, MountOption::FSName("hello".to_string())];
-            if matches.is_present("allow-root") {
+            let mountpoint = &mount.mount_point;
+            let log_group_names = log_group_names_arg(&mount.log_group_name, &regions, &account_ids);
+            // Several log groups named on their own would be hard to tell apart merged into the same files.
+            let layout = mount.layout.unwrap_or(match log_group_names.len() {
+                0 | 1 => Layout::Flat,
+                _ => Layout::LogGroup,
+            });
+            let insights_window = Duration::minutes(mount.insights_window_minutes);
+            // TODO use CloudWatch actor to get this start time
+            // let start_time = cwl
+            //     .get_first_event_time_for_log_group(log_group_name.into())
+            //     .await
+            //     .unwrap_or(Some(default_start_time))
+            //     .unwrap_or(default_start_time);
+
+            // By default whole years, so that minutes after the mount started have files too.
+            let now = Utc::now();
+            let start_time = mount
+                .start_time
+                .unwrap_or_else(|| Utc.ymd((now - Duration::days(365)).year(), 1, 1).and_hms(0, 0, 0));
+            let end_time = mount
+                .end_time
+                .unwrap_or_else(|| Utc.ymd(now.year() + 1, 1, 1).and_hms(0, 0, 0));
+            if start_time >= end_time {
+                clap::Error::with_description(
+                    "--start-time must be before --end-time",
+                    clap::ErrorKind::InvalidValue,
+                )
+                .exit_with(ExitCode::Config);
+            }
+            // Not read-only by default, because queries are written to insights/new.
+            let mut options: Vec<MountOption> = vec![];
+            if mount.allow_root {
                 options.push(MountOption::AllowRoot);
             }
+            if mount.allow_other {
+                options.push(MountOption::AllowOther);
+            }
+            for option in &mount.mount_options {
+                if !options.contains(option) {
+                    options.push(option.clone());
+                }
+            }
+            if !options
+                .iter()
+                .any(|option| matches!(option, MountOption::FSName(_)))
+            {
+                options.push(MountOption::FSName("cwl-mount".to_string()));
+            }
+            if let Some((a, b)) = conflicting_mount_options(&options) {
+                clap::Error::with_description(
+                    &format!("mount options {:?} and {:?} conflict", a, b),
+                    clap::ErrorKind::ArgumentConflict,
+                )
+                .exit_with(ExitCode::Config);
+            }
+            // Users on the host are not the user in the container, so they can only read a shared mount with
+            // allow_other.
+            if running_in_container()
+                && !options.contains(&MountOption::AllowRoot)
+                && !options.contains(&MountOption::AllowOther)
+            {
+                if unsafe { libc::geteuid() } == 0 {
+                    info!("running as root in a container, mounting with allow_other");
+                    options.push(MountOption::AllowOther);
+                } else {
+                    warn!(
+                        "running in a container, pass --allow-other so that users outside the container can read \
+                         the mount"
+                    );
+                }
+            }
+            if running_in_container() && mount_hidden_from_host(mountpoint) {
+                warn!(
+                    "{} is on a volume without shared propagation, so the mount is only seen inside the container. \
+                     To see it on the host, mount the volume with e.g. `docker run --mount \
+                     type=bind,source=/mnt/logs,target=/mnt/logs,bind-propagation=rshared`",
+                    mountpoint.display()
+                );
+            }
+            // Both are mounted with the allow_other FUSE option.
+            if options.contains(&MountOption::AllowRoot) || options.contains(&MountOption::AllowOther) {
+                if let Err(err) = check_user_allow_other() {
+                    clap::Error::with_description(&err, clap::ErrorKind::InvalidValue)
+                        .exit_with(ExitCode::Config);
+                }
+            }
+            if !mount.skip_preflight {
+                match cwl.preflight().await {
+                    Ok(reports) => {
+                        for report in reports {
+                            info!(
+                                "using credentials from {} in region {}{}",
+                                report.credentials_source,
+                                report.region.as_deref().unwrap_or_default(),
+                                report
+                                    .credentials_expiry
+                                    .map(|expiry| format!(", expiring at {}", expiry.to_rfc3339()))
+                                    .unwrap_or_default()
+                            );
+                        }
+                    }
+                    Err(err) => {
+                        error!(
+                            "Preflight check failed, credentials must load and allow logs:DescribeLogGroups: {:?}{}",
+                            err,
+                            credentials_hint(&err)
+                        );
+                        ExitCode::for_error(&err).exit();
+                    }
+                }
+            }
+            // The actor reads several log groups through a filter that matches exactly them.
+            let (log_group_name, log_group_filter) =
+                match (log_group_names.as_slice(), mount.log_group_filter.as_deref()) {
+                    ([], None) => pick_log_groups(&cwl).await,
+                    ([], Some(log_group_filter)) => (None, Some(log_group_filter.to_string())),
+                    ([log_group_name], _) => (Some(log_group_name.clone()), None),
+                    (log_group_names, _) => {
+                        (None, Some(regexes::exact_log_group_names_filter(log_group_names)))
+                    }
+                };
+            if !mount.skip_preflight {
+                match preflight_read(
+                    &cwl,
+                    log_group_name.as_deref(),
+                    log_group_filter.as_deref(),
+                    mount.log_group_exclude.as_deref(),
+                )
+                .await
+                {
+                    Ok(log_group_name) => info!("preflight read {}", log_group_name),
+                    Err((code, err)) => {
+                        error!("Preflight check failed, {}", err);
+                        code.exit();
+                    }
+                }
+            }
 
-            let file_tree = Arc::new(prepare_file_tree(&cwl).await);
-            let hello_fs = HelloFS::new(
-                Handle::current(),
-                cwl,
+            let mount_config = MountConfig {
+                mount_point: mountpoint.clone(),
+                mount_options: options,
+                layout,
                 log_group_name,
                 log_group_filter,
-                file_tree,
-                formatter,
-            );
+                log_group_exclude: mount.log_group_exclude.clone(),
+                start_time,
+                end_time,
+                event_filter: mount.event_filter.event_filter(),
+                formatter: mount.output_format.output_format.clone(),
+                insights_window,
+                readahead_windows: mount.readahead_windows,
+                max_file_size: config.max_file_size(),
+                uid: mount.uid,
+                gid: mount.gid,
+                // The pid file tells the process that started the daemon that the mount succeeded.
+                pid_file: daemon.map(|daemon| pid_file_arg(daemon.pid_file.as_deref(), &daemon.mount_point)),
+            };
 
-            // See: https://github.com/cberner/fuser/issues/179
-            let (send, recv) = std::sync::mpsc::channel();
+            // SIGUSR2 drops cached files without remounting, e.g. after CloudWatch Logs ingested events late.
+            let clear_cache_cwl = cwl.clone();
+            tokio::spawn(async move {
+                let mut signals = match signal(SignalKind::user_defined2()) {
+                    Ok(signals) => signals,
+                    Err(err) => {
+                        warn!("cannot clear the cache on SIGUSR2: {}", err);
+                        return;
+                    }
+                };
+                while signals.recv().await.is_some() {
+                    info!("SIGUSR2 received, clearing the cache");
+                    if let Err(err) = clear_cache_cwl.clear_cache().await {
+                        error!("Failed to clear the cache: {}", err);
+                    }
+                }
+            });
+            if is_stale_mount(mountpoint) {
+                warn!(
+                    "{} is left over from a mount that crashed, unmounting it",
+                    mountpoint.display()
+                );
+                if let Err(err) = force_unmount(mountpoint) {
+                    error!("Failed to mount: {}", err);
+                    ExitCode::MountFailed.exit();
+                }
+            }
+            // Stops cwl-mount on CTRL-C or SIGTERM, as well as when unmounted.
+            let (stop, mut stopped) = tokio::sync::mpsc::unbounded_channel();
             ctrlc::set_handler(move || {
                 info!("CTRL-C pressed");
-                send.send(()).unwrap();
+                let _ = stop.send(());
             })
             .unwrap();
-            info!("starting...");
-            let _guard = fuser::spawn_mount(hello_fs, mountpoint, &vec![]).unwrap();
-            let () = recv.recv().unwrap();
+            // Logged rather than unwrapped so that a daemon, whose stderr goes nowhere, says why it failed.
+            if let Err(err) = run_mount(cwl, mount_config, async move {
+                stopped.recv().await;
+            })
+            .await
+            {
+                error!("Failed to mount: {}", err);
+                match err {
+                    MountError::NoLogGroups | MountError::InvalidTimeRange => ExitCode::Config.exit(),
+                    MountError::Mount(..) | MountError::PidFile(..) => ExitCode::MountFailed.exit(),
+                    MountError::LogGroups(err) => ExitCode::for_error(&err).exit(),
+                }
+            }
         }
+        Command::Completions { .. }
+        | Command::Healthcheck { .. }
+        | Command::Stats { .. }
+        | Command::Umount { .. }
+        | Command::GenerateSystemdUnit { .. }
+        | Command::CheckUpdate
+        | Command::CacheClear { .. } => unreachable!("handled before looking up AWS credentials"),
     }
 
     info!("finishing.");
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use structopt::StructOpt;
+
+    use crate::config::Command;
+    use crate::config::Config;
+    use crate::mount_helper_args;
+    use crate::parse_log_group_name;
+    use crate::systemd_escape_path;
+    use crate::systemd_units;
+
+    fn helper_args(args: &[&str]) -> Result<Option<Vec<String>>, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        mount_helper_args(&Config::clap(), &args)
+    }
+
+    #[test]
+    fn test_parse_log_group_name() {
+        let regions = ["us-west-2".to_string()];
+        let account_ids = ["111111111111".to_string()];
+        let parse = |value: &str| parse_log_group_name(value, &regions, &account_ids);
+        assert_eq!("/app/web", parse("/app/web"));
+        assert_eq!(
+            "/app/web",
+            parse("arn:aws:logs:us-west-2:111111111111:log-group:/app/web:*")
+        );
+        assert_eq!(
+            "arn:aws:logs:us-west-2:222222222222:log-group:/app/web",
+            parse("arn:aws:logs:us-west-2:222222222222:log-group:/app/web:*")
+        );
+    }
+
+    #[test]
+    fn test_mount_helper_args() {
+        assert_eq!(
+            Ok(Some(
+                [
+                    "cwl-mount",
+                    "--region=us-west-2",
+                    "mount",
+                    "/mnt/logs",
+                    "--daemon",
+                    "--log-group-name",
+                    "/app/web",
+                    "--allow-other",
+                    "-o",
+                    "ro,max_read=131072",
+                ]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect()
+            )),
+            helper_args(&[
+                "cwlmount#/app/web",
+                "/mnt/logs",
+                "-o",
+                "ro,region=us-west-2,allow-other,max_read=131072",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_mount_helper_args_takes_a_filter_and_drops_fstab_only_options() {
+        let args = helper_args(&[
+            "^/app/.*",
+            "/mnt/logs",
+            "-s",
+            "-o",
+            "_netdev,noauto,nofail,x-systemd.automount,comment=logs,defaults",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            vec![
+                "cwl-mount",
+                "mount",
+                "/mnt/logs",
+                "--daemon",
+                "--log-group-filter",
+                "^/app/.*"
+            ],
+            args
+        );
+        assert_eq!(
+            Ok(None),
+            helper_args(&["/app/web", "/mnt/logs", "-f", "-o", "_netdev"])
+        );
+    }
+
+    fn generate_units(
+        mount_args: &[&str],
+        automount_idle_secs: Option<u64>,
+    ) -> Result<Vec<(String, String)>, String> {
+        let mount_args: Vec<String> = mount_args.iter().map(|arg| arg.to_string()).collect();
+        let matches = Config::clap()
+            .get_matches_from_safe(
+                ["cwl-mount", "mount", "/mnt/logs"]
+                    .iter()
+                    .map(|arg| arg.to_string())
+                    .chain(mount_args.iter().cloned()),
+            )
+            .unwrap();
+        let mount = match Config::from_clap(&matches).command {
+            Command::Mount(mount) => mount,
+            _ => unreachable!(),
+        };
+        systemd_units(
+            Path::new("/usr/bin/cwl-mount"),
+            &[],
+            Path::new("/mnt/logs"),
+            &mount_args,
+            &mount,
+            &matches,
+            automount_idle_secs,
+        )
+    }
+
+    #[test]
+    fn test_systemd_escape_path() {
+        assert_eq!("-", systemd_escape_path(Path::new("/")));
+        assert_eq!("mnt-logs", systemd_escape_path(Path::new("/mnt/logs/")));
+        assert_eq!(
+            "mnt-app\\x2dlogs",
+            systemd_escape_path(Path::new("/mnt/app-logs"))
+        );
+        assert_eq!("mnt-my\\x20logs", systemd_escape_path(Path::new("/mnt/my logs")));
+        assert_eq!("\\x2emnt-a.b_c", systemd_escape_path(Path::new("/.mnt/a.b_c")));
+        assert_eq!(
+            "mnt-l\\xc3\\xb6gs",
+            systemd_escape_path(Path::new("/mnt/l\u{f6}gs"))
+        );
+    }
+
+    #[test]
+    fn test_systemd_units_runs_a_service() {
+        let units = generate_units(
+            &["--log-group-name", "/app/web", "--output-format", "${message}"],
+            None,
+        )
+        .unwrap();
+        assert_eq!(1, units.len());
+        let (name, service) = &units[0];
+        assert_eq!("cwl-mount-mnt-logs.service", name);
+        assert!(service.contains(
+            "ExecStart=/usr/bin/cwl-mount mount /mnt/logs --log-group-name /app/web --output-format $${message}\n"
+        ));
+        assert!(service.contains("ExecStop=/usr/bin/cwl-mount umount /mnt/logs\n"));
+        assert!(generate_units(&["--log-group-name", "/app/web", "--daemon"], None).is_err());
+        assert!(generate_units(&[], None).is_err());
+    }
+
+    #[test]
+    fn test_systemd_units_automounts() {
+        let units = generate_units(
+            &[
+                "--log-group-name",
+                "/app/web",
+                "--readahead-windows",
+                "2",
+                "-o",
+                "ro",
+            ],
+            Some(600),
+        )
+        .unwrap();
+        let names: Vec<&str> = units.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(vec!["mnt-logs.mount", "mnt-logs.automount"], names);
+        assert!(units[0].1.contains("What=/app/web\n"));
+        assert!(units[0].1.contains("Options=_netdev,readahead-windows=2,ro\n"));
+        assert!(units[1].1.contains("TimeoutIdleSec=600\n"));
+    }
+
+    #[test]
+    fn test_mount_helper_args_rejects_invalid_arguments() {
+        assert!(helper_args(&["/mnt/logs"]).is_err());
+        assert!(helper_args(&["/app/web", "/mnt/logs", "-x"]).is_err());
+        assert!(helper_args(&["/app/web", "/mnt/logs", "-o"]).is_err());
+    }
+}
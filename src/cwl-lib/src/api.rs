@@ -0,0 +1,392 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use format_cwl_log_event::FilteredLogEvent;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use tracing::{debug, instrument};
+
+use crate::{
+    first_possible_event_time, render_page, CloudWatchLogsError, EventFilter, ExportTask, ExportTaskStatus,
+    InsightsQueryRow, LogEventsPage, LogEventsPageToken, LogGroupMetadata, LogStream, LogStreamOrderBy,
+    MetricFilter, PreflightReport, QueryDefinition, SubscriptionFilter, TransformerChain, UsageStats,
+    EXPORT_TASK_POLL_INTERVAL,
+};
+
+/// The events on `pages`, without the events that an earlier page already returned and those that `event_filter`
+/// only filters after fetching.
+pub(crate) fn events_from_pages(
+    pages: impl Stream<Item = Result<Vec<FilteredLogEvent>, CloudWatchLogsError>> + Send,
+    event_filter: EventFilter,
+) -> impl Stream<Item = Result<FilteredLogEvent, CloudWatchLogsError>> + Send {
+    pages
+        .map_ok(|events| stream::iter(events.into_iter().map(Ok)))
+        .try_flatten()
+        // FilterLogEvents can return the same event on more than one page, so drop events that were already returned.
+        .try_filter({
+            let mut seen = HashSet::new();
+            move |event| futures::future::ready(seen.insert(event.event_id.clone()))
+        })
+        .try_filter(move |event| futures::future::ready(event_filter.matches_message(event)))
+}
+
+/// The CloudWatch Logs calls that cwl-mount makes in one region. `CloudWatchLogsImpl` makes them with the AWS SDK
+/// and `FakeCloudWatchLogs` answers them from memory, so the actor and everything built on top of these calls can
+/// be tested without an AWS account.
+///
+/// Implementations only need to provide the calls that map to a CloudWatch Logs API. Paginating through events,
+/// exports and the like are built on top of them.
+#[async_trait]
+pub trait CloudWatchLogsApi: std::fmt::Debug + Send + Sync {
+    fn region(&self) -> Option<&str>;
+
+    /// Calls made so far, for implementations that keep count.
+    fn usage(&self) -> UsageStats {
+        UsageStats::default()
+    }
+
+    /// The account that events are read from, so that `${account_id}` tells accounts apart, or None if it is not
+    /// known.
+    async fn account_id(&self) -> Option<String> {
+        None
+    }
+
+    /// Check that credentials can be loaded and that they are allowed to list log groups, so that problems show
+    /// up before mounting rather than as empty directories later.
+    async fn preflight(&self) -> Result<PreflightReport, CloudWatchLogsError>;
+
+    /// Log group names that start with `prefix`, which CloudWatch Logs filters server-side.
+    async fn get_log_group_names_with_prefix(
+        &self,
+        prefix: Option<&str>,
+    ) -> Result<Vec<String>, CloudWatchLogsError>;
+
+    /// Metadata of the log groups whose names start with `prefix`, listed the same way as
+    /// `get_log_group_names_with_prefix`.
+    async fn describe_log_groups(
+        &self,
+        prefix: Option<&str>,
+    ) -> Result<Vec<LogGroupMetadata>, CloudWatchLogsError>;
+
+    /// One page of FilterLogEvents, along with the token for the next page if there is one.
+    async fn filter_log_events_page(
+        &self,
+        log_group_name: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        event_filter: &EventFilter,
+        next_token: Option<String>,
+    ) -> Result<(Vec<FilteredLogEvent>, Option<String>), CloudWatchLogsError>;
+
+    /// One page of GetLogEvents reading forward from the start time, along with the token for the next page if
+    /// there is one. `position` is how many events of the stream came before this page.
+    #[allow(clippy::too_many_arguments)]
+    async fn get_log_events_page(
+        &self,
+        log_group_name: &str,
+        log_stream_name: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        unmask: bool,
+        next_token: Option<String>,
+        position: usize,
+    ) -> Result<(Vec<FilteredLogEvent>, Option<String>), CloudWatchLogsError>;
+
+    /// Metadata of a log group, or None if it does not exist in this region.
+    async fn describe_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Option<LogGroupMetadata>, CloudWatchLogsError>;
+
+    async fn describe_metric_filters(
+        &self,
+        log_group_name: String,
+    ) -> Result<Vec<MetricFilter>, CloudWatchLogsError>;
+
+    async fn describe_subscription_filters(
+        &self,
+        log_group_name: String,
+    ) -> Result<Vec<SubscriptionFilter>, CloudWatchLogsError>;
+
+    /// Logs Insights queries saved in this region, optionally only those whose name starts with `name_prefix`.
+    async fn describe_query_definitions(
+        &self,
+        name_prefix: Option<String>,
+    ) -> Result<Vec<QueryDefinition>, CloudWatchLogsError>;
+
+    /// Start exporting a log group's events between `start_time` and `end_time` to an S3 bucket in the same region,
+    /// returning the export task ID.
+    async fn create_export_task(
+        &self,
+        log_group_name: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        bucket: String,
+        prefix: Option<String>,
+    ) -> Result<String, CloudWatchLogsError>;
+
+    async fn get_export_task_status(&self, task_id: String) -> Result<ExportTaskStatus, CloudWatchLogsError>;
+
+    /// Time of the most recent event in a log group, taken from the log stream that was written to last. CloudWatch
+    /// Logs updates this eventually, usually within an hour, so it can be a little behind for active log groups.
+    async fn get_last_event_time_for_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Option<DateTime<Utc>>, CloudWatchLogsError>;
+
+    /// Log streams of a log group, optionally only those whose names start with `prefix`.
+    async fn get_log_streams(
+        &self,
+        log_group_name: String,
+        prefix: Option<String>,
+        order_by: LogStreamOrderBy,
+    ) -> Result<Vec<LogStream>, CloudWatchLogsError>;
+
+    /// Run a CloudWatch Logs Insights query and poll until it finishes.
+    async fn run_insights_query(
+        &self,
+        log_group_names: Vec<String>,
+        query_string: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<InsightsQueryRow>, CloudWatchLogsError>;
+
+    #[instrument(level = "debug")]
+    async fn get_log_group_names(&self) -> Result<Vec<String>, CloudWatchLogsError> {
+        self.get_log_group_names_with_prefix(None).await
+    }
+
+    /// Stream log events page by page rather than collecting them all in memory first. When the filter names a
+    /// single log stream and nothing else this uses GetLogEvents, otherwise FilterLogEvents. Events within a log
+    /// group come back sorted by timestamp.
+    fn log_events_stream(
+        &self,
+        log_group_name: String,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        event_filter: EventFilter,
+    ) -> impl Stream<Item = Result<FilteredLogEvent, CloudWatchLogsError>> + Send + '_ {
+        let pages = self.log_event_pages_stream(log_group_name, start_time, end_time, event_filter.clone());
+        events_from_pages(pages, event_filter)
+    }
+
+    /// The pages that `log_events_stream` gets its events from, as CloudWatch Logs returns them. Pages may repeat
+    /// events and have not had the message filter applied.
+    fn log_event_pages_stream(
+        &self,
+        log_group_name: String,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        event_filter: EventFilter,
+    ) -> impl Stream<Item = Result<Vec<FilteredLogEvent>, CloudWatchLogsError>> + Send + '_ {
+        // The state is the token for the next page and how many events came before it, or None once done.
+        let initial_state: Option<(Option<String>, usize)> = Some((None, 0));
+        stream::try_unfold(initial_state, move |state| {
+            let log_group_name = log_group_name.clone();
+            let event_filter = event_filter.clone();
+            async move {
+                let (next_token, position) = match state {
+                    Some(state) => state,
+                    None => return Ok(None),
+                };
+                debug!("tick, start_time: {:?}, end_time: {:?}", start_time, end_time);
+                let (events, next_token) = self
+                    .fetch_page(
+                        &log_group_name,
+                        start_time,
+                        end_time,
+                        &event_filter,
+                        next_token,
+                        position,
+                    )
+                    .await?;
+                let position = position + events.len();
+                Ok::<_, CloudWatchLogsError>(Some((
+                    events,
+                    next_token.map(|next_token| (Some(next_token), position)),
+                )))
+            }
+        })
+    }
+
+    /// One page of events, from GetLogEvents if the filter allows it and otherwise from FilterLogEvents.
+    /// `position` is how many events came before the page.
+    async fn fetch_page(
+        &self,
+        log_group_name: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        event_filter: &EventFilter,
+        next_token: Option<String>,
+        position: usize,
+    ) -> Result<(Vec<FilteredLogEvent>, Option<String>), CloudWatchLogsError> {
+        match event_filter.log_stream_name {
+            Some(ref log_stream_name) if event_filter.can_use_get_log_events() => {
+                self.get_log_events_page(
+                    log_group_name,
+                    log_stream_name,
+                    start_time,
+                    end_time,
+                    event_filter.unmask,
+                    next_token,
+                    position,
+                )
+                .await
+            }
+            _ => {
+                self.filter_log_events_page(log_group_name, start_time, end_time, event_filter, next_token)
+                    .await
+            }
+        }
+    }
+
+    /// One page of a log group's events rendered for display, to read a large time window a bit at a time.
+    /// Pass the `next_page_token` of a page to get the page after it.
+    ///
+    /// Unlike the events of a whole time window, events are not deduplicated across pages.
+    #[instrument(level = "debug", skip(transformers, formatter))]
+    #[allow(clippy::too_many_arguments)]
+    async fn get_rendered_page(
+        &self,
+        log_group_name: String,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        event_filter: EventFilter,
+        transformers: &TransformerChain,
+        formatter: &format_cwl_log_event::LogFormatter,
+        page_token: Option<LogEventsPageToken>,
+    ) -> Result<LogEventsPage, CloudWatchLogsError> {
+        let page_token = page_token.unwrap_or_default();
+        let (events, next_token) = self
+            .fetch_page(
+                &log_group_name,
+                start_time,
+                end_time,
+                &event_filter,
+                page_token.next_token.clone(),
+                page_token.position,
+            )
+            .await?;
+        Ok(render_page(
+            events,
+            next_token,
+            &page_token,
+            &event_filter,
+            transformers,
+            formatter,
+        ))
+    }
+
+    #[instrument(level = "debug")]
+    async fn get_log_events(
+        &self,
+        log_group_name: String,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        limit: Option<i32>,
+        event_filter: EventFilter,
+    ) -> Result<Vec<FilteredLogEvent>, CloudWatchLogsError> {
+        let limit = limit.map_or(usize::MAX, |limit| limit as usize);
+        self.log_events_stream(log_group_name, start_time, end_time, event_filter)
+            .take(limit)
+            .try_collect()
+            .await
+    }
+
+    /// Get log events for a single log stream using GetLogEvents, paginating forward from the start time.
+    #[instrument(level = "debug")]
+    async fn get_log_events_for_stream(
+        &self,
+        log_group_name: String,
+        log_stream_name: String,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        limit: Option<i32>,
+    ) -> Result<Vec<FilteredLogEvent>, CloudWatchLogsError> {
+        let event_filter = EventFilter {
+            log_stream_name: Some(log_stream_name),
+            ..EventFilter::default()
+        };
+        self.get_log_events(log_group_name, start_time, end_time, limit, event_filter)
+            .await
+    }
+
+    /// Export a log group to S3 and wait for the export to finish.
+    #[instrument(level = "debug")]
+    async fn export_to_s3(
+        &self,
+        log_group_name: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        bucket: String,
+        prefix: Option<String>,
+    ) -> Result<ExportTask, CloudWatchLogsError> {
+        let task_id = self
+            .create_export_task(log_group_name.clone(), start_time, end_time, bucket, prefix)
+            .await?;
+        loop {
+            let status = self.get_export_task_status(task_id.clone()).await?;
+            debug!("export task {} for {} is {:?}", task_id, log_group_name, status);
+            if status.is_done() {
+                return Ok(ExportTask {
+                    log_group_name,
+                    region: self.region().map(str::to_string),
+                    task_id,
+                    status,
+                });
+            }
+            tokio::time::sleep(EXPORT_TASK_POLL_INTERVAL).await;
+        }
+    }
+
+    #[instrument(level = "debug")]
+    async fn get_log_stream_names(
+        &self,
+        log_group_name: String,
+        prefix: Option<String>,
+        order_by: LogStreamOrderBy,
+    ) -> Result<Vec<String>, CloudWatchLogsError> {
+        let log_streams = self.get_log_streams(log_group_name, prefix, order_by).await?;
+        Ok(log_streams
+            .into_iter()
+            .map(|log_stream| log_stream.log_stream_name)
+            .collect())
+    }
+
+    #[instrument(level = "debug")]
+    async fn get_first_event_time_for_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Option<DateTime<Utc>>, CloudWatchLogsError> {
+        let now = Utc::now();
+        let metadata = self.describe_log_group(log_group_name.clone()).await?;
+        let mut start = first_possible_event_time(metadata.as_ref(), now);
+
+        // Search windows that double in size. A window stops being scanned at its first event, so small windows
+        // near the lower bound find early events quickly, and each call stays short if it has to be retried.
+        let mut window = Duration::hours(1);
+        while start < now {
+            let end = std::cmp::min(start + window, now);
+            let log_events = self
+                .get_log_events(
+                    log_group_name.clone(),
+                    Some(start),
+                    Some(end),
+                    Some(1),
+                    EventFilter::default(),
+                )
+                .await?;
+            if let Some(log_event) = log_events.first() {
+                return Ok(Some(log_event.timestamp));
+            }
+            start = end;
+            window = window * 2;
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Whether reads skip a log group because reading it failed too many times in a row.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum BreakerState {
+    Closed,
+
+    /// Skip the log group until `retry_at`, after `failures` reads in a row failed.
+    Open {
+        failures: usize,
+        retry_at: DateTime<Utc>,
+    },
+}
+
+#[derive(Debug, Default)]
+struct LogGroupFailures {
+    consecutive_failures: usize,
+    retry_at: Option<DateTime<Utc>>,
+}
+
+/// One circuit breaker per log group, so that a log group that keeps failing, e.g. because IAM denies reading it,
+/// does not slow down every read of the log groups it is mounted with.
+///
+/// After `threshold` reads of a log group fail in a row its breaker opens and reads skip it. Once `cool_down` has
+/// passed the next read tries it again: if that succeeds the breaker closes, and if it fails the breaker opens again
+/// straight away.
+///
+/// Log groups are keyed by the index of their client as well as their name, because the same name in another
+/// region is a different log group.
+#[derive(Debug)]
+pub(crate) struct CircuitBreakers {
+    threshold: usize,
+    cool_down: Duration,
+    log_groups: Mutex<HashMap<(usize, String), LogGroupFailures>>,
+}
+
+impl CircuitBreakers {
+    /// Breakers that never open if `threshold` is None.
+    pub(crate) fn new(threshold: Option<usize>, cool_down: std::time::Duration) -> Self {
+        CircuitBreakers {
+            threshold: threshold.unwrap_or(usize::MAX).max(1),
+            cool_down: Duration::from_std(cool_down).unwrap_or_else(|_| Duration::max_value()),
+            log_groups: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn log_groups(&self) -> std::sync::MutexGuard<'_, HashMap<(usize, String), LogGroupFailures>> {
+        self.log_groups
+            .lock()
+            .expect("circuit breaker lock is never poisoned")
+    }
+
+    pub(crate) fn state(&self, client: usize, log_group_name: &str, now: DateTime<Utc>) -> BreakerState {
+        match self.log_groups().get(&(client, log_group_name.to_string())) {
+            Some(LogGroupFailures {
+                consecutive_failures,
+                retry_at: Some(retry_at),
+            }) if now < *retry_at => BreakerState::Open {
+                failures: *consecutive_failures,
+                retry_at: *retry_at,
+            },
+            _ => BreakerState::Closed,
+        }
+    }
+
+    pub(crate) fn record_success(&self, client: usize, log_group_name: &str) {
+        self.log_groups().remove(&(client, log_group_name.to_string()));
+    }
+
+    /// Returns true if this failure opened the breaker.
+    pub(crate) fn record_failure(&self, client: usize, log_group_name: &str, now: DateTime<Utc>) -> bool {
+        let mut log_groups = self.log_groups();
+        let failures = log_groups
+            .entry((client, log_group_name.to_string()))
+            .or_default();
+        failures.consecutive_failures += 1;
+        if failures.consecutive_failures < self.threshold {
+            return false;
+        }
+        failures.retry_at = Some(
+            now.checked_add_signed(self.cool_down)
+                .unwrap_or(chrono::MAX_DATETIME),
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use crate::circuit_breaker::{BreakerState, CircuitBreakers};
+
+    #[test]
+    fn test_circuit_breaker_opens_after_consecutive_failures() {
+        let now = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let breakers = CircuitBreakers::new(Some(3), std::time::Duration::from_secs(60));
+        assert!(!breakers.record_failure(0, "/app/web", now));
+        breakers.record_success(0, "/app/web");
+        assert!(!breakers.record_failure(0, "/app/web", now));
+        assert!(!breakers.record_failure(0, "/app/web", now));
+        assert!(breakers.record_failure(0, "/app/web", now));
+        let retry_at = now + Duration::seconds(60);
+        assert_eq!(
+            BreakerState::Open {
+                failures: 3,
+                retry_at
+            },
+            breakers.state(0, "/app/web", now)
+        );
+        assert_eq!(BreakerState::Closed, breakers.state(1, "/app/web", now));
+
+        // After the cool-down one more failure opens it again, and a success closes it.
+        assert_eq!(BreakerState::Closed, breakers.state(0, "/app/web", retry_at));
+        assert!(breakers.record_failure(0, "/app/web", retry_at));
+        assert!(matches!(
+            breakers.state(0, "/app/web", retry_at),
+            BreakerState::Open { failures: 4, .. }
+        ));
+        breakers.record_success(0, "/app/web");
+        assert_eq!(BreakerState::Closed, breakers.state(0, "/app/web", retry_at));
+    }
+
+    #[test]
+    fn test_circuit_breaker_disabled() {
+        let now = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let breakers = CircuitBreakers::new(None, std::time::Duration::from_secs(60));
+        for _ in 0..100 {
+            assert!(!breakers.record_failure(0, "/app/web", now));
+        }
+        assert_eq!(BreakerState::Closed, breakers.state(0, "/app/web", now));
+    }
+}
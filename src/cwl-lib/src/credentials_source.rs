@@ -0,0 +1,186 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use std::sync::{Arc, Mutex};
+
+use aws_config::ecs::EcsCredentialsProvider;
+use aws_config::environment::credentials::EnvironmentVariableCredentialsProvider;
+use aws_config::imds::credentials::ImdsCredentialsProvider;
+use aws_config::meta::credentials::{CredentialsProviderChain, LazyCachingCredentialsProvider};
+use aws_config::profile::ProfileFileCredentialsProvider;
+use aws_config::provider_config::ProviderConfig;
+use aws_config::web_identity_token::WebIdentityTokenCredentialsProvider;
+use aws_types::credentials::{future, ProvideCredentials};
+
+/// Which credentials provider supplied the credentials, recorded by the provider itself when it supplies them.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CredentialsSource {
+    selected: Arc<Mutex<Option<&'static str>>>,
+
+    /// The credentials are used to assume a role rather than directly.
+    assumed_role: bool,
+}
+
+impl CredentialsSource {
+    /// Credentials that always come from the provider described by `description`.
+    pub(crate) fn fixed(description: &'static str) -> Self {
+        let source = CredentialsSource::default();
+        source.record(description);
+        source
+    }
+
+    pub(crate) fn assuming_role(self) -> Self {
+        CredentialsSource {
+            assumed_role: true,
+            ..self
+        }
+    }
+
+    fn record(&self, description: &'static str) {
+        *self
+            .selected
+            .lock()
+            .expect("credentials source lock is never poisoned") = Some(description);
+    }
+
+    /// Describe the provider that last supplied credentials, e.g. `profile` or `web identity token (IRSA)`.
+    pub(crate) fn describe(&self) -> String {
+        let selected = *self
+            .selected
+            .lock()
+            .expect("credentials source lock is never poisoned");
+        match (selected, self.assumed_role) {
+            (Some(description), false) => description.to_string(),
+            (Some(description), true) => format!("assumed role, with credentials from {}", description),
+            (None, _) => "unknown".to_string(),
+        }
+    }
+}
+
+/// Records `description` in `source` whenever `provider` supplies credentials.
+#[derive(Debug)]
+struct RecordSource<P> {
+    description: &'static str,
+    provider: P,
+    source: CredentialsSource,
+}
+
+impl<P: ProvideCredentials> ProvideCredentials for RecordSource<P> {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(async move {
+            let credentials = self.provider.provide_credentials().await?;
+            self.source.record(self.description);
+            Ok(credentials)
+        })
+    }
+}
+
+impl<P> RecordSource<P> {
+    fn new(description: &'static str, provider: P, source: &CredentialsSource) -> Self {
+        RecordSource {
+            description,
+            provider,
+            source: source.clone(),
+        }
+    }
+}
+
+/// The same providers in the same order as the SDK's `DefaultCredentialsChain`, which does not say which of them
+/// supplied the credentials, along with where the credentials came from.
+pub(crate) fn default_credentials_chain(
+    config: &ProviderConfig,
+    profile: Option<&str>,
+) -> (LazyCachingCredentialsProvider, CredentialsSource) {
+    let source = CredentialsSource::default();
+    let mut profile_provider = ProfileFileCredentialsProvider::builder().configure(config);
+    if let Some(profile) = profile {
+        profile_provider = profile_provider.profile_name(profile);
+    }
+    let chain = CredentialsProviderChain::first_try(
+        "Environment",
+        RecordSource::new(
+            "environment variables",
+            EnvironmentVariableCredentialsProvider::new(),
+            &source,
+        ),
+    )
+    .or_else(
+        "Profile",
+        RecordSource::new("profile", profile_provider.build(), &source),
+    )
+    .or_else(
+        "WebIdentityToken",
+        RecordSource::new(
+            "web identity token (IRSA)",
+            WebIdentityTokenCredentialsProvider::builder()
+                .configure(config)
+                .build(),
+            &source,
+        ),
+    )
+    .or_else(
+        "EcsContainer",
+        RecordSource::new(
+            "container credentials (ECS or EKS)",
+            EcsCredentialsProvider::builder().configure(config).build(),
+            &source,
+        ),
+    )
+    .or_else(
+        "Ec2InstanceMetadata",
+        RecordSource::new(
+            "EC2 instance metadata (IMDS)",
+            ImdsCredentialsProvider::builder().configure(config).build(),
+            &source,
+        ),
+    );
+    let provider = LazyCachingCredentialsProvider::builder()
+        .configure(config)
+        .load(chain)
+        .build();
+    (provider, source)
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_config::meta::credentials::{provide_credentials_fn, CredentialsProviderChain};
+    use aws_types::credentials::{CredentialsError, ProvideCredentials};
+    use aws_types::Credentials;
+
+    use crate::credentials_source::{CredentialsSource, RecordSource};
+
+    #[test]
+    fn test_credentials_source_records_the_provider_that_supplied_credentials() {
+        let source = CredentialsSource::default();
+        assert_eq!("unknown", source.describe());
+
+        let chain = CredentialsProviderChain::first_try(
+            "Environment",
+            RecordSource::new(
+                "environment variables",
+                provide_credentials_fn(|| async { Err(CredentialsError::not_loaded("not set")) }),
+                &source,
+            ),
+        )
+        .or_else(
+            "WebIdentityToken",
+            RecordSource::new(
+                "web identity token (IRSA)",
+                Credentials::new("AKID", "secret", None, None, "test"),
+                &source,
+            ),
+        );
+        tokio_test::block_on(chain.provide_credentials()).expect("the second provider supplies credentials");
+        assert_eq!("web identity token (IRSA)", source.describe());
+        assert_eq!(
+            "assumed role, with credentials from web identity token (IRSA)",
+            source.assuming_role().describe()
+        );
+        assert_eq!("given", CredentialsSource::fixed("given").describe());
+    }
+}
@@ -0,0 +1,431 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use ring::aead;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use tracing::{debug, warn};
+
+/// Bump this when the format of cached data changes so that old entries are no longer found.
+const DISK_CACHE_VERSION: &str = "2";
+
+const BLOB_EXTENSION: &str = "blob";
+
+/// A 256-bit key with which to encrypt cache entries using AES-256-GCM.
+#[derive(Clone)]
+pub struct CacheEncryptionKey([u8; 32]);
+
+impl CacheEncryptionKey {
+    /// Parse a key written as 64 hex digits, e.g. the output of `openssl rand -hex 32`.
+    pub fn from_hex(key: &str) -> Result<Self, String> {
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(key.trim(), &mut bytes)
+            .map_err(|_| "cache encryption key must be 64 hex digits".to_string())?;
+        Ok(CacheEncryptionKey(bytes))
+    }
+
+    fn aead_key(&self) -> aead::LessSafeKey {
+        aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_256_GCM, &self.0).expect("key is 256 bits"))
+    }
+}
+
+impl std::fmt::Debug for CacheEncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CacheEncryptionKey(..)")
+    }
+}
+
+/// Where to keep rendered log files between runs, and how much space they may take.
+#[derive(Clone, Debug)]
+pub struct DiskCacheOptions {
+    pub dir: PathBuf,
+
+    /// Least recently used entries are removed once the cache grows beyond this many bytes.
+    pub max_size_bytes: u64,
+
+    /// Keeps entries apart when the same directory is used with different credentials, e.g. the profile name and
+    /// role ARN.
+    pub namespace: String,
+
+    /// Encrypt entries so that log content is not stored in plain text.
+    pub encryption_key: Option<CacheEncryptionKey>,
+}
+
+/// A directory of content-addressed blobs, one per cache key. Entries are only ever written for time windows
+/// that no longer change, so they never need to be invalidated, only evicted, unless events arrive later than
+/// expected, see `clear`.
+///
+/// Failing to read or write the cache is logged and otherwise treated like a cache miss.
+#[derive(Clone, Debug)]
+pub(crate) struct DiskCache {
+    options: Arc<DiskCacheOptions>,
+
+    /// Bytes of entries in the directory, kept up to date by `put` so that the directory is only listed to evict.
+    /// Other processes using the same directory are only noticed then.
+    total_size: Arc<Mutex<u64>>,
+}
+
+impl DiskCache {
+    pub(crate) fn new(options: DiskCacheOptions) -> io::Result<Self> {
+        fs::create_dir_all(&options.dir)?;
+        let total_size = entries(&options.dir)?.iter().map(|(_, size, _)| size).sum();
+        Ok(DiskCache {
+            options: Arc::new(options),
+            total_size: Arc::new(Mutex::new(total_size)),
+        })
+    }
+
+    fn path_for_key(&self, key: &str) -> PathBuf {
+        let mut context = digest::Context::new(&digest::SHA256);
+        let encryption = match self.options.encryption_key {
+            Some(_) => "aes-256-gcm",
+            None => "plain",
+        };
+        for part in [DISK_CACHE_VERSION, encryption, &self.options.namespace, key] {
+            context.update(part.as_bytes());
+            context.update(b"\n");
+        }
+        self.options
+            .dir
+            .join(hex::encode(context.finish()))
+            .with_extension(BLOB_EXTENSION)
+    }
+
+    pub(crate) async fn get(&self, key: &str) -> Option<Bytes> {
+        let path = self.path_for_key(key);
+        let cache = self.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let data = read_and_touch(&path)?;
+            cache.decrypt(&path, data)
+        })
+        .await;
+        match result {
+            Ok(Ok(data)) => Some(Bytes::from(data)),
+            Ok(Err(err)) if err.kind() == io::ErrorKind::NotFound => None,
+            Ok(Err(err)) => {
+                warn!("failed to read disk cache entry: {}", err);
+                None
+            }
+            Err(err) => {
+                warn!("failed to read disk cache entry: {}", err);
+                None
+            }
+        }
+    }
+
+    pub(crate) async fn put(&self, key: &str, data: Bytes) {
+        let path = self.path_for_key(key);
+        let cache = self.clone();
+        let result = tokio::task::spawn_blocking(move || cache.write(&path, &data)).await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => warn!("failed to write disk cache entry: {}", err),
+            Err(err) => warn!("failed to write disk cache entry: {}", err),
+        }
+    }
+
+    /// Remove every entry, including those of other namespaces, which cannot be told apart by their file names.
+    pub(crate) async fn clear(&self) {
+        let cache = self.clone();
+        let result = tokio::task::spawn_blocking(move || cache.remove_entries()).await;
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => warn!("failed to clear disk cache: {}", err),
+            Err(err) => warn!("failed to clear disk cache: {}", err),
+        }
+    }
+
+    /// Entries are stored as a random nonce followed by the ciphertext. The file name is authenticated too, so an
+    /// entry cannot be passed off as the entry for another key.
+    fn encrypt(&self, path: &Path, data: &[u8]) -> io::Result<Vec<u8>> {
+        let key = match &self.options.encryption_key {
+            Some(key) => key.aead_key(),
+            None => return Ok(data.to_vec()),
+        };
+        let mut nonce = [0; aead::NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| io::Error::other("failed to generate nonce"))?;
+        let mut in_out = data.to_vec();
+        key.seal_in_place_append_tag(
+            aead::Nonce::assume_unique_for_key(nonce),
+            associated_data(path),
+            &mut in_out,
+        )
+        .map_err(|_| io::Error::other("failed to encrypt cache entry"))?;
+        let mut result = nonce.to_vec();
+        result.extend(in_out);
+        Ok(result)
+    }
+
+    fn decrypt(&self, path: &Path, mut data: Vec<u8>) -> io::Result<Vec<u8>> {
+        let key = match &self.options.encryption_key {
+            Some(key) => key.aead_key(),
+            None => return Ok(data),
+        };
+        if data.len() < aead::NONCE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cache entry is truncated",
+            ));
+        }
+        let mut in_out = data.split_off(aead::NONCE_LEN);
+        let nonce = aead::Nonce::try_assume_unique_for_key(&data)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "cache entry is truncated"))?;
+        let plaintext_len = key
+            .open_in_place(nonce, associated_data(path), &mut in_out)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "failed to decrypt cache entry, was it written with another key?",
+                )
+            })?
+            .len();
+        in_out.truncate(plaintext_len);
+        Ok(in_out)
+    }
+
+    /// Write an entry, then evict entries if the cache grew beyond its maximum size.
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let data = self.encrypt(path, data)?;
+        let replaced_size = fs::metadata(path).map_or(0, |metadata| metadata.len());
+        write_atomically(path, &data)?;
+        let mut total_size = self.total_size();
+        *total_size = total_size.saturating_sub(replaced_size) + data.len() as u64;
+        if *total_size > self.options.max_size_bytes {
+            *total_size = self.evict()?;
+        }
+        Ok(())
+    }
+
+    fn total_size(&self) -> std::sync::MutexGuard<'_, u64> {
+        self.total_size
+            .lock()
+            .expect("disk cache size lock is never poisoned")
+    }
+
+    fn remove_entries(&self) -> io::Result<()> {
+        let mut total_size = self.total_size();
+        for (_, size, path) in entries(&self.options.dir)? {
+            fs::remove_file(&path)?;
+            *total_size = total_size.saturating_sub(size);
+        }
+        Ok(())
+    }
+
+    /// Remove the least recently used entries until the cache fits in its maximum size, and return the size left.
+    fn evict(&self) -> io::Result<u64> {
+        let mut entries = entries(&self.options.dir)?;
+        let mut total_size = entries.iter().map(|(_, size, _)| size).sum();
+        entries.sort();
+        for (_, size, path) in entries {
+            if total_size <= self.options.max_size_bytes {
+                break;
+            }
+            debug!("evicting disk cache entry {:?}", path);
+            fs::remove_file(&path)?;
+            total_size -= size;
+        }
+        Ok(total_size)
+    }
+}
+
+/// The entries in `dir`, with when they were last used and their size.
+fn entries(dir: &Path) -> io::Result<Vec<(SystemTime, u64, PathBuf)>> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some(BLOB_EXTENSION) {
+            continue;
+        }
+        let metadata = fs::metadata(&path)?;
+        entries.push((metadata.modified()?, metadata.len(), path));
+    }
+    Ok(entries)
+}
+
+fn associated_data(path: &Path) -> aead::Aad<Vec<u8>> {
+    let file_name = path
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned());
+    aead::Aad::from(file_name.unwrap_or_default().into_bytes())
+}
+
+/// Read a cache entry and mark it as recently used.
+fn read_and_touch(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut data = vec![];
+    file.read_to_end(&mut data)?;
+    file.set_modified(SystemTime::now())?;
+    Ok(data)
+}
+
+/// Write to a temporary file first so that a crash never leaves a partial entry behind.
+fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+    let temporary_path = path.with_extension(format!("tmp-{}", fastrand::u64(..)));
+    let mut file = fs::File::create(&temporary_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&temporary_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use bytes::Bytes;
+
+    use crate::disk_cache::{CacheEncryptionKey, DiskCache, DiskCacheOptions};
+
+    fn temporary_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("cwl-mount-disk-cache-test-{}", fastrand::u64(..)))
+    }
+
+    #[test]
+    fn test_disk_cache_round_trip() {
+        let dir = temporary_dir();
+        let cache = DiskCache::new(DiskCacheOptions {
+            dir: dir.clone(),
+            max_size_bytes: 1024,
+            namespace: "default".to_string(),
+            encryption_key: None,
+        })
+        .unwrap();
+        tokio_test::block_on(async {
+            assert_eq!(None, cache.get("key").await);
+            cache.put("key", Bytes::from("some logs")).await;
+            assert_eq!(Some(Bytes::from("some logs")), cache.get("key").await);
+        });
+
+        // A different namespace does not see the entry.
+        let other = DiskCache::new(DiskCacheOptions {
+            dir: dir.clone(),
+            max_size_bytes: 1024,
+            namespace: "other".to_string(),
+            encryption_key: None,
+        })
+        .unwrap();
+        assert_eq!(None, tokio_test::block_on(other.get("key")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_disk_cache_evicts_least_recently_used() {
+        let dir = temporary_dir();
+        let cache = DiskCache::new(DiskCacheOptions {
+            dir: dir.clone(),
+            max_size_bytes: 10,
+            namespace: "default".to_string(),
+            encryption_key: None,
+        })
+        .unwrap();
+        tokio_test::block_on(async {
+            cache.put("first", Bytes::from("123456")).await;
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            cache.put("second", Bytes::from("123456")).await;
+            assert_eq!(None, cache.get("first").await);
+            assert_eq!(Some(Bytes::from("123456")), cache.get("second").await);
+        });
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_disk_cache_counts_entries_already_in_the_directory() {
+        let dir = temporary_dir();
+        let options = DiskCacheOptions {
+            dir: dir.clone(),
+            max_size_bytes: 10,
+            namespace: "default".to_string(),
+            encryption_key: None,
+        };
+        let cache = DiskCache::new(options.clone()).unwrap();
+        tokio_test::block_on(cache.put("first", Bytes::from("123456")));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        // A new run starts from the size of what an earlier run left behind.
+        let cache = DiskCache::new(options).unwrap();
+        assert_eq!(6, *cache.total_size());
+        tokio_test::block_on(async {
+            cache.put("first", Bytes::from("1234")).await;
+            assert_eq!(4, *cache.total_size());
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            cache.put("second", Bytes::from("123456")).await;
+            assert_eq!(10, *cache.total_size());
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            cache.put("third", Bytes::from("12")).await;
+            assert_eq!(8, *cache.total_size());
+            assert_eq!(None, cache.get("first").await);
+            cache.clear().await;
+        });
+        assert_eq!(0, *cache.total_size());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_disk_cache_clear_removes_every_entry() {
+        let dir = temporary_dir();
+        let cache = DiskCache::new(DiskCacheOptions {
+            dir: dir.clone(),
+            max_size_bytes: 1024,
+            namespace: "default".to_string(),
+            encryption_key: None,
+        })
+        .unwrap();
+        std::fs::write(dir.join("unrelated.txt"), "keep").unwrap();
+        tokio_test::block_on(async {
+            cache.put("first", Bytes::from("some logs")).await;
+            cache.put("second", Bytes::from("more logs")).await;
+            cache.clear().await;
+            assert_eq!(None, cache.get("first").await);
+            assert_eq!(None, cache.get("second").await);
+        });
+        assert!(dir.join("unrelated.txt").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_disk_cache_encrypts_entries() {
+        let dir = temporary_dir();
+        let options = DiskCacheOptions {
+            dir: dir.clone(),
+            max_size_bytes: 1024,
+            namespace: "default".to_string(),
+            encryption_key: Some(CacheEncryptionKey::from_hex(&"ab".repeat(32)).unwrap()),
+        };
+        let cache = DiskCache::new(options.clone()).unwrap();
+        tokio_test::block_on(cache.put("key", Bytes::from("secret logs")));
+        let blob = std::fs::read(cache.path_for_key("key")).unwrap();
+        assert!(!String::from_utf8_lossy(&blob).contains("secret logs"));
+        assert_eq!(
+            Some(Bytes::from("secret logs")),
+            tokio_test::block_on(cache.get("key"))
+        );
+
+        // Another key cannot read the entry.
+        let other = DiskCache::new(DiskCacheOptions {
+            encryption_key: Some(CacheEncryptionKey::from_hex(&"cd".repeat(32)).unwrap()),
+            ..options
+        })
+        .unwrap();
+        assert_eq!(None, tokio_test::block_on(other.get("key")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_cache_encryption_key_from_hex() {
+        assert!(CacheEncryptionKey::from_hex(&"0f".repeat(32)).is_ok());
+        assert!(CacheEncryptionKey::from_hex("0f").is_err());
+        assert!(CacheEncryptionKey::from_hex(&"zz".repeat(32)).is_err());
+    }
+}
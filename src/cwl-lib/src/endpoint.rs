@@ -0,0 +1,154 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use std::str::FromStr;
+
+use aws_endpoint::partition::endpoint::{Metadata, Protocol, SignatureVersion};
+use aws_endpoint::{AwsEndpoint, BoxError, CredentialScope, ResolveAwsEndpoint};
+use aws_types::region::Region;
+
+/// Groups of regions that are isolated from each other [1]. Credentials, ARNs and endpoints differ between
+/// partitions, so regions can only be read together if they are in the same partition.
+///
+/// [1] https://docs.aws.amazon.com/general/latest/gr/aws-arns-and-namespaces.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Partition {
+    Aws,
+    AwsCn,
+    AwsUsGov,
+}
+
+impl Partition {
+    pub fn for_region(region: &str) -> Partition {
+        if region.starts_with("cn-") {
+            Partition::AwsCn
+        } else if region.starts_with("us-gov-") {
+            Partition::AwsUsGov
+        } else {
+            Partition::Aws
+        }
+    }
+
+    /// The partition as it appears in ARNs, e.g. `aws-us-gov`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Partition::Aws => "aws",
+            Partition::AwsCn => "aws-cn",
+            Partition::AwsUsGov => "aws-us-gov",
+        }
+    }
+
+    /// Regions that are enabled by default in the partition, used for `--all-regions`.
+    pub fn regions(&self) -> &'static [&'static str] {
+        match self {
+            Partition::Aws => crate::ALL_REGIONS,
+            Partition::AwsCn => &["cn-north-1", "cn-northwest-1"],
+            Partition::AwsUsGov => &["us-gov-east-1", "us-gov-west-1"],
+        }
+    }
+
+    pub fn supports_fips(&self) -> bool {
+        *self != Partition::AwsCn
+    }
+
+    /// Template of the CloudWatch Logs endpoint to use in this partition.
+    fn uri_template(&self, use_fips: bool, use_dualstack: bool) -> &'static str {
+        match (self, use_fips, use_dualstack) {
+            (Partition::Aws, false, false) => "logs.{region}.amazonaws.com",
+            (Partition::Aws, true, false) => "logs-fips.{region}.amazonaws.com",
+            (Partition::Aws, false, true) => "logs.{region}.api.aws",
+            (Partition::Aws, true, true) => "logs-fips.{region}.api.aws",
+            (Partition::AwsCn, _, false) => "logs.{region}.amazonaws.com.cn",
+            (Partition::AwsCn, _, true) => "logs.{region}.api.amazonwebservices.com.cn",
+            // The regular GovCloud endpoints are already FIPS 140-2 validated.
+            (Partition::AwsUsGov, _, false) => "logs.{region}.amazonaws.com",
+            (Partition::AwsUsGov, false, true) => "logs.{region}.api.aws",
+            (Partition::AwsUsGov, true, true) => "logs-fips.{region}.api.aws",
+        }
+    }
+}
+
+impl FromStr for Partition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Partition::Aws, Partition::AwsCn, Partition::AwsUsGov]
+            .into_iter()
+            .find(|partition| partition.as_str() == s)
+            .ok_or_else(|| format!("{} isn't a partition, use aws, aws-cn or aws-us-gov", s))
+    }
+}
+
+/// Resolves FIPS and dual-stack CloudWatch Logs endpoints, which the SDK's own resolver does not know about.
+#[derive(Debug)]
+pub(crate) struct EndpointVariants {
+    pub(crate) use_fips: bool,
+    pub(crate) use_dualstack: bool,
+}
+
+impl ResolveAwsEndpoint for EndpointVariants {
+    fn resolve_endpoint(&self, region: &Region) -> Result<AwsEndpoint, BoxError> {
+        let partition = Partition::for_region(region.as_ref());
+        if self.use_fips && !partition.supports_fips() {
+            return Err(format!("CloudWatch Logs has no FIPS endpoints in {}", region.as_ref()).into());
+        }
+        Metadata {
+            uri_template: partition.uri_template(self.use_fips, self.use_dualstack),
+            protocol: Protocol::Https,
+            signature_versions: SignatureVersion::V4,
+            credential_scope: CredentialScope::builder().build(),
+        }
+        .resolve_endpoint(region)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_endpoint::ResolveAwsEndpoint;
+    use aws_types::region::Region;
+
+    use crate::endpoint::{EndpointVariants, Partition};
+
+    fn resolve(use_fips: bool, use_dualstack: bool, region: &'static str) -> Option<String> {
+        let resolver = EndpointVariants {
+            use_fips,
+            use_dualstack,
+        };
+        let endpoint = resolver.resolve_endpoint(&Region::from_static(region)).ok()?;
+        let mut uri = http::Uri::from_static("/");
+        endpoint.set_endpoint(&mut uri, None);
+        uri.host().map(|host| host.to_string())
+    }
+
+    #[test]
+    fn test_partition_for_region() {
+        assert_eq!(Partition::Aws, Partition::for_region("us-west-2"));
+        assert_eq!(Partition::AwsCn, Partition::for_region("cn-north-1"));
+        assert_eq!(Partition::AwsUsGov, Partition::for_region("us-gov-west-1"));
+        assert_eq!(Ok(Partition::AwsUsGov), "aws-us-gov".parse());
+        assert!("aws-iso".parse::<Partition>().is_err());
+    }
+
+    #[test]
+    fn test_endpoint_variants() {
+        assert_eq!(
+            Some("logs-fips.us-east-1.amazonaws.com".to_string()),
+            resolve(true, false, "us-east-1")
+        );
+        assert_eq!(
+            Some("logs.eu-west-1.api.aws".to_string()),
+            resolve(false, true, "eu-west-1")
+        );
+        assert_eq!(
+            Some("logs.cn-north-1.api.amazonwebservices.com.cn".to_string()),
+            resolve(false, true, "cn-north-1")
+        );
+        assert_eq!(
+            Some("logs.us-gov-west-1.amazonaws.com".to_string()),
+            resolve(true, false, "us-gov-west-1")
+        );
+        assert_eq!(None, resolve(true, false, "cn-north-1"));
+    }
+}
@@ -0,0 +1,602 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use aws_sdk_cloudwatchlogs::error::{
+    DescribeLogStreamsError, DescribeLogStreamsErrorKind, FilterLogEventsError, FilterLogEventsErrorKind,
+    GetLogEventsError, GetLogEventsErrorKind, ResourceNotFoundException,
+};
+use aws_smithy_http::body::SdkBody;
+use aws_smithy_http::operation;
+use aws_smithy_http::result::SdkError;
+use chrono::{DateTime, Utc};
+use format_cwl_log_event::FilteredLogEvent;
+use regexes::LogGroupArn;
+
+use crate::rate_limit::ApiAction;
+use crate::usage::Usage;
+use crate::{
+    CloudWatchLogsApi, CloudWatchLogsError, EventFilter, ExportTaskStatus, InsightsQueryRow,
+    LogGroupMetadata, LogStream, LogStreamOrderBy, MetricFilter, PreflightReport, QueryDefinition,
+    SubscriptionFilter, UsageStats,
+};
+
+const DEFAULT_PAGE_SIZE: usize = 100;
+
+#[derive(Debug)]
+struct FakeLogGroup {
+    creation_time: DateTime<Utc>,
+
+    /// Sorted by timestamp, and by when they were put for events with the same timestamp.
+    events: Vec<FilteredLogEvent>,
+    metric_filters: Vec<MetricFilter>,
+    subscription_filters: Vec<SubscriptionFilter>,
+}
+
+impl FakeLogGroup {
+    fn new() -> Self {
+        FakeLogGroup {
+            creation_time: Utc::now(),
+            events: vec![],
+            metric_filters: vec![],
+            subscription_filters: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    log_groups: BTreeMap<String, FakeLogGroup>,
+
+    /// Log groups that were deleted but are still listed, see `delete_log_group`.
+    deleted_log_groups: BTreeSet<String>,
+
+    /// Log groups whose events may not be read, see `deny_log_group`.
+    denied_log_groups: BTreeSet<String>,
+    export_tasks: HashMap<String, ExportTaskStatus>,
+    query_definitions: Vec<QueryDefinition>,
+    next_event_id: u64,
+}
+
+/// CloudWatch Logs in memory, to test code that reads logs without an AWS account. Log groups and their events
+/// are put in with `put_log_event` and read back through `CloudWatchLogsApi` the way CloudWatch Logs would return
+/// them, a page at a time.
+///
+/// Filter patterns only support terms, which must all appear in a message, and not JSON or space-delimited
+/// patterns. Insights queries ignore the query string and return the timestamp and message of every event.
+#[derive(Debug)]
+pub struct FakeCloudWatchLogs {
+    region: Option<String>,
+    account_id: Option<String>,
+    page_size: usize,
+    state: Mutex<FakeState>,
+    calls: AtomicUsize,
+    usage: Usage,
+}
+
+impl FakeCloudWatchLogs {
+    pub fn new<T: Into<String>>(region: Option<T>) -> Self {
+        FakeCloudWatchLogs {
+            region: region.map(|region| region.into()),
+            account_id: None,
+            page_size: DEFAULT_PAGE_SIZE,
+            state: Mutex::new(FakeState::default()),
+            calls: AtomicUsize::new(0),
+            usage: Usage::default(),
+        }
+    }
+
+    /// Return at most this many events per page, to exercise pagination with only a few events.
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Tag events with this account, like `CloudWatchLogsImpl` does with the account of its credentials.
+    pub fn with_account_id(mut self, account_id: impl Into<String>) -> Self {
+        self.account_id = Some(account_id.into());
+        self
+    }
+
+    pub fn create_log_group(&self, log_group_name: impl Into<String>) {
+        self.state()
+            .log_groups
+            .entry(log_group_name.into())
+            .or_insert_with(FakeLogGroup::new);
+    }
+
+    /// Delete a log group. Listing log groups still returns it, as if it had been listed before it was deleted, but
+    /// reading it fails with ResourceNotFoundException.
+    pub fn delete_log_group(&self, log_group_name: impl Into<String>) {
+        let log_group_name = log_group_name.into();
+        let mut state = self.state();
+        state.log_groups.remove(&log_group_name);
+        state.deleted_log_groups.insert(log_group_name);
+    }
+
+    /// Fail reading a log group's events with AccessDeniedException, as if IAM did not allow it, until
+    /// `allow_log_group` is called.
+    pub fn deny_log_group(&self, log_group_name: impl Into<String>) {
+        self.state().denied_log_groups.insert(log_group_name.into());
+    }
+
+    pub fn allow_log_group(&self, log_group_name: &str) {
+        self.state().denied_log_groups.remove(log_group_name);
+    }
+
+    /// Add an event, creating the log group if it does not exist yet.
+    pub fn put_log_event(
+        &self,
+        log_group_name: impl Into<String>,
+        log_stream_name: impl Into<String>,
+        timestamp: DateTime<Utc>,
+        message: impl Into<String>,
+    ) {
+        let log_group_name = log_group_name.into();
+        let mut state = self.state();
+        let event_id = state.next_event_id.to_string();
+        state.next_event_id += 1;
+        // Like the real client, events of a log group in a linked source account carry the name and account in its
+        // ARN.
+        let arn = LogGroupArn::parse(&log_group_name);
+        let mut event = FilteredLogEvent::new(
+            arn.as_ref()
+                .map_or(log_group_name.as_str(), |arn| arn.log_group_name.as_str()),
+            event_id,
+            timestamp,
+            log_stream_name,
+            message,
+            timestamp,
+        );
+        event.region = self.region.clone();
+        event.account_id = match arn {
+            Some(arn) => Some(arn.account_id),
+            None => self.account_id.clone(),
+        };
+        let log_group = state
+            .log_groups
+            .entry(log_group_name)
+            .or_insert_with(FakeLogGroup::new);
+        let index = log_group
+            .events
+            .partition_point(|other| other.timestamp <= timestamp);
+        log_group.events.insert(index, event);
+    }
+
+    pub fn put_metric_filter(&self, metric_filter: MetricFilter) {
+        self.state()
+            .log_groups
+            .entry(metric_filter.log_group_name.clone())
+            .or_insert_with(FakeLogGroup::new)
+            .metric_filters
+            .push(metric_filter);
+    }
+
+    pub fn put_subscription_filter(&self, subscription_filter: SubscriptionFilter) {
+        self.state()
+            .log_groups
+            .entry(subscription_filter.log_group_name.clone())
+            .or_insert_with(FakeLogGroup::new)
+            .subscription_filters
+            .push(subscription_filter);
+    }
+
+    pub fn put_query_definition(&self, query_definition: QueryDefinition) {
+        self.state().query_definitions.push(query_definition);
+    }
+
+    /// How many CloudWatch Logs calls have been made, e.g. to check that something was cached.
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FakeState> {
+        self.state.lock().expect("fake state lock is never poisoned")
+    }
+
+    fn call(&self, action: ApiAction) -> std::sync::MutexGuard<'_, FakeState> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        self.usage.record_call(action);
+        self.state()
+    }
+
+    /// Metadata of a log group, sized by the messages of its events.
+    fn log_group_metadata(&self, log_group_name: &str, log_group: &FakeLogGroup) -> LogGroupMetadata {
+        LogGroupMetadata {
+            log_group_name: log_group_name.to_string(),
+            region: self.region.clone(),
+            arn: None,
+            creation_time: Some(log_group.creation_time),
+            retention_in_days: None,
+            stored_bytes: Some(
+                log_group
+                    .events
+                    .iter()
+                    .map(|event| event.message.len() as i64)
+                    .sum(),
+            ),
+            kms_key_id: None,
+        }
+    }
+
+    /// One page of `events` starting at the offset in `next_token`, along with the token for the next page.
+    fn page<T: Clone>(&self, events: &[T], next_token: Option<String>) -> (Vec<T>, Option<String>) {
+        let offset = next_token.and_then(|token| token.parse().ok()).unwrap_or(0);
+        let end = events.len().min(offset + self.page_size);
+        let next_token = if end < events.len() {
+            Some(end.to_string())
+        } else {
+            None
+        };
+        (events[offset.min(end)..end].to_vec(), next_token)
+    }
+}
+
+fn is_in_time_range(
+    event: &FilteredLogEvent,
+    start_time: Option<DateTime<Utc>>,
+    end_time: Option<DateTime<Utc>>,
+) -> bool {
+    start_time.is_none_or(|start_time| event.timestamp >= start_time)
+        && end_time.is_none_or(|end_time| event.timestamp < end_time)
+}
+
+fn matches_filter(event: &FilteredLogEvent, event_filter: &EventFilter) -> bool {
+    if let Some(ref log_stream_name) = event_filter.log_stream_name {
+        if &event.log_stream_name != log_stream_name {
+            return false;
+        }
+    }
+    if let Some(ref prefix) = event_filter.log_stream_name_prefix {
+        if !event.log_stream_name.starts_with(prefix.as_str()) {
+            return false;
+        }
+    }
+    match event_filter.filter_pattern {
+        Some(ref filter_pattern) => filter_pattern
+            .split_whitespace()
+            .map(|term| term.trim_matches('"'))
+            .all(|term| event.message.contains(term)),
+        None => true,
+    }
+}
+
+/// The error CloudWatch Logs returns when the credentials may not read a log group.
+fn access_denied<E>(new_error: impl FnOnce(aws_smithy_types::Error) -> E) -> Box<SdkError<E>> {
+    let err = new_error(
+        aws_smithy_types::Error::builder()
+            .code("AccessDeniedException")
+            .message("User is not authorized to perform this operation")
+            .build(),
+    );
+    let raw = http::Response::builder()
+        .status(400)
+        .body(SdkBody::empty())
+        .expect("response is valid");
+    Box::new(SdkError::ServiceError {
+        err,
+        raw: operation::Response::new(raw),
+    })
+}
+
+/// The error CloudWatch Logs returns when a log group does not exist.
+fn resource_not_found<E>(
+    log_group_name: &str,
+    new_error: impl FnOnce(ResourceNotFoundException, aws_smithy_types::Error) -> E,
+) -> Box<SdkError<E>> {
+    let message = format!("The specified log group does not exist: {}", log_group_name);
+    let err = new_error(
+        ResourceNotFoundException::builder().message(&message).build(),
+        aws_smithy_types::Error::builder()
+            .code("ResourceNotFoundException")
+            .message(message)
+            .build(),
+    );
+    let raw = http::Response::builder()
+        .status(400)
+        .body(SdkBody::empty())
+        .expect("response is valid");
+    Box::new(SdkError::ServiceError {
+        err,
+        raw: operation::Response::new(raw),
+    })
+}
+
+/// Whether listing log groups with `prefix` lists `log_group_name`. CloudWatch Logs matches the prefix against the
+/// name of a log group in a linked source account, not against its ARN.
+fn has_prefix(log_group_name: &str, prefix: Option<&str>) -> bool {
+    let log_group_name =
+        LogGroupArn::parse(log_group_name).map_or(log_group_name.to_string(), |arn| arn.log_group_name);
+    prefix.is_none_or(|prefix| log_group_name.starts_with(prefix))
+}
+
+#[async_trait]
+impl CloudWatchLogsApi for FakeCloudWatchLogs {
+    fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
+
+    fn usage(&self) -> UsageStats {
+        self.usage.stats()
+    }
+
+    async fn account_id(&self) -> Option<String> {
+        self.account_id.clone()
+    }
+
+    async fn preflight(&self) -> Result<PreflightReport, CloudWatchLogsError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        self.usage.record_call(ApiAction::DescribeLogGroups);
+        Ok(PreflightReport {
+            region: self.region.clone(),
+            credentials_source: "fake".to_string(),
+            credentials_expiry: None,
+        })
+    }
+
+    async fn get_log_group_names_with_prefix(
+        &self,
+        prefix: Option<&str>,
+    ) -> Result<Vec<String>, CloudWatchLogsError> {
+        let state = self.call(ApiAction::DescribeLogGroups);
+        let log_group_names: BTreeSet<&String> = state
+            .log_groups
+            .keys()
+            .chain(&state.deleted_log_groups)
+            .filter(|log_group_name| has_prefix(log_group_name, prefix))
+            .collect();
+        Ok(log_group_names.into_iter().cloned().collect())
+    }
+
+    async fn describe_log_groups(
+        &self,
+        prefix: Option<&str>,
+    ) -> Result<Vec<LogGroupMetadata>, CloudWatchLogsError> {
+        let state = self.call(ApiAction::DescribeLogGroups);
+        Ok(state
+            .log_groups
+            .iter()
+            .filter(|(log_group_name, _)| has_prefix(log_group_name, prefix))
+            .map(|(log_group_name, log_group)| self.log_group_metadata(log_group_name, log_group))
+            .collect())
+    }
+
+    async fn filter_log_events_page(
+        &self,
+        log_group_name: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        event_filter: &EventFilter,
+        next_token: Option<String>,
+    ) -> Result<(Vec<FilteredLogEvent>, Option<String>), CloudWatchLogsError> {
+        let state = self.call(ApiAction::FilterLogEvents);
+        if state.denied_log_groups.contains(log_group_name) {
+            return Err(CloudWatchLogsError::FilterLogEventsError(access_denied(
+                FilterLogEventsError::generic,
+            )));
+        }
+        let log_group = state.log_groups.get(log_group_name).ok_or_else(|| {
+            CloudWatchLogsError::FilterLogEventsError(resource_not_found(log_group_name, |err, meta| {
+                FilterLogEventsError::new(FilterLogEventsErrorKind::ResourceNotFoundException(err), meta)
+            }))
+        })?;
+        let events: Vec<FilteredLogEvent> = log_group
+            .events
+            .iter()
+            .filter(|event| {
+                is_in_time_range(event, start_time, end_time) && matches_filter(event, event_filter)
+            })
+            .cloned()
+            .collect();
+        let page = self.page(&events, next_token);
+        self.usage.record_events(page.0.len());
+        Ok(page)
+    }
+
+    async fn get_log_events_page(
+        &self,
+        log_group_name: &str,
+        log_stream_name: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        _unmask: bool,
+        next_token: Option<String>,
+        _position: usize,
+    ) -> Result<(Vec<FilteredLogEvent>, Option<String>), CloudWatchLogsError> {
+        let state = self.call(ApiAction::GetLogEvents);
+        if state.denied_log_groups.contains(log_group_name) {
+            return Err(CloudWatchLogsError::GetLogEventsError(access_denied(
+                GetLogEventsError::generic,
+            )));
+        }
+        let log_group = state.log_groups.get(log_group_name).ok_or_else(|| {
+            CloudWatchLogsError::GetLogEventsError(resource_not_found(log_group_name, |err, meta| {
+                GetLogEventsError::new(GetLogEventsErrorKind::ResourceNotFoundException(err), meta)
+            }))
+        })?;
+        let events: Vec<FilteredLogEvent> = log_group
+            .events
+            .iter()
+            .filter(|event| {
+                event.log_stream_name == log_stream_name && is_in_time_range(event, start_time, end_time)
+            })
+            .cloned()
+            .collect();
+        let page = self.page(&events, next_token);
+        self.usage.record_events(page.0.len());
+        Ok(page)
+    }
+
+    async fn describe_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Option<LogGroupMetadata>, CloudWatchLogsError> {
+        let state = self.call(ApiAction::DescribeLogGroups);
+        Ok(state
+            .log_groups
+            .get(&log_group_name)
+            .map(|log_group| self.log_group_metadata(&log_group_name, log_group)))
+    }
+
+    async fn describe_metric_filters(
+        &self,
+        log_group_name: String,
+    ) -> Result<Vec<MetricFilter>, CloudWatchLogsError> {
+        Ok(self
+            .call(ApiAction::DescribeMetricFilters)
+            .log_groups
+            .get(&log_group_name)
+            .map(|log_group| log_group.metric_filters.clone())
+            .unwrap_or_default())
+    }
+
+    async fn describe_subscription_filters(
+        &self,
+        log_group_name: String,
+    ) -> Result<Vec<SubscriptionFilter>, CloudWatchLogsError> {
+        Ok(self
+            .call(ApiAction::DescribeSubscriptionFilters)
+            .log_groups
+            .get(&log_group_name)
+            .map(|log_group| log_group.subscription_filters.clone())
+            .unwrap_or_default())
+    }
+
+    async fn describe_query_definitions(
+        &self,
+        name_prefix: Option<String>,
+    ) -> Result<Vec<QueryDefinition>, CloudWatchLogsError> {
+        Ok(self
+            .call(ApiAction::DescribeQueryDefinitions)
+            .query_definitions
+            .iter()
+            .filter(|query_definition| {
+                name_prefix
+                    .as_ref()
+                    .is_none_or(|prefix| query_definition.name.starts_with(prefix.as_str()))
+            })
+            .cloned()
+            .collect())
+    }
+
+    /// Export tasks complete as soon as they are created, without writing anything.
+    async fn create_export_task(
+        &self,
+        _log_group_name: String,
+        _start_time: DateTime<Utc>,
+        _end_time: DateTime<Utc>,
+        _bucket: String,
+        _prefix: Option<String>,
+    ) -> Result<String, CloudWatchLogsError> {
+        let mut state = self.call(ApiAction::CreateExportTask);
+        let task_id = format!("export-task-{}", state.export_tasks.len());
+        state
+            .export_tasks
+            .insert(task_id.clone(), ExportTaskStatus::Completed);
+        Ok(task_id)
+    }
+
+    async fn get_export_task_status(&self, task_id: String) -> Result<ExportTaskStatus, CloudWatchLogsError> {
+        self.call(ApiAction::DescribeExportTasks)
+            .export_tasks
+            .get(&task_id)
+            .cloned()
+            .ok_or(CloudWatchLogsError::ExportTaskNotFound(task_id))
+    }
+
+    async fn get_last_event_time_for_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Option<DateTime<Utc>>, CloudWatchLogsError> {
+        let state = self.call(ApiAction::DescribeLogStreams);
+        let log_group = state.log_groups.get(&log_group_name).ok_or_else(|| {
+            CloudWatchLogsError::DescribeLogStreamsError(resource_not_found(&log_group_name, |err, meta| {
+                DescribeLogStreamsError::new(
+                    DescribeLogStreamsErrorKind::ResourceNotFoundException(err),
+                    meta,
+                )
+            }))
+        })?;
+        Ok(log_group.events.last().map(|event| event.timestamp))
+    }
+
+    async fn get_log_streams(
+        &self,
+        log_group_name: String,
+        prefix: Option<String>,
+        order_by: LogStreamOrderBy,
+    ) -> Result<Vec<LogStream>, CloudWatchLogsError> {
+        if prefix.is_some() && order_by == LogStreamOrderBy::LastEventTime {
+            return Err(CloudWatchLogsError::InvalidArgument(
+                "log streams cannot be ordered by last event time when filtering by prefix".to_string(),
+            ));
+        }
+        let state = self.call(ApiAction::DescribeLogStreams);
+        let log_group = state.log_groups.get(&log_group_name).ok_or_else(|| {
+            CloudWatchLogsError::DescribeLogStreamsError(resource_not_found(&log_group_name, |err, meta| {
+                DescribeLogStreamsError::new(
+                    DescribeLogStreamsErrorKind::ResourceNotFoundException(err),
+                    meta,
+                )
+            }))
+        })?;
+        let mut log_streams: BTreeMap<&str, LogStream> = BTreeMap::new();
+        for event in &log_group.events {
+            if !prefix
+                .as_ref()
+                .is_none_or(|prefix| event.log_stream_name.starts_with(prefix.as_str()))
+            {
+                continue;
+            }
+            let log_stream = log_streams
+                .entry(event.log_stream_name.as_str())
+                .or_insert_with(|| LogStream {
+                    log_stream_name: event.log_stream_name.clone(),
+                    first_event_time: Some(event.timestamp),
+                    last_event_time: None,
+                });
+            log_stream.last_event_time = Some(event.timestamp);
+        }
+        let mut log_streams: Vec<LogStream> = log_streams.into_values().collect();
+        if order_by == LogStreamOrderBy::LastEventTime {
+            log_streams.sort_by_key(|log_stream| std::cmp::Reverse(log_stream.last_event_time));
+        }
+        Ok(log_streams)
+    }
+
+    async fn run_insights_query(
+        &self,
+        log_group_names: Vec<String>,
+        _query_string: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<InsightsQueryRow>, CloudWatchLogsError> {
+        let state = self.call(ApiAction::StartQuery);
+        let scanned: Vec<&FilteredLogEvent> = log_group_names
+            .iter()
+            .filter_map(|log_group_name| state.log_groups.get(log_group_name))
+            .flat_map(|log_group| log_group.events.iter())
+            .filter(|event| is_in_time_range(event, Some(start_time), Some(end_time)))
+            .collect();
+        self.usage.record_call(ApiAction::GetQueryResults);
+        self.usage.record_insights_scan(
+            scanned.len() as f64,
+            scanned.iter().map(|event| event.message.len()).sum::<usize>() as f64,
+        );
+        Ok(scanned
+            .into_iter()
+            .map(|event| {
+                vec![
+                    ("@timestamp".to_string(), event.timestamp.to_rfc3339()),
+                    ("@message".to_string(), event.message.clone()),
+                ]
+            })
+            .collect())
+    }
+}
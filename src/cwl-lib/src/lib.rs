@@ -6,9 +6,30 @@
 #[macro_use]
 extern crate derivative;
 
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
+use async_trait::async_trait;
+use aws_config::default_provider::region::DefaultRegionChain;
+use aws_config::meta::credentials::LazyCachingCredentialsProvider;
+use aws_config::provider_config::ProviderConfig;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::RetryConfig;
+use aws_sdk_cloudwatchlogs::input::{DescribeLogGroupsInput, FilterLogEventsInput, GetLogEventsInput};
+use aws_sdk_cloudwatchlogs::middleware::DefaultMiddleware;
+use aws_sdk_cloudwatchlogs::model::{ExportTaskStatusCode, QueryStatus};
+use aws_sdk_cloudwatchlogs::output::DescribeLogGroupsOutput;
 use aws_sdk_cloudwatchlogs::Client;
+use aws_smithy_client::erase::DynConnector;
+use aws_smithy_http::result::SdkError;
+use aws_smithy_types::retry::ProvideErrorKind;
+use aws_types::credentials::{ProvideCredentials, SharedCredentialsProvider};
 use aws_types::region::Region;
 use bytes::Bytes;
 use chrono::DateTime;
@@ -16,40 +37,311 @@ use chrono::Duration;
 use chrono::TimeZone;
 use chrono::Utc;
 use format_cwl_log_event::FilteredLogEvent;
+use futures::future::join_all;
 use futures::future::try_join_all;
-use leaky_bucket::RateLimiter;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
 use lru::LruCache;
+use regexes::LogGroupArn;
 use regexes::LogGroupNameMatcher;
+pub use regexes::MessageFilter;
+use serde_json::{Map, Value};
 use thiserror::Error;
-use tokio::sync::{mpsc, oneshot};
-use tokio::task::JoinHandle;
-use tracing::{debug, instrument, trace};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot, watch, OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, info, instrument, trace, warn};
+
+pub use aws_types::credentials;
+
+use crate::api::events_from_pages;
+pub use crate::api::CloudWatchLogsApi;
+use crate::circuit_breaker::{BreakerState, CircuitBreakers};
+use crate::credentials_source::{default_credentials_chain, CredentialsSource};
+use crate::disk_cache::DiskCache;
+pub use crate::disk_cache::{CacheEncryptionKey, DiskCacheOptions};
+use crate::endpoint::EndpointVariants;
+pub use crate::endpoint::Partition;
+pub use crate::fake::FakeCloudWatchLogs;
+pub use crate::mailbox::MailboxFullPolicy;
+use crate::mailbox::{mailbox, MailboxReceiver, MailboxSender, SendError};
+use crate::proxy::ProxyConnector;
+pub use crate::proxy::{no_proxy_from_env, ProxyOptions};
+pub use crate::rate_limit::ApiAction;
+use crate::rate_limit::RateLimiters;
+use crate::request_fields::{with_fields, OperationClient};
+pub use crate::retry::RetryPolicy;
+use crate::retry::{is_credentials_error, is_unsupported_by_log_group_class, with_retries, CallFailure};
+pub use crate::transform::{LogEventTransformer, TransformerChain};
+use crate::usage::Usage;
+pub use crate::usage::UsageStats;
+
+mod api;
+mod circuit_breaker;
+mod credentials_source;
+mod disk_cache;
+mod endpoint;
+mod fake;
+mod mailbox;
+mod proxy;
+mod rate_limit;
+mod request_fields;
+mod retry;
+mod transform;
+mod usage;
 
 #[derive(Error, Debug)]
 pub enum CloudWatchLogsError {
     #[error("CloudWatch Logs SDK describe logs error")]
     DescribeLogGroupsError(
-        #[from] aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::DescribeLogGroupsError>,
+        #[from] Box<aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::DescribeLogGroupsError>>,
     ),
 
     #[error("CloudWatch Logs SDK filter log events error")]
     FilterLogEventsError(
-        #[from] aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::FilterLogEventsError>,
+        #[from] Box<aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::FilterLogEventsError>>,
+    ),
+
+    #[error("CloudWatch Logs SDK describe log streams error")]
+    DescribeLogStreamsError(
+        #[from]
+        Box<aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::DescribeLogStreamsError>>,
+    ),
+
+    #[error("CloudWatch Logs SDK create export task error")]
+    CreateExportTaskError(
+        #[from] Box<aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::CreateExportTaskError>>,
+    ),
+
+    #[error("CloudWatch Logs SDK describe export tasks error")]
+    DescribeExportTasksError(
+        #[from]
+        Box<aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::DescribeExportTasksError>>,
+    ),
+
+    #[error("CloudWatch Logs SDK describe metric filters error")]
+    DescribeMetricFiltersError(
+        #[from]
+        Box<aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::DescribeMetricFiltersError>>,
+    ),
+
+    #[error("CloudWatch Logs SDK describe subscription filters error")]
+    DescribeSubscriptionFiltersError(
+        #[from]
+        Box<
+            aws_smithy_http::result::SdkError<
+                aws_sdk_cloudwatchlogs::error::DescribeSubscriptionFiltersError,
+            >,
+        >,
+    ),
+
+    #[error("CloudWatch Logs SDK describe query definitions error")]
+    DescribeQueryDefinitionsError(
+        #[from]
+        Box<aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::DescribeQueryDefinitionsError>>,
+    ),
+
+    #[error("CloudWatch Logs SDK get log events error")]
+    GetLogEventsError(
+        #[from] Box<aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::GetLogEventsError>>,
+    ),
+
+    #[error("CloudWatch Logs SDK start query error")]
+    StartQueryError(
+        #[from] Box<aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::StartQueryError>>,
+    ),
+
+    #[error("CloudWatch Logs SDK get query results error")]
+    GetQueryResultsError(
+        #[from] Box<aws_smithy_http::result::SdkError<aws_sdk_cloudwatchlogs::error::GetQueryResultsError>>,
     ),
 
+    #[error("CloudWatch Logs Insights query failed: {0}")]
+    InsightsQueryFailed(String),
+
     #[error("failed to convert CloudWatch filtered log event: {0}")]
     FailedToConvertCloudWatchFilteredLogEvent(String),
 
+    #[error("failed to load AWS credentials: {0}")]
+    CredentialsUnavailable(String),
+
+    #[error("export task {0} not found")]
+    ExportTaskNotFound(String),
+
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+
     #[error("Invalid GetLogsToDisplay message: {0}")]
     InvalidGetLogsToDisplayMessage(String),
 
     #[error("No CloudWatch Logs log groups match filter: {0}")]
     NoCloudWatchLogGroupsMatchFilter(String),
 
+    /// Getting log events failed for some of the log groups behind a file, one error per log group.
+    #[error("failed to get log events for {}", describe_log_group_errors(.0))]
+    LogGroupsFailed(Vec<(String, CloudWatchLogsError)>),
+
+    #[error("reading it failed {failures} times in a row; trying again after {retry_at}")]
+    CircuitOpen {
+        failures: usize,
+        retry_at: DateTime<Utc>,
+    },
+
+    #[error(
+        "log group {0} is in the Infrequent Access class, which only CloudWatch Logs Insights can read; write a query \
+         to insights/new instead"
+    )]
+    UnsupportedByLogGroupClass(String),
+
+    #[error("task getting log events for log group {0} did not finish")]
+    LogGroupTaskFailed(String),
+
+    #[error("too many requests are waiting for CloudWatch Logs, try again later")]
+    MailboxFull,
+
+    #[error("the CloudWatch Logs actor is not running")]
+    ActorUnavailable,
+
+    #[error("the CloudWatch Logs actor did not respond in time")]
+    RequestTimedOut,
+
+    #[error("requests were still running when the CloudWatch Logs actor shut down")]
+    ShutdownTimedOut,
+
     #[error("unknown cloudwatch logs error")]
     Unknown,
 }
 
+fn describe_log_group_errors(errors: &[(String, CloudWatchLogsError)]) -> String {
+    errors
+        .iter()
+        .map(|(log_group_name, err)| format!("{}: {}", log_group_name, err))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl CloudWatchLogsError {
+    /// Whether CloudWatch Logs reported that the log group or log stream does not exist.
+    pub fn is_resource_not_found(&self) -> bool {
+        match self {
+            CloudWatchLogsError::FilterLogEventsError(err) => {
+                matches!(err.as_ref(), SdkError::ServiceError { err, .. } if err.is_resource_not_found_exception())
+            }
+            CloudWatchLogsError::GetLogEventsError(err) => {
+                matches!(err.as_ref(), SdkError::ServiceError { err, .. } if err.is_resource_not_found_exception())
+            }
+            CloudWatchLogsError::DescribeLogStreamsError(err) => {
+                matches!(err.as_ref(), SdkError::ServiceError { err, .. } if err.is_resource_not_found_exception())
+            }
+            CloudWatchLogsError::StartQueryError(err) => {
+                matches!(err.as_ref(), SdkError::ServiceError { err, .. } if err.is_resource_not_found_exception())
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether AWS rejected the credentials or they could not be loaded, e.g. because an SSO session or
+    /// assumed role expired. Logging in again fixes these without remounting.
+    pub fn is_credentials_error(&self) -> bool {
+        match self {
+            CloudWatchLogsError::DescribeLogGroupsError(err) => is_credentials_error(err),
+            CloudWatchLogsError::FilterLogEventsError(err) => is_credentials_error(err),
+            CloudWatchLogsError::DescribeLogStreamsError(err) => is_credentials_error(err),
+            CloudWatchLogsError::CreateExportTaskError(err) => is_credentials_error(err),
+            CloudWatchLogsError::DescribeExportTasksError(err) => is_credentials_error(err),
+            CloudWatchLogsError::DescribeMetricFiltersError(err) => is_credentials_error(err),
+            CloudWatchLogsError::DescribeSubscriptionFiltersError(err) => is_credentials_error(err),
+            CloudWatchLogsError::DescribeQueryDefinitionsError(err) => is_credentials_error(err),
+            CloudWatchLogsError::GetLogEventsError(err) => is_credentials_error(err),
+            CloudWatchLogsError::StartQueryError(err) => is_credentials_error(err),
+            CloudWatchLogsError::GetQueryResultsError(err) => is_credentials_error(err),
+            CloudWatchLogsError::LogGroupsFailed(errors) => {
+                errors.iter().any(|(_, err)| err.is_credentials_error())
+            }
+            CloudWatchLogsError::CredentialsUnavailable(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the log group's class does not allow reading its events directly, e.g. the Infrequent Access class.
+    pub fn is_unsupported_by_log_group_class(&self) -> bool {
+        match self {
+            CloudWatchLogsError::UnsupportedByLogGroupClass(_) => true,
+            CloudWatchLogsError::LogGroupsFailed(errors) => errors
+                .iter()
+                .any(|(_, err)| err.is_unsupported_by_log_group_class()),
+            _ => false,
+        }
+    }
+
+    /// Whether CloudWatch Logs still asked to slow down after retrying, or too many requests are waiting for it.
+    pub fn is_throttling(&self) -> bool {
+        matches!(self, CloudWatchLogsError::MailboxFull) || self.any_call_failure(&|err| err.is_throttling())
+    }
+
+    /// Whether the credentials are not allowed to make the call, e.g. because IAM does not allow
+    /// logs:FilterLogEvents on the log group.
+    pub fn is_access_denied(&self) -> bool {
+        self.any_call_failure(&|err| err.is_access_denied())
+    }
+
+    /// Whether a call took longer than its timeout, even after retrying.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, CloudWatchLogsError::RequestTimedOut) || self.any_call_failure(&|err| err.is_timeout())
+    }
+
+    fn any_call_failure(&self, check: &dyn Fn(&dyn CallFailure) -> bool) -> bool {
+        match self {
+            CloudWatchLogsError::DescribeLogGroupsError(err) => check(err.as_ref()),
+            CloudWatchLogsError::FilterLogEventsError(err) => check(err.as_ref()),
+            CloudWatchLogsError::DescribeLogStreamsError(err) => check(err.as_ref()),
+            CloudWatchLogsError::CreateExportTaskError(err) => check(err.as_ref()),
+            CloudWatchLogsError::DescribeExportTasksError(err) => check(err.as_ref()),
+            CloudWatchLogsError::DescribeMetricFiltersError(err) => check(err.as_ref()),
+            CloudWatchLogsError::DescribeSubscriptionFiltersError(err) => check(err.as_ref()),
+            CloudWatchLogsError::DescribeQueryDefinitionsError(err) => check(err.as_ref()),
+            CloudWatchLogsError::GetLogEventsError(err) => check(err.as_ref()),
+            CloudWatchLogsError::StartQueryError(err) => check(err.as_ref()),
+            CloudWatchLogsError::GetQueryResultsError(err) => check(err.as_ref()),
+            CloudWatchLogsError::LogGroupsFailed(errors) => {
+                errors.iter().any(|(_, err)| err.any_call_failure(check))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Regions that are enabled by default in the standard AWS partition, see `Partition::regions`.
+pub const ALL_REGIONS: &[&str] = &[
+    "us-east-1",
+    "us-east-2",
+    "us-west-1",
+    "us-west-2",
+    "ca-central-1",
+    "eu-central-1",
+    "eu-north-1",
+    "eu-west-1",
+    "eu-west-2",
+    "eu-west-3",
+    "ap-northeast-1",
+    "ap-northeast-2",
+    "ap-northeast-3",
+    "ap-south-1",
+    "ap-southeast-1",
+    "ap-southeast-2",
+    "sa-east-1",
+];
+
+/// Name a log group that DescribeLogGroups returned with `includeLinkedAccounts` by its ARN if it is in a source
+/// account rather than in `account_id`, the monitoring account.
+fn name_linked_log_group(metadata: LogGroupMetadata, account_id: Option<&str>) -> LogGroupMetadata {
+    match metadata.arn.as_deref().and_then(LogGroupArn::parse) {
+        Some(arn) if Some(arn.account_id.as_str()) != account_id => LogGroupMetadata {
+            log_group_name: arn.identifier(),
+            ..metadata
+        },
+        _ => metadata,
+    }
+}
+
 fn convert_to_filtered_log_event(
     log_group_name: impl Into<std::string::String>,
     value: aws_sdk_cloudwatchlogs::model::FilteredLogEvent,
@@ -85,7 +377,7 @@ fn convert_to_filtered_log_event(
         )),
     }?;
     Ok(format_cwl_log_event::FilteredLogEvent::new(
-        &log_group_name.into().clone(),
+        log_group_name.into(),
         &event_id,
         ingestion_time,
         &log_stream_name,
@@ -94,6 +386,113 @@ fn convert_to_filtered_log_event(
     ))
 }
 
+/// GetLogEvents does not return event IDs, so the event ID is made up from the event's position in the stream.
+fn convert_to_filtered_log_event_for_stream(
+    log_group_name: impl Into<std::string::String>,
+    log_stream_name: &str,
+    position: usize,
+    value: aws_sdk_cloudwatchlogs::model::OutputLogEvent,
+) -> Result<format_cwl_log_event::FilteredLogEvent, CloudWatchLogsError> {
+    let ingestion_time = match value.ingestion_time {
+        Some(ingestion_time) => Ok(chrono::Utc.timestamp_millis(ingestion_time)),
+        None => Err(CloudWatchLogsError::FailedToConvertCloudWatchFilteredLogEvent(
+            "ingestion_time missing".to_string(),
+        )),
+    }?;
+    let message = match value.message {
+        Some(message) => Ok(message),
+        None => Err(CloudWatchLogsError::FailedToConvertCloudWatchFilteredLogEvent(
+            "message missing".to_string(),
+        )),
+    }?;
+    let timestamp = match value.timestamp {
+        Some(timestamp) => Ok(chrono::Utc.timestamp_millis(timestamp)),
+        None => Err(CloudWatchLogsError::FailedToConvertCloudWatchFilteredLogEvent(
+            "timestamp missing".to_string(),
+        )),
+    }?;
+    Ok(format_cwl_log_event::FilteredLogEvent::new(
+        log_group_name.into(),
+        format!("{}-{}", timestamp.timestamp_millis(), position),
+        ingestion_time,
+        log_stream_name,
+        &message,
+        timestamp,
+    ))
+}
+
+/// Server-side filtering applied when fetching log events.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct EventFilter {
+    /// CloudWatch Logs filter pattern [1], e.g. `ERROR` or `{ $.level = "error" }`.
+    ///
+    /// [1] https://docs.aws.amazon.com/AmazonCloudWatch/latest/logs/FilterAndPatternSyntax.html
+    pub filter_pattern: Option<String>,
+
+    /// Only return events from log streams whose names start with this prefix.
+    pub log_stream_name_prefix: Option<String>,
+
+    /// Only return events from this log stream. Cannot be combined with `log_stream_name_prefix`.
+    pub log_stream_name: Option<String>,
+
+    /// Only return events whose message matches this regular expression. Unlike the other filters this is applied
+    /// by cwl-mount, so events that do not match are still fetched.
+    pub message_filter: Option<MessageFilter>,
+
+    /// Return the real values of data that a data protection policy masks [1], which needs the logs:Unmask
+    /// permission.
+    ///
+    /// [1] https://docs.aws.amazon.com/AmazonCloudWatch/latest/logs/mask-sensitive-log-data.html
+    pub unmask: bool,
+}
+
+impl EventFilter {
+    /// GetLogEvents is faster and has a higher quota than FilterLogEvents but can only read a single stream and
+    /// does not support filter patterns.
+    fn can_use_get_log_events(&self) -> bool {
+        self.log_stream_name.is_some()
+            && self.filter_pattern.is_none()
+            && self.log_stream_name_prefix.is_none()
+    }
+
+    fn matches_message(&self, event: &FilteredLogEvent) -> bool {
+        self.message_filter
+            .as_ref()
+            .is_none_or(|message_filter| message_filter.is_match(&event.message))
+    }
+}
+
+/// One row of CloudWatch Logs Insights query results as (field, value) pairs, in the order returned.
+pub type InsightsQueryRow = Vec<(String, String)>;
+
+/// Render CloudWatch Logs Insights query results as tab-separated values with a header line. The `@ptr`
+/// field is an opaque pointer to the underlying log event so it is omitted.
+pub fn render_insights_query_results(rows: &[InsightsQueryRow]) -> Bytes {
+    const POINTER_FIELD: &str = "@ptr";
+    let fields: Vec<&str> = match rows.first() {
+        Some(row) => row
+            .iter()
+            .map(|(field, _)| field.as_str())
+            .filter(|field| *field != POINTER_FIELD)
+            .collect(),
+        None => return Bytes::new(),
+    };
+    let mut lines = vec![fields.join("\t")];
+    for row in rows {
+        let values: Vec<&str> = fields
+            .iter()
+            .map(|field| {
+                row.iter()
+                    .find(|(row_field, _)| row_field == field)
+                    .map(|(_, value)| value.trim_end())
+                    .unwrap_or("")
+            })
+            .collect();
+        lines.push(values.join("\t"));
+    }
+    lines.join("\n").into()
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct TimeBounds {
     pub first_event_time: DateTime<Utc>,
@@ -104,459 +503,5317 @@ pub struct TimeBounds {
 struct CacheKey {
     pub log_group_name_matcher: LogGroupNameMatcher,
     pub time_bounds: TimeBounds,
+    pub event_filter: EventFilter,
     pub formatter: format_cwl_log_event::LogFormatter,
 }
 
+/// Bump this when the same key would hold a different file, e.g. because events are rendered differently, so that old
+/// disk cache entries are no longer found.
+const DISK_CACHE_KEY_VERSION: u32 = 1;
+
+/// The key of a file in the disk cache, which unlike `CacheKey` must stay the same across releases and runs. Every
+/// field is written out, so that adding one to `CacheKey` or `EventFilter` fails to compile until it is added here too.
+/// The limits change what a file holds too, and unlike in memory they can differ between runs.
+fn disk_cache_key(
+    cache_key: &CacheKey,
+    max_events_per_file: Option<usize>,
+    max_file_size: Option<usize>,
+) -> String {
+    let CacheKey {
+        log_group_name_matcher,
+        time_bounds,
+        event_filter,
+        formatter,
+    } = cache_key;
+    let EventFilter {
+        filter_pattern,
+        log_stream_name_prefix,
+        log_stream_name,
+        message_filter,
+        unmask,
+    } = event_filter;
+    serde_json::json!({
+        "version": DISK_CACHE_KEY_VERSION,
+        "log_group_filter": log_group_name_matcher.as_str(),
+        "log_group_exclude": log_group_name_matcher.exclude_as_str(),
+        "start_time_ms": time_bounds.first_event_time.timestamp_millis(),
+        "end_time_ms": time_bounds.last_event_time.timestamp_millis(),
+        "filter_pattern": filter_pattern,
+        "log_stream_name_prefix": log_stream_name_prefix,
+        "log_stream_name": log_stream_name,
+        "message_filter": message_filter.as_ref().map(MessageFilter::as_str),
+        "unmask": unmask,
+        "output_format": formatter.as_str(),
+        "max_events_per_file": max_events_per_file,
+        "max_file_size": max_file_size,
+    })
+    .to_string()
+}
+
+/// A rendered log file, compressed with zstd because log text compresses very well.
 #[derive(Clone, Debug)]
 struct CacheValue {
-    pub data_to_display: Bytes,
+    pub compressed_data_to_display: Bytes,
 }
 
-#[derive(Derivative)]
-#[derivative(Clone, Debug)]
-pub struct CloudWatchLogsImpl {
-    client: aws_sdk_cloudwatchlogs::Client,
+impl CacheValue {
+    fn compress(data_to_display: &[u8], level: i32) -> std::io::Result<Self> {
+        Ok(CacheValue {
+            compressed_data_to_display: zstd::encode_all(data_to_display, level)?.into(),
+        })
+    }
 
-    #[derivative(Debug = "ignore")]
-    rate_limiter: Arc<RateLimiter>,
+    fn decompress(&self) -> std::io::Result<Bytes> {
+        Ok(zstd::decode_all(self.compressed_data_to_display.as_ref())?.into())
+    }
 }
 
-impl CloudWatchLogsImpl {
-    #[instrument(level = "debug")]
-    pub async fn new<T: std::fmt::Debug + Into<String>>(tps: usize, region: Option<T>) -> Self {
-        let mut config = aws_config::from_env();
-        if let Some(region) = region {
-            config = config.region(Region::new(region.into()));
-        }
-        let config = config.load().await;
-        let client = Client::new(&config);
-        Self {
-            client,
-            rate_limiter: Arc::new(
-                RateLimiter::builder()
-                    .max(tps)
-                    .initial(tps)
-                    .refill(tps)
-                    .interval(std::time::Duration::from_secs(1))
-                    .build(),
-            ),
-        }
-    }
+/// How to order log streams when listing them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum LogStreamOrderBy {
+    /// Alphabetically by name.
+    LogStreamName,
 
-    #[instrument(level = "debug")]
-    pub async fn get_log_group_names(&self) -> Result<Vec<String>, CloudWatchLogsError> {
-        const LOG_GROUP_LIMIT: i32 = 50;
-        let mut result = Vec::new();
-        let mut next_token: Option<String> = None;
-        loop {
-            self.rate_limiter.acquire_one().await;
-            let req = self
-                .client
-                .describe_log_groups()
-                .limit(LOG_GROUP_LIMIT)
-                .set_next_token(next_token.clone());
-            let resp = match req.send().await {
-                Ok(inner) => Ok(inner),
-                Err(err) => Err(CloudWatchLogsError::DescribeLogGroupsError(err)),
-            }?;
-            let log_groups = resp.log_groups();
-            if log_groups.is_none() {
-                break;
-            }
-            let log_groups = log_groups.unwrap();
-            if log_groups.is_empty() {
-                break;
-            }
-            log_groups
-                .into_iter()
-                .map(|log_group| log_group.log_group_name().unwrap().to_string())
-                .for_each(|log_group| result.push(log_group));
-            if resp.next_token.is_none() {
-                break;
-            }
-            next_token = resp.next_token;
-        }
-        Ok(result)
-    }
+    /// Most recently written first. CloudWatch Logs does not allow combining this with a name prefix.
+    LastEventTime,
+}
 
-    #[instrument(level = "debug")]
-    pub async fn get_log_events(
-        &self,
-        log_group_name: String,
-        start_time: Option<DateTime<Utc>>,
-        end_time: Option<DateTime<Utc>>,
-        limit: Option<i32>,
-    ) -> Result<Vec<FilteredLogEvent>, CloudWatchLogsError> {
-        const LOGS_BATCH_SIZE: i32 = 10_000;
-        let mut events = Vec::with_capacity(LOGS_BATCH_SIZE as usize);
-        let mut next_token: Option<String> = None;
-        let limit = limit.unwrap_or(usize::MAX as i32) as usize;
-        loop {
-            debug!("tick, start_time: {:?}, end_time: {:?}", start_time, end_time);
-            self.rate_limiter.acquire_one().await;
-            let mut req = self
-                .client
-                .filter_log_events()
-                .log_group_name(&log_group_name)
-                .limit(LOGS_BATCH_SIZE as i32)
-                .set_next_token(next_token);
-            if let Some(start_time) = start_time {
-                req = req.start_time(start_time.timestamp_millis());
-            }
-            if let Some(end_time) = end_time {
-                req = req.end_time(end_time.timestamp_millis());
-            }
-            let resp = match req.send().await {
-                Ok(inner) => Ok(inner),
-                Err(err) => Err(CloudWatchLogsError::FilterLogEventsError(err)),
-            }?;
-            for event in resp.events.unwrap_or(vec![]) {
-                let event = convert_to_filtered_log_event(&log_group_name, event)?;
-                if events.len() >= limit {
-                    return Ok(events);
-                }
-                events.push(event);
-            }
-            if resp.next_token.is_none() {
-                break;
-            }
-            next_token = resp.next_token;
+/// A file that reads a time range, along with what it took to fetch it.
+#[derive(Clone, Debug)]
+pub struct LogPage {
+    pub data: Bytes,
+
+    /// Whether the file came from the in-memory or disk cache. Nothing is fetched then, so the counts below are zero.
+    /// Truncated files are never cached, so `truncated` is false.
+    pub cached: bool,
+
+    /// Log groups read, across every region.
+    pub log_groups_queried: usize,
+
+    /// Events in the file, not counting those left out because the file was truncated.
+    pub events: usize,
+
+    /// FilterLogEvents or GetLogEvents calls made.
+    pub pages_fetched: usize,
+
+    /// Whether the file had more events than the limit and ends with a truncation marker instead of the rest.
+    pub truncated: bool,
+
+    /// How long fetching and merging the events took, including waits for the rate limiter.
+    pub fetch_duration: std::time::Duration,
+}
+
+impl LogPage {
+    fn from_cache(data: Bytes) -> Self {
+        LogPage {
+            data,
+            cached: true,
+            log_groups_queried: 0,
+            events: 0,
+            pages_fetched: 0,
+            truncated: false,
+            fetch_duration: std::time::Duration::ZERO,
         }
-        Ok(events)
     }
+}
 
-    #[instrument(level = "debug")]
-    pub async fn get_first_event_time_for_log_group(
-        &self,
-        log_group_name: String,
-    ) -> Result<Option<DateTime<Utc>>, CloudWatchLogsError> {
-        let search_window: chrono::Duration = Duration::days(365 * 5);
-        let last_event_time = Utc::now();
-        let mut first_event_time = last_event_time - search_window;
-        let log_group_name = log_group_name.into();
-        let log_events = self
-            .get_log_events(
-                log_group_name,
-                Some(first_event_time),
-                Some(last_event_time),
-                Some(1),
-            )
-            .await?;
-        if let Some(log_event) = log_events.first() {
-            first_event_time = log_event.timestamp;
-        } else {
-            return Ok(None);
-        }
+/// A log stream and the time range of its events, if it has any.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogStream {
+    pub log_stream_name: String,
+    pub first_event_time: Option<DateTime<Utc>>,
+    pub last_event_time: Option<DateTime<Utc>>,
+}
 
-        Ok(Some(first_event_time))
-    }
+/// Where to carry on reading a log group's events from. Only meaningful for the same log group, time window and
+/// event filter.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LogEventsPageToken {
+    /// Region of the client that served the first page, so that the rest come from the same region.
+    region: Option<String>,
+    next_token: Option<String>,
+    position: usize,
+    bytes_rendered: u64,
+    events_rendered: usize,
 }
 
-fn is_cacheable(cache_key: &CacheKey) -> bool {
-    Utc::now() - cache_key.time_bounds.last_event_time > Duration::minutes(5)
+/// One page of a log group's events along with how they are displayed. Joining the `data` of every page gives the
+/// same file as reading the whole time window at once.
+#[derive(Clone, Debug)]
+pub struct LogEventsPage {
+    /// Empty if the page is the rest of the file read whole, which happens when the file is cached, when the log
+    /// group is in more than one region, and past the limits on events and bytes per file.
+    pub events: Vec<FilteredLogEvent>,
+
+    /// This page's events formatted for display, starting with the newline that separates them from the previous
+    /// page's.
+    pub data: Bytes,
+
+    /// Bytes rendered by this page and the pages before it, i.e. the offset in the file where the next page starts.
+    pub bytes_rendered: u64,
+
+    /// Pass this to get the next page, or None if this was the last page.
+    pub next_page_token: Option<LogEventsPageToken>,
 }
 
-#[instrument(level = "debug")]
-async fn get_logs_to_display(
-    log_group_name_matcher: LogGroupNameMatcher,
-    start_time: DateTime<Utc>,
-    end_time: DateTime<Utc>,
-    formatter: format_cwl_log_event::LogFormatter,
-    cwl: Arc<CloudWatchLogsImpl>,
-    cache: Arc<tokio::sync::Mutex<LruCache<CacheKey, CacheValue>>>,
-) -> Result<Bytes, CloudWatchLogsError> {
-    let cache_key = CacheKey {
-        log_group_name_matcher: log_group_name_matcher.clone(),
-        time_bounds: TimeBounds {
-            first_event_time: start_time,
-            last_event_time: end_time,
-        },
-        formatter: formatter.clone(),
-    };
-    debug!("get_logs_to_display. cache_key: {:?}", cache_key);
-    let cache = Arc::clone(&cache);
-    {
-        let mut cache = cache.lock().await;
-        if let Some(value) = cache.get(&cache_key) {
-            return Ok(value.data_to_display.clone());
+impl LogEventsPage {
+    /// The last page, with the rest of a file read whole after the `bytes_rendered` of the pages before it.
+    fn rest_of_file(data: Bytes, bytes_rendered: u64) -> Self {
+        let data = data.slice((bytes_rendered as usize).min(data.len())..);
+        LogEventsPage {
+            events: vec![],
+            bytes_rendered: bytes_rendered + data.len() as u64,
+            data,
+            next_page_token: None,
         }
     }
-    let log_group_names: Vec<String> = cwl
-        .get_log_group_names()
-        .await?
+}
+
+/// Render a page of fetched events, leaving out those that `event_filter` only filters after fetching and those
+/// that `transformers` leave out.
+fn render_page(
+    events: Vec<FilteredLogEvent>,
+    next_token: Option<String>,
+    page_token: &LogEventsPageToken,
+    event_filter: &EventFilter,
+    transformers: &TransformerChain,
+    formatter: &format_cwl_log_event::LogFormatter,
+) -> LogEventsPage {
+    // Positions count every fetched event, because GetLogEvents event IDs are made up from them.
+    let position = page_token.position + events.len();
+    let events: Vec<FilteredLogEvent> = events
         .into_iter()
-        .filter(|log_group_name| log_group_name_matcher.is_match(log_group_name))
+        .filter(|event| event_filter.matches_message(event))
+        .filter_map(|event| transformers.apply(event))
         .collect();
-    let mut tasks = vec![];
-    for log_group_name in log_group_names.into_iter() {
-        let cwl = Arc::clone(&cwl);
-        let handle: JoinHandle<Vec<FilteredLogEvent>> = tokio::spawn(async move {
-            debug!(
-                "get_logs_to_display spawning to get logs for log_group_name {}",
-                log_group_name
-            );
-            let logs = cwl
-                .get_log_events(log_group_name, Some(start_time), Some(end_time), None)
-                .await
-                .unwrap();
-            return logs;
-        });
-        tasks.push(handle);
+    let mut data = String::new();
+    for event in events.iter() {
+        if page_token.bytes_rendered > 0 || !data.is_empty() {
+            data.push('\n');
+        }
+        data.push_str(&formatter.format(event.clone()));
     }
-    let mut logs: Vec<FilteredLogEvent> = try_join_all(tasks)
-        .await
-        .unwrap()
-        .into_iter()
-        .flat_map(|e| e)
-        .collect();
-    logs.sort_by_key(|l| l.timestamp);
-
-    trace!("logs: {:?}", logs);
-    let data: Bytes = logs
-        .into_iter()
-        .map(|log| formatter.format(log))
-        .collect::<Vec<String>>()
-        .join("\n")
-        .into();
-    if is_cacheable(&cache_key) {
-        let mut cache = cache.lock().await;
-        cache.put(
-            cache_key,
-            CacheValue {
-                data_to_display: data.clone(),
-            },
-        );
+    let bytes_rendered = page_token.bytes_rendered + data.len() as u64;
+    let next_page_token = next_token.map(|next_token| LogEventsPageToken {
+        region: page_token.region.clone(),
+        next_token: Some(next_token),
+        position,
+        bytes_rendered,
+        events_rendered: page_token.events_rendered + events.len(),
+    });
+    LogEventsPage {
+        events,
+        data: data.into(),
+        bytes_rendered,
+        next_page_token,
     }
-    Ok(data)
 }
 
-// See: https://ryhl.io/blog/actors-with-tokio/
-#[derive(Debug)]
-enum CloudWatchLogsMessage {
-    GetLogGroupNames {
-        respond_to: oneshot::Sender<Result<Vec<String>, CloudWatchLogsError>>,
-    },
-    GetLogEvents {
-        log_group_name: String,
-        start_time: Option<DateTime<Utc>>,
-        end_time: Option<DateTime<Utc>>,
-        limit: Option<i32>,
-        respond_to: oneshot::Sender<Result<Vec<FilteredLogEvent>, CloudWatchLogsError>>,
-    },
-    GetFirstEventTimeForLogGroup {
-        log_group_name: String,
-        respond_to: oneshot::Sender<Result<Option<DateTime<Utc>>, CloudWatchLogsError>>,
-    },
-    GetLogsToDisplay {
-        log_group_name: Option<String>,
-        log_group_filter: Option<String>,
-        start_time: DateTime<Utc>,
-        end_time: DateTime<Utc>,
-        formatter: format_cwl_log_event::LogFormatter,
-        respond_to: oneshot::Sender<Result<Bytes, CloudWatchLogsError>>,
-    },
+/// Settings and size of a log group in one region.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogGroupMetadata {
+    pub log_group_name: String,
+    pub region: Option<String>,
+    pub arn: Option<String>,
+    pub creation_time: Option<DateTime<Utc>>,
+
+    /// None means events are kept forever.
+    pub retention_in_days: Option<i32>,
+    pub stored_bytes: Option<i64>,
+    pub kms_key_id: Option<String>,
 }
 
-#[derive(Debug)]
-struct CloudWatchLogsActor {
-    cwl: Arc<CloudWatchLogsImpl>,
-    logs_display_cache: Arc<tokio::sync::Mutex<LruCache<CacheKey, CacheValue>>>,
+/// A subscription filter that streams a log group's events to Kinesis, Kinesis Data Firehose or Lambda.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SubscriptionFilter {
+    pub log_group_name: String,
+    pub region: Option<String>,
+    pub filter_name: String,
+    pub filter_pattern: String,
+    pub destination_arn: Option<String>,
+    pub role_arn: Option<String>,
+    pub distribution: Option<String>,
+    pub creation_time: Option<DateTime<Utc>>,
 }
 
-impl CloudWatchLogsActor {
-    fn new(cwl: CloudWatchLogsImpl) -> Self {
-        let cache_capacity = Duration::hours(1).num_minutes() as usize;
-        CloudWatchLogsActor {
-            cwl: Arc::new(cwl),
-            logs_display_cache: Arc::new(tokio::sync::Mutex::new(LruCache::new(cache_capacity))),
+/// A metric filter that turns a log group's events that match a pattern into CloudWatch metrics.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricFilter {
+    pub log_group_name: String,
+    pub region: Option<String>,
+    pub filter_name: String,
+    pub filter_pattern: String,
+    pub metric_transformations: Vec<MetricTransformation>,
+    pub creation_time: Option<DateTime<Utc>>,
+}
+
+/// The metric that a metric filter publishes, and the value it publishes for each matching event.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricTransformation {
+    pub metric_namespace: String,
+    pub metric_name: String,
+    pub metric_value: String,
+    pub default_value: Option<f64>,
+}
+
+/// A Logs Insights query saved in the CloudWatch console, which can be run by name.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QueryDefinition {
+    pub query_definition_id: String,
+    pub region: Option<String>,
+    pub name: String,
+    pub query_string: String,
+
+    /// Log groups the query was saved with, if any. Empty means it was saved without log groups.
+    pub log_group_names: Vec<String>,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// How many of the log groups that a call reads one at a time are done, sent as each one finishes so that callers can
+/// show progress.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Progress {
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Await `calls`, sending `progress` each time one finishes.
+async fn try_join_all_with_progress<T>(
+    calls: Vec<impl Future<Output = Result<T, CloudWatchLogsError>>>,
+    progress: Option<watch::Sender<Progress>>,
+) -> Result<Vec<T>, CloudWatchLogsError> {
+    let total = calls.len();
+    let done = AtomicUsize::new(0);
+    let report = |done| {
+        if let Some(progress) = &progress {
+            // Nobody may be watching any more.
+            let _ = progress.send(Progress { done, total });
         }
+    };
+    report(0);
+    try_join_all(calls.into_iter().map(|call| async {
+        let result = call.await;
+        report(done.fetch_add(1, Ordering::Relaxed) + 1);
+        result
+    }))
+    .await
+}
+
+/// Where an export of log events to S3 is at.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ExportTaskStatus {
+    Pending,
+    Running,
+    Completed,
+    Cancelled,
+    Failed(String),
+}
+
+impl ExportTaskStatus {
+    pub fn is_done(&self) -> bool {
+        matches!(
+            self,
+            ExportTaskStatus::Completed | ExportTaskStatus::Cancelled | ExportTaskStatus::Failed(_)
+        )
     }
+}
 
-    #[instrument(level = "debug")]
-    async fn handle_message(&self, msg: CloudWatchLogsMessage) {
-        match msg {
-            CloudWatchLogsMessage::GetLogGroupNames { respond_to } => {
-                let result = self.cwl.get_log_group_names().await;
-                let _ = respond_to.send(result);
-            }
-            CloudWatchLogsMessage::GetLogEvents {
-                log_group_name,
-                start_time,
-                end_time,
-                limit,
-                respond_to,
-            } => {
-                let result = self
-                    .cwl
-                    .get_log_events(log_group_name, start_time, end_time, limit)
-                    .await;
-                let _ = respond_to.send(result);
-            }
-            CloudWatchLogsMessage::GetFirstEventTimeForLogGroup {
-                log_group_name,
-                respond_to,
-            } => {
-                let result = self.cwl.get_first_event_time_for_log_group(log_group_name).await;
-                let _ = respond_to.send(result);
-            }
-            CloudWatchLogsMessage::GetLogsToDisplay {
-                log_group_name,
-                log_group_filter,
-                start_time,
-                end_time,
-                respond_to,
-                formatter,
-            } => {
-                let pattern: String;
-                if let Some(log_group_name) = log_group_name {
-                    pattern = format!("^{}$", log_group_name.as_str());
-                } else if let Some(log_group_filter) = log_group_filter {
-                    pattern = log_group_filter;
-                } else {
-                    let _ = respond_to.send(Err(CloudWatchLogsError::InvalidGetLogsToDisplayMessage(
-                        "Must specify either log_group_name or log_group_filter".to_string(),
-                    )));
-                    return;
-                }
-                let matcher = LogGroupNameMatcher::new(&pattern);
-                let cwl = Arc::clone(&self.cwl);
-                let cache = Arc::clone(&self.logs_display_cache);
-                let result =
-                    get_logs_to_display(matcher, start_time, end_time, formatter, cwl, cache).await;
-                let _ = respond_to.send(result);
-            }
+/// An export of one log group to S3.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExportTask {
+    pub log_group_name: String,
+    pub region: Option<String>,
+    pub task_id: String,
+    pub status: ExportTaskStatus,
+}
+
+/// How often to check on a running export task.
+const EXPORT_TASK_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// A role to assume with STS before calling CloudWatch Logs, e.g. to read logs in another account.
+#[derive(Clone, Debug)]
+pub struct AssumeRole {
+    pub role_arn: String,
+    pub external_id: Option<String>,
+    pub session_name: Option<String>,
+}
+
+/// How to create a CloudWatch Logs client.
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    /// Transactions per second (TPS) at which to call each CloudWatch Logs API.
+    pub tps: usize,
+
+    /// TPS for particular APIs instead of `tps`, e.g. a higher TPS for GetLogEvents, whose quota is higher.
+    pub action_tps: HashMap<ApiAction, usize>,
+
+    /// Calls that may be made to each API at once after a quiet spell, e.g. to list a directory and read a file
+    /// straight away, before calls are held to the TPS. If not set, one second's worth of calls.
+    pub tps_burst: Option<usize>,
+
+    /// How often calls that may be made come back, a share of the TPS each time. Shorter intervals spread calls out
+    /// more evenly.
+    pub tps_refill_interval: std::time::Duration,
+
+    /// AWS region. If not set the region is resolved from the environment.
+    pub region: Option<String>,
+
+    /// Named profile from `~/.aws/config` and `~/.aws/credentials` to take credentials and the region from. If not
+    /// set the `AWS_PROFILE` environment variable or the default profile is used.
+    pub profile: Option<String>,
+
+    /// Role to assume using the default credentials chain. If not set the default credentials are used directly.
+    pub assume_role: Option<AssumeRole>,
+
+    /// Credentials to use instead of the default credentials chain, e.g. from a service that issues and rotates
+    /// them itself. They are asked for credentials again whenever AWS rejects the current ones. `profile` then
+    /// only picks the region, and `assume_role` assumes the role with these credentials.
+    pub credentials_provider: Option<SharedCredentialsProvider>,
+
+    /// How to retry calls that fail because of throttling or server errors.
+    pub retry_policy: RetryPolicy,
+
+    /// How long to wait for the network before giving up, so that a dead connection fails instead of hanging.
+    pub timeouts: Timeouts,
+
+    /// Call FIPS 140-2 validated endpoints, e.g. `logs-fips.us-east-1.amazonaws.com`.
+    pub use_fips: bool,
+
+    /// Call endpoints that accept both IPv4 and IPv6, e.g. `logs.us-east-1.api.aws`.
+    pub use_dualstack: bool,
+
+    /// HTTP proxy to call CloudWatch Logs through. If not set CloudWatch Logs is called directly.
+    pub proxy: Option<ProxyOptions>,
+
+    /// In a monitoring account of CloudWatch cross-account observability [1], also list the log groups of the source
+    /// accounts linked to it. Those are named by their ARN, see `LogGroupArn::identifier`, since log group names are
+    /// only unique within an account.
+    ///
+    /// [1] https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch-Unified-Cross-Account.html
+    pub include_linked_accounts: bool,
+
+    /// With `include_linked_accounts`, only list the log groups of these accounts. If empty, the log groups of the
+    /// monitoring account and of every linked source account are listed.
+    pub account_identifiers: Vec<String>,
+}
+
+/// Timeouts for calls to CloudWatch Logs. `None` waits forever.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Timeouts {
+    /// How long to wait to establish a connection.
+    pub connect: Option<std::time::Duration>,
+
+    /// How long to wait for the first byte of a response.
+    pub read: Option<std::time::Duration>,
+
+    /// How long a whole call may take, including retries and waiting for the rate limiter. A call that times out
+    /// fails with a timeout error.
+    pub operation: Option<std::time::Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Some(std::time::Duration::from_secs(10)),
+            read: Some(std::time::Duration::from_secs(60)),
+            operation: Some(std::time::Duration::from_secs(300)),
         }
     }
 }
 
-#[instrument(level = "debug")]
-async fn run_cloud_watch_logs_actor(
-    actor: Arc<CloudWatchLogsActor>,
-    mut receiver: mpsc::Receiver<CloudWatchLogsMessage>,
-) {
-    while let Some(msg) = receiver.recv().await {
-        debug!("actor sending msg {:?}...", msg);
-        let actor = Arc::clone(&actor);
-        tokio::spawn(async move { actor.handle_message(msg).await });
-        debug!("actor finished sending msg");
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            tps: 5,
+            action_tps: HashMap::new(),
+            tps_burst: None,
+            tps_refill_interval: std::time::Duration::from_secs(1),
+            region: None,
+            profile: None,
+            assume_role: None,
+            credentials_provider: None,
+            retry_policy: RetryPolicy::default(),
+            timeouts: Timeouts::default(),
+            use_fips: false,
+            use_dualstack: false,
+            proxy: None,
+            include_linked_accounts: false,
+            account_identifiers: vec![],
+        }
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct CloudWatchLogsActorHandle {
-    sender: mpsc::Sender<CloudWatchLogsMessage>,
+/// Page size for FilterLogEvents and GetLogEvents, which is the maximum both APIs allow.
+const LOGS_BATCH_SIZE: i32 = 10_000;
+
+/// A client along with the credentials provider it signs requests with.
+#[derive(Clone)]
+struct SdkClient {
+    client: Client,
+
+    /// Sends requests with parameters that `client` does not have, see `request_fields`.
+    operations: Arc<OperationClient>,
+
+    /// STS client with the same credentials, to look up which account they belong to.
+    sts: aws_sdk_sts::Client,
+    credentials_provider: Option<SharedCredentialsProvider>,
+    credentials_source: CredentialsSource,
 }
 
-impl CloudWatchLogsActorHandle {
-    pub fn new(cwl: CloudWatchLogsImpl) -> Self {
-        let (sender, receiver) = mpsc::channel(4);
-        let actor = Arc::new(CloudWatchLogsActor::new(cwl));
-        tokio::spawn(run_cloud_watch_logs_actor(actor, receiver));
+/// TLS settings like the ones the SDK uses for its own connector, which trust the platform's CA certificates.
+fn tls_config() -> rustls::ClientConfig {
+    let mut config = rustls::ClientConfig::new();
+    config.root_store = match rustls_native_certs::load_native_certs() {
+        Ok(store) => store,
+        Err((Some(store), err)) => {
+            warn!("could not load all CA certificates: {}", err);
+            store
+        }
+        Err((None, err)) => {
+            warn!("could not load CA certificates: {}", err);
+            rustls::RootCertStore::empty()
+        }
+    };
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config
+}
+
+/// Send `request` over HTTPS with the same CA certificates as the CloudWatch Logs clients, through `proxy` if given,
+/// e.g. to look up the latest release of cwl-mount.
+pub async fn https_request(
+    request: hyper::Request<hyper::Body>,
+    proxy: Option<ProxyOptions>,
+) -> Result<hyper::Response<hyper::Body>, hyper::Error> {
+    let mut tls_config = tls_config();
+    tls_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    match proxy {
+        Some(proxy) => {
+            let connector = hyper_rustls::HttpsConnector::from((ProxyConnector::new(proxy), tls_config));
+            hyper::Client::builder().build(connector).request(request).await
+        }
+        None => {
+            let mut http = hyper::client::HttpConnector::new();
+            http.enforce_http(false);
+            let connector = hyper_rustls::HttpsConnector::from((http, tls_config));
+            hyper::Client::builder().build(connector).request(request).await
+        }
+    }
+}
+
+/// The region that the AWS SDK would use: from the AWS_REGION or AWS_DEFAULT_REGION environment variables, then
+/// the profile, then instance metadata. None if none of them has one.
+pub async fn default_region(profile: Option<&str>) -> Option<String> {
+    let mut region_chain = DefaultRegionChain::builder();
+    if let Some(profile) = profile {
+        region_chain = region_chain.profile_name(profile);
+    }
+    region_chain
+        .build()
+        .region()
+        .await
+        .map(|region| region.as_ref().to_string())
+}
 
-        Self { sender }
+/// A client along with the region it was resolved to use.
+async fn build_client(options: &ClientOptions) -> (SdkClient, Option<String>) {
+    let mut timeouts = aws_smithy_client::timeout::Settings::new();
+    if let Some(connect) = options.timeouts.connect {
+        timeouts = timeouts.with_connect_timeout(connect);
     }
+    if let Some(read) = options.timeouts.read {
+        timeouts = timeouts.with_read_timeout(read);
+    }
+    let adapter = aws_smithy_client::hyper_ext::Adapter::builder().timeout(&timeouts);
+    let connector = match options.proxy.clone() {
+        Some(proxy) => DynConnector::new(adapter.build(hyper_rustls::HttpsConnector::from((
+            ProxyConnector::new(proxy),
+            tls_config(),
+        )))),
+        None => DynConnector::new(adapter.build(aws_smithy_client::conns::https())),
+    };
+    let region = match options.region.clone() {
+        Some(region) => Some(region),
+        None => default_region(options.profile.as_deref()).await,
+    }
+    .map(Region::new);
+    // Retries are handled by `with_retries` so that they are configurable and also back off on 5xx errors.
+    let mut config = aws_config::from_env()
+        .region(region.clone())
+        .retry_config(RetryConfig::disabled());
+    let (credentials, mut credentials_source) = match options.credentials_provider.clone() {
+        Some(credentials_provider) => (
+            credentials_provider,
+            CredentialsSource::fixed("credentials provider in the client options"),
+        ),
+        None => {
+            let mut provider_config = ProviderConfig::default().with_region(region.clone());
+            if options.proxy.is_some() {
+                // Credentials providers that call AWS, e.g. SSO or web identity tokens, go through the proxy too.
+                provider_config = provider_config.with_http_connector(connector.clone());
+            }
+            let (credentials_chain, credentials_source) =
+                default_credentials_chain(&provider_config, options.profile.as_deref());
+            (
+                SharedCredentialsProvider::new(credentials_chain),
+                credentials_source,
+            )
+        }
+    };
+    if let Some(assume_role) = options.assume_role.clone() {
+        let mut provider = AssumeRoleProvider::builder(assume_role.role_arn);
+        if let Some(external_id) = assume_role.external_id {
+            provider = provider.external_id(external_id);
+        }
+        if let Some(session_name) = assume_role.session_name {
+            provider = provider.session_name(session_name);
+        }
+        if let Some(region) = region {
+            provider = provider.region(region);
+        }
+        if options.proxy.is_some() {
+            provider = provider.connection(connector.clone());
+        }
+        let provider = provider.build(credentials);
+        credentials_source = credentials_source.assuming_role();
+        config =
+            config.credentials_provider(LazyCachingCredentialsProvider::builder().load(provider).build());
+    } else {
+        config = config.credentials_provider(credentials);
+    }
+    let config = config.load().await;
+    let region = config.region().map(|region| region.to_string());
+    let mut client_config = aws_sdk_cloudwatchlogs::config::Builder::from(&config);
+    if options.use_fips || options.use_dualstack {
+        client_config = client_config.endpoint_resolver(EndpointVariants {
+            use_fips: options.use_fips,
+            use_dualstack: options.use_dualstack,
+        });
+    }
+    let mut operations = aws_smithy_client::Builder::new()
+        .connector(connector.clone())
+        .middleware(DefaultMiddleware::new());
+    operations.set_retry_config(RetryConfig::disabled().into());
+    let client = SdkClient {
+        client: Client::from_conf_conn(client_config.build(), connector.clone()),
+        operations: Arc::new(operations.build()),
+        sts: aws_sdk_sts::Client::from_conf_conn((&config).into(), connector),
+        credentials_provider: config.credentials_provider().cloned(),
+        credentials_source,
+    };
+    (client, region)
+}
+
+/// Where credentials came from and how long they last, as found by a preflight check.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PreflightReport {
+    pub region: Option<String>,
+
+    /// Which credentials provider in the chain supplied the credentials, e.g. `profile` or `web identity token
+    /// (IRSA)`.
+    pub credentials_source: String,
+
+    /// When the credentials expire, if they are temporary.
+    pub credentials_expiry: Option<DateTime<Utc>>,
+}
+
+/// Credentials are loaded again at most this often when AWS rejects them, so that a role that is simply not
+/// allowed to do something does not cause a reload on every call.
+const MIN_CREDENTIALS_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Derivative)]
+#[derivative(Clone, Debug)]
+pub struct CloudWatchLogsImpl {
+    /// The client along with how many times it has been replaced to refresh credentials.
+    #[derivative(Debug = "ignore")]
+    client: Arc<std::sync::RwLock<(u64, SdkClient)>>,
+    region: Option<String>,
+    retry_policy: RetryPolicy,
+
+    /// Kept to build a new client with fresh credentials when AWS rejects the current ones.
+    options: ClientOptions,
+
+    /// When credentials were last refreshed. Locked while refreshing so that concurrent calls refresh only once.
+    #[derivative(Debug = "ignore")]
+    last_credentials_refresh: Arc<tokio::sync::Mutex<Option<Instant>>>,
+
+    #[derivative(Debug = "ignore")]
+    rate_limiters: Arc<RateLimiters>,
 
+    #[derivative(Debug = "ignore")]
+    usage: Arc<Usage>,
+
+    /// The account that the credentials belong to, looked up the first time events are read.
+    #[derivative(Debug = "ignore")]
+    account_id: Arc<tokio::sync::OnceCell<Option<String>>>,
+}
+
+impl CloudWatchLogsImpl {
     #[instrument(level = "debug")]
-    pub async fn get_log_group_names(&self) -> Result<Vec<String>, CloudWatchLogsError> {
-        let (send, recv) = oneshot::channel();
-        let msg = CloudWatchLogsMessage::GetLogGroupNames { respond_to: send };
-        let _ = self.sender.send(msg).await;
-        recv.await.expect("Actor task has been killed")
+    pub async fn new<T: std::fmt::Debug + Into<String>>(tps: usize, region: Option<T>) -> Self {
+        Self::with_options(ClientOptions {
+            tps,
+            region: region.map(|region| region.into()),
+            ..ClientOptions::default()
+        })
+        .await
     }
 
     #[instrument(level = "debug")]
-    pub async fn get_log_events(
+    pub async fn with_options(options: ClientOptions) -> Self {
+        let (client, region) = build_client(&options).await;
+        let rate_limiters = Arc::new(RateLimiters::new(
+            options.tps,
+            &options.action_tps,
+            options.tps_burst,
+            options.tps_refill_interval,
+        ));
+        Self {
+            client: Arc::new(std::sync::RwLock::new((0, client))),
+            region,
+            retry_policy: options.retry_policy.clone(),
+            options,
+            last_credentials_refresh: Arc::new(tokio::sync::Mutex::new(None)),
+            rate_limiters,
+            usage: Arc::new(Usage::default()),
+            account_id: Arc::new(tokio::sync::OnceCell::new()),
+        }
+    }
+
+    fn client(&self) -> Client {
+        self.client
+            .read()
+            .expect("client lock is never poisoned")
+            .1
+            .client
+            .clone()
+    }
+
+    fn operation_client(&self) -> Arc<OperationClient> {
+        self.client
+            .read()
+            .expect("client lock is never poisoned")
+            .1
+            .operations
+            .clone()
+    }
+
+    fn credentials_provider(&self) -> Option<SharedCredentialsProvider> {
+        self.client
+            .read()
+            .expect("client lock is never poisoned")
+            .1
+            .credentials_provider
+            .clone()
+    }
+
+    fn credentials_source(&self) -> CredentialsSource {
+        self.client
+            .read()
+            .expect("client lock is never poisoned")
+            .1
+            .credentials_source
+            .clone()
+    }
+
+    fn sts_client(&self) -> aws_sdk_sts::Client {
+        self.client
+            .read()
+            .expect("client lock is never poisoned")
+            .1
+            .sts
+            .clone()
+    }
+
+    /// Wait for the rate limiter of `action`, then count the call. Called before every attempt at a call.
+    async fn start_call(&self, action: ApiAction) {
+        self.rate_limiters.acquire(action).await;
+        self.usage.record_call(action);
+    }
+
+    /// Metadata of a log group that DescribeLogGroups returned in this region, or None if it has no name.
+    fn log_group_metadata(
         &self,
-        log_group_name: String,
-        start_time: Option<DateTime<Utc>>,
-        end_time: Option<DateTime<Utc>>,
+        log_group: aws_sdk_cloudwatchlogs::model::LogGroup,
+    ) -> Option<LogGroupMetadata> {
+        Some(LogGroupMetadata {
+            log_group_name: log_group.log_group_name?,
+            region: self.region.clone(),
+            arn: log_group.arn,
+            creation_time: log_group.creation_time.map(|ms| Utc.timestamp_millis(ms)),
+            retention_in_days: log_group.retention_in_days,
+            stored_bytes: log_group.stored_bytes,
+            kms_key_id: log_group.kms_key_id,
+        })
+    }
+
+    /// One page of DescribeLogGroups with `fields` added to the request, see `request_fields`.
+    async fn describe_log_groups_page(
+        &self,
+        prefix: Option<&str>,
         limit: Option<i32>,
-    ) -> Result<Vec<FilteredLogEvent>, CloudWatchLogsError> {
-        let (send, recv) = oneshot::channel();
-        let msg = CloudWatchLogsMessage::GetLogEvents {
-            respond_to: send,
-            log_group_name,
-            start_time,
-            end_time,
-            limit,
+        next_token: Option<String>,
+        fields: &Map<String, Value>,
+    ) -> Result<DescribeLogGroupsOutput, SdkError<aws_sdk_cloudwatchlogs::error::DescribeLogGroupsError>>
+    {
+        self.with_retries(|| async {
+            self.start_call(ApiAction::DescribeLogGroups).await;
+            let input = DescribeLogGroupsInput::builder()
+                .set_log_group_name_prefix(prefix.map(|prefix| prefix.to_string()))
+                .set_limit(limit)
+                .set_next_token(next_token.clone())
+                .build();
+            let operation = match input {
+                Ok(input) => input.make_operation(self.client().conf()).await,
+                Err(err) => return Err(SdkError::ConstructionFailure(err.into())),
+            };
+            match operation {
+                Ok(operation) => {
+                    self.operation_client()
+                        .call(with_fields(operation, fields.clone()))
+                        .await
+                }
+                Err(err) => Err(SdkError::ConstructionFailure(err.into())),
+            }
+        })
+        .await
+    }
+
+    /// Like `start_call`, but calls for different log groups take turns at the rate limiter.
+    async fn start_log_group_call(&self, action: ApiAction, log_group_name: &str) {
+        self.rate_limiters
+            .acquire_for_log_group(action, log_group_name)
+            .await;
+        self.usage.record_call(action);
+    }
+
+    fn client_generation(&self) -> u64 {
+        self.client.read().expect("client lock is never poisoned").0
+    }
+
+    /// Call `send` with retries, and if AWS rejects the credentials, e.g. because an SSO session or assumed role
+    /// expired, load credentials again and try once more. `send` must get the client from `self.client()` so
+    /// that trying again uses the new credentials.
+    async fn with_retries<T, E, F, Fut>(&self, mut send: F) -> Result<T, SdkError<E>>
+    where
+        E: ProvideErrorKind,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, SdkError<E>>>,
+    {
+        let call = async {
+            let generation = self.client_generation();
+            match with_retries(&self.retry_policy, &mut send).await {
+                Err(err) if is_credentials_error(&err) && self.refresh_credentials(generation).await => {
+                    warn!("AWS rejected the credentials, trying again with refreshed credentials");
+                    with_retries(&self.retry_policy, send).await
+                }
+                result => result,
+            }
         };
-        let _ = self.sender.send(msg).await;
-        recv.await.expect("Actor task has been killed")
+        match self.options.timeouts.operation {
+            Some(timeout) => match tokio::time::timeout(timeout, call).await {
+                Ok(result) => result,
+                Err(_) => Err(SdkError::TimeoutError(
+                    format!("call did not finish within {:?}", timeout).into(),
+                )),
+            },
+            None => call.await,
+        }
+    }
+
+    /// Replace the client with one that loads credentials from scratch, unless another call already did so since
+    /// `generation`. Returns whether there are new credentials to try.
+    async fn refresh_credentials(&self, generation: u64) -> bool {
+        let mut last_credentials_refresh = self.last_credentials_refresh.lock().await;
+        if self.client_generation() != generation {
+            return true;
+        }
+        if let Some(last_credentials_refresh) = *last_credentials_refresh {
+            if last_credentials_refresh.elapsed() < MIN_CREDENTIALS_REFRESH_INTERVAL {
+                return false;
+            }
+        }
+        let (client, _) = build_client(&self.options).await;
+        *last_credentials_refresh = Some(Instant::now());
+        *self.client.write().expect("client lock is never poisoned") = (generation + 1, client);
+        true
     }
 
+    /// Create one client per region. Each client has its own rate limiter because CloudWatch Logs quotas are
+    /// per region.
     #[instrument(level = "debug")]
-    pub async fn get_first_event_time_for_log_group(
+    pub async fn new_for_regions(options: ClientOptions, regions: Vec<String>) -> Vec<Self> {
+        join_all(regions.into_iter().map(|region| {
+            Self::with_options(ClientOptions {
+                region: Some(region),
+                ..options.clone()
+            })
+        }))
+        .await
+    }
+}
+
+/// Fetch every page of a paginated API, passing each call the token that the previous page returned, and collect
+/// their items. The AWS SDK this is built with has no paginators yet, so this stands in for `into_paginator()`.
+async fn collect_pages<T, F, Fut>(fetch_page: F) -> Result<Vec<T>, CloudWatchLogsError>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<(Vec<T>, Option<String>), CloudWatchLogsError>>,
+{
+    collect_pages_until(fetch_page, |_| false).await
+}
+
+/// Like `collect_pages`, but stop after the page that has an item for which `found` is true.
+async fn collect_pages_until<T, F, Fut>(
+    mut fetch_page: F,
+    found: impl Fn(&T) -> bool,
+) -> Result<Vec<T>, CloudWatchLogsError>
+where
+    F: FnMut(Option<String>) -> Fut,
+    Fut: Future<Output = Result<(Vec<T>, Option<String>), CloudWatchLogsError>>,
+{
+    let mut result = Vec::new();
+    let mut next_token = None;
+    loop {
+        let (items, token) = fetch_page(next_token).await?;
+        let done = items.iter().any(&found);
+        result.extend(items);
+        if done {
+            return Ok(result);
+        }
+        match token {
+            Some(token) => next_token = Some(token),
+            None => return Ok(result),
+        }
+    }
+}
+
+#[async_trait]
+impl CloudWatchLogsApi for CloudWatchLogsImpl {
+    fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
+
+    fn usage(&self) -> UsageStats {
+        self.usage.stats()
+    }
+
+    /// The account of the assumed role if there is one, or else looked up once with STS GetCallerIdentity, which
+    /// any credentials may call.
+    async fn account_id(&self) -> Option<String> {
+        self.account_id
+            .get_or_init(|| async {
+                if let Some(assume_role) = &self.options.assume_role {
+                    return assume_role.role_arn.split(':').nth(4).map(str::to_string);
+                }
+                match self
+                    .with_retries(|| async { self.sts_client().get_caller_identity().send().await })
+                    .await
+                {
+                    Ok(resp) => resp.account,
+                    Err(err) => {
+                        warn!("failed to look up the AWS account of the credentials: {}", err);
+                        None
+                    }
+                }
+            })
+            .await
+            .clone()
+    }
+
+    #[instrument(level = "debug")]
+    async fn preflight(&self) -> Result<PreflightReport, CloudWatchLogsError> {
+        let credentials_provider = self.credentials_provider().ok_or_else(|| {
+            CloudWatchLogsError::CredentialsUnavailable("no credentials provider".to_string())
+        })?;
+        let credentials = credentials_provider
+            .provide_credentials()
+            .await
+            .map_err(|err| CloudWatchLogsError::CredentialsUnavailable(err.to_string()))?;
+        match self
+            .with_retries(|| async {
+                self.start_call(ApiAction::DescribeLogGroups).await;
+                self.client().describe_log_groups().limit(1).send().await
+            })
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => Err(CloudWatchLogsError::DescribeLogGroupsError(Box::new(err))),
+        }?;
+        Ok(PreflightReport {
+            region: self.region.clone(),
+            credentials_source: self.credentials_source().describe(),
+            credentials_expiry: credentials.expiry().map(DateTime::<Utc>::from),
+        })
+    }
+
+    #[instrument(level = "debug")]
+    async fn get_log_group_names_with_prefix(
         &self,
-        log_group_name: String,
-    ) -> Result<Option<DateTime<Utc>>, CloudWatchLogsError> {
-        let (send, recv) = oneshot::channel();
-        let msg = CloudWatchLogsMessage::GetFirstEventTimeForLogGroup {
-            respond_to: send,
-            log_group_name,
-        };
-        let _ = self.sender.send(msg).await;
-        recv.await.expect("Actor task has been killed")
+        prefix: Option<&str>,
+    ) -> Result<Vec<String>, CloudWatchLogsError> {
+        Ok(self
+            .describe_log_groups(prefix)
+            .await?
+            .into_iter()
+            .map(|metadata| metadata.log_group_name)
+            .collect())
     }
 
     #[instrument(level = "debug")]
-    pub async fn get_logs_to_display(
+    async fn describe_log_groups(
         &self,
-        log_group_name: Option<String>,
-        log_group_filter: Option<String>,
-        start_time: DateTime<Utc>,
-        end_time: DateTime<Utc>,
-        formatter: format_cwl_log_event::LogFormatter,
-    ) -> Result<Bytes, CloudWatchLogsError> {
-        let (send, recv) = oneshot::channel();
-        let msg = CloudWatchLogsMessage::GetLogsToDisplay {
-            respond_to: send,
-            log_group_name,
-            log_group_filter,
-            start_time,
-            end_time,
-            formatter,
+        prefix: Option<&str>,
+    ) -> Result<Vec<LogGroupMetadata>, CloudWatchLogsError> {
+        const LOG_GROUP_LIMIT: i32 = 50;
+        let fields = &request_fields::linked_accounts(
+            self.options.include_linked_accounts,
+            &self.options.account_identifiers,
+        );
+        let account_id = &match self.options.include_linked_accounts {
+            true => self.account_id().await,
+            false => None,
         };
-        let _ = self.sender.send(msg).await;
-        recv.await.expect("Actor task has been killed")
+        collect_pages(|next_token| async move {
+            let resp = match self
+                .describe_log_groups_page(prefix, Some(LOG_GROUP_LIMIT), next_token, fields)
+                .await
+            {
+                Ok(inner) => Ok(inner),
+                Err(err) => Err(CloudWatchLogsError::DescribeLogGroupsError(Box::new(err))),
+            }?;
+            let page: Vec<LogGroupMetadata> = resp
+                .log_groups
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|log_group| self.log_group_metadata(log_group))
+                .map(|metadata| match self.options.include_linked_accounts {
+                    true => name_linked_log_group(metadata, account_id.as_deref()),
+                    false => metadata,
+                })
+                .collect();
+
+            // An empty page ends the listing, even if it comes with a next token.
+            if page.is_empty() {
+                return Ok((page, None));
+            }
+            Ok((page, resp.next_token))
+        })
+        .await
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use chrono::TimeZone;
-    use chrono::Utc;
+    async fn filter_log_events_page(
+        &self,
+        log_group_name: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        event_filter: &EventFilter,
+        next_token: Option<String>,
+    ) -> Result<(Vec<FilteredLogEvent>, Option<String>), CloudWatchLogsError> {
+        let arn = LogGroupArn::parse(log_group_name);
+        let resp = match self
+            .with_retries(|| async {
+                self.start_log_group_call(ApiAction::FilterLogEvents, log_group_name)
+                    .await;
+                let input = FilterLogEventsInput::builder()
+                    .set_log_group_name(arn.is_none().then(|| log_group_name.to_string()))
+                    .limit(LOGS_BATCH_SIZE)
+                    .set_filter_pattern(event_filter.filter_pattern.clone())
+                    .set_log_stream_name_prefix(event_filter.log_stream_name_prefix.clone())
+                    .set_log_stream_names(event_filter.log_stream_name.clone().map(|name| vec![name]))
+                    .set_start_time(start_time.map(|start_time| start_time.timestamp_millis()))
+                    .set_end_time(end_time.map(|end_time| end_time.timestamp_millis()))
+                    .set_next_token(next_token.clone())
+                    .build();
+                let operation = match input {
+                    Ok(input) => input.make_operation(self.client().conf()).await,
+                    Err(err) => return Err(SdkError::ConstructionFailure(err.into())),
+                };
+                match operation {
+                    Ok(operation) => {
+                        let mut fields = request_fields::unmask(event_filter.unmask);
+                        fields.extend(request_fields::log_group_identifier(arn.as_ref()));
+                        self.operation_client().call(with_fields(operation, fields)).await
+                    }
+                    Err(err) => Err(SdkError::ConstructionFailure(err.into())),
+                }
+            })
+            .await
+        {
+            Ok(inner) => Ok(inner),
+            Err(err) if is_unsupported_by_log_group_class(&err) => Err(
+                CloudWatchLogsError::UnsupportedByLogGroupClass(log_group_name.to_string()),
+            ),
+            Err(err) => Err(CloudWatchLogsError::FilterLogEventsError(Box::new(err))),
+        }?;
+        let (log_group_name, account_id) = match &arn {
+            Some(arn) => (arn.log_group_name.as_str(), Some(arn.account_id.clone())),
+            None => (log_group_name, self.account_id().await),
+        };
+        let mut events = vec![];
+        for event in resp.events.unwrap_or_default() {
+            let mut event = convert_to_filtered_log_event(log_group_name, event)?;
+            event.region = self.region.clone();
+            event.account_id = account_id.clone();
+            events.push(event);
+        }
+        self.usage.record_events(events.len());
+        Ok((events, resp.next_token))
+    }
 
-    use crate::CloudWatchLogsImpl;
+    async fn get_log_events_page(
+        &self,
+        log_group_name: &str,
+        log_stream_name: &str,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        unmask: bool,
+        next_token: Option<String>,
+        position: usize,
+    ) -> Result<(Vec<FilteredLogEvent>, Option<String>), CloudWatchLogsError> {
+        let arn = LogGroupArn::parse(log_group_name);
+        let resp = match self
+            .with_retries(|| async {
+                self.start_log_group_call(ApiAction::GetLogEvents, log_group_name)
+                    .await;
+                let input = GetLogEventsInput::builder()
+                    .set_log_group_name(arn.is_none().then(|| log_group_name.to_string()))
+                    .log_stream_name(log_stream_name)
+                    .limit(LOGS_BATCH_SIZE)
+                    .start_from_head(true)
+                    .set_start_time(start_time.map(|start_time| start_time.timestamp_millis()))
+                    .set_end_time(end_time.map(|end_time| end_time.timestamp_millis()))
+                    .set_next_token(next_token.clone())
+                    .build();
+                let operation = match input {
+                    Ok(input) => input.make_operation(self.client().conf()).await,
+                    Err(err) => return Err(SdkError::ConstructionFailure(err.into())),
+                };
+                match operation {
+                    Ok(operation) => {
+                        let mut fields = request_fields::unmask(unmask);
+                        fields.extend(request_fields::log_group_identifier(arn.as_ref()));
+                        self.operation_client().call(with_fields(operation, fields)).await
+                    }
+                    Err(err) => Err(SdkError::ConstructionFailure(err.into())),
+                }
+            })
+            .await
+        {
+            Ok(inner) => Ok(inner),
+            Err(err) if is_unsupported_by_log_group_class(&err) => Err(
+                CloudWatchLogsError::UnsupportedByLogGroupClass(log_group_name.to_string()),
+            ),
+            Err(err) => Err(CloudWatchLogsError::GetLogEventsError(Box::new(err))),
+        }?;
+        let (log_group_name, account_id) = match &arn {
+            Some(arn) => (arn.log_group_name.as_str(), Some(arn.account_id.clone())),
+            None => (log_group_name, self.account_id().await),
+        };
+        let mut events = vec![];
+        for event in resp.events.unwrap_or_default() {
+            let mut event = convert_to_filtered_log_event_for_stream(
+                log_group_name,
+                log_stream_name,
+                position + events.len(),
+                event,
+            )?;
+            event.region = self.region.clone();
+            event.account_id = account_id.clone();
+            events.push(event);
+        }
+        self.usage.record_events(events.len());
 
-    #[test]
-    fn test_list_log_groups() {
-        let tps = 5;
-        let region = Some("us-west-2");
-        let cwl: CloudWatchLogsImpl = tokio_test::block_on(CloudWatchLogsImpl::new(tps, region));
-        let res = tokio_test::block_on(cwl.get_log_group_names()).unwrap();
-        res.iter().for_each(|l| println!("{}", l));
+        // GetLogEvents returns the token that was passed in once the end of the stream is reached.
+        let next_forward_token = match resp.next_forward_token {
+            Some(token) if Some(&token) != next_token.as_ref() => Some(token),
+            _ => None,
+        };
+        Ok((events, next_forward_token))
+    }
+
+    #[instrument(level = "debug")]
+    async fn describe_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Option<LogGroupMetadata>, CloudWatchLogsError> {
+        let log_group_name = &log_group_name;
+        // A log group of a linked source account is named by its ARN, and is listed from the monitoring account.
+        let arn = &LogGroupArn::parse(log_group_name);
+        let prefix = match arn {
+            Some(arn) => arn.log_group_name.as_str(),
+            None => log_group_name.as_str(),
+        };
+        let fields = &match arn {
+            Some(arn) => request_fields::linked_accounts(true, std::slice::from_ref(&arn.account_id)),
+            None => Map::new(),
+        };
+        let is_log_group = |log_group: &aws_sdk_cloudwatchlogs::model::LogGroup| match arn {
+            Some(arn) => log_group.arn.as_deref().and_then(LogGroupArn::parse).as_ref() == Some(arn),
+            None => log_group.log_group_name.as_deref() == Some(log_group_name.as_str()),
+        };
+        // There is no API to describe a single log group, so list the ones that have its name as a prefix.
+        let log_groups = collect_pages_until(
+            |next_token| async move {
+                let resp = match self
+                    .describe_log_groups_page(Some(prefix), None, next_token, fields)
+                    .await
+                {
+                    Ok(inner) => Ok(inner),
+                    Err(err) => Err(CloudWatchLogsError::DescribeLogGroupsError(Box::new(err))),
+                }?;
+                Ok((resp.log_groups.unwrap_or_default(), resp.next_token))
+            },
+            is_log_group,
+        )
+        .await?;
+        Ok(log_groups
+            .into_iter()
+            .find(is_log_group)
+            .and_then(|log_group| self.log_group_metadata(log_group))
+            .map(|metadata| LogGroupMetadata {
+                log_group_name: log_group_name.clone(),
+                ..metadata
+            }))
+    }
+
+    #[instrument(level = "debug")]
+    async fn describe_metric_filters(
+        &self,
+        log_group_name: String,
+    ) -> Result<Vec<MetricFilter>, CloudWatchLogsError> {
+        let log_group_name = &log_group_name;
+        collect_pages(|next_token| async move {
+            let resp = match self
+                .with_retries(|| async {
+                    self.start_call(ApiAction::DescribeMetricFilters).await;
+                    self.client()
+                        .describe_metric_filters()
+                        .log_group_name(log_group_name)
+                        .set_next_token(next_token.clone())
+                        .send()
+                        .await
+                })
+                .await
+            {
+                Ok(inner) => Ok(inner),
+                Err(err) => Err(CloudWatchLogsError::DescribeMetricFiltersError(Box::new(err))),
+            }?;
+            let mut page = Vec::new();
+            for metric_filter in resp.metric_filters.unwrap_or_default() {
+                let metric_transformations = metric_filter
+                    .metric_transformations
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|transformation| MetricTransformation {
+                        metric_namespace: transformation.metric_namespace.unwrap_or_default(),
+                        metric_name: transformation.metric_name.unwrap_or_default(),
+                        metric_value: transformation.metric_value.unwrap_or_default(),
+                        default_value: transformation.default_value,
+                    })
+                    .collect();
+                page.push(MetricFilter {
+                    log_group_name: log_group_name.clone(),
+                    region: self.region.clone(),
+                    filter_name: metric_filter.filter_name.unwrap_or_default(),
+                    filter_pattern: metric_filter.filter_pattern.unwrap_or_default(),
+                    metric_transformations,
+                    creation_time: metric_filter.creation_time.map(|ms| Utc.timestamp_millis(ms)),
+                });
+            }
+            Ok((page, resp.next_token))
+        })
+        .await
+    }
+
+    #[instrument(level = "debug")]
+    async fn describe_subscription_filters(
+        &self,
+        log_group_name: String,
+    ) -> Result<Vec<SubscriptionFilter>, CloudWatchLogsError> {
+        let log_group_name = &log_group_name;
+        collect_pages(|next_token| async move {
+            let resp = match self
+                .with_retries(|| async {
+                    self.start_call(ApiAction::DescribeSubscriptionFilters).await;
+                    self.client()
+                        .describe_subscription_filters()
+                        .log_group_name(log_group_name)
+                        .set_next_token(next_token.clone())
+                        .send()
+                        .await
+                })
+                .await
+            {
+                Ok(inner) => Ok(inner),
+                Err(err) => Err(CloudWatchLogsError::DescribeSubscriptionFiltersError(Box::new(
+                    err,
+                ))),
+            }?;
+            let mut page = Vec::new();
+            for subscription_filter in resp.subscription_filters.unwrap_or_default() {
+                page.push(SubscriptionFilter {
+                    log_group_name: log_group_name.clone(),
+                    region: self.region.clone(),
+                    filter_name: subscription_filter.filter_name.unwrap_or_default(),
+                    filter_pattern: subscription_filter.filter_pattern.unwrap_or_default(),
+                    destination_arn: subscription_filter.destination_arn,
+                    role_arn: subscription_filter.role_arn,
+                    distribution: subscription_filter
+                        .distribution
+                        .map(|distribution| distribution.as_str().to_string()),
+                    creation_time: subscription_filter
+                        .creation_time
+                        .map(|ms| Utc.timestamp_millis(ms)),
+                });
+            }
+            Ok((page, resp.next_token))
+        })
+        .await
+    }
+
+    #[instrument(level = "debug")]
+    async fn describe_query_definitions(
+        &self,
+        name_prefix: Option<String>,
+    ) -> Result<Vec<QueryDefinition>, CloudWatchLogsError> {
+        let name_prefix = name_prefix.as_deref();
+        collect_pages(|next_token| async move {
+            let resp = match self
+                .with_retries(|| async {
+                    self.start_call(ApiAction::DescribeQueryDefinitions).await;
+                    self.client()
+                        .describe_query_definitions()
+                        .set_query_definition_name_prefix(name_prefix.map(str::to_string))
+                        .set_next_token(next_token.clone())
+                        .send()
+                        .await
+                })
+                .await
+            {
+                Ok(inner) => Ok(inner),
+                Err(err) => Err(CloudWatchLogsError::DescribeQueryDefinitionsError(Box::new(err))),
+            }?;
+            let mut page = Vec::new();
+            for query_definition in resp.query_definitions.unwrap_or_default() {
+                page.push(QueryDefinition {
+                    query_definition_id: query_definition.query_definition_id.unwrap_or_default(),
+                    region: self.region.clone(),
+                    name: query_definition.name.unwrap_or_default(),
+                    query_string: query_definition.query_string.unwrap_or_default(),
+                    log_group_names: query_definition.log_group_names.unwrap_or_default(),
+                    last_modified: query_definition.last_modified.map(|ms| Utc.timestamp_millis(ms)),
+                });
+            }
+            Ok((page, resp.next_token))
+        })
+        .await
+    }
+
+    #[instrument(level = "debug")]
+    async fn create_export_task(
+        &self,
+        log_group_name: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        bucket: String,
+        prefix: Option<String>,
+    ) -> Result<String, CloudWatchLogsError> {
+        let resp = match self
+            .with_retries(|| async {
+                self.start_call(ApiAction::CreateExportTask).await;
+                self.client()
+                    .create_export_task()
+                    .log_group_name(&log_group_name)
+                    .from(start_time.timestamp_millis())
+                    .to(end_time.timestamp_millis())
+                    .destination(&bucket)
+                    .set_destination_prefix(prefix.clone())
+                    .send()
+                    .await
+            })
+            .await
+        {
+            Ok(inner) => Ok(inner),
+            Err(err) => Err(CloudWatchLogsError::CreateExportTaskError(Box::new(err))),
+        }?;
+        resp.task_id.ok_or(CloudWatchLogsError::Unknown)
+    }
+
+    #[instrument(level = "debug")]
+    async fn get_export_task_status(&self, task_id: String) -> Result<ExportTaskStatus, CloudWatchLogsError> {
+        let resp = match self
+            .with_retries(|| async {
+                self.start_call(ApiAction::DescribeExportTasks).await;
+                self.client()
+                    .describe_export_tasks()
+                    .task_id(&task_id)
+                    .send()
+                    .await
+            })
+            .await
+        {
+            Ok(inner) => Ok(inner),
+            Err(err) => Err(CloudWatchLogsError::DescribeExportTasksError(Box::new(err))),
+        }?;
+        let status = resp
+            .export_tasks
+            .unwrap_or_default()
+            .into_iter()
+            .find_map(|export_task| export_task.status)
+            .ok_or_else(|| CloudWatchLogsError::ExportTaskNotFound(task_id.clone()))?;
+        Ok(match status.code {
+            Some(ExportTaskStatusCode::Pending) | Some(ExportTaskStatusCode::PendingCancel) => {
+                ExportTaskStatus::Pending
+            }
+            Some(ExportTaskStatusCode::Running) => ExportTaskStatus::Running,
+            Some(ExportTaskStatusCode::Completed) => ExportTaskStatus::Completed,
+            Some(ExportTaskStatusCode::Cancelled) => ExportTaskStatus::Cancelled,
+            _ => ExportTaskStatus::Failed(status.message.unwrap_or_default()),
+        })
+    }
+
+    #[instrument(level = "debug")]
+    async fn get_last_event_time_for_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Option<DateTime<Utc>>, CloudWatchLogsError> {
+        let resp = match self
+            .with_retries(|| async {
+                self.start_log_group_call(ApiAction::DescribeLogStreams, &log_group_name)
+                    .await;
+                self.client()
+                    .describe_log_streams()
+                    .log_group_name(&log_group_name)
+                    .order_by(aws_sdk_cloudwatchlogs::model::OrderBy::LastEventTime)
+                    .descending(true)
+                    .limit(1)
+                    .send()
+                    .await
+            })
+            .await
+        {
+            Ok(inner) => Ok(inner),
+            Err(err) => Err(CloudWatchLogsError::DescribeLogStreamsError(Box::new(err))),
+        }?;
+        Ok(resp
+            .log_streams
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|log_stream| log_stream.last_event_timestamp)
+            .map(|ms| Utc.timestamp_millis(ms))
+            .next())
+    }
+
+    #[instrument(level = "debug")]
+    async fn get_log_streams(
+        &self,
+        log_group_name: String,
+        prefix: Option<String>,
+        order_by: LogStreamOrderBy,
+    ) -> Result<Vec<LogStream>, CloudWatchLogsError> {
+        const LOG_STREAM_LIMIT: i32 = 50;
+        if prefix.is_some() && order_by == LogStreamOrderBy::LastEventTime {
+            return Err(CloudWatchLogsError::InvalidArgument(
+                "log streams cannot be ordered by last event time when filtering by prefix".to_string(),
+            ));
+        }
+        let (sdk_order_by, descending) = match order_by {
+            LogStreamOrderBy::LogStreamName => (aws_sdk_cloudwatchlogs::model::OrderBy::LogStreamName, false),
+            LogStreamOrderBy::LastEventTime => (aws_sdk_cloudwatchlogs::model::OrderBy::LastEventTime, true),
+        };
+        let log_group_name = &log_group_name;
+        let prefix = prefix.as_deref();
+        let sdk_order_by = &sdk_order_by;
+        collect_pages(|next_token| async move {
+            let resp = match self
+                .with_retries(|| async {
+                    self.start_log_group_call(ApiAction::DescribeLogStreams, log_group_name)
+                        .await;
+                    self.client()
+                        .describe_log_streams()
+                        .log_group_name(log_group_name)
+                        .set_log_stream_name_prefix(prefix.map(str::to_string))
+                        .order_by(sdk_order_by.clone())
+                        .descending(descending)
+                        .limit(LOG_STREAM_LIMIT)
+                        .set_next_token(next_token.clone())
+                        .send()
+                        .await
+                })
+                .await
+            {
+                Ok(inner) => Ok(inner),
+                Err(err) => Err(CloudWatchLogsError::DescribeLogStreamsError(Box::new(err))),
+            }?;
+            let mut page = Vec::new();
+            for log_stream in resp.log_streams.unwrap_or_default() {
+                page.push(LogStream {
+                    log_stream_name: log_stream.log_stream_name.unwrap_or_default(),
+                    first_event_time: log_stream
+                        .first_event_timestamp
+                        .map(|ms| Utc.timestamp_millis(ms)),
+                    last_event_time: log_stream.last_event_timestamp.map(|ms| Utc.timestamp_millis(ms)),
+                });
+            }
+            Ok((page, resp.next_token))
+        })
+        .await
+    }
+
+    #[instrument(level = "debug")]
+    async fn run_insights_query(
+        &self,
+        log_group_names: Vec<String>,
+        query_string: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<InsightsQueryRow>, CloudWatchLogsError> {
+        const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+        let resp = match self
+            .with_retries(|| async {
+                self.start_call(ApiAction::StartQuery).await;
+                self.client()
+                    .start_query()
+                    .set_log_group_names(Some(log_group_names.clone()))
+                    .query_string(&query_string)
+                    .start_time(start_time.timestamp())
+                    .end_time(end_time.timestamp())
+                    .send()
+                    .await
+            })
+            .await
+        {
+            Ok(inner) => Ok(inner),
+            Err(err) => Err(CloudWatchLogsError::StartQueryError(Box::new(err))),
+        }?;
+        let query_id = match resp.query_id {
+            Some(query_id) => Ok(query_id),
+            None => Err(CloudWatchLogsError::InsightsQueryFailed(
+                "query_id missing".to_string(),
+            )),
+        }?;
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let resp = match self
+                .with_retries(|| async {
+                    self.start_call(ApiAction::GetQueryResults).await;
+                    self.client().get_query_results().query_id(&query_id).send().await
+                })
+                .await
+            {
+                Ok(inner) => Ok(inner),
+                Err(err) => Err(CloudWatchLogsError::GetQueryResultsError(Box::new(err))),
+            }?;
+            debug!("query_id: {}, status: {:?}", query_id, resp.status);
+            match resp.status {
+                Some(QueryStatus::Scheduled) | Some(QueryStatus::Running) => continue,
+                Some(QueryStatus::Complete) => {
+                    if let Some(statistics) = resp.statistics {
+                        self.usage
+                            .record_insights_scan(statistics.records_scanned, statistics.bytes_scanned);
+                    }
+                    return Ok(resp
+                        .results
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|row| {
+                            row.into_iter()
+                                .map(|field| {
+                                    (field.field.unwrap_or_default(), field.value.unwrap_or_default())
+                                })
+                                .collect()
+                        })
+                        .collect());
+                }
+                status => {
+                    return Err(CloudWatchLogsError::InsightsQueryFailed(format!(
+                        "query {} finished with status {:?}",
+                        query_id, status
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// For each client, the names of its log groups that match. `log_group_names` has the log group names of each
+/// client in the same order as `clients`. Clients without any matching log groups are left out.
+fn matching_log_group_names<C>(
+    clients: &[Arc<C>],
+    log_group_names: Vec<Vec<String>>,
+    matcher: &LogGroupNameMatcher,
+) -> Vec<(Arc<C>, Vec<String>)> {
+    clients
+        .iter()
+        .zip(log_group_names)
+        .map(|(cwl, log_group_names)| {
+            let log_group_names: Vec<String> = log_group_names
+                .into_iter()
+                .filter(|log_group_name| matcher.is_match(log_group_name))
+                .collect();
+            (Arc::clone(cwl), log_group_names)
+        })
+        .filter(|(_, log_group_names)| !log_group_names.is_empty())
+        .collect()
+}
+
+/// CloudWatch Logs accepts events up to this old, so a log group can have events from before it was created.
+const MAX_EVENT_AGE_AT_INGESTION_DAYS: i64 = 14;
+
+/// The earliest time a log group can have events from. Without metadata, look back five years.
+fn first_possible_event_time(metadata: Option<&LogGroupMetadata>, now: DateTime<Utc>) -> DateTime<Utc> {
+    let mut first_possible_event_time = now - Duration::days(365 * 5);
+    if let Some(metadata) = metadata {
+        if let Some(creation_time) = metadata.creation_time {
+            first_possible_event_time = creation_time - Duration::days(MAX_EVENT_AGE_AT_INGESTION_DAYS);
+        }
+
+        // Events older than the retention period have been deleted. Allow a day because deletion is not instant.
+        if let Some(retention_in_days) = metadata.retention_in_days {
+            first_possible_event_time = std::cmp::max(
+                first_possible_event_time,
+                now - Duration::days(retention_in_days as i64 + 1),
+            );
+        }
+    }
+    first_possible_event_time
+}
+
+/// A log group usually only exists in some of the regions being read, so results from regions where it was not
+/// found are dropped. The not found error is only returned if the log group was not found anywhere.
+fn skip_not_found<T>(results: Vec<Result<T, CloudWatchLogsError>>) -> Result<Vec<T>, CloudWatchLogsError> {
+    let mut values = vec![];
+    let mut not_found = None;
+    for result in results {
+        match result {
+            Ok(value) => values.push(value),
+            Err(err) if err.is_resource_not_found() => not_found = Some(err),
+            Err(err) => return Err(err),
+        }
+    }
+    match not_found {
+        Some(err) if values.is_empty() => Err(err),
+        _ => Ok(values),
+    }
+}
+
+/// Whether a file can be cached because no more events will arrive for its time range. Events are usually
+/// ingested within `ingestion_grace` of their timestamp, but if some of the events read were ingested even later
+/// than that, `ingestion_lag`, the time range is given at least as long.
+fn is_cacheable(cache_key: &CacheKey, ingestion_grace: Duration, ingestion_lag: Duration) -> bool {
+    Utc::now() - cache_key.time_bounds.last_event_time > std::cmp::max(ingestion_grace, ingestion_lag)
+}
+
+/// Events or errors from a live tail, in timestamp order. Dropping the receiver stops the live tail.
+pub type LiveTailReceiver = mpsc::Receiver<Result<FilteredLogEvent, CloudWatchLogsError>>;
+
+/// How often a live tail polls for new events.
+const LIVE_TAIL_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Identifies an event across log groups and regions, because event IDs are only unique within a log group.
+type EventKey = (Option<String>, String, String);
+
+fn event_key(event: &FilteredLogEvent) -> EventKey {
+    (
+        event.region.clone(),
+        event.log_group_name.clone(),
+        event.event_id.clone(),
+    )
+}
+
+/// The order events are shown in. Events with the same timestamp come back from CloudWatch Logs in no particular
+/// order, so they are ordered by log stream and event ID too, which makes reading the same time range twice give the
+/// same file.
+fn event_order(event: &FilteredLogEvent) -> (DateTime<Utc>, &str, &str) {
+    (event.timestamp, &event.log_stream_name, &event.event_id)
+}
+
+/// An event that has been formatted for display, along with what is needed to order and deduplicate it.
+#[derive(Clone, Debug)]
+struct RenderedEvent {
+    timestamp: DateTime<Utc>,
+    log_stream_name: String,
+
+    /// How long after its timestamp the event was ingested.
+    ingestion_lag: Duration,
+    key: EventKey,
+    line: String,
+}
+
+impl RenderedEvent {
+    fn new(event: FilteredLogEvent, formatter: &format_cwl_log_event::LogFormatter) -> Self {
+        RenderedEvent {
+            timestamp: event.timestamp,
+            log_stream_name: event.log_stream_name.clone(),
+            ingestion_lag: event.ingestion_time - event.timestamp,
+            key: event_key(&event),
+            line: formatter.format(event),
+        }
+    }
+
+    /// Same as `event_order`.
+    fn order(&self) -> (DateTime<Utc>, &str, &str) {
+        (self.timestamp, &self.log_stream_name, &self.key.2)
+    }
+}
+
+/// Split `[start_time, end_time)` into at most `count` consecutive ranges of about the same length, on millisecond
+/// boundaries because that is the resolution of event timestamps.
+fn split_time_range(
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    count: usize,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let millis = (end_time - start_time).num_milliseconds().max(0);
+    let count = (count.max(1) as i64).min(millis.max(1));
+    (0..count)
+        .map(|index| {
+            let start = start_time + Duration::milliseconds(millis * index / count);
+            let end = if index + 1 == count {
+                end_time
+            } else {
+                start_time + Duration::milliseconds(millis * (index + 1) / count)
+            };
+            (start, end)
+        })
+        .collect()
+}
+
+/// A log group's events between `start_time` and `end_time` rendered for display, in timestamp order.
+///
+/// Paginating through a busy time range is serial, because each page needs the token from the one before. With
+/// more than one sub-range the range is split and the sub-ranges are fetched at the same time, each collected before
+/// it is passed on so that they come out in order. Every call still waits for the rate limiter.
+///
+/// Every page fetched adds one to `pages_fetched`.
+#[allow(clippy::too_many_arguments)]
+fn rendered_events_stream<'a, C: CloudWatchLogsApi>(
+    cwl: &'a C,
+    log_group_name: String,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+    event_filter: EventFilter,
+    transformers: &'a TransformerChain,
+    formatter: &'a format_cwl_log_event::LogFormatter,
+    sub_ranges: usize,
+    pages_fetched: &'a AtomicUsize,
+) -> BoxStream<'a, Result<RenderedEvent, CloudWatchLogsError>> {
+    let render = move |event| {
+        futures::future::ready(Ok(transformers
+            .apply(event)
+            .map(|event| RenderedEvent::new(event, formatter))))
+    };
+    let events = move |start: DateTime<Utc>, end: DateTime<Utc>, event_filter: EventFilter| {
+        let pages = cwl
+            .log_event_pages_stream(
+                log_group_name.clone(),
+                Some(start),
+                Some(end),
+                event_filter.clone(),
+            )
+            .inspect_ok(move |_| {
+                pages_fetched.fetch_add(1, Ordering::Relaxed);
+            });
+        events_from_pages(pages, event_filter).try_filter_map(render)
+    };
+    if sub_ranges <= 1 {
+        return events(start_time, end_time, event_filter).boxed();
+    }
+    stream::iter(split_time_range(start_time, end_time, sub_ranges))
+        .map(move |(start, end)| events(start, end, event_filter.clone()).try_collect::<Vec<_>>())
+        .buffered(sub_ranges)
+        .map_ok(|events| stream::iter(events.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+}
+
+/// Rendered events of a log group that may wait for the merge before fetching that log group waits too.
+const RENDERED_EVENTS_BUFFER: usize = 1000;
+
+type RenderedEventSender = mpsc::Sender<Result<RenderedEvent, CloudWatchLogsError>>;
+
+/// The events of one log group as they are fetched, in `RenderedEvent::order`, ending with an error if fetching
+/// failed.
+type RenderedEventReceiver = mpsc::Receiver<Result<RenderedEvent, CloudWatchLogsError>>;
+
+/// A log group's fetch permit, which it gives up while the merge is not ready for its events. The merge may be waiting
+/// for the next event of a log group that is itself waiting for a permit, so holding on to it could deadlock.
+struct FetchPermit {
+    permits: Arc<Semaphore>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl FetchPermit {
+    async fn acquire(permits: Arc<Semaphore>) -> Self {
+        let mut permit = FetchPermit {
+            permits,
+            permit: None,
+        };
+        permit.reacquire().await;
+        permit
+    }
+
+    async fn reacquire(&mut self) {
+        let permit = Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .expect("fetch permits are never closed");
+        self.permit = Some(permit);
+    }
+
+    /// Send `result` to the merge, waiting without the permit if the channel is full. Returns false if the receiver
+    /// is gone.
+    async fn send(
+        &mut self,
+        sender: &RenderedEventSender,
+        result: Result<RenderedEvent, CloudWatchLogsError>,
+    ) -> bool {
+        let result = match sender.try_send(result) {
+            Ok(()) => return true,
+            Err(TrySendError::Closed(_)) => return false,
+            Err(TrySendError::Full(result)) => result,
+        };
+        self.permit = None;
+        if sender.send(result).await.is_err() {
+            return false;
+        }
+        self.reacquire().await;
+        true
+    }
+
+    /// Send events that share a timestamp in `RenderedEvent::order`. Returns false if the receiver is gone.
+    async fn send_tied_events(
+        &mut self,
+        sender: &RenderedEventSender,
+        tied: &mut Vec<RenderedEvent>,
+    ) -> bool {
+        tied.sort_by(|a, b| a.order().cmp(&b.order()));
+        for event in tied.drain(..) {
+            if !self.send(sender, Ok(event)).await {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// A file of the merged events of several log groups.
+#[derive(Debug)]
+struct MergedEvents {
+    data: String,
+
+    /// Events written, and whether more were left out because of the limit.
+    events: usize,
+    truncated: bool,
+
+    /// Indexes of the log groups that CloudWatch Logs no longer has, in the order they were passed in.
+    deleted: Vec<usize>,
+
+    /// Indexes of the log groups that were skipped because their circuit breaker is open.
+    skipped: Vec<usize>,
+
+    /// Longest time between an event's timestamp and when it was ingested.
+    max_ingestion_lag: Duration,
+}
+
+/// The line that ends a file that has more events than the limit. Fetching stops at the limit, so only the events
+/// that had already been fetched are counted.
+fn truncation_marker(omitted: usize) -> String {
+    format!(
+        "... output truncated (at least {} events omitted); narrow the time range",
+        omitted
+    )
+}
+
+/// The line that ends a file that would have been larger than the size limit.
+fn size_truncation_marker(max_bytes: usize, omitted: usize) -> String {
+    format!(
+        "... output truncated at {} bytes (at least {} events omitted); narrow the time range",
+        max_bytes, omitted
+    )
+}
+
+/// The line that takes the place of the events of a log group that was deleted since it was listed.
+fn deleted_log_group_warning(log_group_name: &str) -> String {
+    format!(
+        "[cwl-mount] skipped log group {}, it has been deleted",
+        log_group_name
+    )
+}
+
+/// The line that takes the place of the events of a log group that is skipped because it keeps failing.
+fn skipped_log_group_warning(log_group_name: &str, err: &CloudWatchLogsError) -> String {
+    format!("[cwl-mount] skipped log group {}, {}", log_group_name, err)
+}
+
+/// Merge the events of several log groups into one file in timestamp order as they arrive, so that lines are
+/// written out while the rest are still being fetched. Each log group's events must already be in
+/// `RenderedEvent::order`.
+///
+/// The same event can be fetched more than once, e.g. when a log group is matched through two clients for the
+/// same region, so only the first copy is kept. Every log group is read to the end so that the error lists all log
+/// groups that failed, not only the first.
+///
+/// A log group that was deleted since it was listed does not fail the others. Its events are skipped and a warning
+/// line is written after the rest instead. The same goes for a log group whose receiver only yields
+/// `CircuitOpen`, because it failed too many times in a row to be read for now.
+///
+/// Once `max_events` events have been written the rest are not fetched, and the file ends with a truncation marker
+/// instead, so that reading a busy time range by accident does not buffer millions of events. Likewise once the
+/// next event would make the file longer than `max_bytes`. The truncation marker and warning lines come after that
+/// and are not counted.
+async fn merge_rendered_events(
+    receivers: Vec<(String, RenderedEventReceiver)>,
+    max_events: Option<usize>,
+    max_bytes: Option<usize>,
+) -> Result<MergedEvents, CloudWatchLogsError> {
+    let (log_group_names, mut receivers): (Vec<String>, Vec<RenderedEventReceiver>) =
+        receivers.into_iter().unzip();
+    let mut heads: Vec<Option<RenderedEvent>> = vec![None; receivers.len()];
+    let mut heap = BinaryHeap::new();
+    let mut errors = vec![];
+    let mut deleted = vec![];
+    let mut skipped = vec![];
+    let mut max_ingestion_lag = Duration::zero();
+    let mut data = String::new();
+    let mut seen = HashSet::new();
+    let mut written = 0;
+    let mut omitted = None;
+    let mut size_limited = false;
+
+    // Log groups whose next event is needed before the earliest event can be picked.
+    let mut to_receive: Vec<usize> = (0..receivers.len()).collect();
+    loop {
+        for index in to_receive.drain(..) {
+            match receivers[index].recv().await {
+                Some(Ok(event)) => {
+                    // Copies of the same event are ordered by log group so that output does not depend on which
+                    // was fetched first.
+                    let (timestamp, log_stream_name, event_id) = event.order();
+                    heap.push(Reverse((
+                        timestamp,
+                        log_stream_name.to_string(),
+                        event_id.to_string(),
+                        index,
+                    )));
+                    heads[index] = Some(event);
+                }
+                Some(Err(err)) if err.is_resource_not_found() => deleted.push(index),
+                Some(Err(err @ CloudWatchLogsError::CircuitOpen { .. })) => skipped.push((index, err)),
+                Some(Err(err)) => errors.push((log_group_names[index].clone(), err)),
+                None => {}
+            }
+        }
+        let index = match heap.pop() {
+            Some(Reverse((_, _, _, index))) => index,
+            None => break,
+        };
+        let event = heads[index]
+            .take()
+            .expect("every log group in the heap has a head");
+        max_ingestion_lag = std::cmp::max(max_ingestion_lag, event.ingestion_lag);
+        if seen.insert(event.key) {
+            let separator_len = usize::from(!data.is_empty());
+            size_limited =
+                max_bytes.is_some_and(|max_bytes| data.len() + separator_len + event.line.len() > max_bytes);
+            if size_limited || max_events.is_some_and(|max_events| written >= max_events) {
+                omitted = Some(1 + count_unwritten(&heads, &mut receivers, &seen));
+                break;
+            }
+            if !data.is_empty() {
+                data.push('\n');
+            }
+            data.push_str(&event.line);
+            written += 1;
+        }
+        to_receive.push(index);
+    }
+    if !errors.is_empty() {
+        return Err(CloudWatchLogsError::LogGroupsFailed(errors));
+    }
+    deleted.sort_unstable();
+    for index in &deleted {
+        if !data.is_empty() {
+            data.push('\n');
+        }
+        data.push_str(&deleted_log_group_warning(&log_group_names[*index]));
+    }
+    skipped.sort_unstable_by_key(|(index, _)| *index);
+    for (index, err) in &skipped {
+        if !data.is_empty() {
+            data.push('\n');
+        }
+        data.push_str(&skipped_log_group_warning(&log_group_names[*index], err));
+    }
+    if let Some(omitted) = omitted {
+        if !data.is_empty() {
+            data.push('\n');
+        }
+        match max_bytes {
+            Some(max_bytes) if size_limited => data.push_str(&size_truncation_marker(max_bytes, omitted)),
+            _ => data.push_str(&truncation_marker(omitted)),
+        }
+    }
+    Ok(MergedEvents {
+        data,
+        events: written,
+        truncated: omitted.is_some(),
+        deleted,
+        skipped: skipped.into_iter().map(|(index, _)| index).collect(),
+        max_ingestion_lag,
+    })
+}
+
+/// Events that have been fetched but not written, without waiting for any more.
+fn count_unwritten(
+    heads: &[Option<RenderedEvent>],
+    receivers: &mut [RenderedEventReceiver],
+    seen: &HashSet<EventKey>,
+) -> usize {
+    let mut unwritten: HashSet<EventKey> = heads.iter().flatten().map(|event| event.key.clone()).collect();
+    for receiver in receivers.iter_mut() {
+        while let Ok(result) = receiver.try_recv() {
+            if let Ok(event) = result {
+                unwritten.insert(event.key);
+            }
+        }
+    }
+    unwritten.retain(|key| !seen.contains(key));
+    unwritten.len()
+}
+
+/// A log group of a live tail: the index of its client and its name.
+type LiveTailLogGroup = (usize, String);
+
+/// Where a live tail is up to in each log group, so that each poll only fetches the events of a log group from the
+/// latest timestamp sent for it, its high-water mark, onwards.
+///
+/// FilterLogEvents selects events by timestamp, so an event that is ingested after a later event of the same log group
+/// was sent is never seen. Only the events at the high-water mark are remembered, since the next poll starts at that
+/// timestamp and fetches them again.
+#[derive(Debug)]
+struct LiveTailState {
+    start_time: DateTime<Utc>,
+    log_groups: HashMap<LiveTailLogGroup, LiveTailPosition>,
+}
+
+#[derive(Debug)]
+struct LiveTailPosition {
+    high_water_mark: DateTime<Utc>,
+    seen: HashSet<EventKey>,
+}
+
+impl LiveTailState {
+    fn new(start_time: DateTime<Utc>) -> Self {
+        LiveTailState {
+            start_time,
+            log_groups: HashMap::new(),
+        }
+    }
+
+    /// Where the next poll of `log_group` starts.
+    fn start_time(&self, log_group: &LiveTailLogGroup) -> DateTime<Utc> {
+        self.log_groups
+            .get(log_group)
+            .map_or(self.start_time, |position| position.high_water_mark)
+    }
+
+    /// Return the events of `log_group` that were not sent before in timestamp order, and move its high-water mark
+    /// to the latest of them.
+    fn accept(
+        &mut self,
+        log_group: LiveTailLogGroup,
+        mut events: Vec<FilteredLogEvent>,
+    ) -> Vec<FilteredLogEvent> {
+        let start_time = self.start_time;
+        let position = self
+            .log_groups
+            .entry(log_group)
+            .or_insert_with(|| LiveTailPosition {
+                high_water_mark: start_time,
+                seen: HashSet::new(),
+            });
+        events.sort_by(|a, b| event_order(a).cmp(&event_order(b)));
+        let mut new_events = vec![];
+        for event in events {
+            if event.timestamp < position.high_water_mark {
+                continue;
+            }
+            if event.timestamp > position.high_water_mark {
+                position.high_water_mark = event.timestamp;
+                position.seen.clear();
+            }
+            if position.seen.insert(event_key(&event)) {
+                new_events.push(event);
+            }
+        }
+        new_events
+    }
+}
+
+/// Poll for new events until the receiver is dropped, see `LiveTailState`. Errors are sent to the receiver and
+/// polling carries on, so expired sessions or throttling recover on the next poll.
+///
+/// TODO replace polling with StartLiveTail once we are on a version of the AWS SDK that supports it.
+async fn live_tail<C: CloudWatchLogsApi>(
+    log_group_names: Vec<(Arc<C>, Vec<String>)>,
+    event_filter: EventFilter,
+    transformers: TransformerChain,
+    start_time: DateTime<Utc>,
+    sender: mpsc::Sender<Result<FilteredLogEvent, CloudWatchLogsError>>,
+) {
+    let mut state = LiveTailState::new(start_time);
+    while !sender.is_closed() {
+        let now = Utc::now();
+        let mut events = vec![];
+        for (index, (cwl, log_group_names)) in log_group_names.iter().enumerate() {
+            for log_group_name in log_group_names.iter() {
+                let log_group = (index, log_group_name.clone());
+                match cwl
+                    .get_log_events(
+                        log_group_name.clone(),
+                        Some(state.start_time(&log_group)),
+                        Some(now),
+                        None,
+                        event_filter.clone(),
+                    )
+                    .await
+                {
+                    Ok(log_group_events) => events.extend(state.accept(log_group, log_group_events)),
+                    Err(err) => {
+                        if sender.send(Err(err)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+        events.sort_by(|a, b| event_order(a).cmp(&event_order(b)));
+        for event in events.into_iter().filter_map(|event| transformers.apply(event)) {
+            if sender.send(Ok(event)).await.is_err() {
+                return;
+            }
+        }
+        tokio::time::sleep(LIVE_TAIL_POLL_INTERVAL).await;
+    }
+}
+
+/// How the CloudWatch Logs actor schedules work.
+#[derive(Clone, Debug)]
+pub struct ActorOptions {
+    /// Maximum number of log groups fetched at the same time. A broad log group filter otherwise starts one
+    /// fetch per matching log group.
+    pub max_concurrent_fetches: usize,
+
+    /// Also keep rendered log files that no longer change on disk, so that they survive restarts.
+    pub disk_cache: Option<DiskCacheOptions>,
+
+    /// zstd level with which cached log files are compressed, in memory and on disk.
+    pub cache_compression_level: i32,
+
+    /// How long to reuse the list of log groups before calling DescribeLogGroups again. Zero lists them on every
+    /// read.
+    pub log_group_names_ttl: std::time::Duration,
+
+    /// How long after their timestamp events may still be ingested. Files for time ranges that ended longer ago than
+    /// this are final and get cached, as long as none of their events took longer than this to be ingested.
+    pub ingestion_grace: std::time::Duration,
+
+    /// Most events in a file that reads a time range. Files with more end with a truncation marker instead of the
+    /// rest, which are not fetched. None reads every event.
+    pub max_events_per_file: Option<usize>,
+
+    /// Most bytes of events in a file that reads a time range. Files that would be larger end with a truncation
+    /// marker instead of the rest, which are not fetched. None allows files of any size.
+    pub max_file_size: Option<usize>,
+
+    /// Split each log group's time range into this many parts that are fetched at the same time, which makes reading
+    /// busy time ranges faster at the cost of buffering each part until the parts before it are done. One fetches
+    /// the whole range in one go.
+    pub sub_ranges_per_window: usize,
+
+    /// After this many reads of a log group fail in a row, e.g. because IAM denies reading it, reads skip it for
+    /// `circuit_breaker_cool_down` and say so at the end of the file instead. None never skips log groups.
+    pub circuit_breaker_threshold: Option<usize>,
+    pub circuit_breaker_cool_down: std::time::Duration,
+
+    /// Applied to every event between fetching and formatting it.
+    pub transformers: TransformerChain,
+
+    /// Log groups whose names match this regular expression are left out of reads by log group filter, even if the
+    /// filter matches them. Log groups read by name are read anyway.
+    pub log_group_exclude: Option<String>,
+
+    /// Maximum number of requests handled at the same time. Further requests wait in the mailbox.
+    pub max_concurrent_requests: usize,
+
+    /// How many requests may wait in the mailbox, and what happens to requests once it is full.
+    pub mailbox_capacity: usize,
+    pub mailbox_full_policy: MailboxFullPolicy,
+
+    /// How long the handle waits for each response, including the wait for room in the mailbox, before failing the
+    /// request with `RequestTimedOut`. None waits as long as it takes.
+    pub request_timeout: Option<std::time::Duration>,
+}
+
+impl Default for ActorOptions {
+    fn default() -> Self {
+        ActorOptions {
+            max_concurrent_fetches: 8,
+            disk_cache: None,
+            cache_compression_level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            log_group_names_ttl: std::time::Duration::from_secs(60),
+            ingestion_grace: std::time::Duration::from_secs(5 * 60),
+            max_events_per_file: Some(1_000_000),
+            max_file_size: None,
+            sub_ranges_per_window: 1,
+            circuit_breaker_threshold: Some(3),
+            circuit_breaker_cool_down: std::time::Duration::from_secs(5 * 60),
+            transformers: TransformerChain::default(),
+            log_group_exclude: None,
+            max_concurrent_requests: 64,
+            mailbox_capacity: 4,
+            mailbox_full_policy: MailboxFullPolicy::Block,
+            request_timeout: None,
+        }
+    }
+}
+
+// See: https://ryhl.io/blog/actors-with-tokio/
+#[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
+enum CloudWatchLogsMessage {
+    GetLogGroupNames {
+        respond_to: oneshot::Sender<Result<Vec<String>, CloudWatchLogsError>>,
+    },
+    DescribeLogGroups {
+        prefix: Option<String>,
+        respond_to: oneshot::Sender<Result<Vec<LogGroupMetadata>, CloudWatchLogsError>>,
+    },
+    GetLogEvents {
+        log_group_name: String,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        limit: Option<i32>,
+        event_filter: EventFilter,
+        respond_to: oneshot::Sender<Result<Vec<FilteredLogEvent>, CloudWatchLogsError>>,
+    },
+    GetFirstEventTimeForLogGroup {
+        log_group_name: String,
+        respond_to: oneshot::Sender<Result<Option<DateTime<Utc>>, CloudWatchLogsError>>,
+    },
+    DescribeMetricFilters {
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        progress: Option<watch::Sender<Progress>>,
+        respond_to: oneshot::Sender<Result<Vec<MetricFilter>, CloudWatchLogsError>>,
+    },
+    DescribeSubscriptionFilters {
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        progress: Option<watch::Sender<Progress>>,
+        respond_to: oneshot::Sender<Result<Vec<SubscriptionFilter>, CloudWatchLogsError>>,
+    },
+    DescribeQueryDefinitions {
+        name_prefix: Option<String>,
+        respond_to: oneshot::Sender<Result<Vec<QueryDefinition>, CloudWatchLogsError>>,
+    },
+    ExportToS3 {
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        bucket: String,
+        prefix: Option<String>,
+        respond_to: oneshot::Sender<Result<Vec<ExportTask>, CloudWatchLogsError>>,
+    },
+    GetLastEventTimeForLogGroup {
+        log_group_name: String,
+        respond_to: oneshot::Sender<Result<Option<DateTime<Utc>>, CloudWatchLogsError>>,
+    },
+    DescribeLogGroup {
+        log_group_name: String,
+        respond_to: oneshot::Sender<Result<Vec<LogGroupMetadata>, CloudWatchLogsError>>,
+    },
+    GetLogStreamNames {
+        log_group_name: String,
+        prefix: Option<String>,
+        order_by: LogStreamOrderBy,
+        respond_to: oneshot::Sender<Result<Vec<String>, CloudWatchLogsError>>,
+    },
+    RunInsightsQuery {
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        query_string: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        respond_to: oneshot::Sender<Result<Vec<InsightsQueryRow>, CloudWatchLogsError>>,
+    },
+    GetLogsToDisplay {
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        event_filter: EventFilter,
+        formatter: format_cwl_log_event::LogFormatter,
+        respond_to: oneshot::Sender<Result<LogPage, CloudWatchLogsError>>,
+    },
+    PrefetchLogsToDisplay {
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        windows: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+        event_filter: EventFilter,
+        formatter: format_cwl_log_event::LogFormatter,
+        respond_to: oneshot::Sender<Result<(), CloudWatchLogsError>>,
+    },
+    GetUsage {
+        respond_to: oneshot::Sender<Result<UsageStats, CloudWatchLogsError>>,
+    },
+    GetAccountIds {
+        respond_to: oneshot::Sender<Result<Vec<String>, CloudWatchLogsError>>,
+    },
+    ClearCache {
+        respond_to: oneshot::Sender<Result<(), CloudWatchLogsError>>,
+    },
+    Shutdown {
+        timeout: std::time::Duration,
+        respond_to: oneshot::Sender<Result<(), CloudWatchLogsError>>,
+    },
+    Preflight {
+        respond_to: oneshot::Sender<Result<Vec<PreflightReport>, CloudWatchLogsError>>,
+    },
+    GetRenderedPage {
+        log_group_name: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        event_filter: EventFilter,
+        formatter: format_cwl_log_event::LogFormatter,
+        page_token: Option<LogEventsPageToken>,
+        respond_to: oneshot::Sender<Result<LogEventsPage, CloudWatchLogsError>>,
+    },
+    LiveTail {
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        event_filter: EventFilter,
+        start_time: Option<DateTime<Utc>>,
+        respond_to: oneshot::Sender<Result<LiveTailReceiver, CloudWatchLogsError>>,
+    },
+}
+
+impl CloudWatchLogsMessage {
+    /// Fail the request without handling it, e.g. because it was dropped from a full mailbox.
+    fn fail(self, err: CloudWatchLogsError) {
+        match self {
+            CloudWatchLogsMessage::GetLogGroupNames { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::DescribeLogGroups { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::GetLogEvents { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::GetFirstEventTimeForLogGroup { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::DescribeMetricFilters { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::DescribeSubscriptionFilters { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::DescribeQueryDefinitions { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::ExportToS3 { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::GetLastEventTimeForLogGroup { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::DescribeLogGroup { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::GetLogStreamNames { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::RunInsightsQuery { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::GetLogsToDisplay { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::PrefetchLogsToDisplay { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::GetUsage { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::GetAccountIds { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::ClearCache { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::Shutdown { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::Preflight { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::GetRenderedPage { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+            CloudWatchLogsMessage::LiveTail { respond_to, .. } => {
+                let _ = respond_to.send(Err(err));
+            }
+        }
+    }
+}
+
+type LogGroupNamesCache = tokio::sync::Mutex<HashMap<Option<String>, (Instant, Vec<Vec<String>>)>>;
+
+#[derive(Debug)]
+struct CloudWatchLogsActor<C> {
+    /// One client per region. Results from all of them are merged.
+    clients: Vec<Arc<C>>,
+    logs_display_cache: Arc<tokio::sync::Mutex<LruCache<CacheKey, CacheValue>>>,
+
+    /// Bounds how many log groups are fetched at the same time across all reads.
+    fetch_permits: Arc<Semaphore>,
+
+    /// Bounds how many requests are handled at the same time, so that further requests wait in the mailbox.
+    request_permits: Arc<Semaphore>,
+    max_concurrent_requests: usize,
+
+    disk_cache: Option<DiskCache>,
+    cache_compression_level: i32,
+
+    /// Log group names of each client, in the same order as `clients`, and when they were listed, by the prefix
+    /// they were listed with. Every read needs them to find the log groups that match, and listing them takes one
+    /// DescribeLogGroups call per 50 groups.
+    log_group_names_cache: LogGroupNamesCache,
+    log_group_names_ttl: std::time::Duration,
+
+    ingestion_grace: Duration,
+    max_events_per_file: Option<usize>,
+    max_file_size: Option<usize>,
+    sub_ranges_per_window: usize,
+    circuit_breakers: CircuitBreakers,
+    transformers: TransformerChain,
+    log_group_exclude: Option<String>,
+
+    /// Cache hits and misses. API calls and events are counted by each client.
+    usage: Usage,
+}
+
+impl<C: CloudWatchLogsApi + 'static> CloudWatchLogsActor<C> {
+    fn new(clients: Vec<C>, options: ActorOptions) -> Self {
+        let cache_capacity = Duration::hours(1).num_minutes() as usize;
+        let regions: Vec<Option<&str>> = clients.iter().map(|cwl| cwl.region()).collect();
+        let transformers = options.transformers;
+        let disk_cache = options.disk_cache.and_then(|disk_cache_options| {
+            // The same log group name in another region is a different log group, and transformed events render
+            // differently.
+            let mut namespace = format!("{}{:?}", disk_cache_options.namespace, regions);
+            if !transformers.is_empty() {
+                namespace.push_str(&format!("{:?}", transformers));
+            }
+            let disk_cache_options = DiskCacheOptions {
+                namespace,
+                ..disk_cache_options
+            };
+            match DiskCache::new(disk_cache_options) {
+                Ok(disk_cache) => Some(disk_cache),
+                Err(err) => {
+                    warn!("not caching on disk, failed to create cache directory: {}", err);
+                    None
+                }
+            }
+        });
+        CloudWatchLogsActor {
+            clients: clients.into_iter().map(Arc::new).collect(),
+            fetch_permits: Arc::new(Semaphore::new(options.max_concurrent_fetches)),
+            request_permits: Arc::new(Semaphore::new(options.max_concurrent_requests)),
+            max_concurrent_requests: options.max_concurrent_requests,
+            logs_display_cache: Arc::new(tokio::sync::Mutex::new(LruCache::new(cache_capacity))),
+            disk_cache,
+            cache_compression_level: options.cache_compression_level,
+            log_group_names_cache: tokio::sync::Mutex::new(HashMap::new()),
+            log_group_names_ttl: options.log_group_names_ttl,
+            ingestion_grace: Duration::from_std(options.ingestion_grace)
+                .unwrap_or_else(|_| Duration::max_value()),
+            max_events_per_file: options.max_events_per_file,
+            max_file_size: options.max_file_size,
+            sub_ranges_per_window: options.sub_ranges_per_window,
+            circuit_breakers: CircuitBreakers::new(
+                options.circuit_breaker_threshold,
+                options.circuit_breaker_cool_down,
+            ),
+            transformers,
+            log_group_exclude: options.log_group_exclude,
+            usage: Usage::default(),
+        }
+    }
+
+    /// The matcher for a log group name or filter, leaving out excluded log groups if it is a filter.
+    fn log_group_matcher(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+    ) -> Result<LogGroupNameMatcher, CloudWatchLogsError> {
+        let by_name = log_group_name.is_some();
+        let matcher = log_group_name_matcher(log_group_name, log_group_filter)?;
+        Ok(match &self.log_group_exclude {
+            Some(log_group_exclude) if !by_name => matcher.excluding(log_group_exclude),
+            _ => matcher,
+        })
+    }
+
+    #[instrument(level = "debug")]
+    async fn handle_message(&self, msg: CloudWatchLogsMessage) {
+        match msg {
+            CloudWatchLogsMessage::GetLogGroupNames { respond_to } => {
+                let result = self.get_log_group_names().await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::DescribeLogGroups { prefix, respond_to } => {
+                let result = self.describe_log_groups(prefix).await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::GetLogEvents {
+                log_group_name,
+                start_time,
+                end_time,
+                limit,
+                event_filter,
+                respond_to,
+            } => {
+                let result = self
+                    .get_log_events(log_group_name, start_time, end_time, limit, event_filter)
+                    .await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::GetFirstEventTimeForLogGroup {
+                log_group_name,
+                respond_to,
+            } => {
+                let result = self.get_first_event_time_for_log_group(log_group_name).await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::DescribeMetricFilters {
+                log_group_name,
+                log_group_filter,
+                progress,
+                respond_to,
+            } => {
+                let result = self
+                    .describe_metric_filters(log_group_name, log_group_filter, progress)
+                    .await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::DescribeSubscriptionFilters {
+                log_group_name,
+                log_group_filter,
+                progress,
+                respond_to,
+            } => {
+                let result = self
+                    .describe_subscription_filters(log_group_name, log_group_filter, progress)
+                    .await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::DescribeQueryDefinitions {
+                name_prefix,
+                respond_to,
+            } => {
+                let result = self.describe_query_definitions(name_prefix).await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::ExportToS3 {
+                log_group_name,
+                log_group_filter,
+                start_time,
+                end_time,
+                bucket,
+                prefix,
+                respond_to,
+            } => {
+                let result = self
+                    .export_to_s3(
+                        log_group_name,
+                        log_group_filter,
+                        start_time,
+                        end_time,
+                        bucket,
+                        prefix,
+                    )
+                    .await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::GetLastEventTimeForLogGroup {
+                log_group_name,
+                respond_to,
+            } => {
+                let result = self.get_last_event_time_for_log_group(log_group_name).await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::DescribeLogGroup {
+                log_group_name,
+                respond_to,
+            } => {
+                let result = self.describe_log_group(log_group_name).await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::GetLogStreamNames {
+                log_group_name,
+                prefix,
+                order_by,
+                respond_to,
+            } => {
+                let result = self.get_log_stream_names(log_group_name, prefix, order_by).await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::RunInsightsQuery {
+                log_group_name,
+                log_group_filter,
+                query_string,
+                start_time,
+                end_time,
+                respond_to,
+            } => {
+                let result = self
+                    .run_insights_query(
+                        log_group_name,
+                        log_group_filter,
+                        query_string,
+                        start_time,
+                        end_time,
+                    )
+                    .await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::GetLogsToDisplay {
+                log_group_name,
+                log_group_filter,
+                start_time,
+                end_time,
+                event_filter,
+                mut respond_to,
+                formatter,
+            } => {
+                let matcher = match self.log_group_matcher(log_group_name, log_group_filter) {
+                    Ok(matcher) => matcher,
+                    Err(err) => {
+                        let _ = respond_to.send(Err(err));
+                        return;
+                    }
+                };
+
+                // Stop fetching once the caller gives up, e.g. because the file was closed.
+                let result = tokio::select! {
+                    result = self.get_logs_to_display(matcher, start_time, end_time, event_filter, formatter) => {
+                        Some(result)
+                    }
+                    _ = respond_to.closed() => None,
+                };
+                match result {
+                    Some(result) => {
+                        let _ = respond_to.send(result);
+                    }
+                    None => debug!("get_logs_to_display cancelled by the caller"),
+                }
+            }
+            CloudWatchLogsMessage::PrefetchLogsToDisplay {
+                log_group_name,
+                log_group_filter,
+                windows,
+                event_filter,
+                formatter,
+                respond_to,
+            } => {
+                let result = match self.log_group_matcher(log_group_name, log_group_filter) {
+                    Ok(matcher) => {
+                        self.prefetch_logs_to_display(matcher, windows, event_filter, formatter)
+                            .await
+                    }
+                    Err(err) => Err(err),
+                };
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::GetUsage { respond_to } => {
+                let mut usage = self.usage.stats();
+                for cwl in &self.clients {
+                    usage.add(&cwl.usage());
+                }
+                let _ = respond_to.send(Ok(usage));
+            }
+            CloudWatchLogsMessage::GetAccountIds { respond_to } => {
+                let mut account_ids: Vec<String> = join_all(self.clients.iter().map(|cwl| cwl.account_id()))
+                    .await
+                    .into_iter()
+                    .flatten()
+                    .collect();
+                account_ids.sort();
+                account_ids.dedup();
+                let _ = respond_to.send(Ok(account_ids));
+            }
+            CloudWatchLogsMessage::ClearCache { respond_to } => {
+                self.logs_display_cache.lock().await.clear();
+                if let Some(disk_cache) = self.disk_cache.as_ref() {
+                    disk_cache.clear().await;
+                }
+                let _ = respond_to.send(Ok(()));
+            }
+            CloudWatchLogsMessage::Shutdown { respond_to, .. } => {
+                // Handled by `run_cloud_watch_logs_actor`, which never passes it on.
+                let _ = respond_to.send(Ok(()));
+            }
+            CloudWatchLogsMessage::Preflight { respond_to } => {
+                let result = try_join_all(self.clients.iter().map(|cwl| cwl.preflight())).await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::GetRenderedPage {
+                log_group_name,
+                start_time,
+                end_time,
+                event_filter,
+                formatter,
+                page_token,
+                respond_to,
+            } => {
+                let result = self
+                    .get_rendered_page(
+                        log_group_name,
+                        start_time,
+                        end_time,
+                        event_filter,
+                        formatter,
+                        page_token,
+                    )
+                    .await;
+                let _ = respond_to.send(result);
+            }
+            CloudWatchLogsMessage::LiveTail {
+                log_group_name,
+                log_group_filter,
+                event_filter,
+                start_time,
+                respond_to,
+            } => {
+                let result = self
+                    .live_tail(log_group_name, log_group_filter, event_filter, start_time)
+                    .await;
+                let _ = respond_to.send(result);
+            }
+        }
+    }
+
+    /// The file for `cache_key` if it is in the in-memory or disk cache.
+    async fn cached_logs_to_display(&self, cache_key: &CacheKey) -> Option<Bytes> {
+        let cache = Arc::clone(&self.logs_display_cache);
+        {
+            let mut cache = cache.lock().await;
+            if let Some(value) = cache.get(cache_key) {
+                match value.decompress() {
+                    Ok(data) => {
+                        self.usage.record_memory_cache_hit();
+                        return Some(data);
+                    }
+                    Err(err) => warn!("failed to decompress cached log file: {}", err),
+                }
+            }
+        }
+        let disk_cache_key = disk_cache_key(cache_key, self.max_events_per_file, self.max_file_size);
+        let disk_cache = self
+            .disk_cache
+            .as_ref()
+            .filter(|_| is_cacheable(cache_key, self.ingestion_grace, Duration::zero()))?;
+        let compressed_data = disk_cache.get(&disk_cache_key).await?;
+        debug!("get_logs_to_display disk cache hit");
+        let value = CacheValue {
+            compressed_data_to_display: compressed_data,
+        };
+        match value.decompress() {
+            Ok(data) => {
+                cache.lock().await.put(cache_key.clone(), value);
+                self.usage.record_disk_cache_hit();
+                Some(data)
+            }
+            Err(err) => {
+                warn!("failed to decompress cached log file: {}", err);
+                None
+            }
+        }
+    }
+
+    #[instrument(level = "debug")]
+    async fn get_logs_to_display(
+        &self,
+        log_group_name_matcher: LogGroupNameMatcher,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        event_filter: EventFilter,
+        formatter: format_cwl_log_event::LogFormatter,
+    ) -> Result<LogPage, CloudWatchLogsError> {
+        let fetch_started = Instant::now();
+        let cache_key = CacheKey {
+            log_group_name_matcher: log_group_name_matcher.clone(),
+            time_bounds: TimeBounds {
+                first_event_time: start_time,
+                last_event_time: end_time,
+            },
+            event_filter: event_filter.clone(),
+            formatter: formatter.clone(),
+        };
+        debug!("get_logs_to_display. cache_key: {:?}", cache_key);
+        if let Some(data) = self.cached_logs_to_display(&cache_key).await {
+            return Ok(LogPage::from_cache(data));
+        }
+        let cache = Arc::clone(&self.logs_display_cache);
+        let disk_cache_key = disk_cache_key(&cache_key, self.max_events_per_file, self.max_file_size);
+        self.usage.record_cache_miss();
+        let mut tasks = vec![];
+        let mut receivers = vec![];
+        let mut log_groups = vec![];
+        let pages_fetched = Arc::new(AtomicUsize::new(0));
+        for (cwl, log_group_name) in self
+            .matching_log_group_names(&log_group_name_matcher)
+            .await?
+            .into_iter()
+            .flat_map(|(cwl, log_group_names)| {
+                log_group_names
+                    .into_iter()
+                    .map(move |name| (Arc::clone(&cwl), name))
+            })
+        {
+            let (sender, receiver) = mpsc::channel(RENDERED_EVENTS_BUFFER);
+            let index = receivers.len();
+            receivers.push((log_group_name.clone(), receiver));
+            log_groups.push((Arc::clone(&cwl), log_group_name.clone()));
+            if let BreakerState::Open { failures, retry_at } = self.breaker_state(&cwl, &log_group_name) {
+                let _ = sender.try_send(Err(CloudWatchLogsError::CircuitOpen { failures, retry_at }));
+                continue;
+            }
+            let event_filter = event_filter.clone();
+            let transformers = self.transformers.clone();
+            let formatter = formatter.clone();
+            let fetch_permits = Arc::clone(&self.fetch_permits);
+            let sub_ranges = self.sub_ranges_per_window;
+            let pages_fetched = Arc::clone(&pages_fetched);
+            let handle = tokio::spawn(async move {
+                let fetch = async {
+                    let mut permit = FetchPermit::acquire(fetch_permits).await;
+                    debug!(
+                        "get_logs_to_display spawning to get logs for log_group_name {}",
+                        log_group_name
+                    );
+
+                    // Format each page as it arrives so only the rendered lines are kept around, not the events.
+                    let mut events = rendered_events_stream(
+                        cwl.as_ref(),
+                        log_group_name,
+                        start_time,
+                        end_time,
+                        event_filter,
+                        &transformers,
+                        &formatter,
+                        sub_ranges,
+                        &pages_fetched,
+                    );
+
+                    // CloudWatch Logs returns events in timestamp order, so only events that share a timestamp
+                    // need to be held back to be put in order.
+                    let mut tied: Vec<RenderedEvent> = vec![];
+                    while let Some(result) = events.next().await {
+                        let event = match result {
+                            Ok(event) => event,
+                            Err(err) => {
+                                let failed = !err.is_resource_not_found();
+                                if permit.send_tied_events(&sender, &mut tied).await {
+                                    permit.send(&sender, Err(err)).await;
+                                }
+                                return failed;
+                            }
+                        };
+                        if tied
+                            .first()
+                            .is_some_and(|first| first.timestamp != event.timestamp)
+                            && !permit.send_tied_events(&sender, &mut tied).await
+                        {
+                            return false;
+                        }
+                        tied.push(event);
+                    }
+                    permit.send_tied_events(&sender, &mut tied).await;
+                    false
+                };
+
+                // The merge drops the receiver when the read is cancelled, so stop paginating rather than finish.
+                // A cancelled fetch neither succeeded nor failed.
+                tokio::select! {
+                    failed = fetch => Some(failed),
+                    _ = sender.closed() => None,
+                }
+            });
+            tasks.push((index, handle));
+        }
+
+        let merged = merge_rendered_events(receivers, self.max_events_per_file, self.max_file_size).await;
+
+        // A task that panicked ends its log group early, which looks just like the end of its events.
+        let (merged, mut errors) = match merged {
+            Ok(merged) => (Some(merged), vec![]),
+            Err(CloudWatchLogsError::LogGroupsFailed(errors)) => (None, errors),
+            Err(err) => return Err(err),
+        };
+        for (index, handle) in tasks {
+            let (cwl, log_group_name) = &log_groups[index];
+            match handle.await {
+                Ok(Some(failed)) => self.record_read(cwl, log_group_name, failed),
+                Ok(None) => {}
+                Err(err) => {
+                    debug!("task for log group {} failed: {:?}", log_group_name, err);
+                    let err = CloudWatchLogsError::LogGroupTaskFailed(log_group_name.clone());
+                    errors.push((log_group_name.clone(), err));
+                    self.record_read(cwl, log_group_name, true);
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(CloudWatchLogsError::LogGroupsFailed(errors));
+        }
+        let MergedEvents {
+            data,
+            events,
+            truncated,
+            deleted,
+            skipped,
+            max_ingestion_lag,
+        } = merged.expect("merging only fails with errors");
+        let page = LogPage {
+            data: data.into(),
+            cached: false,
+            log_groups_queried: log_groups.len(),
+            events,
+            pages_fetched: pages_fetched.load(Ordering::Relaxed),
+            truncated,
+            fetch_duration: fetch_started.elapsed(),
+        };
+        if !deleted.is_empty() {
+            let deleted: Vec<(Arc<C>, String)> = deleted
+                .into_iter()
+                .map(|index| log_groups[index].clone())
+                .collect();
+            self.forget_log_groups(&deleted).await;
+
+            // Later reads leave the log groups out, so this file would not match them.
+            return Ok(page);
+        }
+        if !skipped.is_empty() {
+            // Later reads include the log groups again once they are retried.
+            return Ok(page);
+        }
+        if page.truncated {
+            // The file is missing events, and narrowing the time range as the truncation marker suggests reads a
+            // different file anyway.
+            return Ok(page);
+        }
+        trace!("data: {:?}", page.data);
+        if is_cacheable(&cache_key, self.ingestion_grace, max_ingestion_lag) {
+            match CacheValue::compress(&page.data, self.cache_compression_level) {
+                Ok(value) => {
+                    if let Some(disk_cache) = self.disk_cache.as_ref() {
+                        disk_cache
+                            .put(&disk_cache_key, value.compressed_data_to_display.clone())
+                            .await;
+                    }
+                    cache.lock().await.put(cache_key, value);
+                }
+                Err(err) => warn!("not caching log file, failed to compress it: {}", err),
+            }
+        }
+        Ok(page)
+    }
+
+    /// Fetch the files for several time windows at the same time so that they are cached by the time they are read.
+    /// Windows that are too recent to be cached are skipped.
+    async fn prefetch_logs_to_display(
+        &self,
+        log_group_name_matcher: LogGroupNameMatcher,
+        windows: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+        event_filter: EventFilter,
+        formatter: format_cwl_log_event::LogFormatter,
+    ) -> Result<(), CloudWatchLogsError> {
+        let now = Utc::now();
+        try_join_all(
+            windows
+                .into_iter()
+                .filter(|(_, end_time)| now - *end_time > self.ingestion_grace)
+                .map(|(start_time, end_time)| {
+                    self.get_logs_to_display(
+                        log_group_name_matcher.clone(),
+                        start_time,
+                        end_time,
+                        event_filter.clone(),
+                        formatter.clone(),
+                    )
+                }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// A page of one log group's events, from the region that has the log group. Joining the pages gives the same
+    /// file as `get_logs_to_display`, so a file that is cached, whose log group is in more than one region, or that
+    /// reaches the limits on events and bytes per file is read whole instead and the rest of it is the last page.
+    async fn get_rendered_page(
+        &self,
+        log_group_name: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        event_filter: EventFilter,
+        formatter: format_cwl_log_event::LogFormatter,
+        page_token: Option<LogEventsPageToken>,
+    ) -> Result<LogEventsPage, CloudWatchLogsError> {
+        let matcher = self.log_group_matcher(Some(log_group_name.clone()), None)?;
+        let read_whole = |bytes_rendered: u64| {
+            let matcher = matcher.clone();
+            let event_filter = event_filter.clone();
+            let formatter = formatter.clone();
+            async move {
+                let page = self
+                    .get_logs_to_display(matcher, start_time, end_time, event_filter, formatter)
+                    .await?;
+                Ok(LogEventsPage::rest_of_file(page.data, bytes_rendered))
+            }
+        };
+        if page_token.is_none() {
+            let cache_key = CacheKey {
+                log_group_name_matcher: matcher.clone(),
+                time_bounds: TimeBounds {
+                    first_event_time: start_time,
+                    last_event_time: end_time,
+                },
+                event_filter: event_filter.clone(),
+                formatter: formatter.clone(),
+            };
+            if let Some(data) = self.cached_logs_to_display(&cache_key).await {
+                return Ok(LogEventsPage::rest_of_file(data, 0));
+            }
+        }
+        let mut page_token = page_token.unwrap_or_default();
+        let cwl = match page_token.next_token {
+            Some(_) => self
+                .clients
+                .iter()
+                .find(|cwl| cwl.region() == page_token.region.as_deref())
+                .map(Arc::clone)
+                .ok_or_else(|| {
+                    CloudWatchLogsError::InvalidArgument(format!(
+                        "page token is for region {:?}, which is not being read",
+                        page_token.region
+                    ))
+                })?,
+            None => {
+                let mut clients: Vec<_> = self
+                    .matching_log_group_names(&matcher)
+                    .await?
+                    .into_iter()
+                    .filter(|(_, log_group_names)| !log_group_names.is_empty())
+                    .map(|(cwl, _)| cwl)
+                    .collect();
+                if clients.len() > 1 {
+                    return read_whole(0).await;
+                }
+                clients.pop().ok_or_else(|| {
+                    CloudWatchLogsError::NoCloudWatchLogGroupsMatchFilter(log_group_name.clone())
+                })?
+            }
+        };
+        page_token.region = cwl.region().map(str::to_string);
+        let bytes_rendered = page_token.bytes_rendered;
+        let events_rendered = page_token.events_rendered;
+        let page = cwl
+            .get_rendered_page(
+                log_group_name,
+                Some(start_time),
+                Some(end_time),
+                event_filter.clone(),
+                &self.transformers,
+                &formatter,
+                Some(page_token),
+            )
+            .await?;
+        let over_limit = |limit: Option<usize>, count: usize| limit.is_some_and(|limit| count > limit);
+        if over_limit(self.max_events_per_file, events_rendered + page.events.len())
+            || over_limit(self.max_file_size, page.bytes_rendered as usize)
+        {
+            return read_whole(bytes_rendered).await;
+        }
+        Ok(page)
+    }
+
+    /// Log group names of each client that start with `prefix`, listed again once the cached ones are older than
+    /// the TTL.
+    async fn log_group_names_by_client(
+        &self,
+        prefix: Option<String>,
+    ) -> Result<Vec<Vec<String>>, CloudWatchLogsError> {
+        // Holding the lock while listing means concurrent reads wait for one listing instead of each starting one.
+        let mut cache = self.log_group_names_cache.lock().await;
+        if let Some((listed_at, log_group_names)) = cache.get(&prefix) {
+            if listed_at.elapsed() < self.log_group_names_ttl {
+                return Ok(log_group_names.clone());
+            }
+        }
+        let log_group_names = try_join_all(
+            self.clients
+                .iter()
+                .map(|cwl| cwl.get_log_group_names_with_prefix(prefix.as_deref())),
+        )
+        .await?;
+        cache.insert(prefix, (Instant::now(), log_group_names.clone()));
+        Ok(log_group_names)
+    }
+
+    /// Index of a client in `clients`, which identifies its region.
+    fn client_index(&self, cwl: &Arc<C>) -> Option<usize> {
+        self.clients.iter().position(|client| Arc::ptr_eq(client, cwl))
+    }
+
+    fn breaker_state(&self, cwl: &Arc<C>, log_group_name: &str) -> BreakerState {
+        match self.client_index(cwl) {
+            Some(index) => self.circuit_breakers.state(index, log_group_name, Utc::now()),
+            None => BreakerState::Closed,
+        }
+    }
+
+    /// Count a read of a log group towards its circuit breaker.
+    fn record_read(&self, cwl: &Arc<C>, log_group_name: &str, failed: bool) {
+        let index = match self.client_index(cwl) {
+            Some(index) => index,
+            None => return,
+        };
+        if !failed {
+            self.circuit_breakers.record_success(index, log_group_name);
+        } else if self
+            .circuit_breakers
+            .record_failure(index, log_group_name, Utc::now())
+        {
+            warn!(
+                "reading log group {} in {} keeps failing, skipping it for a while",
+                log_group_name,
+                cwl.region().unwrap_or("the default region")
+            );
+        }
+    }
+
+    /// Drop log groups that were deleted from the cached log group names, so that reads stop fetching them until
+    /// they are listed again.
+    async fn forget_log_groups(&self, deleted: &[(Arc<C>, String)]) {
+        let mut cache = self.log_group_names_cache.lock().await;
+        for (cwl, log_group_name) in deleted {
+            warn!(
+                "log group {} in {} has been deleted, no longer reading it",
+                log_group_name,
+                cwl.region().unwrap_or("the default region")
+            );
+            let index = match self.client_index(cwl) {
+                Some(index) => index,
+                None => continue,
+            };
+            for (_, log_group_names) in cache.values_mut() {
+                log_group_names[index].retain(|other| other != log_group_name);
+            }
+        }
+    }
+
+    async fn matching_log_group_names(
+        &self,
+        matcher: &LogGroupNameMatcher,
+    ) -> Result<Vec<(Arc<C>, Vec<String>)>, CloudWatchLogsError> {
+        let log_group_names = self.log_group_names_by_client(matcher.literal_prefix()).await?;
+        Ok(matching_log_group_names(&self.clients, log_group_names, matcher))
+    }
+
+    /// Log group names across all regions, without duplicates.
+    async fn get_log_group_names(&self) -> Result<Vec<String>, CloudWatchLogsError> {
+        let log_group_names = self.log_group_names_by_client(None).await?;
+        let mut seen = HashSet::new();
+        Ok(log_group_names
+            .into_iter()
+            .flatten()
+            .filter(|log_group_name| seen.insert(log_group_name.clone()))
+            .collect())
+    }
+
+    /// Metadata of the log groups whose names start with `prefix` in every region, sorted by name and region.
+    async fn describe_log_groups(
+        &self,
+        prefix: Option<String>,
+    ) -> Result<Vec<LogGroupMetadata>, CloudWatchLogsError> {
+        let metadata = try_join_all(
+            self.clients
+                .iter()
+                .map(|cwl| cwl.describe_log_groups(prefix.as_deref())),
+        )
+        .await?;
+        let mut metadata: Vec<LogGroupMetadata> = metadata.into_iter().flatten().collect();
+        metadata.sort_by(|a, b| (&a.log_group_name, &a.region).cmp(&(&b.log_group_name, &b.region)));
+        Ok(metadata)
+    }
+
+    async fn get_log_events(
+        &self,
+        log_group_name: String,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        limit: Option<i32>,
+        event_filter: EventFilter,
+    ) -> Result<Vec<FilteredLogEvent>, CloudWatchLogsError> {
+        let results = join_all(self.clients.iter().map(|cwl| {
+            cwl.get_log_events(
+                log_group_name.clone(),
+                start_time,
+                end_time,
+                limit,
+                event_filter.clone(),
+            )
+        }))
+        .await;
+        let mut events: Vec<FilteredLogEvent> = skip_not_found(results)?
+            .into_iter()
+            .flatten()
+            .filter_map(|event| self.transformers.apply(event))
+            .collect();
+        events.sort_by(|a, b| event_order(a).cmp(&event_order(b)));
+        if let Some(limit) = limit {
+            events.truncate(limit as usize);
+        }
+        Ok(events)
+    }
+
+    async fn get_first_event_time_for_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Option<DateTime<Utc>>, CloudWatchLogsError> {
+        let results = join_all(
+            self.clients
+                .iter()
+                .map(|cwl| cwl.get_first_event_time_for_log_group(log_group_name.clone())),
+        )
+        .await;
+        Ok(skip_not_found(results)?.into_iter().flatten().min())
+    }
+
+    /// Metric filters of every matching log group, sending `progress` as each log group is done.
+    async fn describe_metric_filters(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        progress: Option<watch::Sender<Progress>>,
+    ) -> Result<Vec<MetricFilter>, CloudWatchLogsError> {
+        let matcher = self.log_group_matcher(log_group_name, log_group_filter)?;
+        let log_group_names = self.matching_log_group_names(&matcher).await?;
+        let calls = log_group_names.iter().flat_map(|(cwl, log_group_names)| {
+            log_group_names
+                .iter()
+                .map(move |log_group_name| cwl.describe_metric_filters(log_group_name.clone()))
+        });
+        let metric_filters = try_join_all_with_progress(calls.collect(), progress).await?;
+        Ok(metric_filters.into_iter().flatten().collect())
+    }
+
+    /// Subscription filters of every matching log group, sending `progress` as each log group is done.
+    async fn describe_subscription_filters(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        progress: Option<watch::Sender<Progress>>,
+    ) -> Result<Vec<SubscriptionFilter>, CloudWatchLogsError> {
+        let matcher = self.log_group_matcher(log_group_name, log_group_filter)?;
+        let log_group_names = self.matching_log_group_names(&matcher).await?;
+        let calls = log_group_names.iter().flat_map(|(cwl, log_group_names)| {
+            log_group_names
+                .iter()
+                .map(move |log_group_name| cwl.describe_subscription_filters(log_group_name.clone()))
+        });
+        let subscription_filters = try_join_all_with_progress(calls.collect(), progress).await?;
+        Ok(subscription_filters.into_iter().flatten().collect())
+    }
+
+    /// Saved Logs Insights queries in every region, sorted by name.
+    async fn describe_query_definitions(
+        &self,
+        name_prefix: Option<String>,
+    ) -> Result<Vec<QueryDefinition>, CloudWatchLogsError> {
+        let query_definitions = try_join_all(
+            self.clients
+                .iter()
+                .map(|cwl| cwl.describe_query_definitions(name_prefix.clone())),
+        )
+        .await?;
+        let mut query_definitions: Vec<QueryDefinition> = query_definitions.into_iter().flatten().collect();
+        query_definitions.sort_by(|a, b| (&a.name, &a.region).cmp(&(&b.name, &b.region)));
+        Ok(query_definitions)
+    }
+
+    /// Export every matching log group, one at a time because CloudWatch Logs only runs one export task per
+    /// account at a time.
+    async fn export_to_s3(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        bucket: String,
+        prefix: Option<String>,
+    ) -> Result<Vec<ExportTask>, CloudWatchLogsError> {
+        let matcher = self.log_group_matcher(log_group_name, log_group_filter)?;
+        let log_group_names = self.matching_log_group_names(&matcher).await?;
+        if log_group_names.is_empty() {
+            return Err(CloudWatchLogsError::NoCloudWatchLogGroupsMatchFilter(format!(
+                "{:?}",
+                matcher
+            )));
+        }
+        let mut export_tasks = vec![];
+        for (cwl, log_group_names) in log_group_names {
+            for log_group_name in log_group_names {
+                let export_task = cwl
+                    .export_to_s3(
+                        log_group_name,
+                        start_time,
+                        end_time,
+                        bucket.clone(),
+                        prefix.clone(),
+                    )
+                    .await?;
+                export_tasks.push(export_task);
+            }
+        }
+        Ok(export_tasks)
+    }
+
+    async fn get_last_event_time_for_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Option<DateTime<Utc>>, CloudWatchLogsError> {
+        let results = join_all(
+            self.clients
+                .iter()
+                .map(|cwl| cwl.get_last_event_time_for_log_group(log_group_name.clone())),
+        )
+        .await;
+        Ok(skip_not_found(results)?.into_iter().flatten().max())
+    }
+
+    /// Metadata of the log group in each region where it exists.
+    async fn describe_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Vec<LogGroupMetadata>, CloudWatchLogsError> {
+        let metadata = try_join_all(
+            self.clients
+                .iter()
+                .map(|cwl| cwl.describe_log_group(log_group_name.clone())),
+        )
+        .await?;
+        Ok(metadata.into_iter().flatten().collect())
+    }
+
+    /// Log stream names across all regions where the log group exists, without duplicates.
+    async fn get_log_stream_names(
+        &self,
+        log_group_name: String,
+        prefix: Option<String>,
+        order_by: LogStreamOrderBy,
+    ) -> Result<Vec<String>, CloudWatchLogsError> {
+        let results = join_all(
+            self.clients
+                .iter()
+                .map(|cwl| cwl.get_log_streams(log_group_name.clone(), prefix.clone(), order_by)),
+        )
+        .await;
+        let mut log_streams: Vec<LogStream> = skip_not_found(results)?.into_iter().flatten().collect();
+        match order_by {
+            LogStreamOrderBy::LogStreamName => {
+                log_streams.sort_by(|a, b| a.log_stream_name.cmp(&b.log_stream_name))
+            }
+            LogStreamOrderBy::LastEventTime => {
+                log_streams.sort_by_key(|log_stream| std::cmp::Reverse(log_stream.last_event_time))
+            }
+        }
+        let mut seen = HashSet::new();
+        Ok(log_streams
+            .into_iter()
+            .map(|log_stream| log_stream.log_stream_name)
+            .filter(|log_stream_name| seen.insert(log_stream_name.clone()))
+            .collect())
+    }
+
+    async fn live_tail(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        event_filter: EventFilter,
+        start_time: Option<DateTime<Utc>>,
+    ) -> Result<LiveTailReceiver, CloudWatchLogsError> {
+        let matcher = self.log_group_matcher(log_group_name, log_group_filter)?;
+        let log_group_names = self.matching_log_group_names(&matcher).await?;
+        if log_group_names.is_empty() {
+            return Err(CloudWatchLogsError::NoCloudWatchLogGroupsMatchFilter(format!(
+                "{:?}",
+                matcher
+            )));
+        }
+        let (sender, receiver) = mpsc::channel(1024);
+        tokio::spawn(live_tail(
+            log_group_names,
+            event_filter,
+            self.transformers.clone(),
+            start_time.unwrap_or_else(Utc::now),
+            sender,
+        ));
+        Ok(receiver)
+    }
+
+    async fn run_insights_query(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        query_string: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<InsightsQueryRow>, CloudWatchLogsError> {
+        let matcher = self.log_group_matcher(log_group_name, log_group_filter)?;
+        let log_group_names = self.matching_log_group_names(&matcher).await?;
+        if log_group_names.is_empty() {
+            return Err(CloudWatchLogsError::NoCloudWatchLogGroupsMatchFilter(format!(
+                "{:?}",
+                matcher
+            )));
+        }
+
+        // Insights queries cannot span regions, so run one query per region and concatenate the results.
+        let rows = try_join_all(log_group_names.into_iter().map(|(cwl, log_group_names)| {
+            let query_string = query_string.clone();
+            async move {
+                cwl.run_insights_query(log_group_names, query_string, start_time, end_time)
+                    .await
+            }
+        }))
+        .await?;
+        Ok(rows.into_iter().flatten().collect())
+    }
+}
+
+fn log_group_name_matcher(
+    log_group_name: Option<String>,
+    log_group_filter: Option<String>,
+) -> Result<LogGroupNameMatcher, CloudWatchLogsError> {
+    let pattern = if let Some(log_group_name) = log_group_name {
+        format!("^{}$", regex::escape(&log_group_name))
+    } else if let Some(log_group_filter) = log_group_filter {
+        log_group_filter
+    } else {
+        return Err(CloudWatchLogsError::InvalidGetLogsToDisplayMessage(
+            "Must specify either log_group_name or log_group_filter".to_string(),
+        ));
+    };
+    Ok(LogGroupNameMatcher::new(&pattern))
+}
+
+#[instrument(level = "debug")]
+async fn run_cloud_watch_logs_actor<C: CloudWatchLogsApi + 'static>(
+    actor: Arc<CloudWatchLogsActor<C>>,
+    mut receiver: MailboxReceiver<CloudWatchLogsMessage>,
+) {
+    let (timeout, respond_to) = loop {
+        match receiver.recv().await {
+            Some(CloudWatchLogsMessage::Shutdown { timeout, respond_to }) => break (timeout, respond_to),
+            Some(msg) => spawn_handler(&actor, msg).await,
+            None => return,
+        }
+    };
+
+    // Requests that are already in the mailbox were accepted, so they are still handled.
+    info!("CloudWatch Logs actor shutting down");
+    receiver.close();
+    let mut responders = vec![respond_to];
+    let drained = tokio::time::timeout(timeout, async {
+        while let Some(msg) = receiver.recv().await {
+            match msg {
+                CloudWatchLogsMessage::Shutdown { respond_to, .. } => responders.push(respond_to),
+                msg => spawn_handler(&actor, msg).await,
+            }
+        }
+        let _all_permits = actor
+            .request_permits
+            .acquire_many(actor.max_concurrent_requests as u32)
+            .await
+            .expect("request permits are never closed");
+    })
+    .await;
+    if drained.is_err() {
+        warn!("CloudWatch Logs actor shut down with requests still running");
+        while let Some(msg) = receiver.recv().await {
+            msg.fail(CloudWatchLogsError::ActorUnavailable);
+        }
+    }
+    for respond_to in responders {
+        let _ = respond_to.send(match drained {
+            Ok(()) => Ok(()),
+            Err(_) => Err(CloudWatchLogsError::ShutdownTimedOut),
+        });
+    }
+}
+
+/// Handle `msg` in its own task once fewer than the maximum number of requests are running.
+async fn spawn_handler<C: CloudWatchLogsApi + 'static>(
+    actor: &Arc<CloudWatchLogsActor<C>>,
+    msg: CloudWatchLogsMessage,
+) {
+    debug!("actor sending msg {:?}...", msg);
+    let permit = Arc::clone(&actor.request_permits)
+        .acquire_owned()
+        .await
+        .expect("request permits are never closed");
+    let actor = Arc::clone(actor);
+    tokio::spawn(async move {
+        actor.handle_message(msg).await;
+        drop(permit);
+    });
+    debug!("actor finished sending msg");
+}
+
+#[derive(Clone, Debug)]
+pub struct CloudWatchLogsActorHandle {
+    sender: MailboxSender<CloudWatchLogsMessage>,
+    request_timeout: Option<std::time::Duration>,
+}
+
+impl CloudWatchLogsActorHandle {
+    pub fn new<C: CloudWatchLogsApi + 'static>(cwl: C) -> Self {
+        Self::from_clients(vec![cwl])
+    }
+
+    /// Create an actor that reads from several clients, usually one per region, and merges their results.
+    pub fn from_clients<C: CloudWatchLogsApi + 'static>(clients: Vec<C>) -> Self {
+        Self::with_options(clients, ActorOptions::default())
+    }
+
+    pub fn with_options<C: CloudWatchLogsApi + 'static>(clients: Vec<C>, options: ActorOptions) -> Self {
+        let (sender, receiver) = mailbox(options.mailbox_capacity, options.mailbox_full_policy);
+        let request_timeout = options.request_timeout;
+        let actor = Arc::new(CloudWatchLogsActor::new(clients, options));
+        tokio::spawn(run_cloud_watch_logs_actor(actor, receiver));
+
+        Self {
+            sender,
+            request_timeout,
+        }
+    }
+
+    /// Put a request in the actor's mailbox, failing the request that was dropped to make room if the mailbox was
+    /// full and sheds the oldest request.
+    async fn send(&self, msg: CloudWatchLogsMessage) -> Result<(), CloudWatchLogsError> {
+        match self.sender.send(msg).await {
+            Ok(Some(oldest)) => {
+                warn!("actor mailbox is full, dropping the oldest request");
+                oldest.fail(CloudWatchLogsError::MailboxFull);
+                Ok(())
+            }
+            Ok(None) => Ok(()),
+            Err(SendError::Full(_)) => Err(CloudWatchLogsError::MailboxFull),
+            Err(SendError::Closed(_)) => Err(CloudWatchLogsError::ActorUnavailable),
+        }
+    }
+
+    /// Send a request and wait for its response, which fails with `ActorUnavailable` if the actor stopped without
+    /// responding, e.g. because handling the request panicked, and with `RequestTimedOut` after the request timeout.
+    async fn request<T>(
+        &self,
+        msg: CloudWatchLogsMessage,
+        recv: oneshot::Receiver<Result<T, CloudWatchLogsError>>,
+    ) -> Result<T, CloudWatchLogsError> {
+        let response = async {
+            self.send(msg).await?;
+            recv.await.map_err(|_| CloudWatchLogsError::ActorUnavailable)?
+        };
+        match self.request_timeout {
+            // Dropping the request on timeout closes its channel, which stops reads that are still fetching.
+            Some(timeout) => tokio::time::timeout(timeout, response)
+                .await
+                .unwrap_or(Err(CloudWatchLogsError::RequestTimedOut)),
+            None => response.await,
+        }
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn get_log_group_names(&self) -> Result<Vec<String>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::GetLogGroupNames { respond_to: send };
+        self.request(msg, recv).await
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn describe_log_groups(
+        &self,
+        prefix: Option<String>,
+    ) -> Result<Vec<LogGroupMetadata>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::DescribeLogGroups {
+            respond_to: send,
+            prefix,
+        };
+        self.request(msg, recv).await
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn get_log_events(
+        &self,
+        log_group_name: String,
+        start_time: Option<DateTime<Utc>>,
+        end_time: Option<DateTime<Utc>>,
+        limit: Option<i32>,
+        event_filter: EventFilter,
+    ) -> Result<Vec<FilteredLogEvent>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::GetLogEvents {
+            respond_to: send,
+            log_group_name,
+            start_time,
+            end_time,
+            limit,
+            event_filter,
+        };
+        self.request(msg, recv).await
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn get_first_event_time_for_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Option<DateTime<Utc>>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::GetFirstEventTimeForLogGroup {
+            respond_to: send,
+            log_group_name,
+        };
+        self.request(msg, recv).await
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn describe_metric_filters(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        progress: Option<watch::Sender<Progress>>,
+    ) -> Result<Vec<MetricFilter>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::DescribeMetricFilters {
+            respond_to: send,
+            log_group_name,
+            log_group_filter,
+            progress,
+        };
+        self.request(msg, recv).await
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn describe_subscription_filters(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        progress: Option<watch::Sender<Progress>>,
+    ) -> Result<Vec<SubscriptionFilter>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::DescribeSubscriptionFilters {
+            respond_to: send,
+            log_group_name,
+            log_group_filter,
+            progress,
+        };
+        self.request(msg, recv).await
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn describe_query_definitions(
+        &self,
+        name_prefix: Option<String>,
+    ) -> Result<Vec<QueryDefinition>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::DescribeQueryDefinitions {
+            respond_to: send,
+            name_prefix,
+        };
+        self.request(msg, recv).await
+    }
+
+    /// The saved Logs Insights query called `name`, in any region.
+    #[instrument(level = "debug")]
+    pub async fn find_query_definition(
+        &self,
+        name: &str,
+    ) -> Result<Option<QueryDefinition>, CloudWatchLogsError> {
+        let query_definitions = self.describe_query_definitions(Some(name.to_string())).await?;
+        Ok(query_definitions
+            .into_iter()
+            .find(|query_definition| query_definition.name == name))
+    }
+
+    /// Export matching log groups to an S3 bucket and wait until all exports have finished, which can take hours.
+    #[instrument(level = "debug")]
+    pub async fn export_to_s3(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        bucket: String,
+        prefix: Option<String>,
+    ) -> Result<Vec<ExportTask>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::ExportToS3 {
+            respond_to: send,
+            log_group_name,
+            log_group_filter,
+            start_time,
+            end_time,
+            bucket,
+            prefix,
+        };
+        self.request(msg, recv).await
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn get_last_event_time_for_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Option<DateTime<Utc>>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::GetLastEventTimeForLogGroup {
+            respond_to: send,
+            log_group_name,
+        };
+        self.request(msg, recv).await
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn describe_log_group(
+        &self,
+        log_group_name: String,
+    ) -> Result<Vec<LogGroupMetadata>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::DescribeLogGroup {
+            respond_to: send,
+            log_group_name,
+        };
+        self.request(msg, recv).await
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn get_log_stream_names(
+        &self,
+        log_group_name: String,
+        prefix: Option<String>,
+        order_by: LogStreamOrderBy,
+    ) -> Result<Vec<String>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::GetLogStreamNames {
+            respond_to: send,
+            log_group_name,
+            prefix,
+            order_by,
+        };
+        self.request(msg, recv).await
+    }
+
+    #[instrument(level = "debug")]
+    pub async fn run_insights_query(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        query_string: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<InsightsQueryRow>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::RunInsightsQuery {
+            respond_to: send,
+            log_group_name,
+            log_group_filter,
+            query_string,
+            start_time,
+            end_time,
+        };
+        self.request(msg, recv).await
+    }
+
+    /// Follow new log events for the matching log groups as they arrive, starting with the events since
+    /// `start_time`, or now if None.
+    #[instrument(level = "debug")]
+    pub async fn live_tail(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        event_filter: EventFilter,
+        start_time: Option<DateTime<Utc>>,
+    ) -> Result<LiveTailReceiver, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::LiveTail {
+            respond_to: send,
+            log_group_name,
+            log_group_filter,
+            event_filter,
+            start_time,
+        };
+        self.request(msg, recv).await
+    }
+
+    /// Fetch the files for `windows`, e.g. the next few minutes of a sequential scan, at the same time and cache them,
+    /// so that reading them one after another does not wait for each to be fetched in turn. Returns once all of
+    /// them have been fetched.
+    #[instrument(level = "debug")]
+    pub async fn prefetch_logs_to_display(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        windows: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+        event_filter: EventFilter,
+        formatter: format_cwl_log_event::LogFormatter,
+    ) -> Result<(), CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::PrefetchLogsToDisplay {
+            respond_to: send,
+            log_group_name,
+            log_group_filter,
+            windows,
+            event_filter,
+            formatter,
+        };
+        self.request(msg, recv).await
+    }
+
+    /// Stop the actor. Requests that are sent afterwards fail with `ActorUnavailable`, while requests it already
+    /// accepted are still handled. Returns once they have all finished, or with `ShutdownTimedOut` if some are still
+    /// running after `timeout`.
+    #[instrument(level = "debug")]
+    pub async fn close(&self, timeout: std::time::Duration) -> Result<(), CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::Shutdown {
+            timeout,
+            respond_to: send,
+        };
+        self.send(msg).await?;
+        recv.await.map_err(|_| CloudWatchLogsError::ActorUnavailable)?
+    }
+
+    /// CloudWatch Logs calls made so far across all regions, see `UsageStats`.
+    #[instrument(level = "debug")]
+    pub async fn usage(&self) -> Result<UsageStats, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::GetUsage { respond_to: send };
+        self.request(msg, recv).await
+    }
+
+    /// The accounts that log groups are read from, once each. Accounts that could not be looked up are left out.
+    #[instrument(level = "debug")]
+    pub async fn account_ids(&self) -> Result<Vec<String>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::GetAccountIds { respond_to: send };
+        self.request(msg, recv).await
+    }
+
+    /// Drop every cached file, in memory and on disk, so that later reads fetch them again, e.g. after CloudWatch
+    /// Logs ingested events later than `ActorOptions::ingestion_grace` allowed for. Reads in flight are not affected.
+    #[instrument(level = "debug")]
+    pub async fn clear_cache(&self) -> Result<(), CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::ClearCache { respond_to: send };
+        self.request(msg, recv).await
+    }
+
+    /// Check every region's credentials and permissions, see `CloudWatchLogsApi::preflight`.
+    #[instrument(level = "debug")]
+    pub async fn preflight(&self) -> Result<Vec<PreflightReport>, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::Preflight { respond_to: send };
+        self.request(msg, recv).await
+    }
+
+    /// Read a log group's events for a time window one page at a time, e.g. to serve the start of a large file
+    /// without fetching all of it. Pass `None` to get the first page, then the `next_page_token` of each page.
+    /// Joining the pages gives the same file as `get_logs_to_display` for the log group.
+    #[instrument(level = "debug", skip(formatter))]
+    pub async fn get_rendered_page(
+        &self,
+        log_group_name: String,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        event_filter: EventFilter,
+        formatter: format_cwl_log_event::LogFormatter,
+        page_token: Option<LogEventsPageToken>,
+    ) -> Result<LogEventsPage, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::GetRenderedPage {
+            respond_to: send,
+            log_group_name,
+            start_time,
+            end_time,
+            event_filter,
+            formatter,
+            page_token,
+        };
+        self.request(msg, recv).await
+    }
+
+    pub async fn get_logs_to_display(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        event_filter: EventFilter,
+        formatter: format_cwl_log_event::LogFormatter,
+    ) -> Result<Bytes, CloudWatchLogsError> {
+        let page = self
+            .get_log_page(
+                log_group_name,
+                log_group_filter,
+                start_time,
+                end_time,
+                event_filter,
+                formatter,
+            )
+            .await?;
+        Ok(page.data)
+    }
+
+    /// Same as `get_logs_to_display`, along with what it took to fetch the file.
+    pub async fn get_log_page(
+        &self,
+        log_group_name: Option<String>,
+        log_group_filter: Option<String>,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        event_filter: EventFilter,
+        formatter: format_cwl_log_event::LogFormatter,
+    ) -> Result<LogPage, CloudWatchLogsError> {
+        let (send, recv) = oneshot::channel();
+        let msg = CloudWatchLogsMessage::GetLogsToDisplay {
+            respond_to: send,
+            log_group_name,
+            log_group_filter,
+            start_time,
+            end_time,
+            event_filter,
+            formatter,
+        };
+        self.request(msg, recv).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use aws_smithy_http::result::SdkError;
+    use chrono::DateTime;
+    use chrono::Duration;
+    use chrono::TimeZone;
+    use chrono::Utc;
+    use tokio::sync::mpsc;
+
+    use format_cwl_log_event::FilteredLogEvent;
+    use regexes::LogGroupNameMatcher;
+    use regexes::MessageFilter;
+
+    use crate::credentials::{Credentials, ProvideCredentials, SharedCredentialsProvider};
+    use crate::disk_cache_key;
+    use crate::first_possible_event_time;
+    use crate::is_cacheable;
+    use crate::mailbox::mailbox;
+    use crate::merge_rendered_events;
+    use crate::name_linked_log_group;
+    use crate::render_insights_query_results;
+    use crate::render_page;
+    use crate::split_time_range;
+    use crate::ActorOptions;
+    use crate::ApiAction;
+    use crate::CacheKey;
+    use crate::CacheValue;
+    use crate::ClientOptions;
+    use crate::CloudWatchLogsActorHandle;
+    use crate::CloudWatchLogsApi;
+    use crate::CloudWatchLogsError;
+    use crate::CloudWatchLogsImpl;
+    use crate::EventFilter;
+    use crate::FakeCloudWatchLogs;
+    use crate::LiveTailState;
+    use crate::LogEventsPageToken;
+    use crate::LogGroupMetadata;
+    use crate::MailboxFullPolicy;
+    use crate::Progress;
+    use crate::QueryDefinition;
+    use crate::RenderedEvent;
+    use crate::SubscriptionFilter;
+    use crate::TimeBounds;
+    use crate::TransformerChain;
+    use crate::RENDERED_EVENTS_BUFFER;
+    use crate::{collect_pages, collect_pages_until};
+
+    #[test]
+    fn test_render_insights_query_results() {
+        let row = |timestamp: &str, message: &str| {
+            vec![
+                ("@timestamp".to_string(), timestamp.to_string()),
+                ("@message".to_string(), message.to_string()),
+                ("@ptr".to_string(), "opaque".to_string()),
+            ]
+        };
+        let rows = vec![
+            row("2021-12-04 00:00:07.000", "first\n"),
+            row("2021-12-04 00:00:08.000", "second"),
+        ];
+        let actual = render_insights_query_results(&rows);
+        assert_eq!(
+            "@timestamp\t@message\n2021-12-04 00:00:07.000\tfirst\n2021-12-04 00:00:08.000\tsecond",
+            actual
+        );
+        assert!(render_insights_query_results(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_log_groups_failed_lists_every_log_group() {
+        let err = CloudWatchLogsError::LogGroupsFailed(vec![
+            ("group-a".to_string(), CloudWatchLogsError::Unknown),
+            (
+                "group-b".to_string(),
+                CloudWatchLogsError::LogGroupTaskFailed("group-b".to_string()),
+            ),
+        ]);
+        assert_eq!(
+            "failed to get log events for group-a: unknown cloudwatch logs error, \
+             group-b: task getting log events for log group group-b did not finish",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_log_groups_failed_is_classified_by_its_errors() {
+        let timeout =
+            CloudWatchLogsError::FilterLogEventsError(Box::new(SdkError::TimeoutError("timed out".into())));
+        let err = CloudWatchLogsError::LogGroupsFailed(vec![
+            ("group-a".to_string(), CloudWatchLogsError::Unknown),
+            ("group-b".to_string(), timeout),
+        ]);
+        assert!(err.is_timeout());
+        assert!(!err.is_throttling());
+        assert!(!err.is_access_denied());
+        assert!(CloudWatchLogsError::MailboxFull.is_throttling());
+    }
+
+    #[test]
+    fn test_cache_value_compression_round_trip() {
+        let data = "[i-03e71e7954a899acb] systemd[1]: Starting Rotate log files...\n".repeat(100);
+        let value = CacheValue::compress(data.as_bytes(), 3).unwrap();
+        assert!(value.compressed_data_to_display.len() < data.len() / 10);
+        assert_eq!(data.as_bytes(), value.decompress().unwrap().as_ref());
+    }
+
+    #[test]
+    fn test_is_cacheable_waits_for_late_ingestion() {
+        let now = Utc::now();
+        let cache_key = CacheKey {
+            log_group_name_matcher: LogGroupNameMatcher::new("^/app/web$"),
+            time_bounds: TimeBounds {
+                first_event_time: now - Duration::minutes(11),
+                last_event_time: now - Duration::minutes(10),
+            },
+            event_filter: EventFilter::default(),
+            formatter: format_cwl_log_event::LogFormatter::new("$message").unwrap(),
+        };
+        assert!(is_cacheable(&cache_key, Duration::minutes(5), Duration::zero()));
+        assert!(!is_cacheable(&cache_key, Duration::minutes(15), Duration::zero()));
+        assert!(!is_cacheable(
+            &cache_key,
+            Duration::minutes(5),
+            Duration::minutes(20)
+        ));
+    }
+
+    #[test]
+    fn test_disk_cache_key_writes_out_every_field() {
+        let start = Utc.ymd(2021, 12, 4).and_hms(0, 0, 0);
+        let cache_key = CacheKey {
+            log_group_name_matcher: LogGroupNameMatcher::new("^/app/").excluding("-healthcheck$"),
+            time_bounds: TimeBounds {
+                first_event_time: start,
+                last_event_time: start + Duration::hours(1),
+            },
+            event_filter: EventFilter {
+                filter_pattern: Some("ERROR".to_string()),
+                ..EventFilter::default()
+            },
+            formatter: format_cwl_log_event::LogFormatter::new("$message").unwrap(),
+        };
+        assert_eq!(
+            r#"{"end_time_ms":1638579600000,"filter_pattern":"ERROR","log_group_exclude":"-healthcheck$","log_group_filter":"^/app/","log_stream_name":null,"log_stream_name_prefix":null,"max_events_per_file":1000,"max_file_size":null,"message_filter":null,"output_format":"$message","start_time_ms":1638576000000,"unmask":false,"version":1}"#,
+            disk_cache_key(&cache_key, Some(1000), None)
+        );
+        let unmasked = CacheKey {
+            event_filter: EventFilter {
+                unmask: true,
+                ..cache_key.event_filter.clone()
+            },
+            ..cache_key.clone()
+        };
+        assert_ne!(
+            disk_cache_key(&cache_key, None, None),
+            disk_cache_key(&unmasked, None, None)
+        );
+    }
+
+    #[test]
+    fn test_collect_pages_follows_next_tokens() {
+        let pages = HashMap::from([
+            (None, (vec![1, 2], Some("a".to_string()))),
+            (Some("a".to_string()), (vec![], Some("b".to_string()))),
+            (Some("b".to_string()), (vec![3], None)),
+        ]);
+        let items = tokio_test::block_on(collect_pages(|next_token| {
+            let page = pages[&next_token].clone();
+            async move { Ok(page) }
+        }))
+        .unwrap();
+        assert_eq!(vec![1, 2, 3], items);
+
+        let failed: Result<Vec<i32>, CloudWatchLogsError> =
+            tokio_test::block_on(collect_pages(|_| async { Err(CloudWatchLogsError::Unknown) }));
+        assert!(failed.is_err());
+
+        // Pages after the one with the item are not fetched.
+        let mut fetched = vec![];
+        let items = tokio_test::block_on(collect_pages_until(
+            |next_token| {
+                fetched.push(next_token.clone());
+                let page = pages[&next_token].clone();
+                async move { Ok(page) }
+            },
+            |item| *item == 2,
+        ))
+        .unwrap();
+        assert_eq!(vec![1, 2], items);
+        assert_eq!(vec![None], fetched);
+    }
+
+    #[test]
+    fn test_split_time_range() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let ranges = split_time_range(start, start + Duration::minutes(1), 4);
+        assert_eq!(4, ranges.len());
+        assert_eq!(start, ranges[0].0);
+        assert_eq!(start + Duration::seconds(15), ranges[0].1);
+        assert!(ranges.windows(2).all(|pair| pair[0].1 == pair[1].0));
+        assert_eq!(start + Duration::minutes(1), ranges[3].1);
+
+        // Never more ranges than milliseconds.
+        assert_eq!(
+            2,
+            split_time_range(start, start + Duration::milliseconds(2), 8).len()
+        );
+        assert_eq!(vec![(start, start)], split_time_range(start, start, 4));
+    }
+
+    #[test]
+    fn test_first_possible_event_time() {
+        let now = Utc.ymd(2022, 1, 31).and_hms(0, 0, 0);
+        let metadata = LogGroupMetadata {
+            log_group_name: "group".to_string(),
+            region: None,
+            arn: None,
+            creation_time: Some(Utc.ymd(2022, 1, 1).and_hms(0, 0, 0)),
+            retention_in_days: None,
+            stored_bytes: None,
+            kms_key_id: None,
+        };
+        assert_eq!(
+            now - chrono::Duration::days(365 * 5),
+            first_possible_event_time(None, now)
+        );
+        assert_eq!(
+            Utc.ymd(2021, 12, 18).and_hms(0, 0, 0),
+            first_possible_event_time(Some(&metadata), now)
+        );
+        let metadata = LogGroupMetadata {
+            retention_in_days: Some(7),
+            ..metadata
+        };
+        assert_eq!(
+            Utc.ymd(2022, 1, 23).and_hms(0, 0, 0),
+            first_possible_event_time(Some(&metadata), now)
+        );
+    }
+
+    #[test]
+    fn test_can_use_get_log_events() {
+        let single_stream = EventFilter {
+            log_stream_name: Some("stream".to_string()),
+            ..EventFilter::default()
+        };
+        assert!(single_stream.can_use_get_log_events());
+        assert!(!EventFilter::default().can_use_get_log_events());
+        assert!(!EventFilter {
+            filter_pattern: Some("ERROR".to_string()),
+            ..single_stream
+        }
+        .can_use_get_log_events());
+    }
+
+    #[test]
+    fn test_live_tail_state_drops_events_already_sent() {
+        let start_time = Utc.ymd(2021, 12, 4).and_hms(0, 0, 0);
+        let event = |event_id: &str, seconds: u32| {
+            let timestamp = Utc.ymd(2021, 12, 4).and_hms(0, 0, seconds);
+            FilteredLogEvent::new("group", event_id, timestamp, "stream", event_id, timestamp)
+        };
+        let mut state = LiveTailState::new(start_time);
+        let log_group = (0, "group".to_string());
+
+        let sent = state.accept(log_group.clone(), vec![event("b", 2), event("a", 1)]);
+        assert_eq!(
+            vec!["a", "b"],
+            sent.iter().map(|e| e.event_id.as_str()).collect::<Vec<_>>()
+        );
+
+        // The next poll starts at the latest event sent, and gets it again.
+        assert_eq!(
+            Utc.ymd(2021, 12, 4).and_hms(0, 0, 2),
+            state.start_time(&log_group)
+        );
+        let sent = state.accept(log_group, vec![event("b", 2), event("c", 3)]);
+        assert_eq!(
+            vec!["c"],
+            sent.iter().map(|e| e.event_id.as_str()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_live_tail_state_keeps_a_high_water_mark_per_log_group() {
+        let start_time = Utc.ymd(2021, 12, 4).and_hms(0, 0, 0);
+        let event = |event_id: &str, minutes: i64| {
+            let timestamp = start_time + Duration::minutes(minutes);
+            FilteredLogEvent::new("group", event_id, timestamp, "stream", event_id, timestamp)
+        };
+        let mut state = LiveTailState::new(start_time);
+        let web = (0, "/app/web".to_string());
+        let worker = (1, "/app/worker".to_string());
+
+        state.accept(web.clone(), vec![event("a", 1), event("b", 5), event("c", 5)]);
+        assert_eq!(start_time + Duration::minutes(5), state.start_time(&web));
+        assert_eq!(start_time, state.start_time(&worker));
+
+        // Only the events at the high-water mark are remembered.
+        assert_eq!(2, state.log_groups[&web].seen.len());
+        state.accept(web.clone(), vec![event("b", 5), event("d", 6)]);
+        assert_eq!(1, state.log_groups[&web].seen.len());
+    }
+
+    #[test]
+    fn test_merge_rendered_events_sorts_and_drops_duplicates() {
+        let formatter = format_cwl_log_event::LogFormatter::new("$message").unwrap();
+        let event = |log_group_name: &str, event_id: &str, seconds: u32| {
+            let timestamp = Utc.ymd(2021, 12, 4).and_hms(0, 0, seconds);
+            let event =
+                FilteredLogEvent::new(log_group_name, event_id, timestamp, "stream", event_id, timestamp);
+            RenderedEvent::new(event, &formatter)
+        };
+        let receiver = |events: Vec<RenderedEvent>| {
+            let (sender, receiver) = mpsc::channel(RENDERED_EVENTS_BUFFER);
+            events
+                .into_iter()
+                .for_each(|event| sender.try_send(Ok(event)).unwrap());
+            receiver
+        };
+        let actual = tokio_test::block_on(merge_rendered_events(
+            vec![
+                (
+                    "group-a".to_string(),
+                    receiver(vec![event("group-a", "a1", 1), event("group-a", "a3", 3)]),
+                ),
+                (
+                    "group-b".to_string(),
+                    receiver(vec![event("group-b", "a1", 2), event("group-a", "a3", 3)]),
+                ),
+            ],
+            None,
+            None,
+        ));
+        assert_eq!("a1\na1\na3", actual.unwrap().data);
+    }
+
+    #[test]
+    fn test_merge_rendered_events_truncates_at_max_events() {
+        let formatter = format_cwl_log_event::LogFormatter::new("$message").unwrap();
+        let receiver = |log_group_name: &str, seconds: std::ops::Range<u32>| {
+            let (sender, receiver) = mpsc::channel(RENDERED_EVENTS_BUFFER);
+            for second in seconds {
+                let timestamp = Utc.ymd(2021, 12, 4).and_hms(0, 0, second);
+                let event_id = format!("{}-{}", log_group_name, second);
+                let event = FilteredLogEvent::new(
+                    log_group_name,
+                    &event_id,
+                    timestamp,
+                    "stream",
+                    &event_id,
+                    timestamp,
+                );
+                sender
+                    .try_send(Ok(RenderedEvent::new(event, &formatter)))
+                    .unwrap();
+            }
+            receiver
+        };
+        let actual = tokio_test::block_on(merge_rendered_events(
+            vec![
+                ("a".to_string(), receiver("a", 0..4)),
+                ("b".to_string(), receiver("b", 10..12)),
+            ],
+            Some(3),
+            None,
+        ))
+        .unwrap();
+        assert_eq!(
+            "a-0\na-1\na-2\n... output truncated (at least 3 events omitted); narrow the time range",
+            actual.data
+        );
+    }
+
+    #[test]
+    fn test_merge_rendered_events_truncates_at_max_bytes() {
+        let formatter = format_cwl_log_event::LogFormatter::new("$message").unwrap();
+        let (sender, receiver) = mpsc::channel(RENDERED_EVENTS_BUFFER);
+        for second in 0..4 {
+            let timestamp = Utc.ymd(2021, 12, 4).and_hms(0, 0, second);
+            let event_id = format!("event-{}", second);
+            let event = FilteredLogEvent::new("a", &event_id, timestamp, "stream", &event_id, timestamp);
+            sender
+                .try_send(Ok(RenderedEvent::new(event, &formatter)))
+                .unwrap();
+        }
+        drop(sender);
+
+        // Two events and the newline between them take 15 bytes, so the third would not fit in 20.
+        let actual = tokio_test::block_on(merge_rendered_events(
+            vec![("a".to_string(), receiver)],
+            Some(10),
+            Some(20),
+        ))
+        .unwrap();
+        assert_eq!(
+            "event-0\nevent-1\n... output truncated at 20 bytes (at least 2 events omitted); narrow the time range",
+            actual.data
+        );
+        assert_eq!(2, actual.events);
+        assert!(actual.truncated);
+    }
+
+    #[test]
+    fn test_render_page_continues_where_the_previous_page_ended() {
+        let formatter = format_cwl_log_event::LogFormatter::new("$message").unwrap();
+        let event = |message: &str| {
+            let timestamp = Utc.ymd(2021, 12, 4).and_hms(0, 0, 0);
+            FilteredLogEvent::new("group", message, timestamp, "stream", message, timestamp)
+        };
+        let first = render_page(
+            vec![event("first"), event("second")],
+            Some("token".to_string()),
+            &LogEventsPageToken::default(),
+            &EventFilter::default(),
+            &TransformerChain::default(),
+            &formatter,
+        );
+        assert_eq!("first\nsecond", first.data);
+        assert_eq!(12, first.bytes_rendered);
+        let next_page_token = first.next_page_token.unwrap();
+
+        let empty = render_page(
+            vec![],
+            Some("token-2".to_string()),
+            &next_page_token,
+            &EventFilter::default(),
+            &TransformerChain::default(),
+            &formatter,
+        );
+        assert_eq!("", empty.data);
+        assert_eq!(12, empty.bytes_rendered);
+        let next_page_token = empty.next_page_token.unwrap();
+
+        let last = render_page(
+            vec![event("third")],
+            None,
+            &next_page_token,
+            &EventFilter::default(),
+            &TransformerChain::default(),
+            &formatter,
+        );
+        assert_eq!("\nthird", last.data);
+        assert_eq!(18, last.bytes_rendered);
+        assert_eq!(None, last.next_page_token);
+    }
+
+    #[test]
+    fn test_render_page_leaves_out_events_the_message_filter_rejects() {
+        let formatter = format_cwl_log_event::LogFormatter::new("$message").unwrap();
+        let event = |message: &str| {
+            let timestamp = Utc.ymd(2021, 12, 4).and_hms(0, 0, 0);
+            FilteredLogEvent::new("group", message, timestamp, "stream", message, timestamp)
+        };
+        let event_filter = EventFilter {
+            message_filter: Some(MessageFilter::new("^(first|third)$").unwrap()),
+            ..EventFilter::default()
+        };
+        let first = render_page(
+            vec![event("first"), event("second")],
+            Some("token".to_string()),
+            &LogEventsPageToken::default(),
+            &event_filter,
+            &TransformerChain::default(),
+            &formatter,
+        );
+        assert_eq!("first", first.data);
+
+        // Events that were left out still count towards where the next page continues.
+        let last = render_page(
+            vec![event("third")],
+            None,
+            &first.next_page_token.unwrap(),
+            &event_filter,
+            &TransformerChain::default(),
+            &formatter,
+        );
+        assert_eq!("\nthird", last.data);
+    }
+
+    #[test]
+    fn test_merge_rendered_events_lists_every_failed_log_group() {
+        let failed = || {
+            let (sender, receiver) = mpsc::channel(RENDERED_EVENTS_BUFFER);
+            sender.try_send(Err(CloudWatchLogsError::Unknown)).unwrap();
+            receiver
+        };
+        let (_, empty) = mpsc::channel(RENDERED_EVENTS_BUFFER);
+        let err = tokio_test::block_on(merge_rendered_events(
+            vec![
+                ("group-a".to_string(), failed()),
+                ("group-b".to_string(), empty),
+                ("group-c".to_string(), failed()),
+            ],
+            None,
+            None,
+        ))
+        .unwrap_err();
+        match err {
+            CloudWatchLogsError::LogGroupsFailed(errors) => assert_eq!(
+                vec!["group-a", "group-c"],
+                errors.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>()
+            ),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+
+    fn fake_with_log_groups(start: DateTime<Utc>) -> FakeCloudWatchLogs {
+        let cwl = FakeCloudWatchLogs::new(Some("us-west-2")).with_page_size(2);
+        for (minutes, log_stream_name, message) in [
+            (3, "web-1", "GET /d"),
+            (0, "web-1", "GET /a"),
+            (1, "web-2", "GET /b"),
+            (2, "web-1", "POST /c"),
+            (4, "web-2", "GET /e"),
+        ] {
+            cwl.put_log_event(
+                "/app/web",
+                log_stream_name,
+                start + Duration::minutes(minutes),
+                message,
+            );
+        }
+        cwl.put_log_event("/app/worker", "worker-1", start, "started");
+        cwl.create_log_group("/other");
+        cwl
+    }
+
+    #[test]
+    fn test_list_log_groups() {
+        let cwl = fake_with_log_groups(Utc::now());
+        assert_eq!(
+            vec!["/app/web", "/app/worker", "/other"],
+            tokio_test::block_on(cwl.get_log_group_names()).unwrap()
+        );
+        assert_eq!(
+            vec!["/app/web", "/app/worker"],
+            tokio_test::block_on(cwl.get_log_group_names_with_prefix(Some("/app/"))).unwrap()
+        );
     }
 
     #[test]
     fn test_get_log_events() {
-        let tps = 5;
-        let region = Some("us-west-2");
-        let cwl: CloudWatchLogsImpl = tokio_test::block_on(CloudWatchLogsImpl::new(tps, region));
-        let log_group_name = "babynames-preprod-log-group-syslog".to_string();
-        let start_time = Some(Utc.ymd(2021, 11, 26).and_hms(1, 0, 0));
-        let end_time = Some(Utc.ymd(2021, 11, 26).and_hms(21, 0, 0));
-        let res =
-            tokio_test::block_on(cwl.get_log_events(log_group_name, start_time, end_time, None)).unwrap();
-        res.iter().for_each(|l| println!("{:?}", l.message));
+        let start = Utc::now() - Duration::days(2);
+        let cwl = fake_with_log_groups(start);
+        let messages = |events: Vec<FilteredLogEvent>| -> Vec<String> {
+            events.into_iter().map(|event| event.message).collect()
+        };
+
+        // Every page is read, and the end time is exclusive.
+        let events = tokio_test::block_on(cwl.get_log_events(
+            "/app/web".to_string(),
+            Some(start),
+            Some(start + Duration::minutes(4)),
+            None,
+            EventFilter::default(),
+        ))
+        .unwrap();
+        assert_eq!(vec!["GET /a", "GET /b", "POST /c", "GET /d"], messages(events));
+        assert_eq!(Some("us-west-2"), cwl.region());
+
+        let events = tokio_test::block_on(cwl.get_log_events(
+            "/app/web".to_string(),
+            None,
+            None,
+            Some(3),
+            EventFilter {
+                filter_pattern: Some("GET".to_string()),
+                ..EventFilter::default()
+            },
+        ))
+        .unwrap();
+        assert_eq!(vec!["GET /a", "GET /b", "GET /d"], messages(events));
+
+        // The message filter applies after the filter pattern, and the limit after both.
+        let events = tokio_test::block_on(cwl.get_log_events(
+            "/app/web".to_string(),
+            None,
+            None,
+            Some(2),
+            EventFilter {
+                filter_pattern: Some("GET".to_string()),
+                message_filter: Some(MessageFilter::new("/[bde]$").unwrap()),
+                ..EventFilter::default()
+            },
+        ))
+        .unwrap();
+        assert_eq!(vec!["GET /b", "GET /d"], messages(events));
+
+        let events = tokio_test::block_on(cwl.get_log_events_for_stream(
+            "/app/web".to_string(),
+            "web-1".to_string(),
+            None,
+            None,
+            None,
+        ))
+        .unwrap();
+        assert_eq!(vec!["GET /a", "POST /c", "GET /d"], messages(events));
+
+        let err = tokio_test::block_on(cwl.get_log_events(
+            "/missing".to_string(),
+            None,
+            None,
+            None,
+            EventFilter::default(),
+        ))
+        .unwrap_err();
+        assert!(err.is_resource_not_found());
     }
 
     #[test]
     fn get_time_bounds_for_log_group() {
-        let tps = 5;
-        let region = Some("us-west-2");
-        let cwl: CloudWatchLogsImpl = tokio_test::block_on(CloudWatchLogsImpl::new(tps, region));
-        let log_group_name = "babynames-preprod-log-group-syslog".to_string();
-        let res = tokio_test::block_on(cwl.get_first_event_time_for_log_group(log_group_name)).unwrap();
-        println!("{:?}", res);
+        let start = Utc::now() - Duration::days(2);
+        let cwl = fake_with_log_groups(start);
+        let first_event_time =
+            tokio_test::block_on(cwl.get_first_event_time_for_log_group("/app/web".to_string())).unwrap();
+        assert_eq!(
+            Some(start.timestamp_millis()),
+            first_event_time.map(|time| time.timestamp_millis())
+        );
+        let last_event_time =
+            tokio_test::block_on(cwl.get_last_event_time_for_log_group("/app/web".to_string())).unwrap();
+        assert_eq!(
+            Some((start + Duration::minutes(4)).timestamp_millis()),
+            last_event_time.map(|time| time.timestamp_millis())
+        );
+        assert_eq!(
+            None,
+            tokio_test::block_on(cwl.get_first_event_time_for_log_group("/other".to_string())).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_logs_to_display_merges_log_groups_across_regions() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let us_east_1 = FakeCloudWatchLogs::new(Some("us-east-1"));
+        us_east_1.put_log_event("/app/web", "web-1", start + Duration::seconds(1), "first");
+        us_east_1.put_log_event("/app/web", "web-1", start + Duration::seconds(3), "third");
+        let us_west_2 = FakeCloudWatchLogs::new(Some("us-west-2")).with_page_size(1);
+        us_west_2.put_log_event("/app/web", "web-1", start + Duration::seconds(2), "second");
+        us_west_2.put_log_event("/other", "other-1", start, "not matched");
+        let data = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::from_clients(vec![us_east_1, us_west_2]);
+            handle
+                .get_logs_to_display(
+                    None,
+                    Some("^/app/".to_string()),
+                    start,
+                    start + Duration::minutes(1),
+                    EventFilter::default(),
+                    format_cwl_log_event::LogFormatter::new("$message").unwrap(),
+                )
+                .await
+                .unwrap()
+        });
+        assert_eq!("first\nsecond\nthird", String::from_utf8_lossy(&data));
+    }
+
+    #[test]
+    fn test_find_query_definition_by_name_across_regions() {
+        let query_definition = |region: &str, name: &str| QueryDefinition {
+            query_definition_id: format!("{}-{}", region, name),
+            region: Some(region.to_string()),
+            name: name.to_string(),
+            query_string: format!("fields @message | limit 1 # {}", region),
+            log_group_names: vec![],
+            last_modified: None,
+        };
+        let us_east_1 = FakeCloudWatchLogs::new(Some("us-east-1"));
+        us_east_1.put_query_definition(query_definition("us-east-1", "errors by host"));
+        let us_west_2 = FakeCloudWatchLogs::new(Some("us-west-2"));
+        us_west_2.put_query_definition(query_definition("us-west-2", "errors"));
+        us_west_2.put_query_definition(query_definition("us-west-2", "latency"));
+        tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::from_clients(vec![us_east_1, us_west_2]);
+            let names: Vec<String> = handle
+                .describe_query_definitions(Some("errors".to_string()))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|query_definition| query_definition.name)
+                .collect();
+            assert_eq!(vec!["errors", "errors by host"], names);
+            assert_eq!(
+                Some("fields @message | limit 1 # us-west-2".to_string()),
+                handle
+                    .find_query_definition("errors")
+                    .await
+                    .unwrap()
+                    .map(|query_definition| query_definition.query_string)
+            );
+            assert_eq!(None, handle.find_query_definition("error").await.unwrap());
+        });
+    }
+
+    #[test]
+    fn test_describe_log_groups_lists_each_region() {
+        let us_east_1 = FakeCloudWatchLogs::new(Some("us-east-1"));
+        us_east_1.put_log_event("/app/web", "web-1", Utc::now(), "hello");
+        us_east_1.create_log_group("/other");
+        let us_west_2 = FakeCloudWatchLogs::new(Some("us-west-2"));
+        us_west_2.create_log_group("/app/web");
+        us_west_2.create_log_group("/app/api");
+        tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::from_clients(vec![us_east_1, us_west_2]);
+            let log_groups: Vec<(String, Option<String>, Option<i64>)> = handle
+                .describe_log_groups(Some("/app/".to_string()))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|metadata| (metadata.log_group_name, metadata.region, metadata.stored_bytes))
+                .collect();
+            assert_eq!(
+                vec![
+                    ("/app/api".to_string(), Some("us-west-2".to_string()), Some(0)),
+                    ("/app/web".to_string(), Some("us-east-1".to_string()), Some(5)),
+                    ("/app/web".to_string(), Some("us-west-2".to_string()), Some(0)),
+                ],
+                log_groups
+            );
+        });
+    }
+
+    #[test]
+    fn test_describe_subscription_filters_reports_progress() {
+        let subscription_filter = |log_group_name: &str| SubscriptionFilter {
+            log_group_name: log_group_name.to_string(),
+            region: None,
+            filter_name: "to-kinesis".to_string(),
+            filter_pattern: "ERROR".to_string(),
+            destination_arn: None,
+            role_arn: None,
+            distribution: None,
+            creation_time: None,
+        };
+        let us_east_1 = FakeCloudWatchLogs::new(Some("us-east-1"));
+        us_east_1.put_subscription_filter(subscription_filter("/app/web"));
+        us_east_1.create_log_group("/other");
+        let us_west_2 = FakeCloudWatchLogs::new(Some("us-west-2"));
+        us_west_2.put_subscription_filter(subscription_filter("/app/worker"));
+        tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::from_clients(vec![us_east_1, us_west_2]);
+            let (progress, watch_progress) = tokio::sync::watch::channel(Progress::default());
+            let subscription_filters = handle
+                .describe_subscription_filters(None, Some("^/app/".to_string()), Some(progress))
+                .await
+                .unwrap();
+            assert_eq!(2, subscription_filters.len());
+            assert_eq!(Progress { done: 2, total: 2 }, *watch_progress.borrow());
+        });
+    }
+
+    #[test]
+    fn test_events_with_the_same_timestamp_are_ordered_by_log_stream() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let us_east_1 = FakeCloudWatchLogs::new(Some("us-east-1")).with_page_size(1);
+        us_east_1.put_log_event("/app/web", "web-2", start, "web-2");
+        us_east_1.put_log_event("/app/web", "web-1", start, "web-1");
+        us_east_1.put_log_event("/app/web", "web-0", start + Duration::seconds(1), "later");
+        let us_west_2 = FakeCloudWatchLogs::new(Some("us-west-2"));
+        us_west_2.put_log_event("/app/worker", "a-worker", start, "a-worker");
+        let data = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::from_clients(vec![us_east_1, us_west_2]);
+            handle
+                .get_logs_to_display(
+                    None,
+                    Some("^/app/".to_string()),
+                    start,
+                    start + Duration::minutes(1),
+                    EventFilter::default(),
+                    format_cwl_log_event::LogFormatter::new("$message").unwrap(),
+                )
+                .await
+                .unwrap()
+        });
+        assert_eq!("a-worker\nweb-1\nweb-2\nlater", String::from_utf8_lossy(&data));
+    }
+
+    #[test]
+    fn test_get_logs_to_display_fetches_sub_ranges_in_order() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let cwl = FakeCloudWatchLogs::new(Some("us-east-1")).with_page_size(2);
+        for second in 0..60 {
+            let message = format!("{:02}", second);
+            cwl.put_log_event("/app/web", "web-1", start + Duration::seconds(second), message);
+        }
+        let options = ActorOptions {
+            sub_ranges_per_window: 7,
+            ..ActorOptions::default()
+        };
+        let data = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::with_options(vec![cwl], options);
+            handle
+                .get_logs_to_display(
+                    Some("/app/web".to_string()),
+                    None,
+                    start,
+                    start + Duration::minutes(1),
+                    EventFilter::default(),
+                    format_cwl_log_event::LogFormatter::new("$message").unwrap(),
+                )
+                .await
+                .unwrap()
+        });
+        let expected: Vec<String> = (0..60).map(|second| format!("{:02}", second)).collect();
+        assert_eq!(expected.join("\n"), String::from_utf8_lossy(&data));
+    }
+
+    #[test]
+    fn test_get_logs_to_display_does_not_wait_on_a_log_group_without_a_fetch_permit() {
+        // The merge waits for /app/b's only event, which comes before every event of /app/a, while /app/a holds the
+        // only fetch permit and has more events than fit in its channel.
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let cwl = FakeCloudWatchLogs::new(Some("us-east-1")).with_page_size(100);
+        let count = 2 * RENDERED_EVENTS_BUFFER as i64;
+        for millis in 1..=count {
+            cwl.put_log_event("/app/a", "a-1", start + Duration::milliseconds(millis), "a");
+        }
+        cwl.put_log_event("/app/b", "b-1", start, "b");
+        let options = ActorOptions {
+            max_concurrent_fetches: 1,
+            ..ActorOptions::default()
+        };
+        let data = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::with_options(vec![cwl], options);
+            let read = handle.get_logs_to_display(
+                None,
+                Some("^/app/".to_string()),
+                start,
+                start + Duration::minutes(1),
+                EventFilter::default(),
+                format_cwl_log_event::LogFormatter::new("$message").unwrap(),
+            );
+            tokio::time::timeout(std::time::Duration::from_secs(10), read)
+                .await
+                .expect("read deadlocked")
+                .unwrap()
+        });
+        let data = String::from_utf8_lossy(&data);
+        assert!(data.starts_with("b\na\n"));
+        assert_eq!(count as usize + 1, data.lines().count());
+    }
+
+    #[test]
+    fn test_get_logs_to_display_leaves_out_excluded_log_groups() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let cwl = FakeCloudWatchLogs::new(Some("us-east-1"));
+        cwl.put_log_event("prod-api", "api-1", start, "api");
+        cwl.put_log_event("prod-api-healthcheck", "check-1", start, "check");
+        let options = ActorOptions {
+            log_group_exclude: Some("-healthcheck$".to_string()),
+            ..ActorOptions::default()
+        };
+        let (filtered, by_name) = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::with_options(vec![cwl], options);
+            let read = |log_group_name: Option<&str>, log_group_filter: Option<&str>| {
+                handle.get_logs_to_display(
+                    log_group_name.map(|s| s.to_string()),
+                    log_group_filter.map(|s| s.to_string()),
+                    start,
+                    start + Duration::minutes(1),
+                    EventFilter::default(),
+                    format_cwl_log_event::LogFormatter::new("$message").unwrap(),
+                )
+            };
+            (
+                read(None, Some("^prod-")).await.unwrap(),
+                read(Some("prod-api-healthcheck"), None).await.unwrap(),
+            )
+        });
+        assert_eq!("api", String::from_utf8_lossy(&filtered));
+        assert_eq!("check", String::from_utf8_lossy(&by_name));
+    }
+
+    #[test]
+    fn test_live_tail_starts_with_events_since_the_start_time() {
+        let start = Utc::now() - Duration::minutes(10);
+        let messages = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::new(fake_with_log_groups(start));
+            let mut receiver = handle
+                .live_tail(
+                    Some("/app/web".to_string()),
+                    None,
+                    EventFilter::default(),
+                    Some(start),
+                )
+                .await
+                .unwrap();
+            let mut messages = vec![];
+            for _ in 0..5 {
+                messages.push(receiver.recv().await.unwrap().unwrap().message);
+            }
+            messages
+        });
+        assert_eq!(vec!["GET /a", "GET /b", "POST /c", "GET /d", "GET /e"], messages);
+    }
+
+    #[test]
+    fn test_get_log_page_reports_what_it_fetched() {
+        let start = Utc::now() - Duration::days(2);
+        let options = ActorOptions {
+            max_events_per_file: Some(3),
+            ..ActorOptions::default()
+        };
+        let formatter = format_cwl_log_event::LogFormatter::new("$message").unwrap();
+        let (first, second, worker, worker_again) = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::with_options(vec![fake_with_log_groups(start)], options);
+            let read = |log_group_filter: &str| {
+                handle.get_log_page(
+                    None,
+                    Some(log_group_filter.to_string()),
+                    start,
+                    start + Duration::minutes(5),
+                    EventFilter::default(),
+                    formatter.clone(),
+                )
+            };
+            (
+                read("^/app/").await.unwrap(),
+                read("^/app/").await.unwrap(),
+                read("^/app/worker$").await.unwrap(),
+                read("^/app/worker$").await.unwrap(),
+            )
+        });
+        assert!(!first.cached);
+        assert_eq!(2, first.log_groups_queried);
+        assert_eq!(3, first.events);
+        assert!(first.truncated);
+
+        // /app/web has five events on pages of two, and /app/worker one. Fetching stops at the limit, so not every
+        // page is necessarily fetched.
+        assert!((2..=4).contains(&first.pages_fetched));
+
+        // Truncated files are not cached, so reading again fetches again and is truncated again.
+        assert!(!second.cached);
+        assert!(second.truncated);
+        assert_eq!(first.data, second.data);
+
+        // The time range is long enough ago to be cached.
+        assert!(!worker.truncated);
+        assert!(worker_again.cached);
+        assert_eq!(worker.data, worker_again.data);
+        assert_eq!(0, worker_again.pages_fetched);
+    }
+
+    struct Uppercase;
+
+    impl crate::LogEventTransformer for Uppercase {
+        fn name(&self) -> String {
+            "uppercase".to_string()
+        }
+
+        fn transform(&self, mut event: FilteredLogEvent) -> Option<FilteredLogEvent> {
+            if event.message.starts_with("POST") {
+                return None;
+            }
+            event.message = event.message.to_uppercase();
+            Some(event)
+        }
+    }
+
+    #[test]
+    fn test_actor_applies_transformers_before_formatting() {
+        let start = Utc::now() - Duration::days(2);
+        let options = ActorOptions {
+            transformers: TransformerChain::new().with(Uppercase),
+            ..ActorOptions::default()
+        };
+        let formatter = format_cwl_log_event::LogFormatter::new("$message").unwrap();
+        let (data, events, page) = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::with_options(vec![fake_with_log_groups(start)], options);
+            // Before reading the file whole, which caches it.
+            let page = handle
+                .get_rendered_page(
+                    "/app/web".to_string(),
+                    start,
+                    start + Duration::minutes(5),
+                    EventFilter::default(),
+                    formatter.clone(),
+                    None,
+                )
+                .await
+                .unwrap();
+            let data = handle
+                .get_logs_to_display(
+                    Some("/app/web".to_string()),
+                    None,
+                    start,
+                    start + Duration::minutes(5),
+                    EventFilter::default(),
+                    formatter,
+                )
+                .await
+                .unwrap();
+            let events = handle
+                .get_log_events(
+                    "/app/web".to_string(),
+                    Some(start),
+                    None,
+                    None,
+                    EventFilter::default(),
+                )
+                .await
+                .unwrap();
+            (data, events, page)
+        });
+        assert_eq!("GET /A\nGET /B\nGET /D\nGET /E", String::from_utf8_lossy(&data));
+        assert_eq!(
+            vec!["GET /A", "GET /B", "GET /D", "GET /E"],
+            events.into_iter().map(|event| event.message).collect::<Vec<_>>()
+        );
+        assert_eq!("GET /A\nGET /B", String::from_utf8_lossy(&page.data));
+    }
+
+    #[test]
+    fn test_get_rendered_page_reads_the_rest_whole_once_truncated_or_cached() {
+        let start = Utc::now() - Duration::days(2);
+        let options = ActorOptions {
+            max_events_per_file: Some(3),
+            ..ActorOptions::default()
+        };
+        let formatter = format_cwl_log_event::LogFormatter::new("$message").unwrap();
+        let (pages, whole, first_two, cached) = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::with_options(vec![fake_with_log_groups(start)], options);
+            let get_page = |minutes, page_token| {
+                handle.get_rendered_page(
+                    "/app/web".to_string(),
+                    start,
+                    start + Duration::minutes(minutes),
+                    EventFilter::default(),
+                    formatter.clone(),
+                    page_token,
+                )
+            };
+            let get_whole = |minutes| {
+                handle.get_logs_to_display(
+                    Some("/app/web".to_string()),
+                    None,
+                    start,
+                    start + Duration::minutes(minutes),
+                    EventFilter::default(),
+                    formatter.clone(),
+                )
+            };
+            let mut pages = vec![get_page(5, None).await.unwrap()];
+            while let Some(page_token) = pages.last().unwrap().next_page_token.clone() {
+                pages.push(get_page(5, Some(page_token)).await.unwrap());
+            }
+            let whole = get_whole(5).await.unwrap();
+
+            // Truncated files are not cached, but the file of the first two minutes is.
+            let first_two = get_whole(2).await.unwrap();
+            (pages, whole, first_two, get_page(2, None).await.unwrap())
+        });
+
+        // The second page would have had a fourth event, so the rest of the file is the truncated file's.
+        assert_eq!(2, pages.len());
+        assert_eq!(2, pages[0].events.len());
+        assert!(pages[1].events.is_empty());
+        let joined: Vec<u8> = pages.iter().flat_map(|page| page.data.to_vec()).collect();
+        assert_eq!(whole, joined);
+        assert!(String::from_utf8_lossy(&whole).ends_with("omitted); narrow the time range"));
+        assert_eq!(whole.len() as u64, pages[1].bytes_rendered);
+
+        assert_eq!(first_two, cached.data);
+        assert!(cached.events.is_empty());
+        assert!(cached.next_page_token.is_none());
+    }
+
+    #[test]
+    fn test_client_uses_the_credentials_provider_it_is_given() {
+        let access_key_id = tokio_test::block_on(async {
+            let cwl = CloudWatchLogsImpl::with_options(ClientOptions {
+                region: Some("us-east-1".to_string()),
+                credentials_provider: Some(SharedCredentialsProvider::new(Credentials::new(
+                    "AKIDEXAMPLE",
+                    "secret",
+                    None,
+                    None,
+                    "vault",
+                ))),
+                ..ClientOptions::default()
+            })
+            .await;
+            let credentials = cwl
+                .credentials_provider()
+                .unwrap()
+                .provide_credentials()
+                .await
+                .unwrap();
+            credentials.access_key_id().to_string()
+        });
+        assert_eq!("AKIDEXAMPLE", access_key_id);
+    }
+
+    #[test]
+    fn test_get_logs_to_display_skips_deleted_log_groups() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let cwl = FakeCloudWatchLogs::new(Some("us-east-1"));
+        cwl.put_log_event("/app/web", "web-1", start, "first");
+        cwl.put_log_event("/app/worker", "worker-1", start, "gone");
+        cwl.delete_log_group("/app/worker");
+        let (first, second) = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::new(cwl);
+            let read = || {
+                handle.get_logs_to_display(
+                    None,
+                    Some("^/app/".to_string()),
+                    start,
+                    start + Duration::minutes(1),
+                    EventFilter::default(),
+                    format_cwl_log_event::LogFormatter::new("$message").unwrap(),
+                )
+            };
+            (read().await.unwrap(), read().await.unwrap())
+        });
+        assert_eq!(
+            "first\n[cwl-mount] skipped log group /app/worker, it has been deleted",
+            String::from_utf8_lossy(&first)
+        );
+        assert_eq!("first", String::from_utf8_lossy(&second));
+    }
+
+    #[test]
+    fn test_get_logs_to_display_skips_log_groups_that_keep_failing() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let cwl = FakeCloudWatchLogs::new(Some("us-east-1"));
+        cwl.put_log_event("/app/web", "web-1", start, "first");
+        cwl.put_log_event("/app/worker", "worker-1", start, "denied");
+        cwl.deny_log_group("/app/worker");
+        let options = ActorOptions {
+            circuit_breaker_threshold: Some(2),
+            ..ActorOptions::default()
+        };
+        let results = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::with_options(vec![cwl], options);
+            let mut results = vec![];
+            for _ in 0..3 {
+                let result = handle
+                    .get_logs_to_display(
+                        None,
+                        Some("^/app/".to_string()),
+                        start,
+                        start + Duration::minutes(1),
+                        EventFilter::default(),
+                        format_cwl_log_event::LogFormatter::new("$message").unwrap(),
+                    )
+                    .await;
+                results.push(result);
+            }
+            results
+        });
+        assert!(results[0].as_ref().unwrap_err().is_access_denied());
+        assert!(results[1].as_ref().unwrap_err().is_access_denied());
+        let skipped = String::from_utf8_lossy(results[2].as_ref().unwrap()).to_string();
+        assert!(
+            skipped.starts_with(
+                "first\n[cwl-mount] skipped log group /app/worker, reading it failed 2 times in a row; trying again after "
+            ),
+            "{}",
+            skipped
+        );
+    }
+
+    #[test]
+    fn test_prefetched_windows_are_read_from_the_cache() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let cwl = FakeCloudWatchLogs::new(Some("us-east-1"));
+        for minute in 0..3 {
+            cwl.put_log_event("/app/web", "web-1", start + Duration::minutes(minute), "event");
+        }
+        let windows: Vec<(DateTime<Utc>, DateTime<Utc>)> = (0..3)
+            .map(|minute| {
+                let window_start = start + Duration::minutes(minute);
+                (window_start, window_start + Duration::minutes(1))
+            })
+            .collect();
+        let formatter = format_cwl_log_event::LogFormatter::new("$message").unwrap();
+        let (prefetched, read) = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::new(cwl);
+            handle
+                .prefetch_logs_to_display(
+                    Some("/app/web".to_string()),
+                    None,
+                    windows.clone(),
+                    EventFilter::default(),
+                    formatter.clone(),
+                )
+                .await
+                .unwrap();
+            let prefetched = handle.usage().await.unwrap();
+            for (start_time, end_time) in windows {
+                let data = handle
+                    .get_logs_to_display(
+                        Some("/app/web".to_string()),
+                        None,
+                        start_time,
+                        end_time,
+                        EventFilter::default(),
+                        formatter.clone(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!("event", String::from_utf8_lossy(&data));
+            }
+            (prefetched, handle.usage().await.unwrap())
+        });
+        assert_eq!(Some(&3), prefetched.api_calls.get(&ApiAction::FilterLogEvents));
+        assert_eq!(prefetched.api_calls, read.api_calls);
+        assert_eq!(prefetched.events_returned, read.events_returned);
+        assert_eq!(prefetched.memory_cache_hits + 3, read.memory_cache_hits);
+        assert_eq!(prefetched.cache_misses, read.cache_misses);
+    }
+
+    #[test]
+    fn test_clear_cache_fetches_files_again() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let cwl = FakeCloudWatchLogs::new(Some("us-east-1"));
+        cwl.put_log_event("/app/web", "web-1", start, "event");
+        let formatter = format_cwl_log_event::LogFormatter::new("$message").unwrap();
+        let usage = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::new(cwl);
+            let read = || {
+                handle.get_logs_to_display(
+                    Some("/app/web".to_string()),
+                    None,
+                    start,
+                    start + Duration::minutes(1),
+                    EventFilter::default(),
+                    formatter.clone(),
+                )
+            };
+            read().await.unwrap();
+            read().await.unwrap();
+            handle.clear_cache().await.unwrap();
+            assert_eq!("event", String::from_utf8_lossy(&read().await.unwrap()));
+            handle.usage().await.unwrap()
+        });
+        assert_eq!(Some(&2), usage.api_calls.get(&ApiAction::FilterLogEvents));
+        assert_eq!(1, usage.memory_cache_hits);
+        assert_eq!(2, usage.cache_misses);
+    }
+
+    #[test]
+    fn test_close_finishes_accepted_requests_and_rejects_new_ones() {
+        let cwl = FakeCloudWatchLogs::new(Some("us-east-1"));
+        cwl.create_log_group("/app/web");
+        tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::new(cwl);
+            let (log_group_names, closed) = tokio::join!(
+                handle.get_log_group_names(),
+                handle.close(std::time::Duration::from_secs(5))
+            );
+            assert_eq!(vec!["/app/web".to_string()], log_group_names.unwrap());
+            closed.unwrap();
+            assert!(matches!(
+                handle.get_log_group_names().await,
+                Err(CloudWatchLogsError::ActorUnavailable)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_requests_fail_when_the_actor_does_not_respond() {
+        tokio_test::block_on(async {
+            // A mailbox that nobody handles requests from.
+            let (sender, mut receiver) = mailbox(1, MailboxFullPolicy::Block);
+            let handle = CloudWatchLogsActorHandle {
+                sender,
+                request_timeout: Some(std::time::Duration::from_millis(10)),
+            };
+            let err = handle.get_log_group_names().await.unwrap_err();
+            assert!(matches!(err, CloudWatchLogsError::RequestTimedOut));
+            assert!(err.is_timeout());
+
+            // Dropping a request without responding, e.g. because handling it panicked.
+            receiver.recv().await.unwrap();
+            let (err, _) = tokio::join!(handle.get_log_group_names(), async {
+                drop(receiver.recv().await);
+            });
+            assert!(matches!(err, Err(CloudWatchLogsError::ActorUnavailable)));
+        });
+    }
+
+    #[test]
+    fn test_events_carry_their_account_and_region() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let prod = FakeCloudWatchLogs::new(Some("us-east-1")).with_account_id("111111111111");
+        prod.put_log_event("/app/web", "web-1", start, "first");
+        let data = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::from_clients(vec![prod]);
+            handle
+                .get_logs_to_display(
+                    Some("/app/web".to_string()),
+                    None,
+                    start,
+                    start + Duration::minutes(1),
+                    EventFilter::default(),
+                    format_cwl_log_event::LogFormatter::new("${account_id} ${region} $message").unwrap(),
+                )
+                .await
+                .unwrap()
+        });
+        assert_eq!("111111111111 us-east-1 first", String::from_utf8_lossy(&data));
+    }
+
+    #[test]
+    fn test_log_groups_of_linked_accounts_are_named_by_their_arn() {
+        let metadata = |arn: &str| LogGroupMetadata {
+            log_group_name: "/app/web".to_string(),
+            region: Some("us-east-1".to_string()),
+            arn: Some(arn.to_string()),
+            creation_time: None,
+            retention_in_days: None,
+            stored_bytes: None,
+            kms_key_id: None,
+        };
+        let own = metadata("arn:aws:logs:us-east-1:111111111111:log-group:/app/web:*");
+        assert_eq!(own, name_linked_log_group(own.clone(), Some("111111111111")));
+        let linked = metadata("arn:aws:logs:us-east-1:222222222222:log-group:/app/web:*");
+        assert_eq!(
+            "arn:aws:logs:us-east-1:222222222222:log-group:/app/web",
+            name_linked_log_group(linked, Some("111111111111")).log_group_name
+        );
+    }
+
+    #[test]
+    fn test_get_logs_to_display_reads_log_groups_of_linked_accounts() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let linked = "arn:aws:logs:us-east-1:222222222222:log-group:/app/web";
+        let monitoring = FakeCloudWatchLogs::new(Some("us-east-1")).with_account_id("111111111111");
+        monitoring.put_log_event("/app/web", "web-1", start, "monitoring");
+        monitoring.put_log_event(linked, "web-1", start + Duration::seconds(1), "source");
+        let (filtered, by_arn) = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::from_clients(vec![monitoring]);
+            let read = |log_group_name: Option<&str>, log_group_filter: Option<&str>| {
+                handle.get_logs_to_display(
+                    log_group_name.map(|s| s.to_string()),
+                    log_group_filter.map(|s| s.to_string()),
+                    start,
+                    start + Duration::minutes(1),
+                    EventFilter::default(),
+                    format_cwl_log_event::LogFormatter::new("${account_id} ${log_group_name} $message")
+                        .unwrap(),
+                )
+            };
+            (
+                read(None, Some("^/app/web$")).await.unwrap(),
+                read(Some(linked), None).await.unwrap(),
+            )
+        });
+        assert_eq!(
+            "111111111111 /app/web monitoring\n222222222222 /app/web source",
+            String::from_utf8_lossy(&filtered)
+        );
+        assert_eq!("222222222222 /app/web source", String::from_utf8_lossy(&by_arn));
+    }
+
+    #[test]
+    fn test_account_ids_lists_each_known_account_once() {
+        let account_ids = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::from_clients(vec![
+                FakeCloudWatchLogs::new(Some("us-east-1")).with_account_id("111111111111"),
+                FakeCloudWatchLogs::new(Some("us-west-2")).with_account_id("111111111111"),
+                FakeCloudWatchLogs::new(Some("eu-west-1")),
+            ]);
+            handle.account_ids().await.unwrap()
+        });
+        assert_eq!(vec!["111111111111"], account_ids);
+    }
+
+    #[test]
+    fn test_usage_is_totalled_across_regions() {
+        let start = Utc.ymd(2022, 1, 1).and_hms(0, 0, 0);
+        let us_east_1 = FakeCloudWatchLogs::new(Some("us-east-1"));
+        us_east_1.put_log_event("/app/web", "web-1", start, "first");
+        let us_west_2 = FakeCloudWatchLogs::new(Some("us-west-2")).with_page_size(1);
+        us_west_2.put_log_event("/app/web", "web-1", start + Duration::seconds(1), "second");
+        us_west_2.put_log_event("/app/web", "web-1", start + Duration::seconds(2), "third");
+        let usage = tokio_test::block_on(async {
+            let handle = CloudWatchLogsActorHandle::from_clients(vec![us_east_1, us_west_2]);
+            handle
+                .get_logs_to_display(
+                    Some("/app/web".to_string()),
+                    None,
+                    start,
+                    start + Duration::minutes(1),
+                    EventFilter::default(),
+                    format_cwl_log_event::LogFormatter::new("$message").unwrap(),
+                )
+                .await
+                .unwrap();
+            handle.usage().await.unwrap()
+        });
+        assert_eq!(Some(&3), usage.api_calls.get(&ApiAction::FilterLogEvents));
+        assert_eq!(3, usage.events_returned);
+        assert_eq!(0, usage.insights_bytes_scanned);
     }
 }
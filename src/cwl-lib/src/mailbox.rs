@@ -0,0 +1,246 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tokio::sync::Notify;
+
+/// What to do with a message for the actor when its mailbox is already full.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum MailboxFullPolicy {
+    /// Wait until the actor takes a message out of the mailbox.
+    #[default]
+    Block,
+
+    /// Drop the message that has waited longest, failing its request, to make room for the new one. Suits reads
+    /// whose callers give up after a while anyway.
+    ShedOldest,
+
+    /// Fail the new request straight away.
+    Error,
+}
+
+#[derive(Debug)]
+pub(crate) enum SendError<T> {
+    /// The mailbox is full and the policy is `Error`.
+    Full(T),
+
+    /// The receiving end was closed or dropped, so the message would never be handled.
+    Closed(T),
+}
+
+#[derive(Debug)]
+struct State<T> {
+    messages: VecDeque<T>,
+    senders: usize,
+    closed: bool,
+}
+
+/// Bounded queue of messages for an actor. Unlike a `tokio::sync::mpsc` channel, what happens when it is full is
+/// up to a `MailboxFullPolicy`.
+#[derive(Debug)]
+struct Mailbox<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    policy: MailboxFullPolicy,
+    message_sent: Notify,
+    message_received: Notify,
+}
+
+impl<T> Mailbox<T> {
+    fn state(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().expect("mailbox lock is never poisoned")
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct MailboxSender<T> {
+    mailbox: Arc<Mailbox<T>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct MailboxReceiver<T> {
+    mailbox: Arc<Mailbox<T>>,
+}
+
+/// Create a mailbox that holds up to `capacity` messages, which must be at least one.
+pub(crate) fn mailbox<T>(
+    capacity: usize,
+    policy: MailboxFullPolicy,
+) -> (MailboxSender<T>, MailboxReceiver<T>) {
+    let mailbox = Arc::new(Mailbox {
+        state: Mutex::new(State {
+            messages: VecDeque::with_capacity(capacity),
+            senders: 1,
+            closed: false,
+        }),
+        capacity: capacity.max(1),
+        policy,
+        message_sent: Notify::new(),
+        message_received: Notify::new(),
+    });
+    (
+        MailboxSender {
+            mailbox: Arc::clone(&mailbox),
+        },
+        MailboxReceiver { mailbox },
+    )
+}
+
+impl<T> MailboxSender<T> {
+    /// Put a message in the mailbox, applying the policy if it is full. Returns the message that was dropped to
+    /// make room, if any, so that its request can be failed.
+    pub(crate) async fn send(&self, message: T) -> Result<Option<T>, SendError<T>> {
+        loop {
+            // Register interest before checking, so that a message received in between is not missed.
+            let message_received = self.mailbox.message_received.notified();
+            {
+                let mut state = self.mailbox.state();
+                if state.closed {
+                    return Err(SendError::Closed(message));
+                }
+                if state.messages.len() < self.mailbox.capacity {
+                    state.messages.push_back(message);
+                    self.mailbox.message_sent.notify_one();
+                    return Ok(None);
+                }
+                match self.mailbox.policy {
+                    MailboxFullPolicy::Error => return Err(SendError::Full(message)),
+                    MailboxFullPolicy::ShedOldest => {
+                        let oldest = state.messages.pop_front();
+                        state.messages.push_back(message);
+                        self.mailbox.message_sent.notify_one();
+                        return Ok(oldest);
+                    }
+                    MailboxFullPolicy::Block => {}
+                }
+            }
+            message_received.await;
+        }
+    }
+}
+
+impl<T> Clone for MailboxSender<T> {
+    fn clone(&self) -> Self {
+        self.mailbox.state().senders += 1;
+        MailboxSender {
+            mailbox: Arc::clone(&self.mailbox),
+        }
+    }
+}
+
+impl<T> Drop for MailboxSender<T> {
+    fn drop(&mut self) {
+        self.mailbox.state().senders -= 1;
+        self.mailbox.message_sent.notify_one();
+    }
+}
+
+impl<T> MailboxReceiver<T> {
+    /// Stop accepting messages. Messages already in the mailbox can still be received.
+    pub(crate) fn close(&mut self) {
+        self.mailbox.state().closed = true;
+        self.mailbox.message_received.notify_waiters();
+    }
+
+    /// The next message, or None once the mailbox is empty and every sender has been dropped or it was closed.
+    pub(crate) async fn recv(&mut self) -> Option<T> {
+        loop {
+            let message_sent = self.mailbox.message_sent.notified();
+            {
+                let mut state = self.mailbox.state();
+                if let Some(message) = state.messages.pop_front() {
+                    self.mailbox.message_received.notify_one();
+                    return Some(message);
+                }
+                if state.senders == 0 || state.closed {
+                    return None;
+                }
+            }
+            message_sent.await;
+        }
+    }
+}
+
+impl<T> Drop for MailboxReceiver<T> {
+    fn drop(&mut self) {
+        self.mailbox.state().closed = true;
+        self.mailbox.message_received.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::mailbox::{mailbox, MailboxFullPolicy, SendError};
+
+    #[test]
+    fn test_mailbox_full_policies() {
+        tokio_test::block_on(async {
+            let (sender, mut receiver) = mailbox(2, MailboxFullPolicy::Error);
+            assert!(matches!(sender.send(1).await, Ok(None)));
+            assert!(matches!(sender.send(2).await, Ok(None)));
+            assert!(matches!(sender.send(3).await, Err(SendError::Full(3))));
+            assert_eq!(Some(1), receiver.recv().await);
+
+            let (sender, mut receiver) = mailbox(2, MailboxFullPolicy::ShedOldest);
+            assert!(matches!(sender.send(1).await, Ok(None)));
+            assert!(matches!(sender.send(2).await, Ok(None)));
+            assert!(matches!(sender.send(3).await, Ok(Some(1))));
+            assert_eq!(Some(2), receiver.recv().await);
+            assert_eq!(Some(3), receiver.recv().await);
+        });
+    }
+
+    #[test]
+    fn test_mailbox_blocks_until_a_message_is_received() {
+        tokio_test::block_on(async {
+            let (sender, mut receiver) = mailbox(1, MailboxFullPolicy::Block);
+            sender.send(1).await.unwrap();
+            let sent = Arc::new(AtomicBool::new(false));
+            let blocked = tokio::spawn({
+                let sent = Arc::clone(&sent);
+                async move {
+                    sender.send(2).await.unwrap();
+                    sent.store(true, Ordering::SeqCst);
+                }
+            });
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert!(!sent.load(Ordering::SeqCst));
+            assert_eq!(Some(1), receiver.recv().await);
+            blocked.await.unwrap();
+            assert_eq!(Some(2), receiver.recv().await);
+
+            // Every sender is gone now.
+            assert_eq!(None, receiver.recv().await);
+        });
+    }
+
+    #[test]
+    fn test_closed_mailbox_hands_out_what_it_holds() {
+        tokio_test::block_on(async {
+            let (sender, mut receiver) = mailbox(2, MailboxFullPolicy::Block);
+            sender.send(1).await.unwrap();
+            receiver.close();
+            assert!(matches!(sender.send(2).await, Err(SendError::Closed(2))));
+            assert_eq!(Some(1), receiver.recv().await);
+            assert_eq!(None, receiver.recv().await);
+        });
+    }
+
+    #[test]
+    fn test_mailbox_send_fails_once_receiver_is_dropped() {
+        let (sender, receiver) = mailbox(1, MailboxFullPolicy::Block);
+        drop(receiver);
+        assert!(matches!(
+            tokio_test::block_on(sender.send(1)),
+            Err(SendError::Closed(1))
+        ));
+    }
+}
@@ -0,0 +1,365 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use leaky_bucket::RateLimiter;
+use tokio::sync::oneshot;
+
+/// CloudWatch Logs APIs that cwl-mount calls. Each has its own quota [1], so each is rate limited separately and
+/// fetching events does not hold up listing log groups.
+///
+/// [1] https://docs.aws.amazon.com/AmazonCloudWatch/latest/logs/cloudwatch_limits_cwl.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum ApiAction {
+    DescribeLogGroups,
+    DescribeLogStreams,
+    FilterLogEvents,
+    GetLogEvents,
+    DescribeMetricFilters,
+    DescribeSubscriptionFilters,
+    CreateExportTask,
+    DescribeExportTasks,
+    StartQuery,
+    GetQueryResults,
+    DescribeQueryDefinitions,
+}
+
+impl ApiAction {
+    pub const ALL: &'static [ApiAction] = &[
+        ApiAction::DescribeLogGroups,
+        ApiAction::DescribeLogStreams,
+        ApiAction::FilterLogEvents,
+        ApiAction::GetLogEvents,
+        ApiAction::DescribeMetricFilters,
+        ApiAction::DescribeSubscriptionFilters,
+        ApiAction::CreateExportTask,
+        ApiAction::DescribeExportTasks,
+        ApiAction::StartQuery,
+        ApiAction::GetQueryResults,
+        ApiAction::DescribeQueryDefinitions,
+    ];
+
+    /// The API's name as AWS spells it, e.g. `FilterLogEvents`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiAction::DescribeLogGroups => "DescribeLogGroups",
+            ApiAction::DescribeLogStreams => "DescribeLogStreams",
+            ApiAction::FilterLogEvents => "FilterLogEvents",
+            ApiAction::GetLogEvents => "GetLogEvents",
+            ApiAction::DescribeMetricFilters => "DescribeMetricFilters",
+            ApiAction::DescribeSubscriptionFilters => "DescribeSubscriptionFilters",
+            ApiAction::CreateExportTask => "CreateExportTask",
+            ApiAction::DescribeExportTasks => "DescribeExportTasks",
+            ApiAction::StartQuery => "StartQuery",
+            ApiAction::GetQueryResults => "GetQueryResults",
+            ApiAction::DescribeQueryDefinitions => "DescribeQueryDefinitions",
+        }
+    }
+}
+
+impl FromStr for ApiAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ApiAction::ALL
+            .iter()
+            .find(|action| action.as_str().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| format!("{} isn't a CloudWatch Logs API that cwl-mount calls", s))
+    }
+}
+
+/// One rate limiter per API.
+pub(crate) struct RateLimiters {
+    limiters: HashMap<ApiAction, FairLimiter>,
+}
+
+impl RateLimiters {
+    /// Allow `tps` calls per second to each API, except for the APIs in `action_tps`. Permits come back every
+    /// `refill_interval`, and up to `burst` calls may be made at once after a quiet spell, by default one second's
+    /// worth.
+    pub(crate) fn new(
+        tps: usize,
+        action_tps: &HashMap<ApiAction, usize>,
+        burst: Option<usize>,
+        refill_interval: std::time::Duration,
+    ) -> Self {
+        let limiters = ApiAction::ALL
+            .iter()
+            .map(|action| {
+                let tps = action_tps.get(action).copied().unwrap_or(tps).max(1);
+                let (max, refill) = bucket(tps, burst, refill_interval);
+                let limiter = RateLimiter::builder()
+                    .max(max)
+                    .initial(max)
+                    .refill(refill)
+                    .interval(refill_interval)
+                    .build();
+                (*action, FairLimiter::new(limiter))
+            })
+            .collect();
+        RateLimiters { limiters }
+    }
+
+    /// Wait until `action` may be called.
+    pub(crate) async fn acquire(&self, action: ApiAction) {
+        self.limiters[&action].acquire("").await;
+    }
+
+    /// Wait until `action` may be called for `log_group_name`. Calls waiting for different log groups take turns,
+    /// so paginating through one huge log group does not hold up the others.
+    pub(crate) async fn acquire_for_log_group(&self, action: ApiAction, log_group_name: &str) {
+        self.limiters[&action].acquire(log_group_name).await;
+    }
+}
+
+/// How many permits the bucket of an API that may be called `tps` times per second holds, and how many come back
+/// every `refill_interval`. Refills are rounded to whole calls, and the bucket holds at least one refill so that none
+/// are lost.
+fn bucket(tps: usize, burst: Option<usize>, refill_interval: std::time::Duration) -> (usize, usize) {
+    let refill = ((tps as f64 * refill_interval.as_secs_f64()).round() as usize).max(1);
+    (burst.unwrap_or(tps).max(refill), refill)
+}
+
+/// Callers waiting for a permit, by the key they wait for, and the keys in the order they take turns.
+#[derive(Debug, Default)]
+struct FairQueue {
+    next_waiter_id: u64,
+    turns: VecDeque<String>,
+    waiters: HashMap<String, VecDeque<(u64, oneshot::Sender<()>)>>,
+}
+
+impl FairQueue {
+    fn push(&mut self, key: &str, sender: oneshot::Sender<()>) -> u64 {
+        let id = self.next_waiter_id;
+        self.next_waiter_id += 1;
+        let waiters = self.waiters.entry(key.to_string()).or_default();
+        if waiters.is_empty() {
+            self.turns.push_back(key.to_string());
+        }
+        waiters.push_back((id, sender));
+        id
+    }
+
+    /// Give a permit to the first waiter of the key whose turn it is, which then goes to the back of the line.
+    fn grant(&mut self) {
+        while let Some(key) = self.turns.pop_front() {
+            let waiters = self
+                .waiters
+                .get_mut(&key)
+                .expect("every key in turns has waiters");
+            let (_, sender) = waiters.pop_front().expect("every key in turns has waiters");
+            if waiters.is_empty() {
+                self.waiters.remove(&key);
+            } else {
+                self.turns.push_back(key);
+            }
+            if sender.send(()).is_ok() {
+                return;
+            }
+        }
+    }
+
+    /// Remove a waiter that stopped waiting. Returns false if it was not waiting any more.
+    fn remove(&mut self, key: &str, id: u64) -> bool {
+        let waiters = match self.waiters.get_mut(key) {
+            Some(waiters) => waiters,
+            None => return false,
+        };
+        let index = match waiters.iter().position(|(other, _)| *other == id) {
+            Some(index) => index,
+            None => return false,
+        };
+        waiters.remove(index);
+        if waiters.is_empty() {
+            self.waiters.remove(key);
+            self.turns.retain(|other| other != key);
+        }
+        true
+    }
+}
+
+/// A rate limiter that shares permits round-robin between keys rather than first come, first served.
+///
+/// Every caller joins the queue and then takes one permit from the rate limiter, which it hands to whoever's turn
+/// it is, possibly itself. So there are as many permits on their way as there are waiters.
+struct FairLimiter {
+    limiter: RateLimiter,
+    queue: Mutex<FairQueue>,
+}
+
+impl FairLimiter {
+    fn new(limiter: RateLimiter) -> Self {
+        FairLimiter {
+            limiter,
+            queue: Mutex::new(FairQueue::default()),
+        }
+    }
+
+    fn queue(&self) -> std::sync::MutexGuard<'_, FairQueue> {
+        self.queue.lock().expect("fair queue lock is never poisoned")
+    }
+
+    async fn acquire(&self, key: &str) {
+        let (sender, receiver) = oneshot::channel();
+        let id = self.queue().push(key, sender);
+        let mut waiter = Waiter {
+            limiter: self,
+            key,
+            id,
+            receiver,
+            done: false,
+        };
+        self.limiter.acquire_one().await;
+        self.queue().grant();
+        // The sender is only dropped after sending, or by `Waiter`, which is still here.
+        let _ = (&mut waiter.receiver).await;
+        waiter.done = true;
+    }
+}
+
+/// Keeps the permits balanced with the waiters when a caller stops waiting, e.g. because a read was cancelled.
+struct Waiter<'a> {
+    limiter: &'a FairLimiter,
+    key: &'a str,
+    id: u64,
+    receiver: oneshot::Receiver<()>,
+    done: bool,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let mut queue = self.limiter.queue();
+        // A permit that was already handed to this caller goes to the next waiter instead.
+        if !queue.remove(self.key, self.id) && self.receiver.try_recv().is_ok() {
+            queue.grant();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use tokio::sync::oneshot;
+
+    use crate::rate_limit::{bucket, ApiAction, FairQueue, RateLimiters};
+
+    #[test]
+    fn test_api_action_from_str() {
+        for action in ApiAction::ALL {
+            assert_eq!(Ok(*action), action.as_str().parse());
+        }
+        assert_eq!(Ok(ApiAction::FilterLogEvents), "filterlogevents".parse());
+        assert!("PutLogEvents".parse::<ApiAction>().is_err());
+    }
+
+    #[test]
+    fn test_fair_queue_takes_turns_between_keys() {
+        let mut queue = FairQueue::default();
+        let mut receivers: Vec<_> = ["big", "big", "big", "small"]
+            .into_iter()
+            .map(|key| {
+                let (sender, receiver) = oneshot::channel();
+                queue.push(key, sender);
+                (key, receiver)
+            })
+            .collect();
+        let mut granted = vec![];
+        for _ in 0..receivers.len() {
+            queue.grant();
+            let index = receivers
+                .iter_mut()
+                .position(|(_, receiver)| receiver.try_recv().is_ok())
+                .unwrap();
+            granted.push(receivers.remove(index).0);
+        }
+        assert_eq!(vec!["big", "small", "big", "big"], granted);
+    }
+
+    #[test]
+    fn test_fair_queue_skips_removed_waiters() {
+        let mut queue = FairQueue::default();
+        let (first, _first_receiver) = oneshot::channel();
+        let first = queue.push("a", first);
+        let (second, mut second_receiver) = oneshot::channel();
+        queue.push("b", second);
+        assert!(queue.remove("a", first));
+        assert!(!queue.remove("a", first));
+        queue.grant();
+        assert!(second_receiver.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!((5, 5), bucket(5, None, Duration::from_secs(1)));
+        assert_eq!((20, 5), bucket(5, Some(20), Duration::from_secs(1)));
+        assert_eq!((5, 1), bucket(5, None, Duration::from_millis(200)));
+        assert_eq!((2, 1), bucket(10, Some(2), Duration::from_millis(100)));
+        assert_eq!((10, 10), bucket(5, Some(1), Duration::from_secs(2)));
+        assert_eq!((5, 1), bucket(5, None, Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_burst_allows_calls_at_once() {
+        let rate_limiters = RateLimiters::new(1, &HashMap::new(), Some(3), Duration::from_secs(1));
+        tokio_test::block_on(async {
+            for _ in 0..3 {
+                let call = rate_limiters.acquire(ApiAction::FilterLogEvents);
+                assert!(tokio::time::timeout(Duration::from_millis(50), call)
+                    .await
+                    .is_ok());
+            }
+            let call = rate_limiters.acquire(ApiAction::FilterLogEvents);
+            assert!(tokio::time::timeout(Duration::from_millis(50), call)
+                .await
+                .is_err());
+        });
+    }
+
+    #[test]
+    fn test_cancelled_waiters_do_not_hold_up_others() {
+        let rate_limiters = RateLimiters::new(1, &HashMap::new(), None, Duration::from_secs(1));
+        tokio_test::block_on(async {
+            rate_limiters.acquire(ApiAction::GetLogEvents).await;
+            let cancelled = rate_limiters.acquire_for_log_group(ApiAction::GetLogEvents, "a");
+            assert!(tokio::time::timeout(Duration::from_millis(10), cancelled)
+                .await
+                .is_err());
+            let waiting = rate_limiters.acquire_for_log_group(ApiAction::GetLogEvents, "b");
+            assert!(tokio::time::timeout(Duration::from_secs(3), waiting)
+                .await
+                .is_ok());
+        });
+    }
+
+    #[test]
+    fn test_rate_limiters_are_independent() {
+        let action_tps = HashMap::from([(ApiAction::FilterLogEvents, 1)]);
+        let rate_limiters = RateLimiters::new(5, &action_tps, None, Duration::from_secs(1));
+        tokio_test::block_on(async {
+            rate_limiters.acquire(ApiAction::FilterLogEvents).await;
+            let filter_log_events = rate_limiters.acquire(ApiAction::FilterLogEvents);
+            assert!(tokio::time::timeout(Duration::from_millis(50), filter_log_events)
+                .await
+                .is_err());
+            for _ in 0..5 {
+                let describe_log_groups = rate_limiters.acquire(ApiAction::DescribeLogGroups);
+                assert!(
+                    tokio::time::timeout(Duration::from_millis(50), describe_log_groups)
+                        .await
+                        .is_ok()
+                );
+            }
+        });
+    }
+}
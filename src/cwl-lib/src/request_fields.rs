@@ -0,0 +1,134 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+//! Request parameters that CloudWatch Logs added after the version of the AWS SDK that cwl-mount is built with, e.g.
+//! `unmask` on FilterLogEvents. The SDK's operations are still used to build and sign requests, and the parameters
+//! are added to their JSON bodies.
+
+use aws_sdk_cloudwatchlogs::middleware::DefaultMiddleware;
+use aws_smithy_client::erase::DynConnector;
+use aws_smithy_client::retry::Standard;
+use aws_smithy_http::body::SdkBody;
+use aws_smithy_http::operation::Operation;
+use http::header::{HeaderValue, CONTENT_LENGTH};
+use regexes::LogGroupArn;
+use serde_json::{Map, Value};
+
+/// Sends operations that `with_fields` changed, which the SDK's fluent client has no way to do.
+pub(crate) type OperationClient = aws_smithy_client::Client<DynConnector, DefaultMiddleware, Standard>;
+
+/// Add `fields` to the JSON body of `operation`, replacing fields that the SDK already set.
+pub(crate) fn with_fields<O, R>(operation: Operation<O, R>, fields: Map<String, Value>) -> Operation<O, R> {
+    if fields.is_empty() {
+        return operation;
+    }
+    let (mut request, parts) = operation.into_request_response();
+    let http = request.http_mut();
+    let mut body: Map<String, Value> = http
+        .body()
+        .bytes()
+        .map(|body| serde_json::from_slice(body).expect("the SDK serializes requests as JSON objects"))
+        .unwrap_or_default();
+    body.extend(fields);
+    let body = serde_json::to_vec(&body).expect("JSON objects serialize");
+    http.headers_mut()
+        .insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+    *http.body_mut() = SdkBody::from(body);
+    Operation::from_parts(request, parts)
+}
+
+/// `unmask` of FilterLogEvents and GetLogEvents, which is left out unless it is set.
+pub(crate) fn unmask(unmask: bool) -> Map<String, Value> {
+    let mut fields = Map::new();
+    if unmask {
+        fields.insert("unmask".to_string(), Value::Bool(true));
+    }
+    fields
+}
+
+/// `includeLinkedAccounts` and `accountIdentifiers` of DescribeLogGroups, which a monitoring account sets to also list
+/// the log groups of the source accounts linked to it.
+pub(crate) fn linked_accounts(
+    include_linked_accounts: bool,
+    account_identifiers: &[String],
+) -> Map<String, Value> {
+    let mut fields = Map::new();
+    if include_linked_accounts {
+        fields.insert("includeLinkedAccounts".to_string(), Value::Bool(true));
+        if !account_identifiers.is_empty() {
+            fields.insert(
+                "accountIdentifiers".to_string(),
+                account_identifiers.iter().cloned().map(Value::String).collect(),
+            );
+        }
+    }
+    fields
+}
+
+/// `logGroupIdentifier` of FilterLogEvents and GetLogEvents, which is how a monitoring account reads a log group of a
+/// source account. The SDK's `logGroupName` must then be left out.
+pub(crate) fn log_group_identifier(arn: Option<&LogGroupArn>) -> Map<String, Value> {
+    let mut fields = Map::new();
+    if let Some(arn) = arn {
+        fields.insert("logGroupIdentifier".to_string(), Value::String(arn.identifier()));
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use aws_sdk_cloudwatchlogs::input::FilterLogEventsInput;
+    use aws_sdk_cloudwatchlogs::Config;
+    use aws_types::region::Region;
+    use serde_json::{json, Map, Value};
+
+    use crate::request_fields::{linked_accounts, log_group_identifier, with_fields};
+
+    #[test]
+    fn test_with_fields() {
+        let config = Config::builder().region(Region::new("us-west-2")).build();
+        let operation = tokio_test::block_on(
+            FilterLogEventsInput::builder()
+                .log_group_name("/app/web")
+                .limit(10)
+                .build()
+                .unwrap()
+                .make_operation(&config),
+        )
+        .unwrap();
+        let mut fields = Map::new();
+        fields.insert("unmask".to_string(), Value::Bool(true));
+        fields.insert("limit".to_string(), json!(20));
+        let (request, _) = with_fields(operation, fields).into_request_response();
+        let body: Value = serde_json::from_slice(request.http().body().bytes().unwrap()).unwrap();
+        assert_eq!(
+            json!({"logGroupName": "/app/web", "limit": 20, "unmask": true}),
+            body
+        );
+        assert_eq!(
+            body.to_string().len().to_string(),
+            request.http().headers()["content-length"]
+        );
+    }
+
+    #[test]
+    fn test_cross_account_fields() {
+        assert!(linked_accounts(false, &["222222222222".to_string()]).is_empty());
+        assert_eq!(
+            json!({"includeLinkedAccounts": true}),
+            Value::Object(linked_accounts(true, &[]))
+        );
+        assert_eq!(
+            json!({"includeLinkedAccounts": true, "accountIdentifiers": ["222222222222"]}),
+            Value::Object(linked_accounts(true, &["222222222222".to_string()]))
+        );
+        assert!(log_group_identifier(None).is_empty());
+        let arn = regexes::LogGroupArn::parse("arn:aws:logs:us-west-2:222222222222:log-group:/app/web:*");
+        assert_eq!(
+            json!({"logGroupIdentifier": "arn:aws:logs:us-west-2:222222222222:log-group:/app/web"}),
+            Value::Object(log_group_identifier(arn.as_ref()))
+        );
+    }
+}
@@ -0,0 +1,330 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use std::future::Future;
+use std::time::Duration;
+
+use aws_http::auth::CredentialsStageError;
+use aws_smithy_http::result::SdkError;
+use aws_smithy_types::retry::{ErrorKind, ProvideErrorKind};
+use tracing::debug;
+
+/// Error codes that CloudWatch Logs uses to ask callers to slow down, or that are otherwise safe to retry.
+const RETRYABLE_ERROR_CODES: &[&str] = &[
+    "ThrottlingException",
+    "Throttling",
+    "LimitExceededException",
+    "RequestLimitExceeded",
+    "ServiceUnavailableException",
+];
+
+/// Error codes with which CloudWatch Logs says that calls are being made too often.
+const THROTTLING_ERROR_CODES: &[&str] = &[
+    "ThrottlingException",
+    "Throttling",
+    "LimitExceededException",
+    "RequestLimitExceeded",
+];
+
+/// Error codes with which AWS says that the credentials are not allowed to make a call.
+const ACCESS_DENIED_ERROR_CODES: &[&str] = &["AccessDeniedException", "AccessDenied"];
+
+/// Error codes with which AWS rejects credentials that have expired or are otherwise not accepted any more, which
+/// loading credentials again may fix.
+const CREDENTIALS_ERROR_CODES: &[&str] = &[
+    "ExpiredToken",
+    "ExpiredTokenException",
+    "RequestExpired",
+    "InvalidClientTokenId",
+    "UnrecognizedClientException",
+    "AccessDeniedException",
+];
+
+/// Error codes with which CloudWatch Logs rejects a call that the log group's class does not support.
+const UNSUPPORTED_BY_LOG_GROUP_CLASS_ERROR_CODES: &[&str] =
+    &["InvalidOperationException", "InvalidParameterException"];
+
+/// How to retry CloudWatch Logs calls that fail because of throttling or server errors. Backoff is exponential
+/// with full jitter [1].
+///
+/// [1] https://aws.amazon.com/blogs/architecture/exponential-backoff-and-jitter/
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts including the first one, so `1` disables retries.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(20),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Upper bound of the backoff before the given retry, where the first retry is `1`.
+    fn max_backoff_for_retry(&self, retry: u32) -> Duration {
+        let multiplier = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_backoff
+            .checked_mul(multiplier)
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+
+    fn backoff_for_retry(&self, retry: u32) -> Duration {
+        self.max_backoff_for_retry(retry).mul_f64(fastrand::f64())
+    }
+}
+
+fn is_retryable<E: ProvideErrorKind>(err: &SdkError<E>) -> bool {
+    match err {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError { .. } => true,
+        SdkError::ServiceError { err, raw } => {
+            let status = raw.http().status();
+            err.retryable_error_kind().is_some()
+                || status.is_server_error()
+                || status.as_u16() == 429
+                || err
+                    .code()
+                    .is_some_and(|code| RETRYABLE_ERROR_CODES.contains(&code))
+        }
+        SdkError::ConstructionFailure(_) => false,
+    }
+}
+
+/// Whether AWS rejected the credentials, or they could not be loaded in the first place.
+pub(crate) fn is_credentials_error<E: ProvideErrorKind>(err: &SdkError<E>) -> bool {
+    match err {
+        SdkError::ServiceError { err, .. } => err
+            .code()
+            .is_some_and(|code| CREDENTIALS_ERROR_CODES.contains(&code)),
+        SdkError::ConstructionFailure(err) => {
+            let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err.as_ref());
+            while let Some(err) = source {
+                if err.is::<CredentialsStageError>() {
+                    return true;
+                }
+                source = err.source();
+            }
+            false
+        }
+        _ => false,
+    }
+}
+
+/// Whether CloudWatch Logs rejected the call because of the log group's class, e.g. GetLogEvents for a log group in
+/// the Infrequent Access class. The SDK does not have a separate error for this, so it is told apart by its message.
+pub(crate) fn is_unsupported_by_log_group_class<E: ProvideErrorKind + std::fmt::Display>(
+    err: &SdkError<E>,
+) -> bool {
+    match err {
+        SdkError::ServiceError { err, .. } => {
+            let message = err.to_string().to_ascii_lowercase();
+            err.code()
+                .is_some_and(|code| UNSUPPORTED_BY_LOG_GROUP_CLASS_ERROR_CODES.contains(&code))
+                && (message.contains("infrequent") || message.contains("log group class"))
+        }
+        _ => false,
+    }
+}
+
+/// Ways a call can fail that callers handle differently whichever call it was, e.g. to pick an errno.
+pub(crate) trait CallFailure {
+    /// CloudWatch Logs asked to slow down.
+    fn is_throttling(&self) -> bool;
+
+    /// The credentials are not allowed to make the call.
+    fn is_access_denied(&self) -> bool;
+
+    /// Connecting, reading the response or the whole call took longer than its timeout.
+    fn is_timeout(&self) -> bool;
+}
+
+impl<E: ProvideErrorKind> CallFailure for SdkError<E> {
+    fn is_throttling(&self) -> bool {
+        match self {
+            SdkError::ServiceError { err, raw } => {
+                raw.http().status().as_u16() == 429
+                    || err.retryable_error_kind() == Some(ErrorKind::ThrottlingError)
+                    || err
+                        .code()
+                        .is_some_and(|code| THROTTLING_ERROR_CODES.contains(&code))
+            }
+            _ => false,
+        }
+    }
+
+    fn is_access_denied(&self) -> bool {
+        match self {
+            SdkError::ServiceError { err, .. } => err
+                .code()
+                .is_some_and(|code| ACCESS_DENIED_ERROR_CODES.contains(&code)),
+            _ => false,
+        }
+    }
+
+    fn is_timeout(&self) -> bool {
+        match self {
+            SdkError::TimeoutError(_) => true,
+            SdkError::DispatchFailure(err) => err.is_timeout(),
+            _ => false,
+        }
+    }
+}
+
+/// Call `send` until it succeeds, fails with an error that is not worth retrying, or runs out of attempts.
+pub(crate) async fn with_retries<T, E, F, Fut>(policy: &RetryPolicy, mut send: F) -> Result<T, SdkError<E>>
+where
+    E: ProvideErrorKind,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, SdkError<E>>>,
+{
+    let mut attempt = 1;
+    loop {
+        match send().await {
+            Err(err) if attempt < policy.max_attempts && is_retryable(&err) => {
+                let backoff = policy.backoff_for_retry(attempt);
+                debug!("attempt {} failed, retrying in {:?}", attempt, backoff);
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    use aws_http::auth::CredentialsStageError;
+    use aws_sdk_cloudwatchlogs::error::FilterLogEventsError;
+    use aws_smithy_http::body::SdkBody;
+    use aws_smithy_http::operation;
+    use aws_smithy_http::result::SdkError;
+
+    use crate::retry::{
+        is_credentials_error, is_unsupported_by_log_group_class, with_retries, CallFailure, RetryPolicy,
+    };
+
+    fn fast_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+        }
+    }
+
+    #[test]
+    fn test_max_backoff_for_retry_doubles_up_to_max() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        assert_eq!(Duration::from_millis(100), policy.max_backoff_for_retry(1));
+        assert_eq!(Duration::from_millis(200), policy.max_backoff_for_retry(2));
+        assert_eq!(Duration::from_millis(400), policy.max_backoff_for_retry(3));
+        assert_eq!(Duration::from_millis(500), policy.max_backoff_for_retry(4));
+        assert_eq!(Duration::from_millis(500), policy.max_backoff_for_retry(100));
+        assert!(policy.backoff_for_retry(3) <= Duration::from_millis(400));
+    }
+
+    #[test]
+    fn test_with_retries_retries_timeouts() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<u32, SdkError<FilterLogEventsError>> =
+            tokio_test::block_on(with_retries(&fast_policy(3), || async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(SdkError::TimeoutError("timed out".into())),
+                    attempt => Ok(attempt),
+                }
+            }));
+        assert_eq!(1, result.unwrap());
+        assert_eq!(2, attempts.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_with_retries_gives_up() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), SdkError<FilterLogEventsError>> =
+            tokio_test::block_on(with_retries(&fast_policy(3), || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(SdkError::TimeoutError("timed out".into()))
+            }));
+        assert!(result.is_err());
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_is_credentials_error() {
+        let missing_credentials: SdkError<FilterLogEventsError> =
+            SdkError::ConstructionFailure(Box::new(CredentialsStageError::MissingCredentialsProvider));
+        assert!(is_credentials_error(&missing_credentials));
+        let invalid: SdkError<FilterLogEventsError> = SdkError::ConstructionFailure("invalid".into());
+        assert!(!is_credentials_error(&invalid));
+        let timeout: SdkError<FilterLogEventsError> = SdkError::TimeoutError("timed out".into());
+        assert!(!is_credentials_error(&timeout));
+    }
+
+    fn service_error(code: &str, message: &str) -> SdkError<FilterLogEventsError> {
+        let raw = http::Response::builder()
+            .status(400)
+            .body(SdkBody::empty())
+            .expect("response is valid");
+        SdkError::ServiceError {
+            err: FilterLogEventsError::generic(
+                aws_smithy_types::Error::builder()
+                    .code(code)
+                    .message(message)
+                    .build(),
+            ),
+            raw: operation::Response::new(raw),
+        }
+    }
+
+    #[test]
+    fn test_is_unsupported_by_log_group_class() {
+        assert!(is_unsupported_by_log_group_class(&service_error(
+            "InvalidOperationException",
+            "GetLogEvents is not supported for log groups in the INFREQUENT_ACCESS log group class",
+        )));
+        assert!(!is_unsupported_by_log_group_class(&service_error(
+            "InvalidParameterException",
+            "1 validation error detected: Value at 'limit' failed to satisfy constraint",
+        )));
+        assert!(!is_unsupported_by_log_group_class(&service_error(
+            "AccessDeniedException",
+            "User is not authorized to read log groups in the Infrequent Access class",
+        )));
+    }
+
+    #[test]
+    fn test_call_failure() {
+        let timeout: SdkError<FilterLogEventsError> = SdkError::TimeoutError("timed out".into());
+        assert!(timeout.is_timeout());
+        assert!(!timeout.is_throttling());
+        assert!(!timeout.is_access_denied());
+        let invalid: SdkError<FilterLogEventsError> = SdkError::ConstructionFailure("invalid".into());
+        assert!(!invalid.is_timeout());
+    }
+
+    #[test]
+    fn test_with_retries_does_not_retry_construction_failures() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), SdkError<FilterLogEventsError>> =
+            tokio_test::block_on(with_retries(&fast_policy(3), || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(SdkError::ConstructionFailure("invalid".into()))
+            }));
+        assert!(result.is_err());
+        assert_eq!(1, attempts.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,122 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use std::fmt;
+use std::sync::Arc;
+
+use format_cwl_log_event::FilteredLogEvent;
+
+/// Changes events after they are fetched and before they are formatted, e.g. to redact secrets from messages or to
+/// pull the level out of JSON messages.
+///
+/// Events are ordered and deduplicated by their timestamp, log stream name and event ID, so transformers should leave
+/// those alone.
+pub trait LogEventTransformer: Send + Sync {
+    /// Short name that identifies the transformer and its settings. Rendered files are cached on disk by it, so it
+    /// should change whenever the transformer would change events differently.
+    fn name(&self) -> String;
+
+    /// The transformed event, or None to leave the event out.
+    fn transform(&self, event: FilteredLogEvent) -> Option<FilteredLogEvent>;
+}
+
+/// Transformers that are applied one after the other to every event a mount reads. Empty by default, which leaves
+/// events as they are.
+#[derive(Clone, Default)]
+pub struct TransformerChain {
+    transformers: Vec<Arc<dyn LogEventTransformer>>,
+}
+
+impl TransformerChain {
+    pub fn new() -> Self {
+        TransformerChain::default()
+    }
+
+    /// This chain followed by `transformer`.
+    pub fn with<T: LogEventTransformer + 'static>(mut self, transformer: T) -> Self {
+        self.transformers.push(Arc::new(transformer));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transformers.is_empty()
+    }
+
+    /// Names of the transformers in the order they are applied.
+    pub fn names(&self) -> Vec<String> {
+        self.transformers
+            .iter()
+            .map(|transformer| transformer.name())
+            .collect()
+    }
+
+    /// Apply every transformer in turn, stopping as soon as one leaves the event out.
+    pub fn apply(&self, event: FilteredLogEvent) -> Option<FilteredLogEvent> {
+        self.transformers
+            .iter()
+            .try_fold(event, |event, transformer| transformer.transform(event))
+    }
+}
+
+impl fmt::Debug for TransformerChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use format_cwl_log_event::FilteredLogEvent;
+
+    use crate::transform::{LogEventTransformer, TransformerChain};
+
+    struct Redact(&'static str);
+
+    impl LogEventTransformer for Redact {
+        fn name(&self) -> String {
+            format!("redact({})", self.0)
+        }
+
+        fn transform(&self, mut event: FilteredLogEvent) -> Option<FilteredLogEvent> {
+            event.message = event.message.replace(self.0, "***");
+            Some(event)
+        }
+    }
+
+    struct DropEmpty;
+
+    impl LogEventTransformer for DropEmpty {
+        fn name(&self) -> String {
+            "drop-empty".to_string()
+        }
+
+        fn transform(&self, event: FilteredLogEvent) -> Option<FilteredLogEvent> {
+            Some(event).filter(|event| !event.message.trim().is_empty())
+        }
+    }
+
+    #[test]
+    fn test_transformer_chain_applies_transformers_in_order() {
+        let event = |message: &str| {
+            let timestamp = Utc.ymd(2021, 12, 4).and_hms(0, 0, 0);
+            FilteredLogEvent::new("group", "1", timestamp, "stream", message, timestamp)
+        };
+        let chain = TransformerChain::new().with(Redact("hunter2")).with(DropEmpty);
+        assert_eq!(
+            Some("password=***".to_string()),
+            chain.apply(event("password=hunter2")).map(|event| event.message)
+        );
+        assert!(chain.apply(event(" ")).is_none());
+        assert_eq!("[\"redact(hunter2)\", \"drop-empty\"]", format!("{:?}", chain));
+
+        let empty = TransformerChain::new();
+        assert!(empty.is_empty());
+        assert_eq!(
+            Some(" ".to_string()),
+            empty.apply(event(" ")).map(|event| event.message)
+        );
+    }
+}
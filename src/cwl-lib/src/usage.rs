@@ -0,0 +1,157 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::rate_limit::ApiAction;
+
+/// What has been asked of CloudWatch Logs so far, to tell what reading logs costs. CloudWatch Logs charges for the
+/// bytes that Insights queries scan [1], and API calls count against quotas.
+///
+/// [1] https://aws.amazon.com/cloudwatch/pricing/
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UsageStats {
+    /// Calls made to each API, counting every retry.
+    pub api_calls: BTreeMap<ApiAction, u64>,
+
+    /// Log events returned by FilterLogEvents and GetLogEvents.
+    pub events_returned: u64,
+
+    pub insights_records_scanned: u64,
+    pub insights_bytes_scanned: u64,
+
+    /// Reads of rendered log files answered from memory, from the disk cache, and by fetching events.
+    pub memory_cache_hits: u64,
+    pub disk_cache_hits: u64,
+    pub cache_misses: u64,
+}
+
+impl UsageStats {
+    pub fn total_api_calls(&self) -> u64 {
+        self.api_calls.values().sum()
+    }
+
+    /// Share of reads answered from either cache, or None before the first read.
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let hits = self.memory_cache_hits + self.disk_cache_hits;
+        match hits + self.cache_misses {
+            0 => None,
+            reads => Some(hits as f64 / reads as f64),
+        }
+    }
+
+    /// Add `other` to these stats, e.g. to total the usage of several regions.
+    pub fn add(&mut self, other: &UsageStats) {
+        for (action, calls) in &other.api_calls {
+            *self.api_calls.entry(*action).or_default() += calls;
+        }
+        self.events_returned += other.events_returned;
+        self.insights_records_scanned += other.insights_records_scanned;
+        self.insights_bytes_scanned += other.insights_bytes_scanned;
+        self.memory_cache_hits += other.memory_cache_hits;
+        self.disk_cache_hits += other.disk_cache_hits;
+        self.cache_misses += other.cache_misses;
+    }
+}
+
+/// Counters behind `UsageStats` that calls update as they go.
+#[derive(Debug, Default)]
+pub(crate) struct Usage {
+    api_calls: [AtomicU64; ApiAction::ALL.len()],
+    events_returned: AtomicU64,
+    insights_records_scanned: AtomicU64,
+    insights_bytes_scanned: AtomicU64,
+    memory_cache_hits: AtomicU64,
+    disk_cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl Usage {
+    pub(crate) fn record_call(&self, action: ApiAction) {
+        let index = ApiAction::ALL
+            .iter()
+            .position(|other| *other == action)
+            .expect("every action is in ALL");
+        self.api_calls[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_events(&self, events: usize) {
+        self.events_returned.fetch_add(events as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_insights_scan(&self, records_scanned: f64, bytes_scanned: f64) {
+        self.insights_records_scanned
+            .fetch_add(records_scanned as u64, Ordering::Relaxed);
+        self.insights_bytes_scanned
+            .fetch_add(bytes_scanned as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_memory_cache_hit(&self) {
+        self.memory_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_disk_cache_hit(&self) {
+        self.disk_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> UsageStats {
+        UsageStats {
+            api_calls: ApiAction::ALL
+                .iter()
+                .zip(&self.api_calls)
+                .map(|(action, calls)| (*action, calls.load(Ordering::Relaxed)))
+                .filter(|(_, calls)| *calls > 0)
+                .collect(),
+            events_returned: self.events_returned.load(Ordering::Relaxed),
+            insights_records_scanned: self.insights_records_scanned.load(Ordering::Relaxed),
+            insights_bytes_scanned: self.insights_bytes_scanned.load(Ordering::Relaxed),
+            memory_cache_hits: self.memory_cache_hits.load(Ordering::Relaxed),
+            disk_cache_hits: self.disk_cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rate_limit::ApiAction;
+    use crate::usage::{Usage, UsageStats};
+
+    #[test]
+    fn test_usage_stats() {
+        let usage = Usage::default();
+        usage.record_call(ApiAction::FilterLogEvents);
+        usage.record_call(ApiAction::FilterLogEvents);
+        usage.record_call(ApiAction::StartQuery);
+        usage.record_events(42);
+        usage.record_insights_scan(10.0, 2048.0);
+        assert_eq!(None, usage.stats().cache_hit_rate());
+        usage.record_memory_cache_hit();
+        usage.record_disk_cache_hit();
+        usage.record_memory_cache_hit();
+        usage.record_cache_miss();
+        let stats = usage.stats();
+        assert_eq!(Some(0.75), stats.cache_hit_rate());
+        assert_eq!(Some(&2), stats.api_calls.get(&ApiAction::FilterLogEvents));
+        assert_eq!(None, stats.api_calls.get(&ApiAction::GetLogEvents));
+        assert_eq!(3, stats.total_api_calls());
+        assert_eq!(42, stats.events_returned);
+        assert_eq!(2048, stats.insights_bytes_scanned);
+
+        let mut total = UsageStats::default();
+        total.add(&stats);
+        total.add(&stats);
+        assert_eq!(Some(&4), total.api_calls.get(&ApiAction::FilterLogEvents));
+        assert_eq!(84, total.events_returned);
+        assert_eq!(20, total.insights_records_scanned);
+        assert_eq!(4, total.memory_cache_hits);
+        assert_eq!(Some(0.75), total.cache_hit_rate());
+    }
+}
@@ -1,6 +1,8 @@
+use chrono::format::{Item, StrftimeItems};
 use chrono::DateTime;
 use chrono::SecondsFormat;
 use chrono::Utc;
+use chrono_tz::Tz;
 use pest::Parser;
 
 include!(concat!(env!("OUT_DIR"), "/format_cwl_log_event_parser.rs"));
@@ -8,11 +10,23 @@ include!(concat!(env!("OUT_DIR"), "/format_cwl_log_event_parser.rs"));
 #[derive(thiserror::Error, Debug)]
 pub enum FormatCwlLogEventError {
     #[error(transparent)]
-    CompileError(#[from] pest::error::Error<Rule>),
+    CompileError(#[from] Box<pest::error::Error<Rule>>),
 
-    #[error("unknown format variable '{0}', choose one from 'log_group_name', 'event_id', 'ingestion_time', 'log_stream_name', 'message', 'timestamp'")]
+    #[error("unknown format variable '{0}', choose one from 'log_group_name', 'event_id', 'ingestion_time', 'log_stream_name', 'message', 'timestamp', 'region', 'account_id'")]
     UnknownFormatVariable(String),
 
+    #[error("'{0}' isn't a time, only 'timestamp' and 'ingestion_time' can be given a time zone or format")]
+    NotATime(String),
+
+    #[error("'{0}' isn't a valid time format, use strftime specifiers like %Y-%m-%d %H:%M:%S%.3f")]
+    InvalidTimeFormat(String),
+
+    #[error("unknown time zone '{0}', use an IANA name like America/New_York or UTC")]
+    UnknownTimeZone(String),
+
+    #[error("'{0}' lists no columns, list them after the colon like csv:timestamp,message or leave out the colon for all of them")]
+    NoColumns(String),
+
     #[error("unknown format error")]
     Unknown,
 }
@@ -26,6 +40,12 @@ pub struct FilteredLogEvent {
     pub message: String,
     pub timestamp: DateTime<Utc>,
 
+    /// AWS region the event was read from, if known.
+    pub region: Option<String>,
+
+    /// AWS account the event was read from, if known.
+    pub account_id: Option<String>,
+
     ingestion_time_rfc3339: String,
     timestamp_rfc3339: String,
 }
@@ -48,6 +68,8 @@ impl FilteredLogEvent {
             message: message.into(),
             timestamp,
             timestamp_rfc3339: timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+            region: None,
+            account_id: None,
         }
     }
 }
@@ -60,6 +82,8 @@ enum FilteredLogEventVariable {
     LogStreamName,
     Message,
     Timestamp,
+    Region,
+    AccountId,
 }
 
 impl TryFrom<&str> for FilteredLogEventVariable {
@@ -73,6 +97,8 @@ impl TryFrom<&str> for FilteredLogEventVariable {
             "log_stream_name" => Ok(FilteredLogEventVariable::LogStreamName),
             "message" => Ok(FilteredLogEventVariable::Message),
             "timestamp" => Ok(FilteredLogEventVariable::Timestamp),
+            "region" => Ok(FilteredLogEventVariable::Region),
+            "account_id" => Ok(FilteredLogEventVariable::AccountId),
             _ => Err(FormatCwlLogEventError::UnknownFormatVariable(String::from(value))),
         }
     }
@@ -82,17 +108,178 @@ impl TryFrom<&str> for FilteredLogEventVariable {
 enum FormatValue<T> {
     EscapedDelimeter,
     Variable(T),
+
+    /// A time variable with a time zone or strftime format.
+    FormattedVariable(T, TimeFormat),
     Literal(String),
 }
 
+/// How to show a time, in UTC and as RFC 3339 with milliseconds unless given otherwise.
+#[derive(PartialEq, Hash, Clone, Debug, Eq)]
+struct TimeFormat {
+    time_zone: Tz,
+    format: Option<String>,
+}
+
+impl TimeFormat {
+    fn format(&self, time: DateTime<Utc>) -> String {
+        let time = time.with_timezone(&self.time_zone);
+        match &self.format {
+            Some(format) => time.format(format).to_string(),
+            None => time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        }
+    }
+}
+
+/// Output format that writes each event as a JSON object on one line, rather than a template.
+pub const JSON_OUTPUT_FORMAT: &str = "json";
+
+/// Output format that writes each event as logfmt, e.g. `ts=2021-12-04T00:00:00.000Z stream=web-1 msg="GET /"`.
+pub const LOGFMT_OUTPUT_FORMAT: &str = "logfmt";
+
+/// Columns of the `csv` and `tsv` output formats when none are listed, in the same order as the fields of `json`.
+const DEFAULT_COLUMNS: [FilteredLogEventVariable; 8] = [
+    FilteredLogEventVariable::LogGroupName,
+    FilteredLogEventVariable::LogStreamName,
+    FilteredLogEventVariable::EventId,
+    FilteredLogEventVariable::Timestamp,
+    FilteredLogEventVariable::IngestionTime,
+    FilteredLogEventVariable::Message,
+    FilteredLogEventVariable::Region,
+    FilteredLogEventVariable::AccountId,
+];
+
+/// An event as `JSON_OUTPUT_FORMAT` writes it.
+#[derive(serde::Serialize)]
+struct JsonEvent<'a> {
+    log_group_name: &'a str,
+    log_stream_name: &'a str,
+    event_id: &'a str,
+    timestamp: &'a str,
+    ingestion_time: &'a str,
+    message: &'a str,
+    region: Option<&'a str>,
+    account_id: Option<&'a str>,
+}
+
+#[derive(Clone, Debug, PartialEq, Hash, Eq)]
+enum Format {
+    Template(Vec<FormatValue<FilteredLogEventVariable>>),
+    Json,
+    Logfmt,
+
+    /// A line of values separated by `delimiter` and quoted as RFC 4180 requires.
+    Delimited {
+        delimiter: char,
+        columns: Vec<FilteredLogEventVariable>,
+    },
+}
+
+impl FilteredLogEventVariable {
+    fn value<'a>(&self, event: &'a FilteredLogEvent) -> &'a str {
+        match self {
+            FilteredLogEventVariable::LogGroupName => &event.log_group_name,
+            FilteredLogEventVariable::EventId => &event.event_id,
+            FilteredLogEventVariable::IngestionTime => &event.ingestion_time_rfc3339,
+            FilteredLogEventVariable::LogStreamName => &event.log_stream_name,
+            FilteredLogEventVariable::Message => &event.message,
+            FilteredLogEventVariable::Timestamp => &event.timestamp_rfc3339,
+            FilteredLogEventVariable::Region => event.region.as_deref().unwrap_or(""),
+            FilteredLogEventVariable::AccountId => event.account_id.as_deref().unwrap_or(""),
+        }
+    }
+}
+
+/// `value` as a logfmt value, in double quotes with quotes, backslashes and control characters escaped if it is
+/// empty or contains a space, `=`, a quote or a control character.
+fn logfmt_value(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c <= ' ' || c == '=' || c == '"' || c.is_control()) {
+        return value.to_string();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// `value` as a field separated by `delimiter`, in double quotes if it contains the delimiter, a quote or a line
+/// break, with quotes doubled.
+fn quote_field(value: &str, delimiter: char) -> String {
+    if value.contains([delimiter, '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Hash, Eq)]
 pub struct LogFormatter {
-    instructions: Vec<FormatValue<FilteredLogEventVariable>>,
+    format: Format,
+
+    /// What `new` was given, see `as_str`.
+    source: String,
 }
 
 impl LogFormatter {
+    /// Formatter for a template like `[${log_stream_name}] ${message}`, or `json` for one JSON object per event with
+    /// every field, escaped as JSON needs. `logfmt` writes the timestamp as `ts`, the log group as `group`, the log
+    /// stream as `stream`, the region and account as `region` and `account` if they are known, and the message as
+    /// `msg`. `csv` and `tsv` write every field as comma- or tab-separated values, or
+    /// the fields listed after a colon, e.g. `csv:timestamp,message`.
     pub fn new(format: impl AsRef<str>) -> Result<LogFormatter, FormatCwlLogEventError> {
-        let parser = FormatCwlLogEventParser::parse(Rule::format, format.as_ref())?;
+        Ok(Self {
+            format: Self::parse(format.as_ref())?,
+            source: format.as_ref().to_string(),
+        })
+    }
+
+    /// The format string this formatter was made from, e.g. to tell formatters apart across runs.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    fn parse(format: &str) -> Result<Format, FormatCwlLogEventError> {
+        if format == JSON_OUTPUT_FORMAT {
+            return Ok(Format::Json);
+        }
+        if format == LOGFMT_OUTPUT_FORMAT {
+            return Ok(Format::Logfmt);
+        }
+        let (preset, columns) = match format.split_once(':') {
+            Some((preset, columns)) => (preset, Some(columns)),
+            None => (format, None),
+        };
+        let delimiter = match preset {
+            "csv" => Some(','),
+            "tsv" => Some('\t'),
+            _ => None,
+        };
+        if let Some(delimiter) = delimiter {
+            let columns = match columns {
+                Some(columns) => columns
+                    .split(',')
+                    .filter(|column| !column.trim().is_empty())
+                    .map(|column| FilteredLogEventVariable::try_from(column.trim()))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => DEFAULT_COLUMNS.to_vec(),
+            };
+            if columns.is_empty() {
+                return Err(FormatCwlLogEventError::NoColumns(format.to_string()));
+            }
+            return Ok(Format::Delimited { delimiter, columns });
+        }
+        let parser = FormatCwlLogEventParser::parse(Rule::format, format).map_err(Box::new)?;
         let mut instructions = vec![];
         for pair in parser.into_iter() {
             match pair.as_rule() {
@@ -101,9 +288,44 @@ impl LogFormatter {
                     instructions.push(value);
                 }
                 Rule::variable => {
-                    let identifier = pair.into_inner().next().unwrap().as_str();
+                    let mut inner = pair.into_inner();
+                    let identifier = inner.next().unwrap().as_str();
                     let variable = identifier.try_into()?;
-                    let value = FormatValue::Variable(variable);
+                    let modified = inner.peek().is_some();
+                    let mut time_format = TimeFormat {
+                        time_zone: Tz::UTC,
+                        format: None,
+                    };
+                    for modifier in inner {
+                        match modifier.as_rule() {
+                            Rule::time_zone => {
+                                time_format.time_zone = modifier.as_str().parse().map_err(|_| {
+                                    FormatCwlLogEventError::UnknownTimeZone(modifier.as_str().to_string())
+                                })?;
+                            }
+                            Rule::time_format => {
+                                // Invalid specifiers would only fail once an event is formatted.
+                                let format = modifier.as_str();
+                                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                                    return Err(FormatCwlLogEventError::InvalidTimeFormat(
+                                        format.to_string(),
+                                    ));
+                                }
+                                time_format.format = Some(format.to_string());
+                            }
+                            _ => unreachable!(),
+                        }
+                    }
+                    let value = if !modified {
+                        FormatValue::Variable(variable)
+                    } else if matches!(
+                        variable,
+                        FilteredLogEventVariable::Timestamp | FilteredLogEventVariable::IngestionTime
+                    ) {
+                        FormatValue::FormattedVariable(variable, time_format)
+                    } else {
+                        return Err(FormatCwlLogEventError::NotATime(identifier.to_string()));
+                    };
                     instructions.push(value);
                 }
                 Rule::literal => {
@@ -115,24 +337,66 @@ impl LogFormatter {
             }
         }
 
-        Ok(Self { instructions })
+        Ok(Format::Template(instructions))
     }
 
     pub fn format(&self, event: FilteredLogEvent) -> String {
+        let instructions = match &self.format {
+            Format::Template(instructions) => instructions,
+            Format::Logfmt => {
+                let mut fields = vec![
+                    ("ts", event.timestamp_rfc3339.as_str()),
+                    ("group", &event.log_group_name),
+                    ("stream", &event.log_stream_name),
+                ];
+                if let Some(region) = &event.region {
+                    fields.push(("region", region));
+                }
+                if let Some(account_id) = &event.account_id {
+                    fields.push(("account", account_id));
+                }
+                fields.push(("msg", &event.message));
+                let fields: Vec<String> = fields
+                    .into_iter()
+                    .map(|(key, value)| format!("{}={}", key, logfmt_value(value)))
+                    .collect();
+                return fields.join(" ");
+            }
+            Format::Delimited { delimiter, columns } => {
+                let fields: Vec<String> = columns
+                    .iter()
+                    .map(|column| quote_field(column.value(&event), *delimiter))
+                    .collect();
+                return fields.join(&delimiter.to_string());
+            }
+            Format::Json => {
+                let event = JsonEvent {
+                    log_group_name: &event.log_group_name,
+                    log_stream_name: &event.log_stream_name,
+                    event_id: &event.event_id,
+                    timestamp: &event.timestamp_rfc3339,
+                    ingestion_time: &event.ingestion_time_rfc3339,
+                    message: &event.message,
+                    region: event.region.as_deref(),
+                    account_id: event.account_id.as_deref(),
+                };
+                return serde_json::to_string(&event).expect("events serialize to JSON");
+            }
+        };
         let mut output = String::with_capacity(128);
-        for instruction in self.instructions.iter() {
-            output.push_str(match instruction {
-                FormatValue::EscapedDelimeter => "$",
-                FormatValue::Variable(identifier) => match identifier {
-                    FilteredLogEventVariable::LogGroupName => &event.log_group_name,
-                    FilteredLogEventVariable::EventId => &event.event_id,
-                    FilteredLogEventVariable::IngestionTime => &event.ingestion_time_rfc3339,
-                    FilteredLogEventVariable::LogStreamName => &event.log_stream_name,
-                    FilteredLogEventVariable::Message => &event.message,
-                    FilteredLogEventVariable::Timestamp => &event.timestamp_rfc3339,
-                },
-                FormatValue::Literal(value) => value,
-            });
+        for instruction in instructions.iter() {
+            match instruction {
+                FormatValue::EscapedDelimeter => output.push('$'),
+                FormatValue::Variable(identifier) => output.push_str(identifier.value(&event)),
+                FormatValue::FormattedVariable(identifier, time_format) => {
+                    let time = match identifier {
+                        FilteredLogEventVariable::IngestionTime => event.ingestion_time,
+                        _ => event.timestamp,
+                    };
+                    output.push_str(&time_format.format(time));
+                }
+                FormatValue::Literal(value) => output.push_str(value),
+            }
         }
         output
     }
@@ -167,14 +431,16 @@ mod tests {
 
     #[test]
     fn default_format_passes() {
-        let formatter = LogFormatter::new("[${log_stream_name}] ${message}").expect("default format should pass");
+        let formatter =
+            LogFormatter::new("[${log_stream_name}] ${message}").expect("default format should pass");
         let actual_output = formatter.format(get_test_event_1());
         assert_eq!("[log-stream-name] message", actual_output);
     }
 
     #[test]
     fn default_format_without_braces_passes() {
-        let formatter = LogFormatter::new("[$log_stream_name] $message").expect("default format without braces should pass");
+        let formatter = LogFormatter::new("[$log_stream_name] $message")
+            .expect("default format without braces should pass");
         let actual_output = formatter.format(get_test_event_1());
         assert_eq!("[log-stream-name] message", actual_output);
     }
@@ -186,6 +452,134 @@ mod tests {
         assert_eq!("2014-07-08T09:10:10.789Z - message", actual_output);
     }
 
+    #[test]
+    fn timestamp_with_time_format_passes() {
+        let formatter =
+            LogFormatter::new("${timestamp:%Y-%m-%d %H:%M:%S%.3f} [${ingestion_time:%s}] $message")
+                .expect("time format should pass");
+        assert_eq!(
+            "2014-07-08 09:10:10.789 [1404810611] message",
+            formatter.format(get_test_event_1())
+        );
+    }
+
+    #[test]
+    fn invalid_time_formats_fail() {
+        assert!(LogFormatter::new("${timestamp:}").is_err());
+        assert!(LogFormatter::new("${timestamp:%Q}").is_err());
+        assert!(LogFormatter::new("${message:%H}").is_err());
+        assert!(LogFormatter::new("$timestamp:%H").is_ok());
+    }
+
+    #[test]
+    fn timestamp_with_time_zone_passes() {
+        let formatter = LogFormatter::new(
+            "${timestamp@America/New_York} ${ingestion_time@Asia/Kolkata:%H:%M:%S %Z} ${timestamp@UTC:%H}",
+        )
+        .expect("time zone should pass");
+        assert_eq!(
+            "2014-07-08T05:10:10.789-04:00 14:40:11 IST 09",
+            formatter.format(get_test_event_1())
+        );
+    }
+
+    #[test]
+    fn invalid_time_zones_fail() {
+        assert!(LogFormatter::new("${timestamp@America/Springfield}").is_err());
+        assert!(LogFormatter::new("${timestamp@}").is_err());
+        assert!(LogFormatter::new("${message@Europe/London}").is_err());
+        assert!(LogFormatter::new("${message@UTC}").is_err());
+        assert!(LogFormatter::new("${timestamp:%H@Europe/London}").is_ok());
+    }
+
+    #[test]
+    fn region_format_passes() {
+        let formatter = LogFormatter::new("[$region] $message").expect("region format should pass");
+        let mut event = get_test_event_1();
+        assert_eq!("[] message", formatter.format(event.clone()));
+        event.region = Some("us-west-2".to_string());
+        assert_eq!("[us-west-2] message", formatter.format(event));
+    }
+
+    #[test]
+    fn account_id_format_passes() {
+        let formatter =
+            LogFormatter::new("[${account_id}/${region}] $message").expect("account format should pass");
+        let mut event = get_test_event_1();
+        event.region = Some("us-west-2".to_string());
+        event.account_id = Some("123456789012".to_string());
+        assert_eq!("[123456789012/us-west-2] message", formatter.format(event));
+    }
+
+    #[test]
+    fn json_format_escapes_every_field() {
+        let formatter = LogFormatter::new("json").expect("json format should pass");
+        let mut event = get_test_event_1();
+        event.message = "line \"one\"\n\tline two \\ \u{1}".to_string();
+        event.region = Some("us-west-2".to_string());
+        assert_eq!(
+            concat!(
+                r#"{"log_group_name":"/aws/logs/log-group","log_stream_name":"log-stream-name","event_id":"event-id","#,
+                r#""timestamp":"2014-07-08T09:10:10.789Z","ingestion_time":"2014-07-08T09:10:11.123Z","#,
+                r#""message":"line \"one\"\n\tline two \\ \u0001","region":"us-west-2","account_id":null}"#
+            ),
+            formatter.format(event)
+        );
+    }
+
+    #[test]
+    fn logfmt_format_escapes_values_that_need_it() {
+        let formatter = LogFormatter::new("logfmt").expect("logfmt format should pass");
+        let mut event = get_test_event_1();
+        assert_eq!(
+            "ts=2014-07-08T09:10:10.789Z group=/aws/logs/log-group stream=log-stream-name msg=message",
+            formatter.format(event.clone())
+        );
+        event.message = "level=info said \"hi\"\\\nbye".to_string();
+        event.region = Some("us-west-2".to_string());
+        assert_eq!(
+            concat!(
+                "ts=2014-07-08T09:10:10.789Z group=/aws/logs/log-group stream=log-stream-name region=us-west-2 ",
+                r#"msg="level=info said \"hi\"\\\nbye""#
+            ),
+            formatter.format(event.clone())
+        );
+        event.message = String::new();
+        assert!(formatter.format(event).ends_with(r#" msg="""#));
+    }
+
+    #[test]
+    fn csv_format_quotes_fields_that_need_it() {
+        let formatter = LogFormatter::new("csv").expect("csv format should pass");
+        let mut event = get_test_event_1();
+        event.message = "said \"hi\", twice\nthen left".to_string();
+        assert_eq!(
+            concat!(
+                "/aws/logs/log-group,log-stream-name,event-id,2014-07-08T09:10:10.789Z,2014-07-08T09:10:11.123Z,",
+                "\"said \"\"hi\"\", twice\nthen left\",,"
+            ),
+            formatter.format(event)
+        );
+    }
+
+    #[test]
+    fn tsv_format_with_columns_passes() {
+        let formatter = LogFormatter::new("tsv:timestamp, message").expect("tsv format should pass");
+        let mut event = get_test_event_1();
+        assert_eq!(
+            "2014-07-08T09:10:10.789Z\tmessage",
+            formatter.format(event.clone())
+        );
+        event.message = "a\tb, c".to_string();
+        assert_eq!("2014-07-08T09:10:10.789Z\t\"a\tb, c\"", formatter.format(event));
+    }
+
+    #[test]
+    fn csv_format_with_bad_columns_fails() {
+        assert!(LogFormatter::new("csv:").is_err());
+        assert!(LogFormatter::new("csv:timestamp,level").is_err());
+    }
+
     #[test]
     fn just_escaped_delimiter_passes() {
         let formatter = LogFormatter::new("$$").expect("escaped delimiter should pass");
@@ -3,16 +3,17 @@
  * SPDX-License-Identifier: Apache-2.0.
  */
 
- #[macro_use]
+#[macro_use]
 extern crate derivative;
 
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::sync::Arc;
 
+use bytes::Bytes;
 use chrono::DateTime;
 use chrono::Datelike;
 use chrono::Duration;
-use chrono::TimeZone;
 use chrono::Utc;
 use slotmap::new_key_type;
 use slotmap::SlotMap;
@@ -49,12 +50,7 @@ pub struct File {
 }
 
 impl File {
-    pub fn new<T: Into<String>>(
-        inode: u64,
-        name: T,
-        file_type: FileType,
-        parent: Option<FileKey>,
-    ) -> Self {
+    pub fn new<T: Into<String>>(inode: u64, name: T, file_type: FileType, parent: Option<FileKey>) -> Self {
         Self {
             inode,
             name: name.into(),
@@ -75,9 +71,9 @@ pub struct FileWithFileKey<'a> {
     pub file_key: FileKey,
 }
 
-impl<'a> Into<FileKey> for &FileWithFileKey<'a> {
-    fn into(self) -> FileKey {
-        self.file_key
+impl<'a> From<&FileWithFileKey<'a>> for FileKey {
+    fn from(file: &FileWithFileKey<'a>) -> Self {
+        file.file_key
     }
 }
 
@@ -87,6 +83,9 @@ pub struct FileTree {
     root: Option<FileKey>,
     current_inode: u64,
     inode_to_file_key: HashMap<u64, FileKey>,
+
+    /// If true each child of the root is a directory named after an encoded log group name.
+    log_group_directories: bool,
 }
 
 impl FileTree {
@@ -99,6 +98,7 @@ impl FileTree {
             current_inode: 1,
 
             inode_to_file_key: HashMap::with_capacity(expected_number_of_files),
+            log_group_directories: false,
         };
         let root = file_tree.create_directory("", None);
         file_tree.root = Some(root);
@@ -114,28 +114,29 @@ impl FileTree {
         self._create_file(name, FileType::File(time_bounds), parent)
     }
 
-    pub fn create_directory<T: Into<String>>(
-        &mut self,
-        name: T,
-        parent: Option<FileKey>,
-    ) -> FileKey {
+    pub fn create_directory<T: Into<String>>(&mut self, name: T, parent: Option<FileKey>) -> FileKey {
         self._create_file(name, FileType::Directory, parent)
     }
 
+    /// The largest inode used by the tree. Inodes above this are free for use by [`VirtualFiles`].
+    pub fn max_inode(&self) -> u64 {
+        self.current_inode - 1
+    }
+
     pub fn get_root(&self) -> Option<FileKey> {
         self.root
     }
 
-    pub fn list_root(&self) -> Vec<FileWithFileKey> {
+    pub fn list_root(&self) -> Vec<FileWithFileKey<'_>> {
         self._list_directory(self.root.unwrap()).collect()
     }
 
-    pub fn list_directory<F: Into<FileKey>>(&self, directory: F) -> Vec<FileWithFileKey> {
+    pub fn list_directory<F: Into<FileKey>>(&self, directory: F) -> Vec<FileWithFileKey<'_>> {
         self._list_directory(directory.into()).collect()
     }
 
     /// For the purposes of listing a directory get the parent of a file. If it is the root return itself.
-    pub fn get_parent_for_ls(&self, file: FileKey) -> FileWithFileKey {
+    pub fn get_parent_for_ls(&self, file: FileKey) -> FileWithFileKey<'_> {
         let file = self.sm.get(file).unwrap();
         if let Some(parent_file_key) = file.parent {
             self._create_file_with_file_key(&parent_file_key)
@@ -144,38 +145,92 @@ impl FileTree {
         }
     }
 
-    pub fn get_child_for_inode<T: Into<String>>(&self, parent: u64, filename: T) -> Option<FileWithFileKey> {
-        let directory = self.get_file_by_inode(parent);
-        if directory.is_none() {
+    pub fn get_child_for_inode<T: Into<String>>(
+        &self,
+        parent: u64,
+        filename: T,
+    ) -> Option<FileWithFileKey<'_>> {
+        let directory = self.get_file_by_inode(parent)?;
+        directory
+            .file
+            .children
+            .get(&filename.into())
+            .map(|child| self._create_file_with_file_key(child))
+    }
+
+    /// If the tree has per log group directories get the decoded log group name that a file belongs to.
+    pub fn get_log_group_name(&self, file: FileKey) -> Option<String> {
+        if !self.log_group_directories {
             return None;
         }
-        let directory = directory.unwrap();
-        match directory.file.children.get(&filename.into()) {
-            Some(child) => Some(self._create_file_with_file_key(&child)),
-            None => None,
+        let mut file = self.sm.get(file)?;
+        while let Some(parent_file_key) = file.parent {
+            if Some(parent_file_key) == self.root {
+                return decode_log_group_name(&file.name);
+            }
+            file = self.sm.get(parent_file_key)?;
         }
+        None
     }
 
-    pub fn get_file_by_inode(&self, inode: u64) -> Option<FileWithFileKey> {
+    pub fn get_file_by_inode(&self, inode: u64) -> Option<FileWithFileKey<'_>> {
         self.inode_to_file_key
             .get(&inode)
             .map(|file_key| self._create_file_with_file_key(file_key))
     }
 
-    fn _create_file_with_file_key(&self, file_key: &FileKey) -> FileWithFileKey {
+    /// Files whose time bounds overlap `[start_time, end_time)`, in path order, along with their paths relative to
+    /// the root, e.g. `2021/12/04/00-00`.
+    pub fn files_in_time_range(
+        &self,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Vec<(String, FileWithFileKey<'_>)> {
+        let mut files = vec![];
+        self._collect_files(self.root.unwrap(), "", start_time, end_time, &mut files);
+        files
+    }
+
+    fn _collect_files<'a>(
+        &'a self,
+        directory: FileKey,
+        path: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        files: &mut Vec<(String, FileWithFileKey<'a>)>,
+    ) {
+        for child in self._list_directory(directory) {
+            let child_path = if path.is_empty() {
+                child.file.name.clone()
+            } else {
+                format!("{}/{}", path, child.file.name)
+            };
+            match &child.file.file_type {
+                FileType::Directory => {
+                    self._collect_files(child.file_key, &child_path, start_time, end_time, files)
+                }
+                FileType::File(time_bounds) => {
+                    if time_bounds.end_time >= start_time && time_bounds.start_time < end_time {
+                        files.push((child_path, child));
+                    }
+                }
+            }
+        }
+    }
+
+    fn _create_file_with_file_key(&self, file_key: &FileKey) -> FileWithFileKey<'_> {
         FileWithFileKey {
             file: self.sm.get(*file_key).unwrap(),
             file_key: *file_key,
         }
     }
 
-    fn _list_directory(&self, directory: FileKey) -> Box<dyn Iterator<Item = FileWithFileKey> + '_> {
+    fn _list_directory(&self, directory: FileKey) -> Box<dyn Iterator<Item = FileWithFileKey<'_>> + '_> {
         let directory = self.sm.get(directory).unwrap();
         Box::new(
             directory
                 .children
                 .values()
-                .into_iter()
                 .map(|file_key| self._create_file_with_file_key(file_key)),
         )
     }
@@ -193,12 +248,9 @@ impl FileTree {
                 return *child;
             }
         }
-        let key = self.sm.insert(File::new(
-            self.current_inode,
-            name.clone(),
-            file_type,
-            parent,
-        ));
+        let key = self
+            .sm
+            .insert(File::new(self.current_inode, name.clone(), file_type, parent));
         self.inode_to_file_key.insert(self.current_inode, key);
         self.current_inode += 1;
         if let Some(parent_file_key) = parent {
@@ -209,48 +261,288 @@ impl FileTree {
     }
 }
 
+/// Suffix that turns a file into a filtered view of itself, e.g. `12-30.filter=ERROR` is `12-30` with the
+/// CloudWatch Logs filter pattern `ERROR` applied.
+pub const FILTER_PATTERN_SUFFIX: &str = ".filter=";
+
+/// Suffix that turns a file into a view of the events whose message matches a regular expression, e.g.
+/// `12-30.grep=timed? out` is `12-30` with only the lines whose message matches `timed? out`.
+pub const MESSAGE_FILTER_SUFFIX: &str = ".grep=";
+
+/// How a filtered view filters the events of the file it is derived from.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ViewFilter {
+    /// CloudWatch Logs filter pattern, applied by CloudWatch Logs.
+    FilterPattern(String),
+
+    /// Regular expression that messages must match, applied by cwl-mount after fetching.
+    MessageFilter(String),
+}
+
+/// Split a filename like `12-30.filter=ERROR` or `12-30.grep=ERROR` into the underlying filename and the filter.
+/// Returns None if the filename does not have a non-empty filter suffix. Everything after the first suffix is the
+/// filter, even if it contains another suffix.
+pub fn parse_filtered_filename(filename: &str) -> Option<(&str, ViewFilter)> {
+    let (index, suffix) = [FILTER_PATTERN_SUFFIX, MESSAGE_FILTER_SUFFIX]
+        .into_iter()
+        .filter_map(|suffix| filename.find(suffix).map(|index| (index, suffix)))
+        .min()?;
+    let base_filename = &filename[..index];
+    let filter = filename[index + suffix.len()..].to_string();
+    if base_filename.is_empty() || filter.is_empty() {
+        return None;
+    }
+    let filter = if suffix == FILTER_PATTERN_SUFFIX {
+        ViewFilter::FilterPattern(filter)
+    } else {
+        ViewFilter::MessageFilter(filter)
+    };
+    Some((base_filename, filter))
+}
+
+/// A file that is not part of the [`FileTree`] but is derived from a file in it, for example a filtered view.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VirtualFile {
+    pub inode: u64,
+    pub base_inode: u64,
+    pub filter: ViewFilter,
+}
+
+/// Allocates stable inodes for virtual files so that repeated lookups of the same name get the same inode.
+#[derive(Clone, Debug)]
+pub struct VirtualFiles {
+    next_inode: u64,
+    inodes: HashMap<(u64, ViewFilter), u64>,
+    files: HashMap<u64, VirtualFile>,
+}
+
+impl VirtualFiles {
+    /// `first_inode` must be larger than any inode in the [`FileTree`], see [`FileTree::max_inode`].
+    pub fn new(first_inode: u64) -> Self {
+        Self {
+            next_inode: first_inode,
+            inodes: HashMap::new(),
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn get_or_create(&mut self, base_inode: u64, filter: ViewFilter) -> &VirtualFile {
+        let key = (base_inode, filter);
+        let inode = match self.inodes.get(&key) {
+            Some(inode) => *inode,
+            None => {
+                let inode = self.next_inode;
+                self.next_inode += 1;
+                self.files.insert(
+                    inode,
+                    VirtualFile {
+                        inode,
+                        base_inode,
+                        filter: key.1.clone(),
+                    },
+                );
+                self.inodes.insert(key, inode);
+                inode
+            }
+        };
+        self.files.get(&inode).unwrap()
+    }
+
+    pub fn get(&self, inode: u64) -> Option<&VirtualFile> {
+        self.files.get(&inode)
+    }
+}
+
+/// Name of the directory at the root of the mount that holds CloudWatch Logs Insights queries.
+pub const INSIGHTS_DIRECTORY_NAME: &str = "insights";
+
+/// Name of a hidden file at the root of a mount that reads `ok` if the mount and the process behind it respond.
+pub const HEALTH_FILENAME: &str = ".health";
+
+/// Name of a hidden file at the root of a mount that reads what the mount has done so far, e.g. API calls made and
+/// cache hits.
+pub const STATS_FILENAME: &str = ".stats";
+
+/// Name of the file in the insights directory that new queries are written to.
+pub const INSIGHTS_NEW_QUERY_FILENAME: &str = "new";
+
+/// What reading the results file of an Insights query gives once the query has run: the rendered results, or the
+/// errno that running it failed with. Shared with the task that runs the query, so that reads made meanwhile wait for
+/// it and later reads get the same answer without running it again.
+pub type InsightsResults = Arc<tokio::sync::OnceCell<Result<Bytes, i32>>>;
+
+/// A CloudWatch Logs Insights query written to the insights directory, with its results once they are known.
+#[derive(Clone, Debug)]
+pub struct InsightsQuery {
+    pub inode: u64,
+    pub name: String,
+    pub query_string: String,
+    pub time_bounds: TimeBounds,
+    pub results: InsightsResults,
+}
+
+/// The `insights/` directory. Writing a query string to `insights/new` creates a numbered results file next
+/// to it, e.g. `insights/0001`, whose content is the query results. Like [`VirtualFiles`] it lives outside
+/// the [`FileTree`] because its contents change at runtime.
+#[derive(Clone, Debug)]
+pub struct InsightsDirectory {
+    pub directory_inode: u64,
+    pub new_query_inode: u64,
+    next_inode: u64,
+    names: BTreeMap<String, u64>,
+    queries: HashMap<u64, InsightsQuery>,
+}
+
+impl InsightsDirectory {
+    /// Uses `first_inode` and up; these must not overlap with the [`FileTree`] or other [`VirtualFiles`].
+    pub fn new(first_inode: u64) -> Self {
+        Self {
+            directory_inode: first_inode,
+            new_query_inode: first_inode + 1,
+            next_inode: first_inode + 2,
+            names: BTreeMap::new(),
+            queries: HashMap::new(),
+        }
+    }
+
+    pub fn add_query<T: Into<String>>(&mut self, query_string: T, time_bounds: TimeBounds) -> &InsightsQuery {
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        let name = format!("{:04}", self.queries.len() + 1);
+        self.names.insert(name.clone(), inode);
+        self.queries.insert(
+            inode,
+            InsightsQuery {
+                inode,
+                name,
+                query_string: query_string.into(),
+                time_bounds,
+                results: InsightsResults::default(),
+            },
+        );
+        self.queries.get(&inode).unwrap()
+    }
+
+    pub fn get_query(&self, inode: u64) -> Option<&InsightsQuery> {
+        self.queries.get(&inode)
+    }
+
+    pub fn get_query_by_name(&self, name: &str) -> Option<&InsightsQuery> {
+        self.names.get(name).and_then(|inode| self.queries.get(inode))
+    }
+
+    /// Queries in the order they were created.
+    pub fn list_queries(&self) -> Vec<&InsightsQuery> {
+        self.names
+            .values()
+            .filter_map(|inode| self.queries.get(inode))
+            .collect()
+    }
+}
+
+/// Encode a CloudWatch Logs log group name so that it is a valid single path component.
+///
+/// Log group names may contain `/` and may be exactly `.` or `..`. `%` is not a valid log group name
+/// character [1] so we percent-encode those cases, which means the encoding is reversible using
+/// [`decode_log_group_name`].
+///
+/// [1] https://docs.aws.amazon.com/AmazonCloudWatchLogs/latest/APIReference/API_CreateLogGroup.html
+pub fn encode_log_group_name(log_group_name: &str) -> String {
+    if log_group_name == "." || log_group_name == ".." {
+        return log_group_name.replace('.', "%2E");
+    }
+    let mut result = String::with_capacity(log_group_name.len());
+    for c in log_group_name.chars() {
+        match c {
+            '/' => result.push_str("%2F"),
+            '%' => result.push_str("%25"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Reverse [`encode_log_group_name`]. Returns None if the path component is not a valid encoding.
+pub fn decode_log_group_name(path_component: &str) -> Option<String> {
+    let mut result = String::with_capacity(path_component.len());
+    let mut chars = path_component.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        let hex: String = chars.by_ref().take(2).collect();
+        if hex.len() != 2 {
+            return None;
+        }
+        let decoded = u8::from_str_radix(&hex, 16).ok()?;
+        if !decoded.is_ascii() {
+            return None;
+        }
+        result.push(decoded as char);
+    }
+    Some(result)
+}
+
 pub fn create_file_tree_for_time_range(start_time: DateTime<Utc>, end_time: DateTime<Utc>) -> FileTree {
-    let just_under_one_minute = Duration::minutes(1) - Duration::nanoseconds(1);
     let expected_number_of_files = (end_time - start_time).num_minutes() as usize;
     let mut file_tree = FileTree::new(expected_number_of_files);
-    let mut year = start_time.year();
-    while year <= end_time.year() {
-        let year_file = file_tree.create_directory(
-            year.to_string(),
+    let root = file_tree.get_root();
+    create_files_for_time_range(&mut file_tree, root, start_time, end_time);
+    file_tree
+}
+
+/// Create a file tree where the root has one directory per log group, named using
+/// [`encode_log_group_name`], and each log group directory contains the time range tree.
+pub fn create_file_tree_for_log_groups<T: AsRef<str>>(
+    log_group_names: &[T],
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+) -> FileTree {
+    let expected_number_of_files = (end_time - start_time).num_minutes() as usize * log_group_names.len();
+    let mut file_tree = FileTree::new(expected_number_of_files);
+    file_tree.log_group_directories = true;
+    for log_group_name in log_group_names {
+        let log_group_directory = file_tree.create_directory(
+            encode_log_group_name(log_group_name.as_ref()),
             file_tree.get_root(),
         );
-        for month in 1..=12 {
-            let month_file = file_tree.create_directory(
-                format!("{:02}", month),
-                Some(year_file),
-            );
-            for day in 1..=31 {
-                match Utc.ymd_opt(year, month, day) {
-                    chrono::LocalResult::Single(date) => {
-                        let day_file = file_tree.create_directory(
-                            format!("{:02}", day),
-                            Some(month_file),
-                        );
-                        for hour in 0..=23 {
-                            for minute in 0..=59 {
-                                let filename = format!("{:02}-{:02}", hour, minute);
-                                let time_bound_start = date.and_hms(hour, minute, 0);
-                                let time_bound_end = time_bound_start + just_under_one_minute;
-                                let time_bounds = TimeBounds {
-                                    start_time: time_bound_start,
-                                    end_time: time_bound_end,
-                                };
-                                file_tree.create_file(filename, time_bounds, Some(day_file));
-                            }
-                        }
-                    }
-                    _ => continue,
+        create_files_for_time_range(&mut file_tree, Some(log_group_directory), start_time, end_time);
+    }
+    file_tree
+}
+
+/// Create one file per minute, under year, month and day directories, for the minutes that overlap
+/// `[start_time, end_time)`.
+fn create_files_for_time_range(
+    file_tree: &mut FileTree,
+    parent: Option<FileKey>,
+    start_time: DateTime<Utc>,
+    end_time: DateTime<Utc>,
+) {
+    let just_under_one_minute = Duration::minutes(1) - Duration::nanoseconds(1);
+    let mut date = start_time.date();
+    while date.and_hms(0, 0, 0) < end_time {
+        let year_file = file_tree.create_directory(date.year().to_string(), parent);
+        let month_file = file_tree.create_directory(format!("{:02}", date.month()), Some(year_file));
+        let day_file = file_tree.create_directory(format!("{:02}", date.day()), Some(month_file));
+        for hour in 0..=23 {
+            for minute in 0..=59 {
+                let time_bound_start = date.and_hms(hour, minute, 0);
+                let time_bound_end = time_bound_start + just_under_one_minute;
+                if time_bound_end < start_time || time_bound_start >= end_time {
+                    continue;
                 }
+                let filename = format!("{:02}-{:02}", hour, minute);
+                let time_bounds = TimeBounds {
+                    start_time: time_bound_start,
+                    end_time: time_bound_end,
+                };
+                file_tree.create_file(filename, time_bounds, Some(day_file));
             }
         }
-        year += 1;
+        date = date.succ();
     }
-    file_tree
 }
 
 #[cfg(test)]
@@ -258,7 +550,15 @@ mod tests {
     use chrono::TimeZone;
     use chrono::Utc;
 
+    use crate::create_file_tree_for_log_groups;
     use crate::create_file_tree_for_time_range;
+    use crate::decode_log_group_name;
+    use crate::encode_log_group_name;
+    use crate::parse_filtered_filename;
+    use crate::InsightsDirectory;
+    use crate::TimeBounds;
+    use crate::ViewFilter;
+    use crate::VirtualFiles;
 
     #[test]
     fn test_create_files_for_time_range() {
@@ -271,4 +571,163 @@ mod tests {
         let first_dir_list = actual_result.list_directory(first_dir);
         println!("{:?}", first_dir_list);
     }
+
+    #[test]
+    fn test_create_files_for_time_range_is_bounded() {
+        let start_time = Utc.ymd(2021, 12, 4).and_hms(12, 30, 30);
+        let end_time = Utc.ymd(2021, 12, 5).and_hms(0, 2, 0);
+        let file_tree = create_file_tree_for_time_range(start_time, end_time);
+        let year = file_tree.get_child_for_inode(1, "2021").unwrap();
+        let months = file_tree.list_directory(&year);
+        assert_eq!(
+            vec!["12"],
+            months.iter().map(|file| &file.file.name).collect::<Vec<_>>()
+        );
+        let days = file_tree.list_directory(&months[0]);
+        assert_eq!(
+            vec!["04", "05"],
+            days.iter().map(|file| &file.file.name).collect::<Vec<_>>()
+        );
+        let first_day = file_tree.list_directory(&days[0]);
+        assert_eq!("12-30", first_day.first().unwrap().file.name);
+        assert_eq!("23-59", first_day.last().unwrap().file.name);
+        let second_day = file_tree.list_directory(&days[1]);
+        assert_eq!(
+            vec!["00-00", "00-01"],
+            second_day.iter().map(|file| &file.file.name).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_files_in_time_range() {
+        let start_time = Utc.ymd(2021, 12, 31).and_hms(23, 58, 30);
+        let end_time = Utc.ymd(2022, 1, 1).and_hms(0, 1, 0);
+        let file_tree = create_file_tree_for_log_groups(&["/app/web"], start_time, end_time);
+        let paths: Vec<String> = file_tree
+            .files_in_time_range(start_time, end_time)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(
+            vec![
+                "%2Fapp%2Fweb/2021/12/31/23-58",
+                "%2Fapp%2Fweb/2021/12/31/23-59",
+                "%2Fapp%2Fweb/2022/01/01/00-00",
+            ],
+            paths
+        );
+    }
+
+    #[test]
+    fn test_encode_log_group_name_round_trips() {
+        for log_group_name in ["/aws/lambda/foo", "plain", "a.b#c-d_e", ".", "..", "./..", "/"] {
+            let encoded = encode_log_group_name(log_group_name);
+            assert!(!encoded.contains('/'));
+            assert_ne!(".", encoded);
+            assert_ne!("..", encoded);
+            assert_eq!(Some(log_group_name.to_string()), decode_log_group_name(&encoded));
+        }
+    }
+
+    #[test]
+    fn test_decode_log_group_name_rejects_invalid_escapes() {
+        assert_eq!(None, decode_log_group_name("foo%2"));
+        assert_eq!(None, decode_log_group_name("foo%zz"));
+    }
+
+    #[test]
+    fn test_parse_filtered_filename() {
+        let pattern = |pattern: &str| ViewFilter::FilterPattern(pattern.to_string());
+        assert_eq!(
+            Some(("12-30", pattern("ERROR"))),
+            parse_filtered_filename("12-30.filter=ERROR")
+        );
+        assert_eq!(
+            Some(("12-30", pattern("\"a b\" -c"))),
+            parse_filtered_filename("12-30.filter=\"a b\" -c")
+        );
+        assert_eq!(None, parse_filtered_filename("12-30"));
+        assert_eq!(None, parse_filtered_filename("12-30.filter="));
+        assert_eq!(None, parse_filtered_filename(".filter=ERROR"));
+        assert_eq!(
+            Some((
+                "12-30",
+                ViewFilter::MessageFilter("timed? out.filter=x".to_string())
+            )),
+            parse_filtered_filename("12-30.grep=timed? out.filter=x")
+        );
+        assert_eq!(None, parse_filtered_filename("12-30.grep="));
+    }
+
+    #[test]
+    fn test_virtual_files_have_stable_inodes() {
+        let mut virtual_files = VirtualFiles::new(100);
+        let pattern = |pattern: &str| ViewFilter::FilterPattern(pattern.to_string());
+        let first = virtual_files.get_or_create(5, pattern("ERROR")).inode;
+        let second = virtual_files.get_or_create(5, pattern("WARN")).inode;
+        assert_eq!(100, first);
+        assert_eq!(101, second);
+        assert_eq!(first, virtual_files.get_or_create(5, pattern("ERROR")).inode);
+        assert_eq!(5, virtual_files.get(second).unwrap().base_inode);
+        assert!(virtual_files.get(102).is_none());
+        assert_eq!(
+            102,
+            virtual_files
+                .get_or_create(5, ViewFilter::MessageFilter("ERROR".to_string()))
+                .inode
+        );
+    }
+
+    #[test]
+    fn test_insights_directory_numbers_queries() {
+        let mut insights_directory = InsightsDirectory::new(10);
+        let time_bounds = TimeBounds {
+            start_time: Utc.ymd(2021, 12, 4).and_hms(0, 0, 0),
+            end_time: Utc.ymd(2021, 12, 4).and_hms(1, 0, 0),
+        };
+        let first = insights_directory.add_query("fields @message", time_bounds).inode;
+        let second = insights_directory.add_query("stats count(*)", time_bounds).inode;
+        assert_eq!(12, first);
+        assert_eq!(13, second);
+        assert_eq!(
+            second,
+            insights_directory.get_query_by_name("0002").unwrap().inode
+        );
+        let names: Vec<&str> = insights_directory
+            .list_queries()
+            .iter()
+            .map(|query| query.name.as_str())
+            .collect();
+        assert_eq!(vec!["0001", "0002"], names);
+
+        let results = &insights_directory.get_query(first).unwrap().results;
+        assert!(results.get().is_none());
+        results.set(Ok("results".into())).unwrap();
+        assert_eq!(
+            Some(&Ok("results".into())),
+            insights_directory.get_query(first).unwrap().results.get()
+        );
+    }
+
+    #[test]
+    fn test_create_file_tree_for_log_groups() {
+        let start_time = Utc.ymd(2021, 12, 4).and_hms(0, 0, 0);
+        let end_time = Utc.ymd(2021, 12, 4).and_hms(1, 0, 0);
+        let file_tree = create_file_tree_for_log_groups(&["/aws/lambda/foo", "bar"], start_time, end_time);
+        let root_list = file_tree.list_root();
+        let names: Vec<&str> = root_list.iter().map(|f| f.file.name.as_str()).collect();
+        assert_eq!(vec!["%2Faws%2Flambda%2Ffoo", "bar"], names);
+
+        let log_group_directory = root_list.first().unwrap();
+        let year = file_tree
+            .get_child_for_inode(log_group_directory.file.inode, "2021")
+            .unwrap();
+        let month = file_tree.get_child_for_inode(year.file.inode, "12").unwrap();
+        let day = file_tree.get_child_for_inode(month.file.inode, "04").unwrap();
+        let minute = file_tree.get_child_for_inode(day.file.inode, "00-00").unwrap();
+        assert_eq!(
+            Some("/aws/lambda/foo".to_string()),
+            file_tree.get_log_group_name(minute.file_key)
+        );
+    }
 }
@@ -33,7 +33,7 @@ pub fn valid_cwl_log_group_name<T: AsRef<str>>(log_group_name: T) -> bool {
     $               # end of string
     "#
     );
-    return pattern.is_match(log_group_name);
+    pattern.is_match(log_group_name)
 }
 
 pub fn clap_validate_cwl_log_group_name<T: Into<String>>(log_group_name: T) -> Result<(), String> {
@@ -47,6 +47,152 @@ pub fn clap_validate_cwl_log_group_name<T: Into<String>>(log_group_name: T) -> R
     }
 }
 
+/// A log group ARN [1], e.g. `arn:aws:logs:us-east-1:123456789012:log-group:/my/group`.
+///
+/// [1] https://docs.aws.amazon.com/AmazonCloudWatch/latest/logs/iam-access-control-overview-cwl.html
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogGroupArn {
+    pub partition: String,
+    pub region: String,
+    pub account_id: String,
+    pub log_group_name: String,
+}
+
+impl LogGroupArn {
+    /// Parse a log group ARN, with or without the `:*` that DescribeLogGroups puts at the end.
+    pub fn parse<T: AsRef<str>>(arn: T) -> Option<LogGroupArn> {
+        let pattern = regex!(r"^arn:(aws[a-z-]*):logs:([a-z0-9-]+):(\d{12}):log-group:([^:]+)(:\*)?$");
+        let captures = pattern.captures(arn.as_ref())?;
+        let log_group_name = captures[4].to_string();
+        if !valid_cwl_log_group_name(&log_group_name) {
+            return None;
+        }
+        Some(LogGroupArn {
+            partition: captures[1].to_string(),
+            region: captures[2].to_string(),
+            account_id: captures[3].to_string(),
+            log_group_name,
+        })
+    }
+
+    /// The ARN without the `:*` at the end, which is what the `logGroupIdentifier` of FilterLogEvents and
+    /// GetLogEvents takes for log groups in other accounts.
+    pub fn identifier(&self) -> String {
+        format!(
+            "arn:{}:logs:{}:{}:log-group:{}",
+            self.partition, self.region, self.account_id, self.log_group_name
+        )
+    }
+}
+
+/// Like `clap_validate_cwl_log_group_name`, but also accepts a log group ARN.
+pub fn clap_validate_cwl_log_group_name_or_arn<T: Into<String>>(value: T) -> Result<(), String> {
+    let value = value.into();
+    if value.starts_with("arn:") {
+        match LogGroupArn::parse(&value) {
+            Some(_) => Ok(()),
+            None => Err(format!("{} is not a valid CloudWatch Logs log group ARN", value)),
+        }
+    } else {
+        clap_validate_cwl_log_group_name(value)
+    }
+}
+
+/// Check if the string is a valid AWS CloudWatch Logs log stream name [1], which is also what a log stream name
+/// prefix must look like.
+///
+/// [1] https://docs.aws.amazon.com/AmazonCloudWatchLogs/latest/APIReference/API_CreateLogStream.html
+pub fn valid_cwl_log_stream_name<T: AsRef<str>>(log_stream_name: T) -> bool {
+    const MIN_LENGTH: usize = 1;
+    const MAX_LENGTH: usize = 512;
+    let log_stream_name: &str = log_stream_name.as_ref();
+    if log_stream_name.len() < MIN_LENGTH || log_stream_name.len() > MAX_LENGTH {
+        return false;
+    }
+    let pattern = regex!(r"^[^:*]+$");
+    pattern.is_match(log_stream_name)
+}
+
+pub fn clap_validate_cwl_log_stream_name<T: Into<String>>(log_stream_name: T) -> Result<(), String> {
+    let log_stream_name = log_stream_name.into();
+    match valid_cwl_log_stream_name(&log_stream_name) {
+        true => Ok(()),
+        false => Err(format!(
+            "{} is not a valid CloudWatch Logs log stream name",
+            log_stream_name
+        )),
+    }
+}
+
+/// Check if the string is an IAM role ARN [1], e.g. `arn:aws:iam::123456789012:role/ReadLogs`.
+///
+/// [1] https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_identifiers.html#identifiers-arns
+pub fn valid_iam_role_arn<T: AsRef<str>>(role_arn: T) -> bool {
+    let pattern = regex!(r"^arn:aws[a-z-]*:iam::\d{12}:role/[A-Za-z0-9_+=,.@/-]+$");
+    pattern.is_match(role_arn.as_ref())
+}
+
+pub fn clap_validate_iam_role_arn<T: Into<String>>(role_arn: T) -> Result<(), String> {
+    let role_arn = role_arn.into();
+    match valid_iam_role_arn(&role_arn) {
+        true => Ok(()),
+        false => Err(format!("{} is not a valid IAM role ARN", role_arn)),
+    }
+}
+
+/// Check if the string is a 12 digit AWS account ID.
+pub fn valid_account_id<T: AsRef<str>>(account_id: T) -> bool {
+    let pattern = regex!(r"^\d{12}$");
+    pattern.is_match(account_id.as_ref())
+}
+
+pub fn clap_validate_account_id<T: Into<String>>(account_id: T) -> Result<(), String> {
+    let account_id = account_id.into();
+    match valid_account_id(&account_id) {
+        true => Ok(()),
+        false => Err(format!("{} is not a valid AWS account ID", account_id)),
+    }
+}
+
+/// Check if the string is a valid STS role session name [1].
+///
+/// [1] https://docs.aws.amazon.com/STS/latest/APIReference/API_AssumeRole.html
+pub fn valid_role_session_name<T: AsRef<str>>(session_name: T) -> bool {
+    let pattern = regex!(r"^[A-Za-z0-9_+=,.@-]{2,64}$");
+    pattern.is_match(session_name.as_ref())
+}
+
+pub fn clap_validate_role_session_name<T: Into<String>>(session_name: T) -> Result<(), String> {
+    let session_name = session_name.into();
+    match valid_role_session_name(&session_name) {
+        true => Ok(()),
+        false => Err(format!(
+            "{} is not a valid role session name, use 2 to 64 letters, digits or any of _+=,.@-",
+            session_name
+        )),
+    }
+}
+
+/// Check if the string is a plausible CloudWatch Logs filter pattern [1]. The syntax itself is validated by
+/// CloudWatch Logs, here we only check the length constraints.
+///
+/// [1] https://docs.aws.amazon.com/AmazonCloudWatchLogs/latest/APIReference/API_FilterLogEvents.html
+pub fn clap_validate_filter_pattern<T: Into<String>>(filter_pattern: T) -> Result<(), String> {
+    const MAX_LENGTH: usize = 1024;
+    let filter_pattern = filter_pattern.into();
+    if filter_pattern.trim().is_empty() {
+        return Err("filter pattern must not be empty".to_string());
+    }
+    if filter_pattern.len() > MAX_LENGTH {
+        return Err(format!(
+            "filter pattern is {} characters long, maximum is {}",
+            filter_pattern.len(),
+            MAX_LENGTH
+        ));
+    }
+    Ok(())
+}
+
 pub fn validate_regex(regex: String) -> Result<(), String> {
     match regex::Regex::new(&regex) {
         Ok(_) => Ok(()),
@@ -57,6 +203,42 @@ pub fn validate_regex(regex: String) -> Result<(), String> {
     }
 }
 
+pub fn clap_validate_message_filter(regex: String) -> Result<(), String> {
+    MessageFilter::new(&regex).map(|_| ())
+}
+
+/// A regular expression that log event messages must match, applied by cwl-mount after events are fetched, for
+/// when CloudWatch Logs filter patterns are not enough.
+#[derive(Derivative)]
+#[derivative(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct MessageFilter {
+    original_regex: String,
+
+    #[derivative(Debug = "ignore")]
+    #[derivative(PartialEq = "ignore")]
+    #[derivative(Hash = "ignore")]
+    matcher: regex::Regex,
+}
+
+impl MessageFilter {
+    pub fn new(re: &str) -> Result<Self, String> {
+        let matcher = regex::Regex::new(re)
+            .map_err(|err| format!("{} is not a valid regular expression for messages: {}", re, err))?;
+        Ok(Self {
+            original_regex: re.to_string(),
+            matcher,
+        })
+    }
+
+    pub fn is_match(&self, message: &str) -> bool {
+        self.matcher.is_match(message)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.original_regex
+    }
+}
+
 #[derive(Derivative)]
 #[derivative(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct LogGroupNameMatcher {
@@ -66,6 +248,14 @@ pub struct LogGroupNameMatcher {
     #[derivative(PartialEq = "ignore")]
     #[derivative(Hash = "ignore")]
     matcher: regex::Regex,
+
+    /// Log group names that match this are left out even if `matcher` matches them.
+    original_exclude_regex: Option<String>,
+
+    #[derivative(Debug = "ignore")]
+    #[derivative(PartialEq = "ignore")]
+    #[derivative(Hash = "ignore")]
+    exclude_matcher: Option<regex::Regex>,
 }
 
 impl LogGroupNameMatcher {
@@ -75,14 +265,96 @@ impl LogGroupNameMatcher {
         Self {
             original_regex: re,
             matcher,
+            original_exclude_regex: None,
+            exclude_matcher: None,
+        }
+    }
+
+    /// This matcher, but leaving out log group names that match `re`, e.g. `^prod-` excluding
+    /// `^prod-.*-healthcheck$`.
+    pub fn excluding(self, re: &str) -> Self {
+        Self {
+            original_exclude_regex: Some(re.to_string()),
+            exclude_matcher: Some(regex::Regex::new(re).unwrap()),
+            ..self
         }
     }
 
+    pub fn as_str(&self) -> &str {
+        &self.original_regex
+    }
+
+    /// The pattern passed to `excluding`, if any.
+    pub fn exclude_as_str(&self) -> Option<&str> {
+        self.original_exclude_regex.as_deref()
+    }
+
+    /// Log groups in other accounts are named by their ARN, see `LogGroupArn::identifier`. Those match if either the
+    /// ARN or the log group name in it matches, so that `^/aws/lambda/` also picks up the Lambda functions of linked
+    /// source accounts.
     pub fn is_match(&self, expr: &str) -> bool {
-        self.matcher.is_match(expr)
+        let arn = LogGroupArn::parse(expr);
+        let exprs = [Some(expr), arn.as_ref().map(|arn| arn.log_group_name.as_str())];
+        let exprs = exprs.iter().flatten();
+        exprs.clone().any(|expr| self.matcher.is_match(expr))
+            && !self
+                .exclude_matcher
+                .as_ref()
+                .is_some_and(|exclude_matcher| exprs.clone().any(|expr| exclude_matcher.is_match(expr)))
+    }
+
+    /// Literal text that every matching log group name starts with, if the pattern is anchored at the start, e.g.
+    /// `/aws/lambda/` for `^/aws/lambda/.*-prod$`. This is conservative: it stops at the first character that is
+    /// not plain text, and gives up on patterns with alternations. For a pattern that starts with a log group ARN, this
+    /// is the start of the log group name in it, since that is what listing log groups filters on.
+    pub fn literal_prefix(&self) -> Option<String> {
+        let pattern = self.original_regex.strip_prefix('^')?;
+        if pattern.contains('|') {
+            return None;
+        }
+        let mut prefix = String::new();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            let literal = match c {
+                '\\' => match chars.next() {
+                    Some(escaped) if escaped.is_ascii_punctuation() => escaped,
+                    _ => break,
+                },
+                '.' | '[' | ']' | '(' | ')' | '{' | '}' | '*' | '+' | '?' | '$' | '^' => break,
+                c => c,
+            };
+            match chars.peek() {
+                // The character may not be there at all.
+                Some('?') | Some('*') | Some('{') => break,
+                // The character may be repeated, so what follows is not part of the prefix.
+                Some('+') => {
+                    prefix.push(literal);
+                    break;
+                }
+                _ => prefix.push(literal),
+            }
+        }
+        if prefix.starts_with("arn:") {
+            prefix = prefix.split_once(":log-group:")?.1.to_string();
+        }
+        if prefix.is_empty() {
+            None
+        } else {
+            Some(prefix)
+        }
     }
 }
 
+/// A log group filter that matches exactly the given log group names and nothing else, e.g.
+/// `^(?:/aws/lambda/a|my\.group)$`.
+pub fn exact_log_group_names_filter<T: AsRef<str>>(log_group_names: &[T]) -> String {
+    let alternatives: Vec<String> = log_group_names
+        .iter()
+        .map(|log_group_name| regex::escape(log_group_name.as_ref()))
+        .collect();
+    format!("^(?:{})$", alternatives.join("|"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,6 +379,144 @@ mod tests {
         assert!(clap_validate_cwl_log_group_name("log-group+").is_err());
     }
 
+    #[test]
+    fn test_log_group_arn() {
+        assert_eq!(
+            Some(LogGroupArn {
+                partition: "aws".to_string(),
+                region: "us-east-1".to_string(),
+                account_id: "123456789012".to_string(),
+                log_group_name: "/aws/lambda/my-function".to_string(),
+            }),
+            LogGroupArn::parse("arn:aws:logs:us-east-1:123456789012:log-group:/aws/lambda/my-function:*")
+        );
+        assert!(
+            LogGroupArn::parse("arn:aws-us-gov:logs:us-gov-west-1:123456789012:log-group:my-group").is_some()
+        );
+        assert!(
+            LogGroupArn::parse("arn:aws:logs:us-east-1:123456789012:log-group:my-group:log-stream:a")
+                .is_none()
+        );
+        assert_eq!(
+            "arn:aws:logs:us-east-1:123456789012:log-group:/aws/lambda/my-function",
+            LogGroupArn::parse("arn:aws:logs:us-east-1:123456789012:log-group:/aws/lambda/my-function:*")
+                .unwrap()
+                .identifier()
+        );
+        assert!(clap_validate_cwl_log_group_name_or_arn("my-group").is_ok());
+        assert!(clap_validate_cwl_log_group_name_or_arn("arn:aws:iam::123456789012:role/ReadLogs").is_err());
+    }
+
+    #[test]
+    fn test_valid_cwl_log_stream_name() {
+        assert!(valid_cwl_log_stream_name("2021/12/04/[$LATEST]abcdef"));
+        assert!(valid_cwl_log_stream_name("i-0123456789abcdef0"));
+        assert!(!valid_cwl_log_stream_name(""));
+        assert!(!valid_cwl_log_stream_name("foo:bar"));
+        assert!(!valid_cwl_log_stream_name("foo*"));
+    }
+
+    #[test]
+    fn test_valid_iam_role_arn() {
+        assert!(valid_iam_role_arn("arn:aws:iam::123456789012:role/ReadLogs"));
+        assert!(valid_iam_role_arn(
+            "arn:aws-us-gov:iam::123456789012:role/path/ReadLogs"
+        ));
+        assert!(!valid_iam_role_arn("arn:aws:iam::123456789012:user/someone"));
+        assert!(!valid_iam_role_arn("arn:aws:iam::1234:role/ReadLogs"));
+        assert!(!valid_iam_role_arn("ReadLogs"));
+    }
+
+    #[test]
+    fn test_valid_role_session_name() {
+        assert!(valid_role_session_name("cwl-mount"));
+        assert!(valid_role_session_name("someone@example.com"));
+        assert!(!valid_role_session_name("a"));
+        assert!(!valid_role_session_name("has space"));
+    }
+
+    #[test]
+    fn test_valid_account_id() {
+        assert!(valid_account_id("123456789012"));
+        assert!(!valid_account_id("1234"));
+        assert!(clap_validate_account_id("12345678901a").is_err());
+    }
+
+    #[test]
+    fn test_clap_validate_filter_pattern() {
+        assert!(clap_validate_filter_pattern("ERROR").is_ok());
+        assert!(clap_validate_filter_pattern("{ $.level = \"error\" }").is_ok());
+        assert!(clap_validate_filter_pattern(" ").is_err());
+        let too_long: String = (0..1025).map(|_| "a").collect();
+        assert!(clap_validate_filter_pattern(too_long).is_err());
+    }
+
+    #[test]
+    fn test_message_filter() {
+        let filter = MessageFilter::new("timed? out").unwrap();
+        assert!(filter.is_match("request timed out after 30s"));
+        assert!(!filter.is_match("request succeeded"));
+        assert_eq!("timed? out", filter.as_str());
+        assert!(clap_validate_message_filter("(unclosed".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_literal_prefix() {
+        let prefix = |re: &str| LogGroupNameMatcher::new(re).literal_prefix();
+        assert_eq!(Some("/aws/lambda/".to_string()), prefix("^/aws/lambda/.*-prod$"));
+        assert_eq!(Some("my-service.prod".to_string()), prefix("^my-service\\.prod$"));
+        assert_eq!(Some("my-service".to_string()), prefix("^my-services?"));
+        assert_eq!(Some("ab".to_string()), prefix("^ab+c"));
+        assert_eq!(Some("app-".to_string()), prefix("^app-\\d+"));
+        assert_eq!(None, prefix("/aws/lambda/"));
+        assert_eq!(None, prefix("^foo|^bar"));
+        assert_eq!(None, prefix("^(foo|bar)"));
+        assert_eq!(None, prefix("^.*"));
+        assert_eq!(
+            Some("/app/web".to_string()),
+            prefix(&format!(
+                "^{}$",
+                regex::escape("arn:aws:logs:us-east-1:222222222222:log-group:/app/web")
+            ))
+        );
+        assert_eq!(None, prefix("^arn:aws:logs:us-east-1:"));
+    }
+
+    #[test]
+    fn test_log_group_name_matcher_arn() {
+        let arn = "arn:aws:logs:us-east-1:222222222222:log-group:/app/web-healthcheck";
+        let matcher = LogGroupNameMatcher::new("^/app/");
+        assert!(matcher.is_match(arn));
+        assert!(!matcher.clone().excluding("-healthcheck$").is_match(arn));
+        assert!(LogGroupNameMatcher::new(":222222222222:").is_match(arn));
+        assert!(!LogGroupNameMatcher::new(":333333333333:").is_match(arn));
+        let exact = LogGroupNameMatcher::new(&exact_log_group_names_filter(&[arn]));
+        assert!(exact.is_match(arn));
+        assert!(!exact.is_match("/app/web-healthcheck"));
+    }
+
+    #[test]
+    fn test_log_group_name_matcher_excluding() {
+        let matcher = LogGroupNameMatcher::new("^prod-").excluding("^prod-.*-healthcheck$");
+        assert!(matcher.is_match("prod-api"));
+        assert!(!matcher.is_match("prod-api-healthcheck"));
+        assert!(!matcher.is_match("staging-api"));
+        assert_eq!(Some("prod-".to_string()), matcher.literal_prefix());
+        assert_ne!(LogGroupNameMatcher::new("^prod-"), matcher);
+        assert!(format!("{:?}", matcher).contains("healthcheck"));
+    }
+
+    #[test]
+    fn test_exact_log_group_names_filter() {
+        let filter = exact_log_group_names_filter(&["/aws/lambda/a", "my.group"]);
+        assert_eq!("^(?:/aws/lambda/a|my\\.group)$", filter);
+        let matcher = LogGroupNameMatcher::new(&filter);
+        assert!(matcher.is_match("/aws/lambda/a"));
+        assert!(matcher.is_match("my.group"));
+        assert!(!matcher.is_match("/aws/lambda/ab"));
+        assert!(!matcher.is_match("myXgroup"));
+    }
+
     #[test]
     fn test_valid_cwl_log_group_name_does_not_match_too_long() {
         let log_group_name: String = (0..1000).map(|_| "a").collect();
@@ -0,0 +1,183 @@
+/*
+ * Copyright Kitten Cat LLC. All Rights Reserved.
+ * SPDX-License-Identifier: Apache-2.0.
+ */
+
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::NaiveDate;
+use chrono::Utc;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum TimeExpressionError {
+    #[error("'{0}' isn't a time, use RFC 3339 like 2021-12-04T00:00:00Z, a date like 2021-12-04, 'now', 'today' or 'yesterday' optionally followed by + or - a duration like now-2h, or a duration ago like 45m or -45m")]
+    InvalidTime(String),
+
+    #[error("'{0}' isn't a duration, use a positive number and one of s, m, h, d or w like 10m")]
+    InvalidDuration(String),
+
+    #[error("'{0}' is too far from now")]
+    OutOfRange(String),
+}
+
+/// Parse a positive duration made of a number and a unit, one of s, m, h, d or w, e.g. `10m`.
+pub fn parse_duration(expression: &str) -> Result<Duration, TimeExpressionError> {
+    let err = || TimeExpressionError::InvalidDuration(expression.to_string());
+    let unit_index = expression.find(|c: char| !c.is_ascii_digit()).ok_or_else(err)?;
+    let (amount, unit) = expression.split_at(unit_index);
+    let amount = amount
+        .parse::<i64>()
+        .ok()
+        .filter(|amount| *amount > 0)
+        .ok_or_else(err)?;
+    let seconds = match unit {
+        "s" => Some(amount),
+        "m" => amount.checked_mul(60),
+        "h" => amount.checked_mul(60 * 60),
+        "d" => amount.checked_mul(24 * 60 * 60),
+        "w" => amount.checked_mul(7 * 24 * 60 * 60),
+        _ => return Err(err()),
+    };
+    seconds
+        .filter(|seconds| *seconds <= i64::MAX / 1000)
+        .map(Duration::seconds)
+        .ok_or_else(|| TimeExpressionError::OutOfRange(expression.to_string()))
+}
+
+/// Parse a time given either exactly or relative to `now`, all in UTC:
+///
+/// - RFC 3339, e.g. `2021-12-04T00:00:00Z`
+/// - a date, meaning its midnight, e.g. `2021-12-04`
+/// - `now`, `today` or `yesterday`, the last two meaning midnight
+/// - one of those followed by + or - a duration, e.g. `now-2h` or `yesterday+9h`
+/// - a duration, optionally preceded by -, meaning that long ago, e.g. `45m` or `-45m`
+pub fn parse_time(expression: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, TimeExpressionError> {
+    let expression = expression.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(expression) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(expression, "%Y-%m-%d") {
+        return Ok(DateTime::from_utc(date.and_hms(0, 0, 0), Utc));
+    }
+
+    let invalid = || TimeExpressionError::InvalidTime(expression.to_string());
+    let out_of_range = || TimeExpressionError::OutOfRange(expression.to_string());
+    let base_end = expression.find(['+', '-']).unwrap_or(expression.len());
+    let (base, offset) = expression.split_at(base_end);
+    let today = now.date().and_hms(0, 0, 0);
+    let base = match base {
+        "now" => now,
+        "today" => today,
+        "yesterday" => today - Duration::days(1),
+        "" => now,
+        _ => {
+            // A duration on its own is how long ago.
+            return match parse_duration(expression) {
+                Ok(duration) => now.checked_sub_signed(duration).ok_or_else(out_of_range),
+                Err(_) => Err(invalid()),
+            };
+        }
+    };
+    if offset.is_empty() {
+        return if expression.is_empty() {
+            Err(invalid())
+        } else {
+            Ok(base)
+        };
+    }
+    let (sign, duration) = offset.split_at(1);
+    if duration.is_empty() {
+        return Err(invalid());
+    }
+    let duration = parse_duration(duration)?;
+    match sign {
+        "+" => base.checked_add_signed(duration).ok_or_else(out_of_range),
+        _ => base.checked_sub_signed(duration).ok_or_else(out_of_range),
+    }
+}
+
+pub fn clap_validate_duration<T: Into<String>>(expression: T) -> Result<(), String> {
+    parse_duration(&expression.into())
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+pub fn clap_validate_time_expression<T: Into<String>>(expression: T) -> Result<(), String> {
+    parse_time(&expression.into(), Utc::now())
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::TimeZone;
+    use chrono::Utc;
+
+    use crate::parse_duration;
+    use crate::parse_time;
+    use crate::TimeExpressionError;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Ok(Duration::seconds(30)), parse_duration("30s"));
+        assert_eq!(Ok(Duration::minutes(10)), parse_duration("10m"));
+        assert_eq!(Ok(Duration::hours(2)), parse_duration("2h"));
+        assert_eq!(Ok(Duration::days(1)), parse_duration("1d"));
+        assert_eq!(Ok(Duration::weeks(2)), parse_duration("2w"));
+        for invalid in ["", "10", "m", "0m", "-5m", "10x", "1.5h", "10 m"] {
+            assert_eq!(
+                Err(TimeExpressionError::InvalidDuration(invalid.to_string())),
+                parse_duration(invalid),
+                "{}",
+                invalid
+            );
+        }
+        assert!(matches!(
+            parse_duration("99999999999999999w"),
+            Err(TimeExpressionError::OutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let now = Utc.ymd(2021, 12, 4).and_hms(10, 30, 0);
+        let cases = [
+            ("2021-12-01T08:00:00Z", Utc.ymd(2021, 12, 1).and_hms(8, 0, 0)),
+            ("2021-12-01T08:00:00+01:00", Utc.ymd(2021, 12, 1).and_hms(7, 0, 0)),
+            ("2021-12-01", Utc.ymd(2021, 12, 1).and_hms(0, 0, 0)),
+            ("now", now),
+            ("now-2h", Utc.ymd(2021, 12, 4).and_hms(8, 30, 0)),
+            ("now+1d", Utc.ymd(2021, 12, 5).and_hms(10, 30, 0)),
+            ("today", Utc.ymd(2021, 12, 4).and_hms(0, 0, 0)),
+            ("yesterday", Utc.ymd(2021, 12, 3).and_hms(0, 0, 0)),
+            ("yesterday+9h", Utc.ymd(2021, 12, 3).and_hms(9, 0, 0)),
+            ("-45m", Utc.ymd(2021, 12, 4).and_hms(9, 45, 0)),
+            ("45m", Utc.ymd(2021, 12, 4).and_hms(9, 45, 0)),
+            (" now ", now),
+        ];
+        for (expression, expected) in cases {
+            assert_eq!(Ok(expected), parse_time(expression, now), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_parse_time_errors() {
+        let now = Utc.ymd(2021, 12, 4).and_hms(10, 30, 0);
+        for invalid in ["", "tomorrow", "2021-13-01", "12/04/2021", "now-", "now*2h", "-"] {
+            assert!(
+                matches!(parse_time(invalid, now), Err(TimeExpressionError::InvalidTime(_))),
+                "{}",
+                invalid
+            );
+        }
+        assert_eq!(
+            Err(TimeExpressionError::InvalidDuration("2x".to_string())),
+            parse_time("now-2x", now)
+        );
+        assert!(parse_time("now-2x", now)
+            .unwrap_err()
+            .to_string()
+            .contains("use a positive number"));
+    }
+}